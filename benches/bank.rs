@@ -0,0 +1,68 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use transactomatic::bank::Bank;
+
+mod workload;
+use workload::Workload;
+
+fn perform_transaction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("perform_transaction");
+    for dispute_ratio in [0.0, 0.01, 0.1] {
+        let workload = Workload {
+            dispute_ratio,
+            ..Workload::default()
+        };
+        let instructions = workload.instructions();
+        group.throughput(Throughput::Elements(instructions.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(dispute_ratio),
+            &instructions,
+            |b, instructions| {
+                b.iter_batched(
+                    || instructions.clone(),
+                    |instructions| {
+                        let mut bank = Bank::new();
+                        for ti in instructions {
+                            let _ = bank.perform_transaction(ti);
+                        }
+                        bank
+                    },
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+fn clients(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clients");
+    for clients in [10, 1000, u16::MAX] {
+        let instructions = Workload {
+            clients,
+            ..Workload::default()
+        }
+        .instructions();
+        group.throughput(Throughput::Elements(instructions.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(clients),
+            &instructions,
+            |b, instructions| {
+                b.iter_batched(
+                    || instructions.clone(),
+                    |instructions| {
+                        let mut bank = Bank::new();
+                        for ti in instructions {
+                            let _ = bank.perform_transaction(ti);
+                        }
+                        bank
+                    },
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, perform_transaction, clients);
+criterion_main!(benches);
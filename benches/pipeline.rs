@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::io::{self, Cursor};
+use transactomatic::cli;
+
+mod workload;
+use workload::Workload;
+
+fn csv_pipeline(c: &mut Criterion) {
+    let workload = Workload::default();
+    let input = workload.csv();
+
+    let mut group = c.benchmark_group("csv_pipeline");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("run", |b| {
+        b.iter(|| cli::run(input.as_bytes(), io::sink()).unwrap());
+    });
+    for parse_threads in [1, 2, 4] {
+        group.bench_with_input(
+            BenchmarkId::new("parse_threads", parse_threads),
+            &parse_threads,
+            |b, &parse_threads| {
+                b.iter(|| {
+                    let mut options = cli::Options {
+                        parse_threads,
+                        ..cli::Options::default()
+                    };
+                    cli::run_with_options(Cursor::new(input.as_bytes()), io::sink(), &mut options)
+                        .unwrap();
+                });
+            },
+        );
+    }
+    for threads in [2, 4] {
+        group.bench_with_input(
+            BenchmarkId::new("threads", threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let mut options = cli::Options {
+                        threads,
+                        ..cli::Options::default()
+                    };
+                    cli::run_with_options(Cursor::new(input.as_bytes()), io::sink(), &mut options)
+                        .unwrap();
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, csv_pipeline);
+criterion_main!(benches);
@@ -0,0 +1,160 @@
+//! Synthetic workloads shared by the benchmarks.
+//!
+//! A [`Workload`](struct.Workload.html) describes the shape of an input: how many clients, how many instructions, and
+//! what fraction of deposits are disputed.  Generation is deterministic for a given seed so runs are comparable.
+
+// Each benchmark only uses part of this module.
+#![allow(dead_code)]
+
+use std::fmt::Write;
+use transactomatic::bank::amount::Amount;
+use transactomatic::bank::transaction::instruction::{
+    TransactionInstruction, TransactionInstructionKind,
+};
+use transactomatic::bank::{account::AccountId, transaction::TransactionId};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Workload {
+    pub clients: u16,
+    pub transactions: u64,
+    /// Fraction of deposits that are later disputed.  Half of those are resolved and a tenth charged back.
+    pub dispute_ratio: f64,
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            clients: 1000,
+            transactions: 100_000,
+            dispute_ratio: 0.01,
+            seed: 0x5eed,
+        }
+    }
+}
+
+impl Workload {
+    /// Generate the instructions.  Roughly two thirds of the rest are deposits and one third withdrawals.
+    pub fn instructions(&self) -> Vec<TransactionInstruction> {
+        let mut rng = XorShift(self.seed.max(1));
+        let mut instructions = Vec::with_capacity(self.transactions as usize);
+        let mut deposits = vec![];
+        for tx in 0..self.transactions {
+            let client = AccountId::Number(rng.below(u64::from(self.clients.max(1))) as u16);
+            let roll = rng.unit();
+            let instruction = if roll < self.dispute_ratio && !deposits.is_empty() {
+                let (client, tx) = deposits.swap_remove(rng.below(deposits.len() as u64) as usize);
+                instruction(TransactionInstructionKind::Dispute, client, tx, None)
+            } else if roll < self.dispute_ratio * 1.5 {
+                instruction(
+                    TransactionInstructionKind::Resolve,
+                    client,
+                    TransactionId(tx),
+                    None,
+                )
+            } else if roll < self.dispute_ratio * 1.6 {
+                instruction(
+                    TransactionInstructionKind::Chargeback,
+                    client,
+                    TransactionId(tx),
+                    None,
+                )
+            } else if rng.below(3) < 2 {
+                deposits.push((client, TransactionId(tx)));
+                let amount = Amount::new(rng.below(1_000_000) as i64, 4);
+                instruction(
+                    TransactionInstructionKind::Deposit,
+                    client,
+                    TransactionId(tx),
+                    Some(amount),
+                )
+            } else {
+                let amount = Amount::new(rng.below(500_000) as i64, 4);
+                instruction(
+                    TransactionInstructionKind::Withdrawal,
+                    client,
+                    TransactionId(tx),
+                    Some(amount),
+                )
+            };
+            instructions.push(instruction);
+        }
+        // Settle disputes on transactions that were actually disputed.
+        let disputed: Vec<_> = instructions
+            .iter()
+            .filter(|ti| ti.kind == TransactionInstructionKind::Dispute)
+            .map(|ti| (ti.client, ti.tx))
+            .collect();
+        let mut disputed = disputed.into_iter();
+        for ti in &mut instructions {
+            if matches!(
+                ti.kind,
+                TransactionInstructionKind::Resolve | TransactionInstructionKind::Chargeback
+            ) {
+                match disputed.next() {
+                    Some((client, tx)) => {
+                        ti.client = client;
+                        ti.tx = tx;
+                    }
+                    None => break,
+                }
+            }
+        }
+        instructions
+    }
+
+    /// Generate the instructions as CSV input.
+    pub fn csv(&self) -> String {
+        let mut csv = String::from("type,client,tx,amount\n");
+        for ti in self.instructions() {
+            let kind = match ti.kind {
+                TransactionInstructionKind::Deposit => "deposit",
+                TransactionInstructionKind::Withdrawal => "withdrawal",
+                TransactionInstructionKind::Dispute => "dispute",
+                TransactionInstructionKind::Resolve => "resolve",
+                TransactionInstructionKind::Chargeback => "chargeback",
+                TransactionInstructionKind::Reinstate => "reinstate",
+            };
+            let amount = ti.amount.map(|a| a.to_string()).unwrap_or_default();
+            writeln!(csv, "{kind},{},{},{amount}", ti.client, ti.tx.0).unwrap();
+        }
+        csv
+    }
+}
+
+fn instruction(
+    kind: TransactionInstructionKind,
+    client: AccountId,
+    tx: TransactionId,
+    amount: Option<Amount>,
+) -> TransactionInstruction {
+    TransactionInstruction {
+        kind,
+        client,
+        tx,
+        amount,
+        correlation_id: None,
+        operator_reference: None,
+        timestamp: None,
+    }
+}
+
+/// Small deterministic generator; the benchmarks don't need good randomness.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
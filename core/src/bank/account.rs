@@ -0,0 +1,357 @@
+use super::amount::Amount;
+use super::transaction::{Error, TransactionId};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Longest alphanumeric client code, in bytes.
+pub const MAX_CODE_LEN: usize = 22;
+
+/// A client id: a number, as in the original input format, or an alphanumeric code such as `"CUST-0042"`.
+///
+/// Ids made only of digits with a value up to 65535 are numbers, and serialize as numbers; anything else of up to
+/// [`MAX_CODE_LEN`](constant.MAX_CODE_LEN.html) bytes is a code, and serializes as a string.  Numbers sort before
+/// codes.  Codes are stored inline, so ids are `Copy` either way.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AccountId {
+    Number(u16),
+    Code(Code),
+}
+
+/// An alphanumeric client code.  See [`AccountId`](enum.AccountId.html).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Code {
+    len: u8,
+    bytes: [u8; MAX_CODE_LEN],
+}
+
+/// Errors related to parsing an [`AccountId`](enum.AccountId.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdError {
+    Empty,
+    /// The id is a code longer than [`MAX_CODE_LEN`](constant.MAX_CODE_LEN.html) bytes.
+    TooLong,
+}
+
+impl fmt::Display for IdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdError::Empty => write!(f, "empty client id"),
+            IdError::TooLong => write!(f, "client code longer than {MAX_CODE_LEN} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for IdError {}
+
+impl Code {
+    /// The code as a string.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        // Codes are only made from strings, and cut on their length.
+        std::str::from_utf8(&self.bytes[..usize::from(self.len)]).unwrap_or_default()
+    }
+}
+
+impl TryFrom<&str> for Code {
+    type Error = IdError;
+
+    fn try_from(code: &str) -> Result<Self, Self::Error> {
+        if code.is_empty() {
+            return Err(IdError::Empty);
+        }
+        let len = u8::try_from(code.len())
+            .ok()
+            .filter(|len| usize::from(*len) <= MAX_CODE_LEN)
+            .ok_or(IdError::TooLong)?;
+        let mut bytes = [0; MAX_CODE_LEN];
+        bytes[..code.len()].copy_from_slice(code.as_bytes());
+        Ok(Self { len, bytes })
+    }
+}
+
+impl Ord for Code {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for Code {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl AccountId {
+    /// The id if it's a number.
+    #[must_use]
+    pub fn number(self) -> Option<u16> {
+        match self {
+            AccountId::Number(number) => Some(number),
+            AccountId::Code(_) => None,
+        }
+    }
+}
+
+impl From<u16> for AccountId {
+    fn from(number: u16) -> Self {
+        AccountId::Number(number)
+    }
+}
+
+impl FromStr for AccountId {
+    type Err = IdError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        if !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_digit()) {
+            if let Ok(number) = id.parse() {
+                return Ok(AccountId::Number(number));
+            }
+        }
+        Code::try_from(id).map(AccountId::Code)
+    }
+}
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountId::Number(number) => write!(f, "{number}"),
+            AccountId::Code(code) => f.write_str(code.as_str()),
+        }
+    }
+}
+
+impl Serialize for AccountId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            AccountId::Number(number) => serializer.serialize_u16(*number),
+            AccountId::Code(code) => serializer.serialize_str(code.as_str()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AccountId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = AccountId;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a client number or code")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Account {
+    pub client: AccountId,
+    pub available: Amount,
+    pub held: Amount,
+    pub locked: bool,
+    /// The chargeback that locked the account, if it is locked because of one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_by: Option<TransactionId>,
+    #[serde(
+        rename = "type",
+        default,
+        skip_serializing_if = "AccountType::is_standard"
+    )]
+    pub account_type: AccountType,
+    /// Withdrawals can't take the available funds below this.  Overrides the bank's minimum balance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum_balance: Option<Amount>,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+}
+
+/// What kind of account a client has, which decides what it allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountType {
+    /// Withdrawals can't take the available funds below zero.
+    #[default]
+    Standard,
+    /// Withdrawals can take the available funds down to `-limit`.
+    Credit { limit: Amount },
+    /// Funds are held for someone else, so they can't be withdrawn directly.  Deposits and disputes work as usual.
+    Escrow,
+}
+
+/// Details about an account's owner, so that reports make sense to people.  Nothing here affects how instructions are
+/// applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The account's id in another system.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// An owned copy of an account's state at a point in time, as it appears in the account report.
+///
+/// Unlike `&Account` this doesn't borrow the bank, so summaries can be collected while the bank keeps changing.
+/// Amounts are rescaled to the four decimal places used in the report, and the total is included.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AccountSummary {
+    pub client: AccountId,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl Account {
+    #[must_use]
+    pub fn new(client: AccountId) -> Self {
+        Self {
+            client,
+            available: Amount::from(0),
+            held: Amount::from(0),
+            locked: false,
+            locked_by: None,
+            account_type: AccountType::Standard,
+            minimum_balance: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    /// Total balance isn't stored internally to avoid having to remember updating it every time.
+    #[must_use]
+    pub fn total(&self) -> Amount {
+        let mut total = self.available + self.held;
+        total.rescale(4);
+        total
+    }
+
+    /// Check that the account's type and minimum balance allow withdrawing `amount` from it now.  `minimum_balance`
+    /// is the bank's, used if the account doesn't have its own.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`Error::WithdrawalNotAllowed`](../transaction/enum.Error.html) for escrow accounts,
+    /// [`Error::InsufficientFunds`](../transaction/enum.Error.html) if the withdrawal would go over the limit,
+    /// [`Error::BelowMinimumBalance`](../transaction/enum.Error.html) if it would leave less than the minimum balance,
+    /// and [`Error::AmountOverflow`](../transaction/enum.Error.html) if the balance would be out of range.
+    pub fn check_withdrawal(
+        &self,
+        amount: Amount,
+        minimum_balance: Option<Amount>,
+    ) -> Result<(), Error> {
+        let floor = match self.account_type {
+            AccountType::Standard => Amount::from(0),
+            AccountType::Credit { limit } => -limit,
+            AccountType::Escrow => return Err(Error::WithdrawalNotAllowed),
+        };
+        let remaining = self
+            .available
+            .checked_sub(amount)
+            .ok_or(Error::AmountOverflow)?;
+        if remaining < floor {
+            return Err(Error::InsufficientFunds);
+        }
+        if let Some(minimum) = self.minimum_balance.or(minimum_balance) {
+            if remaining < minimum {
+                return Err(Error::BelowMinimumBalance);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AccountType {
+    /// Returns `true` for standard accounts, which is what accounts are unless configured otherwise.
+    #[must_use]
+    pub fn is_standard(&self) -> bool {
+        *self == AccountType::Standard
+    }
+}
+
+impl Metadata {
+    /// Returns `true` if nothing is known about the account's owner.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.reference.is_none() && self.tags.is_empty()
+    }
+}
+
+impl From<&Account> for AccountSummary {
+    fn from(account: &Account) -> Self {
+        let mut available = account.available;
+        available.rescale(4);
+        let mut held = account.held;
+        held.rescale(4);
+
+        Self {
+            client: account.client,
+            available,
+            held,
+            total: account.total(),
+            locked: account.locked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_and_codes() {
+        let id = |id: &str| id.parse::<AccountId>();
+        assert_eq!(id("42"), Ok(AccountId::Number(42)));
+        assert_eq!(id("042"), Ok(AccountId::Number(42)));
+        assert_eq!(id("65536").unwrap().to_string(), "65536");
+        assert_eq!(id("CUST-0042").unwrap().to_string(), "CUST-0042");
+        assert_eq!(id(""), Err(IdError::Empty));
+        assert_eq!(
+            id(&"x".repeat(MAX_CODE_LEN)).map(AccountId::number),
+            Ok(None)
+        );
+        assert_eq!(id(&"x".repeat(MAX_CODE_LEN + 1)), Err(IdError::TooLong));
+
+        let mut ids = vec![
+            id("b").unwrap(),
+            id("10").unwrap(),
+            id("ab").unwrap(),
+            id("9").unwrap(),
+        ];
+        ids.sort();
+        assert_eq!(
+            ids.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["9", "10", "ab", "b"]
+        );
+
+        let json = serde_json::to_string(&ids).unwrap();
+        assert_eq!(json, r#"[9,10,"ab","b"]"#);
+        assert_eq!(serde_json::from_str::<Vec<AccountId>>(&json).unwrap(), ids);
+    }
+}
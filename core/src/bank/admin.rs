@@ -0,0 +1,311 @@
+//! This module contains administrative actions on accounts, for operators correcting a live bank.
+//!
+//! [`Bank::administer`](../struct.Bank.html#method.administer) takes an [`Action`](enum.Action.html) and the operator
+//! taking it:
+//!
+//! | Action | Effect |
+//! |---|---|
+//! | `lock` | Locks the account, so it takes no instructions until it's unlocked. |
+//! | `unlock` | Unlocks the account, like [`Bank::unlock`](../struct.Bank.html#method.unlock). |
+//! | `close` | Locks an account with no funds, available or held. |
+//! | `adjust` | Adds `amount`, which can be negative, to the available funds, to correct a balance. |
+//! | `force_resolve` | Resolves the dispute of transaction `tx`, even on a locked account where a `resolve` would be refused. |
+//!
+//! Every action notifies observers with [`Event::AccountAdministered`](../event/enum.Event.html), naming the operator,
+//! as well as any event the change itself has, such as `DisputeResolved` for `force_resolve`.  An
+//! [audit log](../audit/index.html) records it with
+//! [`Writer::administer`](../audit/struct.Writer.html#method.administer).
+//!
+//! Like [unlocking](../struct.Bank.html#method.unlock), actions are administrative changes, so they're kept by
+//! snapshots but not by the write-ahead log.  Adjustments and forced resolutions clear the rollback journal, so
+//! instructions from before them can't be rolled back.
+
+use super::account::{Account, AccountId};
+use super::amount::Amount;
+use super::event::Event;
+use super::transaction::instruction::{TransactionInstruction, TransactionInstructionKind};
+use super::transaction::{self, TransactionId};
+use super::Bank;
+use serde::{Deserialize, Serialize};
+
+/// An administrative action on an account.
+///
+/// Serializes with the action in an `action` field, e.g. `{"action":"adjust","amount":"-1.5"}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Lock,
+    Unlock,
+    Close,
+    Adjust { amount: Amount },
+    ForceResolve { tx: TransactionId },
+}
+
+/// Reasons an action can't be taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The client has no account.
+    NotFound,
+    /// An account being closed has funds.
+    NotEmpty,
+    /// An adjustment would leave negative available funds.
+    Overdrawn,
+    /// An adjustment would take the balance, or the bank's totals, out of the range an amount can hold.
+    AmountOverflow,
+    /// A dispute can't be resolved, for the reason given.
+    Resolve(transaction::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "account not found"),
+            Error::NotEmpty => write!(f, "account still has funds"),
+            Error::Overdrawn => write!(f, "adjustment would leave negative available funds"),
+            Error::AmountOverflow => write!(f, "balance would be out of range"),
+            Error::Resolve(err) => write!(f, "can't resolve dispute: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Resolve(err) => Some(err),
+            Error::NotFound | Error::NotEmpty | Error::Overdrawn | Error::AmountOverflow => None,
+        }
+    }
+}
+
+impl Action {
+    /// The action with its amount and transaction replaced, for records of a purged client.
+    #[must_use]
+    pub fn redacted(self) -> Self {
+        match self {
+            Action::Adjust { .. } => Action::Adjust {
+                amount: Amount::default(),
+            },
+            Action::ForceResolve { .. } => Action::ForceResolve {
+                tx: super::purge::TOMBSTONE_TX,
+            },
+            action => action,
+        }
+    }
+}
+
+impl Bank {
+    /// Take `action` on `client`'s account on the authority of `operator`, returning the account as it is afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client has no account, or the action can't be taken on it.  The account is unchanged.
+    pub fn administer(
+        &mut self,
+        client: &AccountId,
+        action: Action,
+        operator: &str,
+    ) -> Result<&Account, Error> {
+        self.rehydrate(*client);
+        self.refresh_totals();
+        let account = self.accounts.get(client).ok_or(Error::NotFound)?;
+        let zero = Amount::default();
+        match action {
+            Action::Close if account.available != zero || account.held != zero => {
+                return Err(Error::NotEmpty)
+            }
+            Action::Adjust { amount } => {
+                let available = account
+                    .available
+                    .checked_add(amount)
+                    .ok_or(Error::AmountOverflow)?;
+                if available < zero {
+                    return Err(Error::Overdrawn);
+                }
+            }
+            _ => {}
+        }
+        self.totals.remove(account);
+        let changed = self.change(client, action, operator);
+        self.totals.add(&self.accounts[client]);
+        changed?;
+        self.archive.touch(*client);
+
+        tracing::info!(?client, ?action, operator, "account administered");
+        self.observers.notify(&Event::AccountAdministered {
+            client: *client,
+            action,
+            operator_reference: operator.to_string(),
+        });
+        Ok(&self.accounts[client])
+    }
+
+    /// Make the change `action` needs to `client`'s account, which exists.  Totals are left to the caller.
+    fn change(&mut self, client: &AccountId, action: Action, operator: &str) -> Result<(), Error> {
+        match action {
+            Action::Lock | Action::Close => {
+                let account = Self::instruction_account(&mut self.accounts, *client);
+                account.locked = true;
+            }
+            Action::Unlock => {
+                let account = Self::instruction_account(&mut self.accounts, *client);
+                if account.locked {
+                    account.locked = false;
+                    account.locked_by = None;
+                    self.observers
+                        .notify(&Event::AccountUnlocked { client: *client });
+                }
+            }
+            Action::Adjust { amount } => {
+                let account = Self::instruction_account(&mut self.accounts, *client);
+                let (available, _) = Self::in_range(
+                    &self.totals,
+                    account.available.checked_add(amount),
+                    Some(account.held),
+                )
+                .map_err(|_| Error::AmountOverflow)?;
+                account.available = available;
+                if let Some(journal) = &mut self.journal {
+                    journal.clear();
+                }
+            }
+            Action::ForceResolve { tx } => {
+                self.resolve(&TransactionInstruction {
+                    kind: TransactionInstructionKind::Resolve,
+                    client: *client,
+                    tx,
+                    amount: None,
+                    correlation_id: None,
+                    operator_reference: Some(operator.to_string()),
+                    timestamp: None,
+                })
+                .map_err(Error::Resolve)?;
+                self.totals.open_disputes -= 1;
+                if let Some(journal) = &mut self.journal {
+                    journal.clear();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::transaction::instruction::TransactionInstructionKind as Kind;
+
+    fn instruction(kind: Kind, tx: u64, amount: i64) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(1),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn administers_accounts() {
+        let mut bank = Bank::new();
+        let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let sink = std::sync::Arc::clone(&events);
+        bank.register_observer(move |e: &Event| sink.lock().unwrap().push(e.clone()));
+        let client = AccountId::Number(1);
+        bank.perform_transaction(instruction(Kind::Deposit, 1, 5))
+            .unwrap();
+        bank.perform_transaction(instruction(Kind::Deposit, 2, 3))
+            .unwrap();
+        bank.perform_transaction(instruction(Kind::Dispute, 2, 0))
+            .unwrap();
+
+        assert_eq!(
+            bank.administer(&AccountId::Number(2), Action::Lock, "ops"),
+            Err(Error::NotFound)
+        );
+        assert!(
+            bank.administer(&client, Action::Lock, "ops")
+                .unwrap()
+                .locked
+        );
+        assert_eq!(bank.totals().locked_accounts, 1);
+        // A locked account refuses a resolve, but not a forced one.
+        assert!(bank
+            .perform_transaction(instruction(Kind::Resolve, 2, 0))
+            .is_err());
+        let account = bank
+            .administer(
+                &client,
+                Action::ForceResolve {
+                    tx: TransactionId(2),
+                },
+                "ops",
+            )
+            .unwrap();
+        assert_eq!(account.available, Amount::from(8));
+        assert_eq!(bank.totals().open_disputes, 0);
+        assert!(matches!(
+            bank.administer(
+                &client,
+                Action::ForceResolve {
+                    tx: TransactionId(2)
+                },
+                "ops"
+            ),
+            Err(Error::Resolve(transaction::Error::NotDisputed))
+        ));
+
+        let adjust = |amount| Action::Adjust {
+            amount: Amount::from(amount),
+        };
+        assert_eq!(
+            bank.administer(&client, adjust(-9), "ops"),
+            Err(Error::Overdrawn)
+        );
+        assert_eq!(
+            bank.administer(&client, Action::Close, "ops"),
+            Err(Error::NotEmpty)
+        );
+        let totals = bank.totals();
+        assert_eq!(
+            bank.administer(
+                &client,
+                Action::Adjust {
+                    amount: Amount::MAX
+                },
+                "ops"
+            ),
+            Err(Error::AmountOverflow)
+        );
+        assert_eq!(bank.totals(), totals);
+        bank.administer(&client, adjust(-8), "ops").unwrap();
+        assert_eq!(bank.totals().total, Amount::default());
+        bank.administer(&client, Action::Unlock, "ops").unwrap();
+        assert!(
+            bank.administer(&client, Action::Close, "ops")
+                .unwrap()
+                .locked
+        );
+
+        let administered = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| matches!(e, Event::AccountAdministered { .. }))
+            .count();
+        assert_eq!(administered, 5);
+        assert_eq!(
+            serde_json::to_string(&Event::AccountAdministered {
+                client,
+                action: adjust(-8),
+                operator_reference: "ops".to_string(),
+            })
+            .unwrap(),
+            format!(
+                r#"{{"event":"AccountAdministered","client":1,"action":"adjust","amount":"{}","operator_reference":"ops"}}"#,
+                Amount::from(-8)
+            )
+        );
+    }
+}
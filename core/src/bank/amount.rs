@@ -0,0 +1,332 @@
+//! This module contains the type used for monetary amounts.
+//!
+//! By default [`Amount`](type.Amount.html) is a [`rust_decimal::Decimal`](https://docs.rs/rust_decimal).  Building
+//! with the `fixed-point` feature makes it a [`Fixed`](struct.Fixed.html) instead: an `i64` count of ten-thousandths.
+//! Decimal arithmetic is the hot path when applying transactions, and fixed-point arithmetic is several times faster,
+//! at the cost of rounding every amount to four decimal places as it is read and a range of about ±922 trillion.
+//!
+//! `Fixed` mirrors the parts of `Decimal`'s API that the rest of the crate uses, so code written against `Amount`
+//! compiles either way.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+#[cfg(not(feature = "fixed-point"))]
+pub use rust_decimal::Decimal as Amount;
+
+#[cfg(feature = "fixed-point")]
+pub type Amount = Fixed;
+
+/// Number of decimal places a `Fixed` holds.
+const SCALE: u32 = 4;
+/// `10^SCALE`
+const ONE: i64 = 10_000;
+
+/// A fixed-point amount with four decimal places.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+
+/// Errors related to parsing a `Fixed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The input isn't a decimal number.
+    Invalid(String),
+    /// The number doesn't fit.
+    Overflow(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Invalid(s) => write!(f, "invalid amount {s:?}"),
+            Error::Overflow(s) => write!(f, "amount {s:?} is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+    pub const MAX: Fixed = Fixed(i64::MAX);
+
+    /// The amount `num * 10^-scale`, rounded to four decimal places.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result doesn't fit, like `Decimal::new` does for an invalid scale.
+    #[must_use]
+    pub fn new(num: i64, scale: u32) -> Self {
+        let value = if scale <= SCALE {
+            i128::from(num) * 10_i128.pow(SCALE - scale)
+        } else {
+            round(i128::from(num), scale - SCALE)
+        };
+        Fixed(i64::try_from(value).expect("amount out of range"))
+    }
+
+    /// The underlying count of ten-thousandths.
+    #[must_use]
+    pub fn to_raw(self) -> i64 {
+        self.0
+    }
+
+    #[must_use]
+    pub fn is_sign_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    #[must_use]
+    pub fn abs(&self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    /// `self + other`, or `None` if it doesn't fit.
+    #[must_use]
+    pub fn checked_add(self, other: Fixed) -> Option<Self> {
+        self.0.checked_add(other.0).map(Fixed)
+    }
+
+    /// `self - other`, or `None` if it doesn't fit.
+    #[must_use]
+    pub fn checked_sub(self, other: Fixed) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Fixed)
+    }
+
+    /// Round to `scale` decimal places.  Amounts never have more than four, so rescaling to four or more does
+    /// nothing.
+    pub fn rescale(&mut self, scale: u32) {
+        if scale < SCALE {
+            let factor = 10_i128.pow(SCALE - scale);
+            let rounded = round(i128::from(self.0), SCALE - scale) * factor;
+            self.0 = i64::try_from(rounded).unwrap_or(self.0);
+        }
+    }
+}
+
+/// The nearest `f64` to an amount, for statistics rather than arithmetic on balances.
+#[cfg(not(feature = "fixed-point"))]
+#[must_use]
+pub fn to_f64(amount: Amount) -> f64 {
+    use rust_decimal::prelude::ToPrimitive;
+    amount.to_f64().unwrap_or(f64::NAN)
+}
+
+/// The nearest `f64` to an amount, for statistics rather than arithmetic on balances.
+#[cfg(feature = "fixed-point")]
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn to_f64(amount: Amount) -> f64 {
+    amount.0 as f64 / ONE as f64
+}
+
+/// Divide by `10^digits`, rounding half away from zero like `Decimal::rescale`.
+fn round(value: i128, digits: u32) -> i128 {
+    let factor = 10_i128.pow(digits);
+    let (quotient, remainder) = (value / factor, value % factor);
+    if remainder.abs() * 2 >= factor {
+        quotient + value.signum()
+    } else {
+        quotient
+    }
+}
+
+macro_rules! impl_from_int {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Fixed {
+                fn from(n: $t) -> Self {
+                    Fixed(i64::from(n) * ONE)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_int!(i8, i16, i32, u8, u16, u32);
+
+impl From<i64> for Fixed {
+    fn from(n: i64) -> Self {
+        Fixed::new(n, 0)
+    }
+}
+
+impl FromStr for Fixed {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Invalid(s.to_string());
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let mut value: i128 = 0;
+        for b in whole
+            .bytes()
+            .chain(fraction.bytes().chain(std::iter::repeat(b'0')).take(4))
+        {
+            value = value * 10 + i128::from(b - b'0');
+            if value > i128::from(i64::MAX) {
+                return Err(Error::Overflow(s.to_string()));
+            }
+        }
+        // Only the first dropped digit decides rounding, like `Decimal::rescale`.
+        if fraction.as_bytes().get(4).is_some_and(|b| *b >= b'5') {
+            value += 1;
+        }
+        if negative {
+            value = -value;
+        }
+        i64::try_from(value)
+            .map(Fixed)
+            .map_err(|_| Error::Overflow(s.to_string()))
+    }
+}
+
+/// Always four decimal places, matching the account report.
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let one = ONE.unsigned_abs();
+        write!(f, "{sign}{}.{:04}", abs / one, abs % one)
+    }
+}
+
+/// Panics on overflow, in release builds too, like `Decimal`.  The bank uses
+/// [`checked_add`](#method.checked_add) on balances.
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        self.checked_add(other).expect("amount out of range")
+    }
+}
+
+/// Panics on overflow, in release builds too, like `Decimal`.  The bank uses
+/// [`checked_sub`](#method.checked_sub) on balances.
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        self.checked_sub(other).expect("amount out of range")
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        *self = *self - other;
+    }
+}
+
+/// Serialized as a string, like `Decimal`.
+impl Serialize for Fixed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fixed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Fixed;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a decimal number")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Fixed, E> {
+                s.trim().parse().map_err(E::custom)
+            }
+        }
+
+        // Asking for a string gets CSV fields verbatim instead of going through a float.
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        for (input, want) in [
+            ("1", "1.0000"),
+            ("1.5", "1.5000"),
+            (".25", "0.2500"),
+            ("-2.0001", "-2.0001"),
+            ("1.000042", "1.0000"),
+            ("1.00005", "1.0001"),
+            ("-1.00005", "-1.0001"),
+        ] {
+            assert_eq!(input.parse::<Fixed>().unwrap().to_string(), want, "{input}");
+        }
+        assert!("".parse::<Fixed>().is_err());
+        assert!("1.2.3".parse::<Fixed>().is_err());
+        assert!("99999999999999999999".parse::<Fixed>().is_err());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut a = Fixed::new(15, 1);
+        a -= Fixed::from(2);
+        assert_eq!(a, Fixed::new(-5, 1));
+        assert!(a.is_sign_negative());
+        assert_eq!(a.abs() + a, Fixed::ZERO);
+
+        let mut b = Fixed::new(12345, 4);
+        b.rescale(2);
+        assert_eq!(b, Fixed::new(123, 2));
+
+        assert_eq!(Fixed::MAX.checked_add(Fixed::new(1, 4)), None);
+        assert_eq!((-Fixed::MAX).checked_sub(Fixed::new(2, 4)), None);
+        assert_eq!(
+            Fixed::MAX.checked_sub(Fixed::new(1, 4)),
+            Some(Fixed(i64::MAX - 1))
+        );
+    }
+
+    #[test]
+    fn serde() {
+        let json = serde_json::to_string(&Fixed::new(15, 1)).unwrap();
+        assert_eq!(json, r#""1.5000""#);
+        assert_eq!(
+            serde_json::from_str::<Fixed>(&json).unwrap(),
+            Fixed::new(15, 1)
+        );
+
+        let mut reader = csv::Reader::from_reader("amount\n 1.00005\n".as_bytes());
+        let row: (Fixed,) = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(row.0, Fixed::new(10001, 4));
+    }
+}
@@ -0,0 +1,195 @@
+//! This module contains [proptest](https://docs.rs/proptest) strategies for instructions and instruction histories.
+//! Needs the `proptest` feature.
+//!
+//! [`TransactionInstruction`](../transaction/instruction/struct.TransactionInstruction.html) implements `Arbitrary`
+//! with a few clients and transaction ids, so that random instructions collide often enough to exercise disputes,
+//! duplicates, and locked accounts rather than only opening new accounts.  Deposits and withdrawals always have an
+//! amount and the other kinds never do, and reinstatements always have an operator reference, as in valid input.
+//!
+//! ```
+//! # #[cfg(feature = "proptest")] {
+//! use proptest::prelude::*;
+//! use transactomatic_core::bank::arbitrary::instructions;
+//! use transactomatic_core::Bank;
+//!
+//! proptest! {
+//!     fn total_is_available_plus_held(history in instructions(4, 100)) {
+//!         let mut bank = Bank::new();
+//!         for ti in history {
+//!             let _ = bank.perform_transaction(ti);
+//!         }
+//!         for account in bank.accounts() {
+//!             prop_assert_eq!(account.total(), account.available + account.held);
+//!         }
+//!     }
+//! }
+//! # total_is_available_plus_held();
+//! # }
+//! ```
+
+use super::account::AccountId;
+use super::amount::Amount;
+use super::transaction::instruction::{TransactionInstruction, TransactionInstructionKind};
+use super::transaction::TransactionId;
+use proptest::prelude::*;
+use std::convert::TryFrom;
+
+/// Number of clients used by `TransactionInstruction`'s `Arbitrary` implementation.
+const CLIENTS: u16 = 4;
+/// Number of transaction ids used by `TransactionInstruction`'s `Arbitrary` implementation.
+const TRANSACTIONS: u64 = 32;
+
+/// Amounts from zero to 1,000 with up to four decimal places.
+pub fn amount() -> impl Strategy<Value = Amount> {
+    (0..=10_000_000_i64, 0..=4_u32).prop_map(|(num, scale)| {
+        let mut amount = Amount::new(num, 4);
+        amount.rescale(scale);
+        amount
+    })
+}
+
+/// An instruction for one of `clients` clients, numbered from 1, and one of `transactions` transaction ids, numbered
+/// from 1.
+pub fn instruction(
+    clients: u16,
+    transactions: u64,
+) -> impl Strategy<Value = TransactionInstruction> {
+    (
+        any::<TransactionInstructionKind>(),
+        1..=clients.max(1),
+        1..=transactions.max(1),
+        amount(),
+        proptest::option::weighted(0.1, "[a-z0-9-]{1,12}"),
+    )
+        .prop_map(|(kind, client, tx, amount, correlation_id)| {
+            let amount = match kind {
+                TransactionInstructionKind::Deposit | TransactionInstructionKind::Withdrawal => {
+                    Some(amount)
+                }
+                _ => None,
+            };
+            let operator_reference =
+                (kind == TransactionInstructionKind::Reinstate).then(|| format!("OPS-{tx}"));
+            TransactionInstruction {
+                kind,
+                client: AccountId::Number(client),
+                tx: TransactionId(tx),
+                amount,
+                correlation_id,
+                operator_reference,
+                timestamp: None,
+            }
+        })
+}
+
+/// Up to `len` instructions for `clients` clients, reusing transaction ids so that disputes usually find something.
+pub fn instructions(
+    clients: u16,
+    len: usize,
+) -> impl Strategy<Value = Vec<TransactionInstruction>> {
+    let transactions = u64::try_from(len / 2).unwrap_or(u64::MAX);
+    prop::collection::vec(instruction(clients, transactions), 0..=len)
+}
+
+/// A valid dispute flow: a deposit, a dispute of it, and then a resolve or a chargeback.
+pub fn dispute_flow(
+    client: AccountId,
+    tx: TransactionId,
+) -> impl Strategy<Value = Vec<TransactionInstruction>> {
+    (amount(), any::<bool>()).prop_map(move |(amount, charged_back)| {
+        let instruction = |kind, amount| TransactionInstruction {
+            kind,
+            client,
+            tx,
+            amount,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+        vec![
+            instruction(TransactionInstructionKind::Deposit, Some(amount)),
+            instruction(TransactionInstructionKind::Dispute, None),
+            instruction(
+                if charged_back {
+                    TransactionInstructionKind::Chargeback
+                } else {
+                    TransactionInstructionKind::Resolve
+                },
+                None,
+            ),
+        ]
+    })
+}
+
+/// Up to `len` [dispute flows](fn.dispute_flow.html), each with its own transaction id, one after the other.  Every
+/// instruction is valid unless an earlier chargeback locked the client's account.
+pub fn dispute_flows(
+    clients: u16,
+    len: usize,
+) -> impl Strategy<Value = Vec<TransactionInstruction>> {
+    prop::collection::vec(1..=clients.max(1), 0..=len).prop_flat_map(|clients| {
+        clients
+            .into_iter()
+            .zip(1..)
+            .map(|(client, tx)| dispute_flow(AccountId::Number(client), TransactionId(tx)))
+            .collect::<Vec<_>>()
+            .prop_map(|flows| flows.into_iter().flatten().collect())
+    })
+}
+
+impl Arbitrary for TransactionInstructionKind {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with((): ()) -> Self::Strategy {
+        prop_oneof![
+            3 => Just(TransactionInstructionKind::Deposit),
+            2 => Just(TransactionInstructionKind::Withdrawal),
+            1 => Just(TransactionInstructionKind::Dispute),
+            1 => Just(TransactionInstructionKind::Resolve),
+            1 => Just(TransactionInstructionKind::Chargeback),
+            1 => Just(TransactionInstructionKind::Reinstate),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for TransactionInstruction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with((): ()) -> Self::Strategy {
+        instruction(CLIENTS, TRANSACTIONS).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::Bank;
+
+    proptest! {
+        #[test]
+        fn total_is_available_plus_held(history in instructions(4, 200)) {
+            let mut bank = Bank::new();
+            for ti in history {
+                let _ = bank.perform_transaction(ti);
+            }
+            for account in bank.accounts() {
+                prop_assert_eq!(account.total(), account.available + account.held);
+            }
+        }
+
+        #[test]
+        fn settled_disputes_hold_nothing(flows in dispute_flows(4, 30)) {
+            let mut bank = Bank::new();
+            for ti in flows {
+                let _ = bank.perform_transaction(ti);
+            }
+            for account in bank.accounts() {
+                prop_assert_eq!(account.held, Amount::from(0));
+                prop_assert!(!account.available.is_sign_negative());
+            }
+        }
+    }
+}
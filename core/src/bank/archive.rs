@@ -0,0 +1,390 @@
+//! This module contains the account archival policy.
+//!
+//! A long-running bank keeps every account it has ever seen in memory, even those of clients who have long since
+//! emptied their accounts and gone away.  An [`ArchivePolicy`](struct.ArchivePolicy.html) set with
+//! [`Bank::set_archive_policy`](../struct.Bank.html#method.set_archive_policy) moves dormant accounts out of memory
+//! into an archive file: accounts with nothing available or held, that aren't locked, and that haven't had an
+//! instruction for the policy's `dormant_for`.  Only a small index entry per archived account stays in memory.
+//!
+//! Dormant accounts are looked for at most once a minute, as instructions are applied.  An archived account is brought
+//! back into memory as it was, metadata, type, and transaction history included, as soon as an instruction for its
+//! client arrives or it's changed by an administrative call.  Its transactions stay in the bank, so they can still be
+//! disputed then.
+//!
+//! Archived accounts aren't returned by [`Bank::accounts`](../struct.Bank.html#method.accounts) or
+//! [`Bank::account`](../struct.Bank.html#method.account), and aren't counted by the
+//! [totals](../totals/index.html); [`Bank::archived_account`](../struct.Bank.html#method.archived_account) reads one
+//! back, and [`Bank::history`](../struct.Bank.html#method.history) still has their transactions.
+//! [Snapshots](../snapshot/index.html) and clones include them as ordinary accounts.  The archive file is removed when
+//! the bank is dropped.
+
+use super::account::{Account, AccountId};
+use super::amount::Amount;
+use super::transaction::TransactionId;
+use super::{Bank, Map};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Distinguishes archive files created by one process.
+static ARCHIVE_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Least time between looks for dormant accounts, in seconds.
+const SWEEP_INTERVAL: u64 = 60;
+
+/// Which accounts a bank moves out of memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivePolicy {
+    /// How long an account has to go without an instruction before it's archived.
+    pub dormant_for: Duration,
+    /// Where the archive file is created, when the first account is archived.
+    pub dir: PathBuf,
+}
+
+/// An archived account, as written to the file.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Archived {
+    pub(crate) account: Account,
+    /// The client's transaction ids in the order they were applied.
+    pub(crate) history: Vec<TransactionId>,
+}
+
+/// Archival state of a bank.
+#[derive(Debug, Default)]
+pub(crate) struct Archive {
+    policy: Option<ArchivePolicy>,
+    /// When each account in memory last had an instruction, in seconds since the Unix epoch.  Only kept while there
+    /// is a policy.
+    last_active: Map<AccountId, u64>,
+    /// Where each archived account is in the file: offset and length.
+    index: Map<AccountId, (u64, usize)>,
+    file: Option<ArchiveFile>,
+    /// When dormant accounts were last looked for.
+    swept: u64,
+}
+
+/// The file archived accounts are written to.
+#[derive(Debug)]
+struct ArchiveFile {
+    path: PathBuf,
+    /// Locked so that accounts can be read back through a shared reference.
+    file: Mutex<File>,
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+impl Archive {
+    pub(crate) fn is_archived(&self, client: AccountId) -> bool {
+        self.index.contains_key(&client)
+    }
+
+    /// Number of archived accounts.
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Note that `client` had an instruction just now.
+    pub(crate) fn touch(&mut self, client: AccountId) {
+        if self.policy.is_some() {
+            self.last_active.insert(client, now());
+        }
+    }
+
+    pub(crate) fn forget(&mut self, client: AccountId) {
+        self.last_active.remove(&client);
+    }
+
+    fn write(&mut self, archived: &Archived) -> io::Result<()> {
+        let bytes = serde_json::to_vec(archived)?;
+        if self.file.is_none() {
+            let dir = self
+                .policy
+                .as_ref()
+                .map_or_else(std::env::temp_dir, |policy| policy.dir.clone());
+            self.file = Some(ArchiveFile::create(&dir)?);
+        }
+        let file = self.file.as_mut().expect("archive file was just created");
+        let file = file.file.get_mut().expect("archive file lock poisoned");
+        let offset = file.seek(SeekFrom::End(0))?;
+        file.write_all(&bytes)?;
+        self.index
+            .insert(archived.account.client, (offset, bytes.len()));
+        Ok(())
+    }
+
+    /// Read `client`'s archived account back, leaving it in the archive.
+    pub(crate) fn read(&self, client: AccountId) -> Option<Archived> {
+        let (offset, len) = *self.index.get(&client)?;
+        let mut buf = vec![0; len];
+        let result = {
+            let mut file = self
+                .file
+                .as_ref()?
+                .file
+                .lock()
+                .expect("archive file lock poisoned");
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut buf))
+        };
+        match result.and_then(|()| Ok(serde_json::from_slice(&buf)?)) {
+            Ok(archived) => Some(archived),
+            Err(err) => {
+                tracing::error!(?err, ?client, "error reading archived account");
+                None
+            }
+        }
+    }
+
+    /// Take `client`'s account out of the archive.  An account that can't be read is left in it.
+    fn take(&mut self, client: AccountId) -> Option<Archived> {
+        let archived = self.read(client)?;
+        self.index.remove(&client);
+        Some(archived)
+    }
+
+    /// Every archived account, in no particular order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Archived> + '_ {
+        self.index
+            .keys()
+            .filter_map(move |client| self.read(*client))
+    }
+
+    /// Take every account that can be read out of the archive.
+    pub(crate) fn drain(&mut self) -> Vec<Archived> {
+        let clients: Vec<AccountId> = self.index.keys().copied().collect();
+        clients
+            .into_iter()
+            .filter_map(|client| self.take(client))
+            .collect()
+    }
+}
+
+impl ArchiveFile {
+    fn create(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(format!(
+            "transactomatic-archive-{}-{}",
+            std::process::id(),
+            ARCHIVE_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        tracing::info!(?path, "archiving dormant accounts");
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl Drop for ArchiveFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            tracing::warn!(?err, path = ?self.path, "error removing archive file");
+        }
+    }
+}
+
+impl Bank {
+    /// Set which accounts the bank moves out of memory, or `None` to stop archiving.  Accounts already archived stay
+    /// in the archive until they're needed, and the archive file stays where it was first created.  Accounts already
+    /// held count as active from now.
+    pub fn set_archive_policy(&mut self, policy: Option<ArchivePolicy>) {
+        let archive = &mut self.archive;
+        if policy.is_none() {
+            archive.last_active.clear();
+        } else if archive.policy.is_none() {
+            let now = now();
+            archive.last_active = self.accounts.keys().map(|client| (*client, now)).collect();
+            archive.swept = now;
+        }
+        archive.policy = policy;
+    }
+
+    /// Read a client's archived account back without bringing it into memory.  `None` if the client's account isn't
+    /// archived.
+    #[must_use]
+    pub fn archived_account(&self, client: &AccountId) -> Option<Account> {
+        self.archive.read(*client).map(|archived| archived.account)
+    }
+
+    /// Archive the accounts that have been dormant since `now`, in seconds since the Unix epoch, returning how many
+    /// were archived.  Accounts are archived as instructions are applied anyway; this is for archiving without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the archive file can't be created or written.  Accounts that couldn't be written are kept
+    /// in memory.
+    pub fn archive_dormant(&mut self, now: u64) -> io::Result<usize> {
+        let Some(policy) = &self.archive.policy else {
+            return Ok(0);
+        };
+        self.archive.swept = now;
+        let cutoff = now.saturating_sub(policy.dormant_for.as_secs());
+        let accounts = &self.accounts;
+        let dormant: Vec<AccountId> = self
+            .archive
+            .last_active
+            .iter()
+            .filter(|(_, active)| **active <= cutoff)
+            .map(|(client, _)| *client)
+            .filter(|client| accounts.get(client).is_some_and(is_empty))
+            .collect();
+        self.refresh_totals();
+        for client in &dormant {
+            let Some(account) = self.accounts.remove(client) else {
+                continue;
+            };
+            let history = self.history.remove(client).unwrap_or_default();
+            let archived_account = Archived { account, history };
+            if let Err(err) = self.archive.write(&archived_account) {
+                self.accounts.insert(*client, archived_account.account);
+                self.history.insert(*client, archived_account.history);
+                return Err(err);
+            }
+            tracing::debug!(?client, "account archived");
+            self.totals.remove(&archived_account.account);
+            self.archive.last_active.remove(client);
+        }
+        if !dormant.is_empty() {
+            tracing::info!(accounts = dormant.len(), "archived dormant accounts");
+        }
+        Ok(dormant.len())
+    }
+
+    /// Archive dormant accounts if it's been long enough since they were last looked for.
+    pub(crate) fn sweep_archive(&mut self) {
+        if self.archive.policy.is_none() {
+            return;
+        }
+        let now = now();
+        if now < self.archive.swept + SWEEP_INTERVAL {
+            return;
+        }
+        if let Err(err) = self.archive_dormant(now) {
+            tracing::error!(?err, "error archiving dormant accounts");
+        }
+    }
+
+    /// Bring `client`'s account back into memory if it's archived.
+    pub(crate) fn rehydrate(&mut self, client: AccountId) {
+        if !self.archive.is_archived(client) {
+            return;
+        }
+        let Some(archived) = self.archive.take(client) else {
+            return;
+        };
+        tracing::debug!(?client, "account rehydrated");
+        self.refresh_totals();
+        self.totals.add(&archived.account);
+        self.accounts.insert(client, archived.account);
+        if !archived.history.is_empty() {
+            self.history.insert(client, archived.history);
+        }
+        self.archive.touch(client);
+    }
+}
+
+/// Whether an account has nothing in it to keep it in memory.
+fn is_empty(account: &Account) -> bool {
+    account.available == Amount::default() && account.held == Amount::default() && !account.locked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind as Kind,
+    };
+
+    fn instruction(kind: Kind, client: u16, tx: u64, amount: i64) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn archives_and_rehydrates() {
+        let mut bank = Bank::new();
+        bank.set_archive_policy(Some(ArchivePolicy {
+            dormant_for: Duration::from_hours(30 * 24),
+            dir: std::env::temp_dir(),
+        }));
+        bank.perform_transaction(instruction(Kind::Deposit, 1, 1, 5))
+            .unwrap();
+        bank.perform_transaction(instruction(Kind::Withdrawal, 1, 2, 5))
+            .unwrap();
+        bank.perform_transaction(instruction(Kind::Deposit, 2, 3, 5))
+            .unwrap();
+        let (one, two) = (AccountId::Number(1), AccountId::Number(2));
+
+        assert_eq!(bank.archive_dormant(now()).unwrap(), 0);
+        // Client 2 still has funds, so only client 1 is archived.
+        assert_eq!(bank.archive_dormant(now() + 31 * 86_400).unwrap(), 1);
+        assert!(bank.account(&one).is_none());
+        assert_eq!(bank.archived_account(&one).unwrap().client, one);
+        assert!(bank.account(&two).is_some());
+        assert_eq!(bank.totals().accounts, 1);
+        assert_eq!(bank.history(one).count(), 2);
+
+        let mut buf = vec![];
+        bank.save_snapshot(&mut buf).unwrap();
+        let restored = Bank::load_snapshot(buf.as_slice()).unwrap();
+        assert!(restored.account(&one).is_some());
+        assert_eq!(restored.history(one).count(), 2);
+
+        // A dispute brings the account back, and its transactions are still there.
+        bank.perform_transaction(instruction(Kind::Dispute, 1, 1, 0))
+            .unwrap();
+        let account = bank.account(&one).unwrap();
+        assert_eq!(account.held, Amount::from(5));
+        assert!(bank.archived_account(&one).is_none());
+        assert_eq!(bank.history(one).count(), 2);
+        assert_eq!(bank.totals().accounts, 2);
+
+        let path = bank.archive.file.as_ref().unwrap().path.clone();
+        drop(bank);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keeps_unreadable_accounts_archived() {
+        let mut bank = Bank::new();
+        bank.set_archive_policy(Some(ArchivePolicy {
+            dormant_for: Duration::from_secs(1),
+            dir: std::env::temp_dir(),
+        }));
+        bank.perform_transaction(instruction(Kind::Deposit, 1, 1, 0))
+            .unwrap();
+        assert_eq!(bank.archive_dormant(now() + 2).unwrap(), 1);
+        let one = AccountId::Number(1);
+
+        let path = bank.archive.file.as_ref().unwrap().path.clone();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        assert!(bank.archive.take(one).is_none());
+        assert!(bank.archive.drain().is_empty());
+        assert!(bank.archive.is_archived(one));
+    }
+}
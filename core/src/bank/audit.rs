@@ -0,0 +1,567 @@
+//! This module contains the tamper-evident audit log of changes to accounts.
+//!
+//! Every instruction that changes an account is recorded with the account as it was before and after, a sequence
+//! number, and a timestamp, and so is every [administrative action](../admin/index.html), with the operator who took
+//! it.  Each record also carries the SHA-256 hash of the previous record and its own hash, which
+//! covers everything else in the record.  Editing, removing, or reordering records breaks the chain, which
+//! [`verify`](fn.verify.html) detects.  Like the [write-ahead log](../wal/index.html) the audit log is JSON Lines.
+//!
+//! The chain only proves that the log is consistent with itself: someone able to rewrite the whole file can recompute
+//! every hash.  Keep a copy of the latest hash somewhere else to detect that.
+//!
+//! When an account is [purged](../purge/index.html), [`redact`](fn.redact.html) rewrites the log without the client's
+//! details and rebuilds the chain, so the latest hash changes and the copy has to be replaced too.
+
+use super::account::{Account, AccountId, AccountSummary};
+use super::admin::{self, Action};
+use super::amount::Amount;
+use super::purge;
+use super::retry::{Retry, Retrying};
+use super::transaction::instruction::TransactionInstruction;
+use super::Bank;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The previous hash of the first record in a log.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Errors related to reading or writing the audit log.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Format(serde_json::Error),
+}
+
+/// A single entry in the audit log.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Record {
+    /// Position in the log, starting at 1.
+    pub seq: u64,
+    /// Seconds since the Unix epoch when the record was written.
+    pub timestamp: u64,
+    /// The instruction that changed the account, if one did.  Its `client` is who the change was for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instruction: Option<TransactionInstruction>,
+    /// The administrative action that changed the account, if an operator took one instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin: Option<Administered>,
+    /// The account before the instruction, or `None` if the instruction opened it.
+    pub before: Option<AccountSummary>,
+    pub after: AccountSummary,
+    /// Hash of the previous record, or [`GENESIS`](constant.GENESIS.html) for the first one.
+    pub prev: String,
+    /// Hash of this record, as hex.
+    pub hash: String,
+}
+
+/// An administrative action on an account, as recorded in the log.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Administered {
+    pub client: AccountId,
+    #[serde(flatten)]
+    pub action: Action,
+    /// Who took the action.
+    pub operator_reference: String,
+}
+
+/// Where a log stopped being intact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Broken {
+    /// The record doesn't follow on from the one before it.
+    Sequence {
+        line: u64,
+        expected: u64,
+        found: u64,
+    },
+    /// The record's `prev` isn't the hash of the record before it.
+    Chain { seq: u64 },
+    /// The record's contents don't match its hash.
+    Hash { seq: u64 },
+}
+
+/// Appends records to an audit log file.
+#[derive(Debug)]
+pub struct Writer {
+    file: io::BufWriter<Retrying<fs::File>>,
+    seq: u64,
+    prev: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading or writing audit log: {err}"),
+            Error::Format(err) => write!(f, "invalid audit record: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Format(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Error::Io(err.into())
+        } else {
+            Error::Format(err)
+        }
+    }
+}
+
+impl std::fmt::Display for Broken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Broken::Sequence {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "record on line {line} has sequence number {found}, expected {expected}"
+            ),
+            Broken::Chain { seq } => write!(
+                f,
+                "record {seq} doesn't follow on from the record before it"
+            ),
+            Broken::Hash { seq } => write!(f, "record {seq} has been altered"),
+        }
+    }
+}
+
+impl Record {
+    /// The client the change was for.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the record has neither an instruction nor an action, which records written by a
+    /// [`Writer`](struct.Writer.html) always have.
+    #[must_use]
+    pub fn client(&self) -> AccountId {
+        match (&self.instruction, &self.admin) {
+            (Some(instruction), _) => instruction.client,
+            (None, Some(admin)) => admin.client,
+            (None, None) => panic!("audit record {} changed nothing", self.seq),
+        }
+    }
+
+    /// The hash this record should have.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the record can't be serialized, which doesn't happen.
+    #[must_use]
+    pub fn compute_hash(&self) -> String {
+        #[derive(Serialize)]
+        struct Contents<'a> {
+            seq: u64,
+            timestamp: u64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            instruction: Option<&'a TransactionInstruction>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            admin: Option<&'a Administered>,
+            before: &'a Option<AccountSummary>,
+            after: &'a AccountSummary,
+            prev: &'a str,
+        }
+
+        let contents = serde_json::to_vec(&Contents {
+            seq: self.seq,
+            timestamp: self.timestamp,
+            instruction: self.instruction.as_ref(),
+            admin: self.admin.as_ref(),
+            before: &self.before,
+            after: &self.after,
+            prev: &self.prev,
+        })
+        .expect("audit records always serialize");
+        let mut hash = String::with_capacity(64);
+        for byte in Sha256::digest(contents) {
+            // Writing to a String can't fail.
+            let _ = write!(hash, "{byte:02x}");
+        }
+        hash
+    }
+}
+
+impl Writer {
+    /// Open an audit log for appending, creating it if necessary.  Records in an existing log are continued from its
+    /// last record, without verifying it.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the log can't be opened or an existing log can't be read.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let (mut seq, mut prev) = (0, GENESIS.to_string());
+        if path.exists() {
+            for record in records(fs::File::open(path)?) {
+                let record = record?;
+                seq = record.seq;
+                prev = record.hash;
+            }
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: io::BufWriter::new(Retrying::new(file)),
+            seq,
+            prev,
+        })
+    }
+
+    /// Retry writes to the log after transient errors.
+    #[must_use]
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.file.get_mut().set_retry(retry);
+        self
+    }
+
+    /// Apply an instruction to `bank`, recording the change if it was applied.  The bank's verdict is returned as
+    /// the inner result.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the record can't be written.  The instruction has been applied to the bank regardless.
+    pub fn perform<'b>(
+        &mut self,
+        bank: &'b mut Bank,
+        instruction: TransactionInstruction,
+    ) -> Result<Result<&'b Account, super::transaction::Error>, Error> {
+        let before = bank.account(&instruction.client).map(AccountSummary::from);
+        let recorded = instruction.clone();
+        match bank.perform_transaction(instruction) {
+            Ok(account) => {
+                self.append(recorded, before, AccountSummary::from(account))?;
+                Ok(Ok(account))
+            }
+            Err(err) => Ok(Err(err)),
+        }
+    }
+
+    /// Take an administrative `action` on `client`'s account in `bank` on the authority of `operator`, recording the
+    /// change if it was made.  The bank's verdict is returned as the inner result.  The operator is kept in the
+    /// record's `operator_reference`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the record can't be written.  The action has been taken regardless.
+    pub fn administer<'b>(
+        &mut self,
+        bank: &'b mut Bank,
+        client: AccountId,
+        action: Action,
+        operator: &str,
+    ) -> Result<Result<&'b Account, admin::Error>, Error> {
+        let before = bank.account(&client).map(AccountSummary::from);
+        match bank.administer(&client, action, operator) {
+            Ok(account) => {
+                let admin = Administered {
+                    client,
+                    action,
+                    operator_reference: operator.to_string(),
+                };
+                self.push(None, Some(admin), before, AccountSummary::from(account))?;
+                Ok(Ok(account))
+            }
+            Err(err) => Ok(Err(err)),
+        }
+    }
+
+    /// Append a change to the log.
+    ///
+    /// Records are buffered; call [`flush`](#method.flush) to make sure they have reached the file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the record can't be written.
+    pub fn append(
+        &mut self,
+        instruction: TransactionInstruction,
+        before: Option<AccountSummary>,
+        after: AccountSummary,
+    ) -> Result<(), Error> {
+        self.push(Some(instruction), None, before, after)
+    }
+
+    fn push(
+        &mut self,
+        instruction: Option<TransactionInstruction>,
+        admin: Option<Administered>,
+        before: Option<AccountSummary>,
+        after: AccountSummary,
+    ) -> Result<(), Error> {
+        let mut record = Record {
+            seq: self.seq + 1,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            instruction,
+            admin,
+            before,
+            after,
+            prev: std::mem::take(&mut self.prev),
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash();
+        let written = serde_json::to_writer(&mut self.file, &record)
+            .map_err(Error::from)
+            .and_then(|()| Ok(self.file.write_all(b"\n")?));
+        self.seq = record.seq;
+        self.prev = record.hash;
+        written
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if buffered records can't be written.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Iterate over the records in an audit log.
+pub fn records<R: io::Read>(reader: R) -> impl Iterator<Item = Result<Record, Error>> {
+    io::BufReader::new(reader)
+        .lines()
+        .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+}
+
+/// Copy an audit log from `reader` to `writer`, redacting the records of `client`, whose account has been purged.
+///
+/// A redacted record keeps its sequence number, timestamp, and the kind of instruction, but its client is the
+/// [tombstone](../purge/fn.tombstone.html), its transaction is [`TOMBSTONE_TX`](../purge/constant.TOMBSTONE_TX.html),
+/// its amounts are gone, and its operator reference is `operator_reference`, who authorized the purge.  Every record's
+/// hash is recomputed so the copy verifies.  Returns the number of records redacted.
+///
+/// The log isn't verified first; a broken log comes out intact, so [`verify`](fn.verify.html) it beforehand.
+///
+/// # Errors
+///
+/// Will return `Err` if the log can't be read or a record can't be parsed or written.
+pub fn redact<R: io::Read, W: io::Write>(
+    reader: R,
+    mut writer: W,
+    client: AccountId,
+    operator_reference: &str,
+) -> Result<u64, Error> {
+    let tombstone = purge::tombstone();
+    let (mut redacted, mut prev) = (0, GENESIS.to_string());
+    for record in records(reader) {
+        let mut record = record?;
+        if record.client() == client {
+            record.instruction = record
+                .instruction
+                .map(|instruction| TransactionInstruction {
+                    client: tombstone,
+                    tx: purge::TOMBSTONE_TX,
+                    amount: None,
+                    correlation_id: None,
+                    operator_reference: Some(operator_reference.to_string()),
+                    timestamp: None,
+                    ..instruction
+                });
+            record.admin = record.admin.map(|admin| Administered {
+                client: tombstone,
+                action: admin.action.redacted(),
+                operator_reference: operator_reference.to_string(),
+            });
+            record.before = None;
+            record.after = AccountSummary {
+                client: tombstone,
+                available: Amount::default(),
+                held: Amount::default(),
+                total: Amount::default(),
+                locked: false,
+            };
+            redacted += 1;
+        }
+        record.prev = prev;
+        record.hash = record.compute_hash();
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n")?;
+        prev = record.hash;
+    }
+    writer.flush()?;
+    Ok(redacted)
+}
+
+/// Check that every record in an audit log is intact and chained to the one before it.
+///
+/// Returns the number of records and the hash of the last one, or where the log is broken.
+///
+/// # Errors
+///
+/// Will return `Err` if the log can't be read or a record can't be parsed.
+pub fn verify<R: io::Read>(reader: R) -> Result<Result<(u64, String), Broken>, Error> {
+    let (mut line, mut seq, mut prev) = (0, 0, GENESIS.to_string());
+    for record in records(reader) {
+        let record = record?;
+        line += 1;
+        if record.seq != seq + 1 {
+            return Ok(Err(Broken::Sequence {
+                line,
+                expected: seq + 1,
+                found: record.seq,
+            }));
+        }
+        if record.prev != prev {
+            return Ok(Err(Broken::Chain { seq: record.seq }));
+        }
+        if record.compute_hash() != record.hash {
+            return Ok(Err(Broken::Hash { seq: record.seq }));
+        }
+        seq = record.seq;
+        prev = record.hash;
+    }
+    Ok(Ok((seq, prev)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::{instruction::TransactionInstructionKind, TransactionId};
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        tx: u64,
+        amount: i64,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(1),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn records_and_verifies_changes() {
+        let path =
+            std::env::temp_dir().join(format!("transactomatic-audit-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut bank = Bank::new();
+
+        let mut writer = Writer::open(&path).unwrap();
+        let deposit = instruction(TransactionInstructionKind::Deposit, 1, 5);
+        assert!(writer.perform(&mut bank, deposit).unwrap().is_ok());
+        let overdraw = instruction(TransactionInstructionKind::Withdrawal, 2, 10);
+        assert!(writer.perform(&mut bank, overdraw).unwrap().is_err());
+        writer.flush().unwrap();
+        drop(writer);
+        let mut writer = Writer::open(&path).unwrap();
+        let withdrawal = instruction(TransactionInstructionKind::Withdrawal, 3, 2);
+        assert!(writer.perform(&mut bank, withdrawal).unwrap().is_ok());
+        let client = AccountId::Number(1);
+        assert!(writer
+            .administer(&mut bank, client, Action::Lock, "ops")
+            .unwrap()
+            .is_ok());
+        assert!(writer
+            .administer(&mut bank, AccountId::Number(2), Action::Lock, "ops")
+            .unwrap()
+            .is_err());
+        writer.flush().unwrap();
+
+        let log = fs::read_to_string(&path).unwrap();
+        let records: Vec<Record> = records(log.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 3, "rejected instructions aren't changes");
+        assert_eq!(records[0].before, None);
+        assert_eq!(records[1].before, Some(records[0].after));
+        assert_eq!(records[1].after.available, Amount::from(3));
+        assert_eq!(records[2].instruction, None);
+        assert_eq!(records[2].admin.as_ref().unwrap().operator_reference, "ops");
+        assert!(records[2].after.locked);
+        let (count, last) = verify(log.as_bytes()).unwrap().unwrap();
+        assert_eq!((count, last.as_str()), (3, records[2].hash.as_str()));
+
+        let tampered = log.replacen("\"3.0000\"", "\"30.0000\"", 1);
+        assert_eq!(
+            verify(tampered.as_bytes()).unwrap(),
+            Err(Broken::Hash { seq: 2 })
+        );
+        let second = log.lines().nth(1).unwrap();
+        assert_eq!(
+            verify(second.as_bytes()).unwrap(),
+            Err(Broken::Sequence {
+                line: 1,
+                expected: 1,
+                found: 2
+            })
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn redacts_purged_clients() {
+        let mut bank = Bank::new();
+        let mut log = vec![];
+        for (client, tx) in [(1, 1), (2, 2), (1, 3)] {
+            let instruction = TransactionInstruction {
+                client: AccountId::Number(client),
+                ..instruction(TransactionInstructionKind::Deposit, tx, 5)
+            };
+            let before = bank.account(&instruction.client).map(AccountSummary::from);
+            let after =
+                AccountSummary::from(bank.perform_transaction(instruction.clone()).unwrap());
+            let mut record = Record {
+                seq: tx,
+                timestamp: 0,
+                instruction: Some(instruction),
+                admin: None,
+                before,
+                after,
+                prev: GENESIS.to_string(),
+                hash: String::new(),
+            };
+            if let Some(last) = records(log.as_slice()).last() {
+                record.prev = last.unwrap().hash;
+            }
+            record.hash = record.compute_hash();
+            serde_json::to_writer(&mut log, &record).unwrap();
+            log.push(b'\n');
+        }
+
+        let mut redacted = vec![];
+        assert_eq!(
+            redact(log.as_slice(), &mut redacted, AccountId::Number(1), "DPO-1").unwrap(),
+            2
+        );
+        assert!(verify(redacted.as_slice()).unwrap().is_ok());
+        let records: Vec<Record> = records(redacted.as_slice()).map(Result::unwrap).collect();
+        assert_eq!(records[0].client(), purge::tombstone());
+        assert_eq!(records[0].instruction.as_ref().unwrap().amount, None);
+        assert_eq!(records[0].after.available, Amount::default());
+        assert_eq!(records[1].after.client, AccountId::Number(2));
+        assert_eq!(records[1].after.available, Amount::from(5));
+        assert!(!String::from_utf8(redacted)
+            .unwrap()
+            .contains("\"client\":1"));
+    }
+}
@@ -0,0 +1,322 @@
+//! This module contains balance history: each client's balances at the end of every period, and optionally after
+//! every instruction.
+//!
+//! Once a period is set with [`Bank::set_balance_period`](../struct.Bank.html#method.set_balance_period), every
+//! applied instruction with a timestamp updates its client's entry for the period the timestamp falls in, so the entry
+//! ends up holding the balances at the end of that period.  Periods are fixed-length and aligned to the Unix epoch, so
+//! a period of 86 400 seconds gives end-of-day balances in UTC.  Periods in which nothing happened to a client's account
+//! have no entry; the balances are those of the client's previous entry.
+//!
+//! Input is expected in chronological order: an instruction from before the client's latest period starts a new entry
+//! instead of changing an earlier one.  Like the retention policy, the history isn't kept by
+//! [snapshots](../snapshot/index.html).
+//!
+//! A [balance series](../struct.Bank.html#method.set_balance_series) is finer grained: a point after every
+//! instruction applied to a client's account, or after every `n`th one, for charting how balances evolve without
+//! consuming the bank's [events](../event/index.html).  It doesn't need timestamps, and can be capped to each
+//! client's most recent points so that memory doesn't grow with the input.
+
+use super::account::{Account, AccountId, AccountSummary};
+use super::amount::Amount;
+use super::Bank;
+use super::Map;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::num::{NonZeroU64, NonZeroUsize};
+
+/// A client's balances at the end of a period, rescaled like the account report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PeriodBalance {
+    /// Start of the period, in seconds since the Unix epoch.
+    pub period: u64,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// How much of each client's balance series to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeriesOptions {
+    /// Keep a point for the first of a client's instructions and every `every`th one after it.  `1` keeps them all.
+    pub every: NonZeroUsize,
+    /// Keep at most this many points per client, dropping the oldest.
+    pub capacity: Option<usize>,
+}
+
+impl Default for SeriesOptions {
+    fn default() -> Self {
+        Self {
+            every: NonZeroUsize::MIN,
+            capacity: None,
+        }
+    }
+}
+
+/// A client's balances after an instruction, rescaled like the account report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BalancePoint {
+    /// Number of instructions the bank had applied, including this one, since the series was started.  Orders points
+    /// across clients.
+    pub seq: u64,
+    /// The instruction's timestamp, if it had one.
+    pub timestamp: Option<u64>,
+    pub available: Amount,
+    pub held: Amount,
+}
+
+/// A client's points and how many of their instructions have been applied.
+#[derive(Debug, Clone, Default)]
+struct ClientSeries {
+    applied: usize,
+    points: VecDeque<BalancePoint>,
+}
+
+/// Balance series of a bank.
+#[derive(Debug, Clone)]
+pub(crate) struct BalanceSeries {
+    options: SeriesOptions,
+    applied: u64,
+    clients: Map<AccountId, ClientSeries>,
+}
+
+impl BalanceSeries {
+    /// Record `account`'s balances after an instruction.
+    pub(crate) fn record(&mut self, timestamp: Option<u64>, account: &Account) {
+        self.applied += 1;
+        let series = self.clients.entry(account.client).or_default();
+        series.applied += 1;
+        if (series.applied - 1) % self.options.every != 0 {
+            return;
+        }
+        if self.options.capacity == Some(series.points.len()) && series.points.pop_front().is_none()
+        {
+            return;
+        }
+        let summary = AccountSummary::from(account);
+        series.points.push_back(BalancePoint {
+            seq: self.applied,
+            timestamp,
+            available: summary.available,
+            held: summary.held,
+        });
+    }
+
+    pub(crate) fn forget(&mut self, client: AccountId) {
+        self.clients.remove(&client);
+    }
+
+    /// Merge another bank's series into this one.  The clients of the two banks shouldn't overlap, and sequence
+    /// numbers from different banks aren't comparable.
+    pub(crate) fn absorb(&mut self, other: BalanceSeries) {
+        self.applied = self.applied.max(other.applied);
+        self.clients.extend(other.clients);
+    }
+}
+
+/// Balance history of a bank.
+#[derive(Debug, Clone)]
+pub(crate) struct BalanceHistory {
+    period: NonZeroU64,
+    clients: Map<AccountId, Vec<PeriodBalance>>,
+}
+
+impl BalanceHistory {
+    fn new(period: NonZeroU64) -> Self {
+        Self {
+            period,
+            clients: Map::default(),
+        }
+    }
+
+    pub(crate) fn forget(&mut self, client: AccountId) {
+        self.clients.remove(&client);
+    }
+
+    /// Record `account`'s balances after an instruction made at `timestamp`.
+    pub(crate) fn record(&mut self, timestamp: u64, account: &Account) {
+        let period = timestamp - timestamp % self.period;
+        let summary = AccountSummary::from(account);
+        let balance = PeriodBalance {
+            period,
+            available: summary.available,
+            held: summary.held,
+            total: summary.total,
+            locked: summary.locked,
+        };
+        let history = self.clients.entry(account.client).or_default();
+        match history.last_mut() {
+            Some(last) if last.period == period => *last = balance,
+            _ => history.push(balance),
+        }
+    }
+
+    /// Merge another bank's history into this one.  The clients of the two banks shouldn't overlap.
+    pub(crate) fn absorb(&mut self, other: BalanceHistory) {
+        self.clients.extend(other.clients);
+    }
+}
+
+impl Bank {
+    /// Keep each client's balances at the end of every period of this many seconds, or stop keeping them with `None`.
+    /// Changing the period discards the history so far.
+    pub fn set_balance_period(&mut self, period: Option<NonZeroU64>) {
+        if self.balance_history.as_ref().map(|history| history.period) != period {
+            self.balance_history = period.map(BalanceHistory::new);
+        }
+    }
+
+    /// A client's balances at the end of every period in which an instruction was applied to their account, oldest
+    /// first.  Empty unless a [balance period](#method.set_balance_period) is set.
+    #[must_use]
+    pub fn balance_history(&self, client: AccountId) -> &[PeriodBalance] {
+        self.balance_history
+            .as_ref()
+            .and_then(|history| history.clients.get(&client))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Keep a series of each client's balances after their instructions, or stop keeping it with `None`.  Changing the
+    /// options discards the series so far.
+    pub fn set_balance_series(&mut self, options: Option<SeriesOptions>) {
+        if self.balance_series.as_ref().map(|series| series.options) != options {
+            self.balance_series = options.map(|options| BalanceSeries {
+                options,
+                applied: 0,
+                clients: Map::default(),
+            });
+        }
+    }
+
+    /// A client's balance series, oldest point first.  Empty unless a [series](#method.set_balance_series) is kept.
+    pub fn balance_series(&self, client: AccountId) -> impl Iterator<Item = &BalancePoint> {
+        self.balance_series
+            .as_ref()
+            .and_then(|series| series.clients.get(&client))
+            .into_iter()
+            .flat_map(|series| series.points.iter())
+    }
+
+    /// Every client with a balance history and their history, in no particular order.
+    pub fn balance_histories(&self) -> impl Iterator<Item = (AccountId, &[PeriodBalance])> {
+        self.balance_history
+            .iter()
+            .flat_map(|history| history.clients.iter())
+            .map(|(client, history)| (*client, history.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind,
+    };
+    use crate::bank::transaction::TransactionId;
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        tx: u64,
+        amount: Option<i64>,
+        timestamp: Option<u64>,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(1),
+            tx: TransactionId(tx),
+            amount: amount.map(Amount::from),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn end_of_day_balances() {
+        const DAY: u64 = 86_400;
+        let mut bank = Bank::new();
+        bank.set_balance_period(NonZeroU64::new(DAY));
+        for ti in [
+            instruction(TransactionInstructionKind::Deposit, 1, Some(10), Some(10)),
+            instruction(
+                TransactionInstructionKind::Deposit,
+                2,
+                Some(5),
+                Some(DAY - 1),
+            ),
+            instruction(
+                TransactionInstructionKind::Withdrawal,
+                3,
+                Some(3),
+                Some(DAY),
+            ),
+            instruction(TransactionInstructionKind::Deposit, 4, Some(1), None),
+            instruction(
+                TransactionInstructionKind::Dispute,
+                1,
+                None,
+                Some(3 * DAY + 5),
+            ),
+        ] {
+            bank.perform_transaction(ti).unwrap();
+        }
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Withdrawal,
+            5,
+            Some(100),
+            Some(3 * DAY + 6),
+        ))
+        .unwrap_err();
+
+        let history: Vec<_> = bank
+            .balance_history(AccountId::Number(1))
+            .iter()
+            .map(|balance| (balance.period, balance.available, balance.held))
+            .collect();
+        assert_eq!(
+            history,
+            [
+                (0, Amount::from(15), Amount::from(0)),
+                (DAY, Amount::from(12), Amount::from(0)),
+                (3 * DAY, Amount::from(3), Amount::from(10)),
+            ]
+        );
+        assert!(bank.balance_history(AccountId::Number(2)).is_empty());
+
+        bank.set_balance_period(NonZeroU64::new(DAY));
+        assert_eq!(bank.balance_history(AccountId::Number(1)).len(), 3);
+        bank.set_balance_period(None);
+        assert_eq!(bank.balance_histories().count(), 0);
+    }
+
+    #[test]
+    fn downsampled_series() {
+        let mut bank = Bank::new();
+        bank.set_balance_series(Some(SeriesOptions {
+            every: NonZeroUsize::new(2).unwrap(),
+            capacity: Some(2),
+        }));
+        for tx in 1..=7_u8 {
+            bank.perform_transaction(instruction(
+                TransactionInstructionKind::Deposit,
+                tx.into(),
+                Some(tx.into()),
+                Some(u64::from(tx) * 10),
+            ))
+            .unwrap();
+        }
+
+        let series: Vec<_> = bank
+            .balance_series(AccountId::Number(1))
+            .map(|point| (point.seq, point.timestamp, point.available))
+            .collect();
+        assert_eq!(
+            series,
+            [
+                (5, Some(50), Amount::from(15)),
+                (7, Some(70), Amount::from(28)),
+            ]
+        );
+        assert_eq!(bank.balance_series(AccountId::Number(2)).count(), 0);
+    }
+}
@@ -0,0 +1,225 @@
+//! This module contains the events a [Bank](../struct.Bank.html) emits while applying instructions.
+//!
+//! Observers registered with [`Bank::register_observer`](../struct.Bank.html#method.register_observer) are notified
+//! of every event as it happens, so notifications, metrics, or audit sinks can be built without parsing logs.
+
+use super::account::AccountId;
+use super::admin::Action;
+use super::amount::Amount;
+use super::transaction::{instruction::TransactionInstructionKind, Error, TransactionId};
+use serde::Serialize;
+
+/// Something that happened inside the bank.
+///
+/// Serializes with the variant name in an `event` field, e.g. `{"event":"AccountCreated","client":1}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    AccountCreated {
+        client: AccountId,
+    },
+    DepositApplied {
+        client: AccountId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    WithdrawalApplied {
+        client: AccountId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    DisputeOpened {
+        client: AccountId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    DisputeResolved {
+        client: AccountId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    ChargebackApplied {
+        client: AccountId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    /// An account locked by the chargeback of `tx` was unlocked by a `reinstate` instruction.
+    AccountReinstated {
+        client: AccountId,
+        tx: TransactionId,
+        operator_reference: String,
+    },
+    /// A locked account was unlocked with [`Bank::unlock`](../struct.Bank.html#method.unlock).
+    AccountUnlocked {
+        client: AccountId,
+    },
+    /// A client's account was purged with [`Bank::purge`](../struct.Bank.html#method.purge), on the authority of
+    /// `operator_reference`.
+    AccountPurged {
+        client: AccountId,
+        operator_reference: String,
+    },
+    /// An operator took an administrative `action` on a client's account with
+    /// [`Bank::administer`](../struct.Bank.html#method.administer).
+    AccountAdministered {
+        client: AccountId,
+        #[serde(flatten)]
+        action: Action,
+        operator_reference: String,
+    },
+    /// A previously applied instruction was undone by [`Bank::rollback`](../struct.Bank.html#method.rollback).
+    InstructionRolledBack {
+        client: AccountId,
+        tx: TransactionId,
+    },
+    /// An instruction wasn't applied.  `error` is the same error returned to the caller.
+    InstructionRejected {
+        client: AccountId,
+        tx: TransactionId,
+        kind: TransactionInstructionKind,
+        error: Error,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        correlation_id: Option<String>,
+    },
+}
+
+impl Event {
+    /// The client the event is about.
+    #[must_use]
+    pub fn client(&self) -> AccountId {
+        match self {
+            Event::AccountCreated { client }
+            | Event::DepositApplied { client, .. }
+            | Event::WithdrawalApplied { client, .. }
+            | Event::DisputeOpened { client, .. }
+            | Event::DisputeResolved { client, .. }
+            | Event::ChargebackApplied { client, .. }
+            | Event::AccountReinstated { client, .. }
+            | Event::AccountUnlocked { client }
+            | Event::AccountPurged { client, .. }
+            | Event::AccountAdministered { client, .. }
+            | Event::InstructionRolledBack { client, .. }
+            | Event::InstructionRejected { client, .. } => *client,
+        }
+    }
+
+    /// The event with its amount rescaled to the four decimal places of the account report, so that it's written the
+    /// same whichever amount type the bank uses.
+    #[must_use]
+    pub fn rescaled(&self) -> Event {
+        let mut event = self.clone();
+        match &mut event {
+            Event::DepositApplied { amount, .. }
+            | Event::WithdrawalApplied { amount, .. }
+            | Event::DisputeOpened { amount, .. }
+            | Event::DisputeResolved { amount, .. }
+            | Event::ChargebackApplied { amount, .. }
+            | Event::AccountAdministered {
+                action: Action::Adjust { amount },
+                ..
+            } => amount.rescale(4),
+            _ => {}
+        }
+        event
+    }
+}
+
+/// Receives events from a bank.
+///
+/// Observers are called synchronously from inside the bank, so they should be quick.  Any closure taking an `&Event`
+/// is an observer.
+pub trait Observer: Send {
+    fn notify(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event) + Send> Observer for F {
+    fn notify(&mut self, event: &Event) {
+        self(event);
+    }
+}
+
+/// The observers registered with a bank.
+#[derive(Default)]
+pub(crate) struct Observers(Vec<Box<dyn Observer>>);
+
+impl Observers {
+    pub(crate) fn register<O: Observer + 'static>(&mut self, observer: O) {
+        self.0.push(Box::new(observer));
+    }
+
+    pub(crate) fn notify(&mut self, event: &Event) {
+        for observer in &mut self.0 {
+            observer.notify(event);
+        }
+    }
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::{transaction::instruction::TransactionInstruction, Bank};
+    use std::sync::{Arc, Mutex};
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        tx: u64,
+        amount: Option<Amount>,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(1),
+            tx: TransactionId(tx),
+            amount,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn events_are_emitted() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut bank = Bank::new();
+        let sink = Arc::clone(&events);
+        bank.register_observer(move |e: &Event| sink.lock().unwrap().push(e.clone()));
+
+        let _ = bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            1,
+            Some(Amount::from(5)),
+        ));
+        let _ = bank.perform_transaction(instruction(
+            TransactionInstructionKind::Withdrawal,
+            2,
+            Some(Amount::from(10)),
+        ));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 1, None));
+        let _ =
+            bank.perform_transaction(instruction(TransactionInstructionKind::Chargeback, 1, None));
+
+        let (client, tx) = (AccountId::Number(1), TransactionId(1));
+        let amount = Amount::from(5);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Event::AccountCreated { client },
+                Event::DepositApplied { client, tx, amount },
+                Event::InstructionRejected {
+                    client,
+                    tx: TransactionId(2),
+                    kind: TransactionInstructionKind::Withdrawal,
+                    error: Error::InsufficientFunds,
+                    correlation_id: None,
+                },
+                Event::DisputeOpened { client, tx, amount },
+                Event::ChargebackApplied { client, tx, amount },
+            ]
+        );
+    }
+}
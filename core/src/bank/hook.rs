@@ -0,0 +1,151 @@
+//! This module contains hooks for customizing how a [Bank](../struct.Bank.html) processes instructions.
+//!
+//! Hooks registered with [`Bank::register_hook`](../struct.Bank.html#method.register_hook) run before and after every
+//! instruction.  Unlike [observers](../event/index.html), a hook can veto an instruction before it is applied, which
+//! makes it the place for custom validation such as sanctions lists or business rules.
+
+use super::account::Account;
+use super::transaction::{instruction::TransactionInstruction, Error};
+
+/// What a hook wants done with an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Carry on processing the instruction.
+    Allow,
+    /// Don't apply the instruction.  The caller gets [`Error::RejectedByHook`](../transaction/enum.Error.html).
+    Reject,
+}
+
+/// Custom processing run around every instruction.
+///
+/// Both methods have default implementations so a hook only needs to implement the one it cares about.
+pub trait Hook: Send {
+    /// Called before an instruction is applied, before any of the bank's own checks.
+    ///
+    /// The first hook to return [`Decision::Reject`](enum.Decision.html#variant.Reject) stops the instruction; later
+    /// hooks aren't consulted.
+    fn before_apply(&mut self, _instruction: &TransactionInstruction) -> Decision {
+        Decision::Allow
+    }
+
+    /// Called after the bank has tried to apply an instruction that every hook allowed, with the outcome.
+    fn after_apply(
+        &mut self,
+        _instruction: &TransactionInstruction,
+        _result: Result<&Account, Error>,
+    ) {
+    }
+}
+
+/// The hooks registered with a bank.
+#[derive(Default)]
+pub(crate) struct Hooks(Vec<Box<dyn Hook>>);
+
+impl Hooks {
+    pub(crate) fn register<H: Hook + 'static>(&mut self, hook: H) {
+        self.0.push(Box::new(hook));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn before_apply(&mut self, instruction: &TransactionInstruction) -> Decision {
+        for hook in &mut self.0 {
+            if hook.before_apply(instruction) == Decision::Reject {
+                return Decision::Reject;
+            }
+        }
+        Decision::Allow
+    }
+
+    pub(crate) fn after_apply(
+        &mut self,
+        instruction: &TransactionInstruction,
+        result: Result<&Account, Error>,
+    ) {
+        for hook in &mut self.0 {
+            hook.after_apply(instruction, result);
+        }
+    }
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Hooks({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::amount::Amount;
+    use crate::bank::{
+        account::AccountId,
+        transaction::{instruction::TransactionInstructionKind, TransactionId},
+        Bank,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// Rejects everything for one client and counts what it sees afterwards.
+    struct Sanctions {
+        blocked: AccountId,
+        applied: Arc<Mutex<Vec<Result<Amount, Error>>>>,
+    }
+
+    impl Hook for Sanctions {
+        fn before_apply(&mut self, instruction: &TransactionInstruction) -> Decision {
+            if instruction.client == self.blocked {
+                Decision::Reject
+            } else {
+                Decision::Allow
+            }
+        }
+
+        fn after_apply(&mut self, _: &TransactionInstruction, result: Result<&Account, Error>) {
+            self.applied
+                .lock()
+                .unwrap()
+                .push(result.map(|account| account.available));
+        }
+    }
+
+    fn deposit(client: u16, tx: u64) -> TransactionInstruction {
+        TransactionInstruction {
+            kind: TransactionInstructionKind::Deposit,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(1)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn hooks_can_reject() {
+        let applied = Arc::new(Mutex::new(vec![]));
+        let mut bank = Bank::new();
+        bank.register_hook(Sanctions {
+            blocked: AccountId::Number(2),
+            applied: Arc::clone(&applied),
+        });
+
+        assert!(bank.perform_transaction(deposit(1, 1)).is_ok());
+        assert_eq!(
+            bank.perform_transaction(deposit(2, 2)).unwrap_err(),
+            Error::RejectedByHook
+        );
+        assert_eq!(
+            bank.perform_transaction(deposit(1, 1)).unwrap_err(),
+            Error::DuplicateTransaction
+        );
+
+        // The rejected client never had an account created.
+        assert_eq!(bank.accounts().count(), 1);
+        assert_eq!(
+            *applied.lock().unwrap(),
+            [Ok(Amount::from(1)), Err(Error::DuplicateTransaction)]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! This module contains the invariants checked after every instruction once
+//! [`Bank::enable_invariant_checks`](../struct.Bank.html#method.enable_invariant_checks) has been called.
+//!
+//! The checks are meant for tests, fuzzing, and chasing down a suspected bug on real input, not for production runs:
+//! a violation means the bank itself is wrong, so it panics instead of returning an error.  The panic message has the
+//! instruction, its outcome, and the account before and after.
+
+use super::account::{Account, AccountSummary};
+use super::amount::Amount;
+use super::transaction::{
+    instruction::{TransactionInstruction, TransactionInstructionKind},
+    Error,
+};
+use std::fmt;
+
+/// Something that has to hold for every account after every instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invariant {
+    /// Held funds are never negative.
+    HeldNotNegative,
+    /// The total is the sum of available and held funds, to the four decimal places of the report.
+    TotalIsAvailablePlusHeld,
+    /// Only a `reinstate` instruction changes a locked account.
+    LockedAccountUnchanged,
+    /// A rejected instruction doesn't change the account, apart from opening it.
+    RejectedInstructionUnchanged,
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Invariant::HeldNotNegative => write!(f, "held funds are never negative"),
+            Invariant::TotalIsAvailablePlusHeld => write!(f, "total is available plus held"),
+            Invariant::LockedAccountUnchanged => {
+                write!(f, "locked accounts only change on reinstate")
+            }
+            Invariant::RejectedInstructionUnchanged => {
+                write!(f, "rejected instructions don't change the account")
+            }
+        }
+    }
+}
+
+/// An invariant that didn't hold, with everything needed to reproduce it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub invariant: Invariant,
+    pub instruction: TransactionInstruction,
+    pub result: Result<(), Error>,
+    /// The account before the instruction, or `None` if the instruction opened it.
+    pub before: Option<AccountSummary>,
+    pub after: AccountSummary,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invariant violated: {}\n  instruction: {:?}\n  result: {:?}\n  before: {:?}\n  after: {:?}",
+            self.invariant, self.instruction, self.result, self.before, self.after
+        )
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// Check the account an instruction was for, given the account before it.
+pub(crate) fn check(
+    instruction: &TransactionInstruction,
+    result: Result<(), Error>,
+    before: Option<AccountSummary>,
+    after: &Account,
+) -> Result<(), Box<Violation>> {
+    let summary = AccountSummary::from(after);
+    let violation = |invariant| {
+        Box::new(Violation {
+            invariant,
+            instruction: instruction.clone(),
+            result,
+            before,
+            after: summary,
+        })
+    };
+    let unchanged = |before: &AccountSummary| {
+        before.available == summary.available
+            && before.held == summary.held
+            && before.locked == summary.locked
+    };
+
+    if after.held < Amount::default() {
+        return Err(violation(Invariant::HeldNotNegative));
+    }
+    let mut sum = after.available + after.held;
+    sum.rescale(4);
+    if after.total() != sum {
+        return Err(violation(Invariant::TotalIsAvailablePlusHeld));
+    }
+    if let Some(before) = &before {
+        if before.locked
+            && instruction.kind != TransactionInstructionKind::Reinstate
+            && !unchanged(before)
+        {
+            return Err(violation(Invariant::LockedAccountUnchanged));
+        }
+    }
+    if result.is_err() {
+        let opened = Account::new(after.client);
+        let before = before.unwrap_or_else(|| AccountSummary::from(&opened));
+        if !unchanged(&before) {
+            return Err(violation(Invariant::RejectedInstructionUnchanged));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::transaction::TransactionId;
+    use crate::bank::Bank;
+
+    #[test]
+    fn checks_accounts() {
+        let instruction = |kind, amount: Option<u32>| TransactionInstruction {
+            kind,
+            client: AccountId::Number(1),
+            tx: TransactionId(1),
+            amount: amount.map(Amount::from),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+        let mut bank = Bank::new();
+        bank.enable_invariant_checks();
+        for (kind, amount) in [
+            (TransactionInstructionKind::Deposit, Some(5)),
+            (TransactionInstructionKind::Withdrawal, Some(10)),
+            (TransactionInstructionKind::Dispute, None),
+            (TransactionInstructionKind::Chargeback, None),
+            (TransactionInstructionKind::Deposit, Some(5)),
+        ] {
+            let _ = bank.perform_transaction(instruction(kind, amount));
+        }
+
+        let deposit = instruction(TransactionInstructionKind::Deposit, Some(5));
+        let mut account = Account::new(AccountId::Number(1));
+        let opened = AccountSummary::from(&account);
+        let before = Some(opened);
+        account.held = Amount::from(-1);
+        let violation = check(&deposit, Ok(()), before, &account).unwrap_err();
+        assert_eq!(violation.invariant, Invariant::HeldNotNegative);
+
+        account.held = Amount::default();
+        account.available = Amount::from(5);
+        let violation = check(&deposit, Err(Error::AccountFrozen), before, &account).unwrap_err();
+        assert_eq!(violation.invariant, Invariant::RejectedInstructionUnchanged);
+        assert!(
+            violation
+                .to_string()
+                .contains("before: Some(AccountSummary"),
+            "{}",
+            violation
+        );
+        let locked = Some(AccountSummary {
+            locked: true,
+            ..opened
+        });
+        account.locked = true;
+        let violation = check(&deposit, Ok(()), locked, &account).unwrap_err();
+        assert_eq!(violation.invariant, Invariant::LockedAccountUnchanged);
+    }
+}
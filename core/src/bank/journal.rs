@@ -0,0 +1,302 @@
+//! This module contains the rollback journal.
+//!
+//! When enabled with [`Bank::enable_journal`](../struct.Bank.html#method.enable_journal), the bank records how to undo
+//! each instruction it processes.  [`Bank::rollback`](../struct.Bank.html#method.rollback) then unwinds the most
+//! recent instructions without rebuilding state from the start of the input.
+//!
+//! The journal only lives in memory; it isn't included in snapshots.
+
+use super::account::{Account, AccountId};
+use super::amount::Amount;
+use super::event::Event;
+use super::transaction::{instruction::TransactionInstructionKind, Error, TransactionId};
+use super::Bank;
+use std::collections::VecDeque;
+
+/// Undo information for the most recent instructions.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+/// How to undo a single instruction.
+#[derive(Debug)]
+pub(crate) struct Entry {
+    client: AccountId,
+    /// The account's balances before the instruction, or `None` if the instruction created the account.
+    previous: Option<Balances>,
+    change: Change,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Balances {
+    available: Amount,
+    held: Amount,
+    locked: bool,
+    locked_by: Option<TransactionId>,
+}
+
+/// What an instruction did to the transaction store.
+#[derive(Debug, Clone, Copy)]
+enum Change {
+    /// Nothing; the instruction was rejected.
+    None,
+    /// A deposit or withdrawal was recorded.
+    Inserted(TransactionId),
+    /// An existing transaction gained an amendment.
+    Amended(TransactionId),
+}
+
+impl From<&Account> for Balances {
+    fn from(account: &Account) -> Self {
+        Self {
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            locked_by: account.locked_by,
+        }
+    }
+}
+
+impl Journal {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Forget every entry, so that nothing before now can be rolled back.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Record an instruction, forgetting the oldest entry if the journal is full.
+    pub(crate) fn record(
+        &mut self,
+        client: AccountId,
+        tx: TransactionId,
+        kind: TransactionInstructionKind,
+        previous: Option<Balances>,
+        result: Result<(), Error>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let change = match (result, kind) {
+            (Err(_), _) => Change::None,
+            (
+                Ok(()),
+                TransactionInstructionKind::Deposit | TransactionInstructionKind::Withdrawal,
+            ) => Change::Inserted(tx),
+            (Ok(()), _) => Change::Amended(tx),
+        };
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            client,
+            previous,
+            change,
+        });
+    }
+}
+
+impl Bank {
+    /// Start recording undo information for up to `capacity` of the most recent instructions.
+    ///
+    /// Instructions processed before the journal was enabled can't be rolled back.  Enabling the journal again clears
+    /// it.
+    pub fn enable_journal(&mut self, capacity: usize) {
+        self.journal = Some(Journal::new(capacity));
+    }
+
+    /// Undo the last `n` instructions passed to [`perform_transaction`](#method.perform_transaction), including ones
+    /// that were rejected, most recent first.
+    ///
+    /// Returns the number of instructions actually rolled back, which is less than `n` if the journal doesn't go back
+    /// that far or isn't enabled.
+    pub fn rollback(&mut self, n: usize) -> usize {
+        self.refresh_totals();
+        let Some(journal) = &mut self.journal else {
+            return 0;
+        };
+
+        let mut rolled_back = 0;
+        while rolled_back < n {
+            let Some(entry) = journal.entries.pop_back() else {
+                break;
+            };
+            tracing::debug!(?entry, "rolling back instruction");
+
+            match entry.change {
+                Change::None => {}
+                Change::Inserted(tx) => {
+                    self.transactions
+                        .remove(self.transactions.key(entry.client, tx));
+                    if let Some(history) = self.history.get_mut(&entry.client) {
+                        history.pop();
+                    }
+                    self.observers.notify(&Event::InstructionRolledBack {
+                        client: entry.client,
+                        tx,
+                    });
+                }
+                Change::Amended(tx) => {
+                    let key = self.transactions.key(entry.client, tx);
+                    if let Some(txn) = self.transactions.get_mut(key) {
+                        let was_disputed = txn.is_disputed();
+                        txn.revert_amendment();
+                        match (was_disputed, txn.is_disputed()) {
+                            (true, false) => self.totals.open_disputes -= 1,
+                            (false, true) => self.totals.open_disputes += 1,
+                            _ => {}
+                        }
+                    }
+                    self.observers.notify(&Event::InstructionRolledBack {
+                        client: entry.client,
+                        tx,
+                    });
+                }
+            }
+
+            if let Some(balances) = entry.previous {
+                if let Some(account) = self.accounts.get_mut(&entry.client) {
+                    self.totals.remove(account);
+                    account.available = balances.available;
+                    account.held = balances.held;
+                    account.locked = balances.locked;
+                    account.locked_by = balances.locked_by;
+                    self.totals.add(account);
+                }
+            } else {
+                if let Some(account) = self.accounts.remove(&entry.client) {
+                    self.totals.remove(&account);
+                }
+                self.history.remove(&entry.client);
+                self.archive.forget(entry.client);
+            }
+            rolled_back += 1;
+        }
+        rolled_back
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::amount::Amount;
+    use crate::bank::{
+        account::AccountId,
+        transaction::{
+            instruction::{TransactionInstruction, TransactionInstructionKind},
+            TransactionId,
+        },
+        Bank,
+    };
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        client: u16,
+        tx: u64,
+        amount: Option<u32>,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: amount.map(Amount::from),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn rollback_restores_state() {
+        let mut bank = Bank::new();
+        bank.enable_journal(10);
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            1,
+            1,
+            Some(10),
+        ))
+        .unwrap();
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            2,
+            2,
+            Some(5),
+        ))
+        .unwrap();
+        bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 1, 1, None))
+            .unwrap();
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Chargeback,
+            1,
+            1,
+            None,
+        ))
+        .unwrap();
+        // Rejected, but still counts as an instruction.
+        let _ = bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            1,
+            3,
+            Some(1),
+        ));
+
+        assert_eq!(bank.rollback(4), 4);
+
+        let account = bank.account(&AccountId::Number(1)).unwrap();
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.held, Amount::from(0));
+        assert!(!account.locked);
+        assert!(bank
+            .transaction(&TransactionId(1))
+            .unwrap()
+            .amendment_history()
+            .is_empty());
+        assert!(bank.account(&AccountId::Number(2)).is_none());
+        assert!(bank.transaction(&TransactionId(2)).is_none());
+        assert_eq!(bank.history(AccountId::Number(2)).count(), 0);
+
+        // The rolled back deposit can be applied again.
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            2,
+            2,
+            Some(5),
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn rollback_is_limited_by_capacity() {
+        let mut bank = Bank::new();
+        assert_eq!(bank.rollback(1), 0);
+
+        bank.enable_journal(1);
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            1,
+            1,
+            Some(1),
+        ))
+        .unwrap();
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            1,
+            2,
+            Some(1),
+        ))
+        .unwrap();
+
+        assert_eq!(bank.rollback(2), 1);
+        assert_eq!(
+            bank.account(&AccountId::Number(1)).unwrap().available,
+            Amount::from(1)
+        );
+    }
+}
@@ -0,0 +1,1303 @@
+//! This module contains most of the logic of the application.
+//!
+//! A [Bank](struct.Bank.html) is the system used to keep track of accounts and transactions, as well as apply transactions.
+
+use account::{Account, AccountId, AccountSummary, AccountType, Metadata};
+use amount::Amount;
+use archive::Archive;
+use balances::{BalanceHistory, BalanceSeries};
+use event::{Event, Observer, Observers};
+use hook::{Decision, Hook, Hooks};
+use journal::{Balances, Journal};
+use retention::Retention;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
+use store::TransactionStore;
+use totals::Totals;
+use tracing::instrument;
+use transaction::{
+    instruction::{TransactionInstruction, TransactionInstructionKind},
+    Error, IdScope, Transaction, TransactionAmendment, TransactionId,
+};
+
+pub mod account;
+pub mod admin;
+pub mod amount;
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
+pub mod archive;
+pub mod audit;
+pub mod balances;
+pub mod event;
+pub mod hook;
+pub mod invariant;
+pub mod journal;
+pub mod purge;
+pub mod retention;
+pub mod retry;
+pub mod shard;
+pub mod snapshot;
+mod store;
+pub mod totals;
+pub mod transaction;
+pub mod wal;
+
+/// Hasher for the bank's maps: `FxHash` with the `fxhash` feature, otherwise the standard library's DoS-resistant
+/// default.
+#[cfg(feature = "fxhash")]
+pub(crate) type BuildHasher = rustc_hash::FxBuildHasher;
+#[cfg(not(feature = "fxhash"))]
+pub(crate) type BuildHasher = std::collections::hash_map::RandomState;
+
+pub(crate) type Map<K, V> = HashMap<K, V, BuildHasher>;
+pub(crate) type Set<T> = HashSet<T, BuildHasher>;
+
+/// A Bank is the system used to keep track of accounts and transactions.
+///
+/// Cloning a bank copies its accounts, transactions, retention policy, and balance history and series.  Observers,
+/// hooks, invariant checks, and the rollback journal belong to the original and aren't copied; the same goes for
+/// (de)serialization, which uses the [snapshot](snapshot/index.html) format.
+#[derive(Debug, Default)]
+pub struct Bank {
+    accounts: Map<AccountId, Account>,
+    transactions: TransactionStore,
+    /// Ids of each client's transactions in the order they were applied.
+    history: Map<AccountId, Vec<TransactionId>>,
+    observers: Observers,
+    hooks: Hooks,
+    journal: Option<Journal>,
+    retention: Retention,
+    /// Minimum balance for accounts without their own.
+    minimum_balance: Option<Amount>,
+    balance_history: Option<BalanceHistory>,
+    balance_series: Option<BalanceSeries>,
+    totals: Totals,
+    /// Whether an account has been changed through `account_mut` since the totals were last counted.
+    totals_stale: bool,
+    check_invariants: bool,
+    archive: Archive,
+    /// Available funds of purged accounts, still counted in the totals.
+    purged: Amount,
+}
+
+/// Clones hold archived accounts in memory.
+impl Clone for Bank {
+    fn clone(&self) -> Self {
+        let mut accounts = self.accounts.clone();
+        let mut history = self.history.clone();
+        for archived in self.archive.iter() {
+            let client = archived.account.client;
+            accounts.insert(client, archived.account);
+            if !archived.history.is_empty() {
+                history.insert(client, archived.history);
+            }
+        }
+        Self {
+            accounts,
+            transactions: self.transactions.clone(),
+            history,
+            retention: self.retention.clone(),
+            minimum_balance: self.minimum_balance,
+            balance_history: self.balance_history.clone(),
+            balance_series: self.balance_series.clone(),
+            totals: self.totals,
+            totals_stale: self.totals_stale || self.archive.len() > 0,
+            purged: self.purged,
+            ..Bank::default()
+        }
+    }
+}
+
+impl Bank {
+    #[must_use]
+    pub fn new() -> Self {
+        Bank::default()
+    }
+
+    /// Create a bank with room for `accounts` accounts and `transactions` transactions before it needs to grow.
+    ///
+    /// Growing the maps means rehashing everything in them, which is noticeable on large inputs whose size is known
+    /// roughly in advance.
+    #[must_use]
+    pub fn with_capacity(accounts: usize, transactions: usize) -> Self {
+        Bank {
+            accounts: Map::with_capacity_and_hasher(accounts, BuildHasher::default()),
+            transactions: TransactionStore::with_capacity(transactions),
+            history: Map::with_capacity_and_hasher(accounts, BuildHasher::default()),
+            ..Bank::default()
+        }
+    }
+
+    /// Return an iterator over the accounts.  This a convenience so that the underlying storage doesn't have to be exposed.
+    /// Accounts that have been [archived](archive/index.html) aren't included.
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    /// Look up a client's account.
+    #[must_use]
+    pub fn account(&self, client: &AccountId) -> Option<&Account> {
+        self.accounts.get(client)
+    }
+
+    /// Look up a client's account for modification.
+    ///
+    /// Changes made through this reference bypass the bank's checks, hooks, and events.  It's intended for
+    /// administrative corrections, not for applying transactions.
+    pub fn account_mut(&mut self, client: &AccountId) -> Option<&mut Account> {
+        self.rehydrate(*client);
+        self.totals_stale = true;
+        self.accounts.get_mut(client)
+    }
+
+    /// Unlock a client's account after a chargeback has been dealt with, returning the account if there is one.
+    ///
+    /// Notifies observers with [`Event::AccountUnlocked`](event/enum.Event.html) if the account was locked.  Like
+    /// other administrative changes this isn't an instruction, so it's kept by snapshots but not by the write-ahead log.
+    pub fn unlock(&mut self, client: &AccountId) -> Option<&Account> {
+        self.refresh_totals();
+        let account = self.accounts.get_mut(client)?;
+        if account.locked {
+            self.totals.locked_accounts -= 1;
+            account.locked = false;
+            account.locked_by = None;
+            tracing::info!(?client, "account unlocked");
+            self.observers
+                .notify(&Event::AccountUnlocked { client: *client });
+        }
+        Some(account)
+    }
+
+    /// Attach metadata to a client's account, replacing any it had, and return the account.  The account is opened if
+    /// the client doesn't have one yet.
+    ///
+    /// Like [`unlock`](#method.unlock) this is an administrative change, so it's kept by snapshots but not by the
+    /// write-ahead log.
+    pub fn set_metadata(&mut self, client: AccountId, metadata: Metadata) -> &Account {
+        let account = self.open_account(client);
+        account.metadata = metadata;
+        account
+    }
+
+    /// Set the type of a client's account and return the account.  The account is opened if the client doesn't have
+    /// one yet.  Balances are left as they are, even if the new type wouldn't have allowed them.
+    ///
+    /// Like [`unlock`](#method.unlock) this is an administrative change, so it's kept by snapshots but not by the
+    /// write-ahead log.
+    pub fn set_account_type(&mut self, client: AccountId, account_type: AccountType) -> &Account {
+        let account = self.open_account(client);
+        account.account_type = account_type;
+        account
+    }
+
+    /// Set the minimum balance for accounts that don't have their own, or `None` for no minimum.  Withdrawals that
+    /// would leave less are rejected with [`Error::BelowMinimumBalance`](transaction/enum.Error.html).  Like the
+    /// retention policy this is configuration, not state, so snapshots don't keep it.
+    pub fn set_minimum_balance(&mut self, minimum: Option<Amount>) {
+        self.minimum_balance = minimum;
+    }
+
+    /// Set the minimum balance of a client's account, overriding the bank's, and return the account.  The account is
+    /// opened if the client doesn't have one yet.
+    pub fn set_account_minimum_balance(
+        &mut self,
+        client: AccountId,
+        minimum: Option<Amount>,
+    ) -> &Account {
+        let account = self.open_account(client);
+        account.minimum_balance = minimum;
+        account
+    }
+
+    /// Look up a transaction by id.  Transactions that have been [spilled](#method.spill_transactions) to disk are
+    /// read back as owned copies.
+    ///
+    /// Ids don't identify a transaction on their own when they are [unique per client](#method.set_id_scope), so this
+    /// always returns `None` then; use [`client_transaction`](#method.client_transaction) instead.
+    #[must_use]
+    pub fn transaction(&self, tx: &TransactionId) -> Option<Cow<'_, Transaction>> {
+        match self.transactions.scope() {
+            IdScope::Global => self.transactions.get((None, *tx)),
+            IdScope::PerClient => None,
+        }
+    }
+
+    /// Look up one of `client`'s transactions by id, whether ids are unique per client or across the bank.
+    #[must_use]
+    pub fn client_transaction(
+        &self,
+        client: AccountId,
+        tx: TransactionId,
+    ) -> Option<Cow<'_, Transaction>> {
+        self.transactions
+            .get(self.transactions.key(client, tx))
+            .filter(|txn| txn.client == client)
+    }
+
+    /// Set where transaction ids have to be unique.  Meant to be set before any instructions are applied; transactions
+    /// the bank already holds are refiled, but ids retired by the [retention policy](retention/index.html) aren't.
+    /// Like the retention policy this is configuration, but [snapshots](snapshot/index.html) keep it, since the
+    /// transactions in them can't be told apart without it.
+    pub fn set_id_scope(&mut self, scope: IdScope) {
+        self.transactions.set_scope(scope);
+    }
+
+    /// Return an iterator over all transactions, in no particular order.
+    pub fn transactions(&self) -> impl Iterator<Item = Cow<'_, Transaction>> {
+        self.transactions.iter()
+    }
+
+    /// Return an iterator over the transactions belonging to `client`, in the order they were applied.  The same as
+    /// [`history`](#method.history).
+    pub fn client_transactions(
+        &self,
+        client: AccountId,
+    ) -> impl Iterator<Item = Cow<'_, Transaction>> {
+        self.history(client)
+    }
+
+    /// Return an iterator over the transactions belonging to `client` in the order they were applied.  Each
+    /// transaction carries its own amendment history.
+    pub fn history(&self, client: AccountId) -> impl Iterator<Item = Cow<'_, Transaction>> {
+        let archived = match self.history.get(&client) {
+            Some(_) => None,
+            None => self.archive.read(client).map(|archived| archived.history),
+        };
+        self.history
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .copied()
+            .chain(archived.into_iter().flatten())
+            .filter_map(move |tx| self.transactions.get(self.transactions.key(client, tx)))
+    }
+
+    /// Keep at most `capacity` transactions in memory, spilling the oldest to a temporary file in `dir`.
+    ///
+    /// Transactions are only kept so that they can be disputed later; with this enabled a bank processing an
+    /// arbitrarily long input needs a fixed amount of memory for transactions, apart from a small index entry for
+    /// each one on disk.  Spilled transactions are read back when they are disputed, resolved, or charged back.  The
+    /// file is removed when the bank is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the spill file can't be created or existing transactions can't be written to it.
+    pub fn spill_transactions(&mut self, capacity: usize, dir: &Path) -> io::Result<()> {
+        self.transactions.enable_spill(capacity, dir)
+    }
+
+    /// Register an observer to be notified of every [`Event`](event/enum.Event.html) the bank emits.
+    pub fn register_observer<O: Observer + 'static>(&mut self, observer: O) {
+        self.observers.register(observer);
+    }
+
+    /// Check the [invariants](invariant/index.html) of the account after every instruction from now on, panicking
+    /// with a [`Violation`](invariant/struct.Violation.html) if one doesn't hold.
+    pub fn enable_invariant_checks(&mut self) {
+        self.check_invariants = true;
+    }
+
+    /// Turn [invariant checks](#method.enable_invariant_checks) on or off from the next instruction.
+    pub fn set_invariant_checks(&mut self, enabled: bool) {
+        self.check_invariants = enabled;
+    }
+
+    /// Register a hook to run before and after every instruction.  Hooks run in the order they were registered.
+    pub fn register_hook<H: Hook + 'static>(&mut self, hook: H) {
+        self.hooks.register(hook);
+    }
+
+    /// Perform a transaction based on the [`TransactionInput`](transaction/struct.TransactionInput.html).
+    ///
+    /// This method returns a Result with a reference to the affected account.
+    /// This is to allow the caller to see the current state after the transaction has been applied.
+    ///
+    /// The Error returned does not necessarily indicate a critical error; it may just mean that the transaction wasn't applied.
+    /// For example, the input could be a disputed Transaction for which the original Transaction doesn't exist.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is an error converting the `TransactionInstruction` into
+    /// a `Transaction`. Both types are controlled in this codebase so this
+    /// should never happen.
+    ///
+    /// With [invariant checks](#method.enable_invariant_checks) enabled, also panics if the account breaks an
+    /// invariant.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if it can't process the instruction.
+    #[instrument(skip(self), fields(correlation_id = ti.correlation_id.as_deref()))]
+    pub fn perform_transaction(&mut self, ti: TransactionInstruction) -> Result<&Account, Error> {
+        let (client, tx, kind, timestamp) = (ti.client, ti.tx, ti.kind, ti.timestamp);
+        let correlation_id = ti.correlation_id.clone();
+        self.sweep_archive();
+        self.rehydrate(client);
+        let previous = self
+            .journal
+            .as_ref()
+            .map(|_| self.accounts.get(&client).map(Balances::from));
+        self.refresh_totals();
+        if let Some(account) = self.accounts.get(&client) {
+            self.totals.remove(account);
+        }
+        let checked = self.check_invariants.then(|| {
+            (
+                ti.clone(),
+                self.accounts.get(&client).map(AccountSummary::from),
+            )
+        });
+
+        let result = if self.hooks.is_empty() {
+            self.apply(ti)
+        } else if self.hooks.before_apply(&ti) == Decision::Reject {
+            tracing::info!("instruction rejected by hook");
+            Err(Error::RejectedByHook)
+        } else {
+            let result = self.apply(ti.clone());
+            let accounts = &self.accounts;
+            self.hooks
+                .after_apply(&ti, result.map(|()| &accounts[&client]));
+            result
+        };
+
+        if let Some(account) = self.accounts.get(&client) {
+            self.totals.add(account);
+            self.archive.touch(client);
+            if let Some((ti, before)) = checked {
+                if let Err(violation) = invariant::check(&ti, result, before, account) {
+                    tracing::error!(?violation, "invariant violated");
+                    panic!("{}", violation);
+                }
+            }
+        }
+        if let (Some(journal), Some(previous)) = (&mut self.journal, previous) {
+            journal.record(client, tx, kind, previous, result);
+        }
+
+        if let Err(error) = result {
+            self.observers.notify(&Event::InstructionRejected {
+                client,
+                tx,
+                kind,
+                error,
+                correlation_id,
+            });
+            return Err(error);
+        }
+        match kind {
+            TransactionInstructionKind::Dispute => self.totals.open_disputes += 1,
+            TransactionInstructionKind::Resolve | TransactionInstructionKind::Chargeback => {
+                self.totals.open_disputes -= 1;
+            }
+            TransactionInstructionKind::Deposit
+            | TransactionInstructionKind::Withdrawal
+            | TransactionInstructionKind::Reinstate => {}
+        }
+        let account = &self.accounts[&client];
+        if let (Some(history), Some(timestamp)) = (&mut self.balance_history, timestamp) {
+            history.record(timestamp, account);
+        }
+        if let Some(series) = &mut self.balance_series {
+            series.record(timestamp, account);
+        }
+        Ok(account)
+    }
+
+    /// Perform a batch of transactions in order, returning one result per instruction.
+    ///
+    /// Each successful result is a summary of the affected account immediately after that instruction was applied.
+    /// A failed instruction doesn't stop the batch.
+    pub fn apply_batch<I>(&mut self, instructions: I) -> Vec<Result<AccountSummary, Error>>
+    where
+        I: IntoIterator<Item = TransactionInstruction>,
+    {
+        instructions
+            .into_iter()
+            .map(|ti| self.perform_transaction(ti).map(AccountSummary::from))
+            .collect()
+    }
+
+    /// Move the accounts and transactions of another bank into this one.  Used to merge shards, whose clients don't
+    /// overlap.
+    fn absorb(&mut self, mut other: Bank) {
+        self.refresh_totals();
+        self.totals.absorb(other.totals());
+        self.purged += other.purged;
+        for archived in other.archive.drain() {
+            let client = archived.account.client;
+            self.totals.add(&archived.account);
+            self.accounts.insert(client, archived.account);
+            self.history.insert(client, archived.history);
+        }
+        if other.archive.len() > 0 {
+            tracing::error!(
+                accounts = other.archive.len(),
+                "archived accounts couldn't be read and weren't merged"
+            );
+        }
+        self.accounts.extend(other.accounts);
+        for txn in other.transactions {
+            if self
+                .transactions
+                .contains(self.transactions.key(txn.client, txn.tx))
+            {
+                tracing::warn!(tx = ?txn.tx, "transaction id used on more than one shard");
+            } else {
+                self.transactions.insert(txn);
+            }
+        }
+        self.history.extend(other.history);
+        self.retention.absorb(other.retention);
+        match (&mut self.balance_history, other.balance_history) {
+            (Some(history), Some(other)) => history.absorb(other),
+            (None, other) => self.balance_history = other,
+            (Some(_), None) => {}
+        }
+        match (&mut self.balance_series, other.balance_series) {
+            (Some(series), Some(other)) => series.absorb(other),
+            (None, other) => self.balance_series = other,
+            (Some(_), None) => {}
+        }
+    }
+
+    fn apply(&mut self, ti: TransactionInstruction) -> Result<(), Error> {
+        let observers = &mut self.observers;
+        let account = self.accounts.entry(ti.client).or_insert_with(|| {
+            tracing::info!("creating account");
+            observers.notify(&Event::AccountCreated { client: ti.client });
+            Account::new(ti.client)
+        });
+
+        if account.locked && ti.kind != TransactionInstructionKind::Reinstate {
+            tracing::warn!(?account, "account is locked");
+            return Err(Error::AccountFrozen);
+        }
+
+        ti.validate()?;
+
+        let (key, kind) = (self.transactions.key(ti.client, ti.tx), ti.kind);
+        let result = match ti.kind {
+            TransactionInstructionKind::Deposit => self.deposit(ti),
+            TransactionInstructionKind::Withdrawal => self.withdraw(ti),
+            TransactionInstructionKind::Dispute => self.dispute(&ti),
+            TransactionInstructionKind::Resolve => self.resolve(&ti),
+            TransactionInstructionKind::Chargeback => self.chargeback(&ti),
+            TransactionInstructionKind::Reinstate => self.reinstate(ti),
+        };
+        if result.is_ok() {
+            self.apply_retention(key, kind);
+        }
+        result
+    }
+
+    /// A client's account, opened if the client doesn't have one, for administrative changes.
+    fn open_account(&mut self, client: AccountId) -> &mut Account {
+        self.rehydrate(client);
+        self.archive.touch(client);
+        let (observers, totals) = (&mut self.observers, &mut self.totals);
+        self.accounts.entry(client).or_insert_with(|| {
+            tracing::info!(?client, "creating account");
+            observers.notify(&Event::AccountCreated { client });
+            let account = Account::new(client);
+            totals.add(&account);
+            account
+        })
+    }
+
+    /// The account for an instruction.  Only valid after `apply` has created it.
+    fn instruction_account(
+        accounts: &mut Map<AccountId, Account>,
+        client: AccountId,
+    ) -> &mut Account {
+        accounts
+            .get_mut(&client)
+            .expect("account is created before instructions are applied")
+    }
+
+    /// An account's new balances, if they and the bank's totals with them fit in an `Amount`.  `totals` leave the
+    /// account out, as they do while an instruction is applied.
+    fn in_range(
+        totals: &Totals,
+        available: Option<Amount>,
+        held: Option<Amount>,
+    ) -> Result<(Amount, Amount), Error> {
+        let fits = || {
+            let (available, held) = (available?, held?);
+            totals.available.checked_add(available)?;
+            totals.held.checked_add(held)?;
+            totals.total.checked_add(available.checked_add(held)?)?;
+            Some((available, held))
+        };
+        fits().ok_or_else(|| {
+            tracing::error!("balance out of range");
+            Error::AmountOverflow
+        })
+    }
+
+    fn deposit(&mut self, ti: TransactionInstruction) -> Result<(), Error> {
+        let key = self.transactions.key(ti.client, ti.tx);
+        let account = Self::instruction_account(&mut self.accounts, ti.client);
+        if self.transactions.contains(key) || self.retention.is_retired(key) {
+            tracing::error!(id = ?ti.tx, "transaction id already exists");
+            return Err(Error::DuplicateTransaction);
+        }
+
+        let amount = ti.amount.ok_or(Error::MissingAmount)?;
+        let (available, _) = Self::in_range(
+            &self.totals,
+            account.available.checked_add(amount),
+            Some(account.held),
+        )?;
+        tracing::info!("applying transaction");
+        tracing::trace!(?account, "applying transaction");
+        account.available = available;
+        tracing::trace!(?account, "transaction applied to account");
+        self.observers.notify(&Event::DepositApplied {
+            client: ti.client,
+            tx: ti.tx,
+            amount,
+        });
+        self.history.entry(ti.client).or_default().push(ti.tx);
+        self.transactions.insert(
+            Transaction::try_from(ti)
+                .expect("deposits and withdrawals with amounts are transactions"),
+        );
+        Ok(())
+    }
+
+    fn withdraw(&mut self, ti: TransactionInstruction) -> Result<(), Error> {
+        let key = self.transactions.key(ti.client, ti.tx);
+        let account = Self::instruction_account(&mut self.accounts, ti.client);
+        if self.transactions.contains(key) || self.retention.is_retired(key) {
+            tracing::error!(id = ?ti.tx, "transaction id already exists");
+            return Err(Error::DuplicateTransaction);
+        }
+
+        let amount = ti.amount.ok_or(Error::MissingAmount)?;
+        if let Err(err) = account.check_withdrawal(amount, self.minimum_balance) {
+            tracing::error!(%err, "withdrawal not allowed");
+            return Err(err);
+        }
+        let (available, _) = Self::in_range(
+            &self.totals,
+            account.available.checked_sub(amount),
+            Some(account.held),
+        )?;
+
+        tracing::info!("applying transaction");
+        tracing::trace!(?account, "applying transaction",);
+        account.available = available;
+        self.observers.notify(&Event::WithdrawalApplied {
+            client: ti.client,
+            tx: ti.tx,
+            amount,
+        });
+        self.history.entry(ti.client).or_default().push(ti.tx);
+        self.transactions.insert(
+            Transaction::try_from(ti)
+                .expect("deposits and withdrawals with amounts are transactions"),
+        );
+        tracing::trace!(?account, "transaction applied to account");
+        Ok(())
+    }
+
+    fn dispute(&mut self, ti: &TransactionInstruction) -> Result<(), Error> {
+        let key = self.transactions.key(ti.client, ti.tx);
+        let account = Self::instruction_account(&mut self.accounts, ti.client);
+        if let Some(prev_txn) = self.transactions.get_mut(key) {
+            if prev_txn.client == ti.client {
+                let (available, held) = Self::in_range(
+                    &self.totals,
+                    account.available.checked_sub(prev_txn.amount),
+                    account.held.checked_add(prev_txn.amount),
+                )?;
+                tracing::trace!(?account, "applying transaction to account");
+                account.available = available;
+                account.held = held;
+                prev_txn.amend(TransactionAmendment::Dispute);
+                tracing::trace!(?account, "transaction applied to account");
+                self.observers.notify(&Event::DisputeOpened {
+                    client: ti.client,
+                    tx: ti.tx,
+                    amount: prev_txn.amount,
+                });
+                Ok(())
+            } else {
+                tracing::error!("transaction client doesn't match instruction client");
+                Err(Error::ClientMismatch)
+            }
+        } else {
+            tracing::info!("original transaction not found for instruction");
+            Err(self.retention.missing(key))
+        }
+    }
+
+    fn resolve(&mut self, ti: &TransactionInstruction) -> Result<(), Error> {
+        let key = self.transactions.key(ti.client, ti.tx);
+        let account = Self::instruction_account(&mut self.accounts, ti.client);
+        if let Some(prev_txn) = self.transactions.get_mut(key) {
+            if prev_txn.client == ti.client {
+                if prev_txn.is_disputed() {
+                    let (available, held) = Self::in_range(
+                        &self.totals,
+                        account.available.checked_add(prev_txn.amount),
+                        account.held.checked_sub(prev_txn.amount),
+                    )?;
+                    tracing::trace!(?account, "applying transaction to account");
+                    account.available = available;
+                    account.held = held;
+                    prev_txn.amend(TransactionAmendment::Resolve);
+                    tracing::trace!(?account, "transaction applied to account");
+                    self.observers.notify(&Event::DisputeResolved {
+                        client: ti.client,
+                        tx: ti.tx,
+                        amount: prev_txn.amount,
+                    });
+                    Ok(())
+                } else {
+                    tracing::warn!(txn = ?prev_txn, "transaction is not in dispute");
+                    Err(Error::NotDisputed)
+                }
+            } else {
+                tracing::error!(
+                    prev_tx_client = ?prev_txn.client,
+                    instruction_client = ?ti.client,
+                    "transaction client doesn't match instruction client"
+                );
+                Err(Error::ClientMismatch)
+            }
+        } else {
+            tracing::info!("original transaction not found for instruction");
+            Err(self.retention.missing(key))
+        }
+    }
+
+    fn chargeback(&mut self, ti: &TransactionInstruction) -> Result<(), Error> {
+        let key = self.transactions.key(ti.client, ti.tx);
+        let account = Self::instruction_account(&mut self.accounts, ti.client);
+        if let Some(prev_txn) = self.transactions.get_mut(key) {
+            if prev_txn.is_disputed() {
+                let (_, held) = Self::in_range(
+                    &self.totals,
+                    Some(account.available),
+                    account.held.checked_sub(prev_txn.amount),
+                )?;
+                tracing::trace!(?account, "applying transaction to account");
+                account.held = held;
+                prev_txn.amend(TransactionAmendment::Chargeback);
+                account.locked = true;
+                account.locked_by = Some(ti.tx);
+                tracing::trace!(?account, "transaction applied to account");
+                self.observers.notify(&Event::ChargebackApplied {
+                    client: ti.client,
+                    tx: ti.tx,
+                    amount: prev_txn.amount,
+                });
+                Ok(())
+            } else {
+                tracing::warn!(txn = ?prev_txn, "transaction is not in dispute");
+                Err(Error::NotDisputed)
+            }
+        } else {
+            tracing::info!("original transaction not found for instruction");
+            Err(self.retention.missing(key))
+        }
+    }
+
+    fn reinstate(&mut self, ti: TransactionInstruction) -> Result<(), Error> {
+        let key = self.transactions.key(ti.client, ti.tx);
+        let account = Self::instruction_account(&mut self.accounts, ti.client);
+        let Some(prev_txn) = self.transactions.get_mut(key) else {
+            tracing::info!("original transaction not found for instruction");
+            return Err(self.retention.missing(key));
+        };
+        if prev_txn.client != ti.client {
+            tracing::error!("transaction client doesn't match instruction client");
+            return Err(Error::ClientMismatch);
+        }
+        if prev_txn.amendment_history().last() != Some(&TransactionAmendment::Chargeback) {
+            tracing::warn!(txn = ?prev_txn, "transaction is not charged back");
+            return Err(Error::NotChargedBack);
+        }
+        prev_txn.amend(TransactionAmendment::Reinstate);
+        account.locked = false;
+        account.locked_by = None;
+        let operator_reference = ti.operator_reference.unwrap_or_default();
+        tracing::info!(%operator_reference, "account reinstated");
+        self.observers.notify(&Event::AccountReinstated {
+            client: ti.client,
+            tx: ti.tx,
+            operator_reference,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transaction::TransactionKind;
+    use super::*;
+    use crate::bank::amount::Amount;
+
+    #[test]
+    fn deposit_transaction() {
+        let mut bank = Bank::new();
+        let account = bank
+            .perform_transaction(TransactionInstruction {
+                client: AccountId::Number(0),
+                tx: TransactionId(0),
+                amount: Some(Amount::new(12345, 4)),
+                kind: TransactionInstructionKind::Deposit,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            })
+            .unwrap();
+
+        assert_eq!(Amount::new(12345, 4), account.total());
+    }
+
+    #[test]
+    fn withdrawal_transaction() {
+        let mut bank = Bank::new();
+        bank.accounts.insert(
+            AccountId::Number(0),
+            Account {
+                available: Amount::new(10, 4),
+                ..Account::new(AccountId::Number(0))
+            },
+        );
+        bank.recount_totals();
+
+        let account = bank
+            .perform_transaction(TransactionInstruction {
+                client: AccountId::Number(0),
+                tx: TransactionId(0),
+                amount: Some(Amount::new(1, 4)),
+                kind: TransactionInstructionKind::Withdrawal,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            })
+            .unwrap();
+
+        assert_eq!(Amount::new(9, 4), account.total());
+    }
+
+    #[test]
+    fn withdrawal_transaction_with_insufficient_funds() {
+        let mut bank = Bank::new();
+        let result = bank.perform_transaction(TransactionInstruction {
+            client: AccountId::Number(0),
+            tx: TransactionId(0),
+            amount: Some(Amount::new(1, 4)),
+            kind: TransactionInstructionKind::Withdrawal,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        });
+
+        assert_eq!(result.unwrap_err(), transaction::Error::InsufficientFunds);
+    }
+
+    #[test]
+    fn rejects_balances_out_of_range() {
+        let mut bank = Bank::new();
+        let instruction = |kind, client, tx, amount| TransactionInstruction {
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount,
+            kind,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+        let deposit = |client, tx, amount| {
+            instruction(
+                TransactionInstructionKind::Deposit,
+                client,
+                tx,
+                Some(amount),
+            )
+        };
+
+        bank.perform_transaction(deposit(0, 0, Amount::MAX))
+            .unwrap();
+        assert_eq!(
+            bank.perform_transaction(deposit(0, 1, Amount::from(1)))
+                .unwrap_err(),
+            transaction::Error::AmountOverflow
+        );
+        // Held funds count towards the total too.
+        bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 0, 0, None))
+            .unwrap();
+        assert_eq!(
+            bank.perform_transaction(deposit(0, 2, Amount::from(1)))
+                .unwrap_err(),
+            transaction::Error::AmountOverflow
+        );
+        // So do other clients' balances, through the bank's totals.
+        assert_eq!(
+            bank.perform_transaction(deposit(1, 3, Amount::from(1)))
+                .unwrap_err(),
+            transaction::Error::AmountOverflow
+        );
+        let account = bank.account(&AccountId::Number(0)).unwrap();
+        assert_eq!(account.held, Amount::MAX);
+        assert_eq!(account.available, Amount::from(0));
+    }
+
+    #[test]
+    fn dispute_transaction() {
+        let mut bank = Bank::new();
+        bank.accounts.insert(
+            AccountId::Number(0),
+            Account {
+                available: Amount::from(10),
+                ..Account::new(AccountId::Number(0))
+            },
+        );
+        let tx = TransactionId(0);
+        let txn = Transaction::new(
+            AccountId::Number(0),
+            tx,
+            TransactionKind::Deposit,
+            Amount::from(10),
+        );
+        bank.transactions.insert(txn);
+        bank.recount_totals();
+
+        let account = bank
+            .perform_transaction(TransactionInstruction {
+                client: AccountId::Number(0),
+                tx: TransactionId(0),
+                amount: None,
+                kind: TransactionInstructionKind::Dispute,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            })
+            .unwrap();
+
+        assert_eq!(account.available, Amount::from(0));
+        assert_eq!(account.total(), Amount::from(10));
+        assert_eq!(account.held, Amount::from(10));
+        assert_eq!(
+            bank.transaction(&tx).unwrap().amendment_history(),
+            [TransactionAmendment::Dispute]
+        );
+    }
+
+    #[test]
+    fn resolve_transaction() {
+        let mut bank = Bank::new();
+        bank.accounts.insert(
+            AccountId::Number(0),
+            Account {
+                available: Amount::from(5),
+                held: Amount::from(5),
+                ..Account::new(AccountId::Number(0))
+            },
+        );
+        let tx = TransactionId(0);
+        let mut txn = Transaction::new(
+            AccountId::Number(0),
+            tx,
+            TransactionKind::Deposit,
+            Amount::from(5),
+        );
+        txn.amend(TransactionAmendment::Dispute);
+        bank.transactions.insert(txn);
+        bank.recount_totals();
+
+        let account = bank
+            .perform_transaction(TransactionInstruction {
+                client: AccountId::Number(0),
+                tx: TransactionId(0),
+                amount: None,
+                kind: TransactionInstructionKind::Resolve,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            })
+            .unwrap();
+
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total(), Amount::from(10));
+        assert_eq!(account.held, Amount::from(0));
+        assert_eq!(
+            bank.transaction(&tx).unwrap().amendment_history(),
+            [TransactionAmendment::Dispute, TransactionAmendment::Resolve]
+        );
+    }
+
+    #[test]
+    fn chargeback_transaction() {
+        let mut bank = Bank::new();
+        bank.accounts.insert(
+            AccountId::Number(0),
+            Account {
+                available: Amount::from(5),
+                held: Amount::from(5),
+                ..Account::new(AccountId::Number(0))
+            },
+        );
+        let tx = TransactionId(0);
+        let mut txn = Transaction::new(
+            AccountId::Number(0),
+            tx,
+            TransactionKind::Deposit,
+            Amount::from(5),
+        );
+        txn.amend(TransactionAmendment::Dispute);
+        bank.transactions.insert(txn);
+        bank.recount_totals();
+
+        let account = bank
+            .perform_transaction(TransactionInstruction {
+                client: AccountId::Number(0),
+                tx: TransactionId(0),
+                amount: None,
+                kind: TransactionInstructionKind::Chargeback,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            })
+            .unwrap();
+
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.total(), Amount::from(5));
+        assert_eq!(account.held, Amount::from(0));
+        assert!(account.locked);
+        assert_eq!(
+            bank.transaction(&tx).unwrap().amendment_history(),
+            [
+                TransactionAmendment::Dispute,
+                TransactionAmendment::Chargeback
+            ]
+        );
+    }
+
+    #[test]
+    fn account_lookup() {
+        let mut bank = Bank::new();
+        bank.perform_transaction(TransactionInstruction {
+            client: AccountId::Number(3),
+            tx: TransactionId(0),
+            amount: Some(Amount::from(2)),
+            kind: TransactionInstructionKind::Deposit,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        })
+        .unwrap();
+
+        assert_eq!(
+            bank.account(&AccountId::Number(3)).unwrap().available,
+            Amount::from(2)
+        );
+        assert!(bank.account(&AccountId::Number(4)).is_none());
+
+        bank.account_mut(&AccountId::Number(3)).unwrap().locked = true;
+        assert!(bank.account(&AccountId::Number(3)).unwrap().locked);
+        assert!(!bank.unlock(&AccountId::Number(3)).unwrap().locked);
+        assert!(bank.unlock(&AccountId::Number(4)).is_none());
+    }
+
+    #[test]
+    fn transaction_queries() {
+        let mut bank = Bank::new();
+        for (client, tx) in [(0, 5), (1, 1), (0, 2)] {
+            bank.perform_transaction(TransactionInstruction {
+                client: AccountId::Number(client),
+                tx: TransactionId(tx),
+                amount: Some(Amount::from(1)),
+                kind: TransactionInstructionKind::Deposit,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            })
+            .unwrap();
+        }
+
+        assert_eq!(
+            bank.transaction(&TransactionId(1)).unwrap().client,
+            AccountId::Number(1)
+        );
+        assert!(bank.transaction(&TransactionId(3)).is_none());
+        assert_eq!(bank.transactions().count(), 3);
+        let txs: Vec<_> = bank.history(AccountId::Number(0)).map(|t| t.tx).collect();
+        assert_eq!(txs, [TransactionId(5), TransactionId(2)]);
+        assert_eq!(bank.history(AccountId::Number(7)).count(), 0);
+        assert!(bank
+            .client_transactions(AccountId::Number(0))
+            .map(|t| t.tx)
+            .eq(txs));
+    }
+
+    #[test]
+    fn apply_batch() {
+        let instruction = |kind, tx, amount| TransactionInstruction {
+            client: AccountId::Number(0),
+            tx: TransactionId(tx),
+            amount,
+            kind,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+        let mut bank = Bank::new();
+        let results = bank.apply_batch(vec![
+            instruction(
+                TransactionInstructionKind::Deposit,
+                0,
+                Some(Amount::from(3)),
+            ),
+            instruction(
+                TransactionInstructionKind::Withdrawal,
+                1,
+                Some(Amount::from(5)),
+            ),
+            instruction(TransactionInstructionKind::Dispute, 0, None),
+        ]);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].unwrap().available, Amount::from(3));
+        assert_eq!(results[1], Err(Error::InsufficientFunds));
+        assert_eq!(results[2].unwrap().held, Amount::from(3));
+        assert_eq!(results[2].unwrap().total, Amount::from(3));
+    }
+
+    #[test]
+    fn negative_amount() {
+        let mut bank = Bank::new();
+        let result = bank.perform_transaction(TransactionInstruction {
+            client: AccountId::Number(0),
+            tx: TransactionId(0),
+            amount: Some(Amount::new(-1, 4)),
+            kind: TransactionInstructionKind::Deposit,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        });
+
+        assert!(matches!(result, Err(Error::NegativeAmount)));
+    }
+
+    #[test]
+    fn missing_amount() {
+        let mut bank = Bank::new();
+        for (kind, tx) in [
+            (TransactionInstructionKind::Deposit, 1),
+            (TransactionInstructionKind::Withdrawal, 2),
+        ] {
+            let result = bank.perform_transaction(TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(tx),
+                amount: None,
+                kind,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            });
+            assert_eq!(result.err(), Some(Error::MissingAmount));
+        }
+        assert_eq!(bank.transaction(&TransactionId(1)), None);
+        assert_eq!(
+            bank.account(&AccountId::Number(1)).unwrap().available,
+            Amount::from(0)
+        );
+    }
+
+    #[test]
+    fn account_types() {
+        let mut bank = Bank::new();
+        bank.set_account_type(
+            AccountId::Number(1),
+            AccountType::Credit {
+                limit: Amount::from(10),
+            },
+        );
+        bank.set_account_type(AccountId::Number(2), AccountType::Escrow);
+        let instruction = |kind, client, tx, amount| TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+
+        let account = bank
+            .perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 1, 1, 8))
+            .unwrap();
+        assert_eq!(account.available, Amount::from(-8));
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 1, 2, 3)),
+            Err(Error::InsufficientFunds)
+        );
+
+        bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 2, 3, 5))
+            .unwrap();
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 2, 4, 1)),
+            Err(Error::WithdrawalNotAllowed)
+        );
+    }
+
+    #[test]
+    fn minimum_balance() {
+        let mut bank = Bank::new();
+        bank.set_minimum_balance(Some(Amount::from(2)));
+        bank.set_account_minimum_balance(AccountId::Number(2), Some(Amount::from(0)));
+        let instruction = |kind, client, tx, amount| TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+        for (client, tx) in [(1, 1), (2, 2)] {
+            bank.perform_transaction(instruction(
+                TransactionInstructionKind::Deposit,
+                client,
+                tx,
+                5,
+            ))
+            .unwrap();
+        }
+
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 1, 3, 4)),
+            Err(Error::BelowMinimumBalance)
+        );
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 1, 4, 6)),
+            Err(Error::InsufficientFunds)
+        );
+        bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 1, 5, 3))
+            .unwrap();
+        // Client 2's own minimum overrides the bank's.
+        bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 2, 6, 5))
+            .unwrap();
+    }
+
+    #[test]
+    fn reinstate_after_chargeback() {
+        let mut bank = Bank::new();
+        let instruction = |kind, tx, operator_reference: Option<&str>| TransactionInstruction {
+            kind,
+            client: AccountId::Number(1),
+            tx: TransactionId(tx),
+            amount: (kind == TransactionInstructionKind::Deposit).then(|| Amount::from(5)),
+            correlation_id: None,
+            operator_reference: operator_reference.map(String::from),
+            timestamp: None,
+        };
+        for (kind, tx) in [
+            (TransactionInstructionKind::Deposit, 1),
+            (TransactionInstructionKind::Deposit, 2),
+            (TransactionInstructionKind::Dispute, 1),
+        ] {
+            bank.perform_transaction(instruction(kind, tx, None))
+                .unwrap();
+        }
+        assert_eq!(
+            bank.perform_transaction(instruction(
+                TransactionInstructionKind::Reinstate,
+                1,
+                Some("OPS-1")
+            )),
+            Err(Error::NotChargedBack)
+        );
+        bank.perform_transaction(instruction(TransactionInstructionKind::Chargeback, 1, None))
+            .unwrap();
+        assert_eq!(
+            bank.perform_transaction(instruction(
+                TransactionInstructionKind::Reinstate,
+                1,
+                Some(" ")
+            )),
+            Err(Error::MissingOperatorReference)
+        );
+
+        let account = bank
+            .perform_transaction(instruction(
+                TransactionInstructionKind::Reinstate,
+                1,
+                Some("OPS-1"),
+            ))
+            .unwrap();
+        assert!(!account.locked);
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(
+            bank.transaction(&TransactionId(1))
+                .unwrap()
+                .amendment_history(),
+            [
+                TransactionAmendment::Dispute,
+                TransactionAmendment::Chargeback,
+                TransactionAmendment::Reinstate
+            ]
+        );
+        assert_eq!(
+            bank.perform_transaction(instruction(
+                TransactionInstructionKind::Reinstate,
+                1,
+                Some("OPS-1")
+            )),
+            Err(Error::NotChargedBack)
+        );
+    }
+
+    #[test]
+    fn per_client_ids() {
+        let mut bank = Bank::new();
+        bank.set_id_scope(IdScope::PerClient);
+        let instruction = |kind, client, amount: Option<i64>| TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(1),
+            amount: amount.map(Amount::from),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+        bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, Some(5)))
+            .unwrap();
+        bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 2, Some(7)))
+            .unwrap();
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 2, Some(1))),
+            Err(Error::DuplicateTransaction)
+        );
+        let account = bank
+            .perform_transaction(instruction(TransactionInstructionKind::Dispute, 2, None))
+            .unwrap();
+        assert_eq!(account.held, Amount::from(7));
+        assert!(bank.transaction(&TransactionId(1)).is_none());
+        assert_eq!(
+            bank.client_transaction(AccountId::Number(1), TransactionId(1))
+                .unwrap()
+                .amount,
+            Amount::from(5)
+        );
+
+        let mut snapshot = vec![];
+        bank.save_snapshot(&mut snapshot).unwrap();
+        let mut bank = Bank::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, Some(1))),
+            Err(Error::DuplicateTransaction)
+        );
+        bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 3, Some(1)))
+            .unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+//! This module contains the right-to-erasure purge of an account.
+//!
+//! [`Bank::purge`](../struct.Bank.html#method.purge) removes a client's account and every trace of the client the bank
+//! keeps: its transactions, its transaction history, its balance history and series, and undo information for it.
+//! The account's available funds stay in the bank's [totals](../totals/index.html), so the bank still adds up to the
+//! money it holds, and [snapshots](../snapshot/index.html) keep that amount without saying whose it was.
+//!
+//! Transaction ids of a purged account are [retired](../retention/index.html) when ids are unique across the bank, so
+//! they can't be reused; when ids are only unique per client they're forgotten along with the client.  An account
+//! with funds held by a dispute can't be purged until the dispute is settled.  The rollback journal is cleared, so
+//! instructions from before a purge can't be rolled back.
+//!
+//! Records of the client kept outside the bank, such as an [audit log](../audit/index.html), are redacted separately,
+//! with [`TOMBSTONE`](constant.TOMBSTONE.html) standing in for the client.
+
+use super::account::{AccountId, AccountSummary, Code};
+use super::amount::Amount;
+use super::event::Event;
+use super::transaction::{IdScope, TransactionId};
+use super::Bank;
+use std::convert::TryFrom;
+
+/// The client code standing in for a purged client in redacted records.  Don't give it to a real client.
+pub const TOMBSTONE: &str = "REDACTED";
+
+/// The transaction id standing in for a purged client's transactions in redacted records that need one.
+pub const TOMBSTONE_TX: TransactionId = TransactionId(0);
+
+/// Reasons an account can't be purged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The client has no account.
+    NotFound,
+    /// The account has funds held by a dispute.
+    InDispute,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "account not found"),
+            Error::InDispute => write!(f, "account has funds held by a dispute"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The client id standing in for a purged client.
+///
+/// # Panics
+///
+/// Never panics: the tombstone is a valid client code.
+#[must_use]
+pub fn tombstone() -> AccountId {
+    AccountId::Code(Code::try_from(TOMBSTONE).expect("the tombstone is a valid code"))
+}
+
+impl Bank {
+    /// Remove `client`'s account and everything the bank keeps about the client, returning the account as it was.
+    /// `operator_reference` says who authorized the purge, for the log and for observers, which are notified with
+    /// [`Event::AccountPurged`](event/enum.Event.html).
+    ///
+    /// Like [`unlock`](#method.unlock) this is an administrative change, so it's kept by snapshots but not by the
+    /// write-ahead log.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client has no account, or it has funds held by a dispute.
+    pub fn purge(
+        &mut self,
+        client: &AccountId,
+        operator_reference: &str,
+    ) -> Result<AccountSummary, Error> {
+        self.rehydrate(*client);
+        let account = self.accounts.get(client).ok_or(Error::NotFound)?;
+        if account.held != Amount::default() {
+            return Err(Error::InDispute);
+        }
+        self.refresh_totals();
+        let account = self.accounts.remove(client).ok_or(Error::NotFound)?;
+        self.totals.remove(&account);
+        self.purged += account.available;
+
+        let scope = self.transactions.scope();
+        for tx in self.history.remove(client).unwrap_or_default() {
+            let key = self.transactions.key(*client, tx);
+            self.transactions.remove(key);
+            if scope == IdScope::Global {
+                self.retention.retire(key);
+            }
+        }
+        self.retention.forget_client(*client);
+        self.archive.forget(*client);
+        if let Some(history) = &mut self.balance_history {
+            history.forget(*client);
+        }
+        if let Some(series) = &mut self.balance_series {
+            series.forget(*client);
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+
+        tracing::info!(?client, operator_reference, "account purged");
+        self.observers.notify(&Event::AccountPurged {
+            client: *client,
+            operator_reference: operator_reference.to_string(),
+        });
+        Ok(AccountSummary::from(&account))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::transaction;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind as Kind,
+    };
+
+    fn instruction(kind: Kind, client: u16, tx: u64, amount: i64) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn purges_and_keeps_totals() {
+        let mut bank = Bank::new();
+        bank.perform_transaction(instruction(Kind::Deposit, 1, 1, 5))
+            .unwrap();
+        bank.perform_transaction(instruction(Kind::Deposit, 1, 2, 3))
+            .unwrap();
+        bank.perform_transaction(instruction(Kind::Deposit, 2, 3, 1))
+            .unwrap();
+        bank.perform_transaction(instruction(Kind::Dispute, 1, 2, 0))
+            .unwrap();
+        let (one, two) = (AccountId::Number(1), AccountId::Number(2));
+
+        assert_eq!(bank.purge(&one, "DPO-1"), Err(Error::InDispute));
+        assert_eq!(
+            bank.purge(&AccountId::Number(3), "DPO-1"),
+            Err(Error::NotFound)
+        );
+        bank.perform_transaction(instruction(Kind::Resolve, 1, 2, 0))
+            .unwrap();
+        let before = bank.totals();
+        let purged = bank.purge(&one, "DPO-1").unwrap();
+        assert_eq!(purged.available, Amount::from(8));
+
+        assert!(bank.account(&one).is_none());
+        assert!(bank.account(&two).is_some());
+        assert_eq!(bank.history(one).count(), 0);
+        assert!(bank.transaction(&TransactionId(1)).is_none());
+        assert_eq!(bank.totals().total, before.total);
+        assert_eq!(bank.totals().accounts, 1);
+        // The ids can't be used again.
+        assert_eq!(
+            bank.perform_transaction(instruction(Kind::Deposit, 1, 1, 5))
+                .unwrap_err(),
+            transaction::Error::DuplicateTransaction
+        );
+
+        let mut buf = vec![];
+        bank.save_snapshot(&mut buf).unwrap();
+        let restored = Bank::load_snapshot(buf.as_slice()).unwrap();
+        assert_eq!(restored.totals(), bank.totals());
+    }
+}
@@ -0,0 +1,229 @@
+//! This module contains the transaction retention policy.
+//!
+//! A bank keeps every deposit and withdrawal in case it is disputed, so memory grows with the length of the input.  A
+//! [`RetentionPolicy`](struct.RetentionPolicy.html) set with
+//! [`Bank::set_retention_policy`](../struct.Bank.html#method.set_retention_policy) retires transactions that can no
+//! longer be amended: charged back ones, and ones that have fallen out of the dispute window.
+//!
+//! A retired transaction is compacted down to its id.  The id still counts for duplicate detection, and instructions
+//! referring to it fail with [`Error::TransactionRetired`](../transaction/enum.Error.html) rather than
+//! `TransactionNotFound`.  Transactions in dispute are never retired until the dispute is settled.
+//!
+//! Retired transactions can't be restored by [`Bank::rollback`](../struct.Bank.html#method.rollback), so rolling back
+//! past a retirement leaves the transaction retired.  [Snapshots](../snapshot/index.html) include the retired ids but
+//! not the policy or the current dispute window, which starts afresh after loading one.
+
+use super::account::AccountId;
+use super::store::Key;
+use super::transaction::{instruction::TransactionInstructionKind, Error};
+use super::Bank;
+use super::Set;
+use std::collections::VecDeque;
+
+/// Which transactions a bank stops keeping.  The default keeps everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Retire transactions once they have been charged back.  The account is locked at that point, so they can't be
+    /// referred to again.
+    pub drop_charged_back: bool,
+    /// Only the most recent this-many deposits and withdrawals can be disputed; older ones are retired.
+    pub dispute_window: Option<usize>,
+}
+
+/// Retention state of a bank.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Retention {
+    policy: RetentionPolicy,
+    /// Deposits and withdrawals within the dispute window, oldest first.
+    window: VecDeque<Key>,
+    /// Transactions that left the window while in dispute.  They are retired once the dispute is settled.
+    expired: Set<Key>,
+    retired: Set<Key>,
+}
+
+impl Retention {
+    pub(crate) fn is_retired(&self, key: Key) -> bool {
+        self.retired.contains(&key)
+    }
+
+    /// The error for an instruction referring to a transaction that isn't in the store.
+    pub(crate) fn missing(&self, key: Key) -> Error {
+        if self.is_retired(key) {
+            Error::TransactionRetired
+        } else {
+            Error::TransactionNotFound
+        }
+    }
+
+    /// Keys of retired transactions, in no particular order.
+    pub(crate) fn retired(&self) -> impl Iterator<Item = Key> + '_ {
+        self.retired.iter().copied()
+    }
+
+    pub(crate) fn retire(&mut self, key: Key) {
+        self.retired.insert(key);
+    }
+
+    /// Forget every transaction filed under `client`, for when ids are unique per client and the client is purged.
+    pub(crate) fn forget_client(&mut self, client: AccountId) {
+        let other = |key: &Key| key.0 != Some(client);
+        self.window.retain(other);
+        self.expired.retain(other);
+        self.retired.retain(other);
+    }
+
+    /// Merge another bank's retention state into this one, keeping this one's policy.
+    pub(crate) fn absorb(&mut self, other: Retention) {
+        self.window.extend(other.window);
+        self.expired.extend(other.expired);
+        self.retired.extend(other.retired);
+    }
+}
+
+impl Bank {
+    /// Set which transactions the bank stops keeping.  Applies from the next instruction; a smaller dispute window
+    /// takes effect at the next deposit or withdrawal.
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) {
+        self.retention.policy = policy;
+        if policy.dispute_window.is_none() {
+            self.retention.window.clear();
+        }
+    }
+
+    /// Update retention state after an instruction was applied successfully.
+    pub(crate) fn apply_retention(&mut self, key: Key, kind: TransactionInstructionKind) {
+        let retention = &mut self.retention;
+        match kind {
+            TransactionInstructionKind::Deposit | TransactionInstructionKind::Withdrawal => {
+                let Some(window) = retention.policy.dispute_window else {
+                    return;
+                };
+                retention.window.push_back(key);
+                while retention.window.len() > window {
+                    let Some(old) = retention.window.pop_front() else {
+                        break;
+                    };
+                    match self.transactions.get(old) {
+                        Some(txn) if txn.is_disputed() => {
+                            retention.expired.insert(old);
+                        }
+                        // Already retired by a chargeback.
+                        None => {}
+                        Some(_) => {
+                            tracing::debug!(tx = ?old.1, "retiring transaction outside dispute window");
+                            self.transactions.remove(old);
+                            retention.retired.insert(old);
+                        }
+                    }
+                }
+            }
+            TransactionInstructionKind::Dispute | TransactionInstructionKind::Reinstate => {}
+            TransactionInstructionKind::Resolve => {
+                if retention.expired.remove(&key) {
+                    tracing::debug!(tx = ?key.1, "retiring transaction outside dispute window");
+                    self.transactions.remove(key);
+                    retention.retired.insert(key);
+                }
+            }
+            TransactionInstructionKind::Chargeback => {
+                let expired = retention.expired.remove(&key);
+                if expired || retention.policy.drop_charged_back {
+                    tracing::debug!(tx = ?key.1, "retiring charged back transaction");
+                    self.transactions.remove(key);
+                    retention.retired.insert(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::amount::Amount;
+    use crate::bank::{
+        account::AccountId,
+        transaction::{instruction::TransactionInstruction, TransactionId},
+    };
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        client: u16,
+        tx: u64,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: match kind {
+                TransactionInstructionKind::Deposit | TransactionInstructionKind::Withdrawal => {
+                    Some(Amount::from(1))
+                }
+                _ => None,
+            },
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn dispute_window() {
+        let mut bank = Bank::new();
+        bank.set_retention_policy(RetentionPolicy {
+            dispute_window: Some(2),
+            ..RetentionPolicy::default()
+        });
+        bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, 1))
+            .unwrap();
+        bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 1, 1))
+            .unwrap();
+        for tx in 2..=4 {
+            bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, tx))
+                .unwrap();
+        }
+
+        // Transaction 2 fell out of the window; transaction 1 did too but is still in dispute.
+        assert!(bank.transaction(&TransactionId(2)).is_none());
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 1, 2))
+                .unwrap_err(),
+            Error::TransactionRetired
+        );
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, 2))
+                .unwrap_err(),
+            Error::DuplicateTransaction
+        );
+
+        bank.perform_transaction(instruction(TransactionInstructionKind::Resolve, 1, 1))
+            .unwrap();
+        assert!(bank.transaction(&TransactionId(1)).is_none());
+        assert_eq!(
+            bank.account(&AccountId::Number(1)).unwrap().available,
+            Amount::from(4)
+        );
+    }
+
+    #[test]
+    fn drop_charged_back() {
+        let mut bank = Bank::new();
+        bank.set_retention_policy(RetentionPolicy {
+            drop_charged_back: true,
+            ..RetentionPolicy::default()
+        });
+        bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, 1))
+            .unwrap();
+        bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 1, 1))
+            .unwrap();
+        bank.perform_transaction(instruction(TransactionInstructionKind::Chargeback, 1, 1))
+            .unwrap();
+
+        assert!(bank.transaction(&TransactionId(1)).is_none());
+        assert_eq!(
+            bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 2, 1))
+                .unwrap_err(),
+            Error::DuplicateTransaction
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! This module contains retrying of transient storage errors.
+//!
+//! Errors are either [transient](trait.Transient.html), like a file that is briefly locked or a write that timed out,
+//! or permanent, like a full disk or an instruction the bank rejects.  Trying again might get past a transient error,
+//! but a permanent one fails the same way every time.  A [`Retry`](struct.Retry.html) policy tries an operation again
+//! after a transient error, waiting twice as long after each failed attempt, and gives up straight away on a permanent
+//! one.
+//!
+//! The [write-ahead log](../wal/index.html) and [audit log](../audit/index.html) writers retry the writes to their
+//! files, through [`Retrying`](struct.Retrying.html).  Retrying at that level is safe: a write that fails hasn't
+//! written anything, so a retried record is never written twice.
+
+use super::{admin, audit, snapshot, transaction, wal};
+use std::io;
+use std::time::Duration;
+
+/// Whether an error might not happen again if the operation is retried.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+impl Transient for io::Error {
+    fn is_transient(&self) -> bool {
+        matches!(
+            self.kind(),
+            io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::ResourceBusy
+        )
+    }
+}
+
+/// Rejections are the bank's verdict on an instruction, so they are never transient.
+impl Transient for transaction::Error {
+    fn is_transient(&self) -> bool {
+        false
+    }
+}
+
+impl Transient for admin::Error {
+    fn is_transient(&self) -> bool {
+        false
+    }
+}
+
+impl Transient for wal::Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            wal::Error::Io(err) => err.is_transient(),
+            wal::Error::Format(_) | wal::Error::UnsupportedVersion(_) => false,
+        }
+    }
+}
+
+impl Transient for audit::Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            audit::Error::Io(err) => err.is_transient(),
+            audit::Error::Format(_) => false,
+        }
+    }
+}
+
+impl Transient for snapshot::Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            snapshot::Error::Io(err) => err.is_transient(),
+            snapshot::Error::Format(_) | snapshot::Error::UnsupportedVersion(_) => false,
+        }
+    }
+}
+
+/// How often to retry after transient errors, and how long to wait in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Number of retries after the first attempt.  0 doesn't retry.
+    pub retries: u32,
+    /// Wait before the first retry, doubled before each one after it.
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    /// Don't retry.
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl Retry {
+    /// Run `op`, trying again while it fails with a transient error and there are retries left.
+    ///
+    /// # Errors
+    ///
+    /// Will return `op`'s error if it is permanent or the retries have run out.
+    pub fn run<T, E, F>(&self, mut op: F) -> Result<T, E>
+    where
+        E: Transient + std::fmt::Display,
+        F: FnMut() -> Result<T, E>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(err) if err.is_transient() && attempt < self.retries => {
+                    let wait = self.backoff.saturating_mul(1 << attempt.min(16));
+                    attempt += 1;
+                    tracing::warn!(%err, attempt, ?wait, "retrying after transient error");
+                    std::thread::sleep(wait);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A writer whose writes and flushes are retried after transient errors.
+#[derive(Debug)]
+pub struct Retrying<W> {
+    inner: W,
+    retry: Retry,
+}
+
+impl<W: io::Write> Retrying<W> {
+    /// Write to `inner` without retrying, until [`set_retry`](#method.set_retry) is called.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            retry: Retry::default(),
+        }
+    }
+
+    pub fn set_retry(&mut self, retry: Retry) {
+        self.retry = retry;
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: io::Write> io::Write for Retrying<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Self { inner, retry } = self;
+        retry.run(|| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Self { inner, retry } = self;
+        retry.run(|| inner.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Fails each write a number of times before accepting it.
+    struct Flaky {
+        failures: u32,
+        kind: io::ErrorKind,
+        failed: u32,
+        written: Vec<u8>,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failed < self.failures {
+                self.failed += 1;
+                return Err(io::Error::new(self.kind, "flaky"));
+            }
+            self.failed = 0;
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn flaky(failures: u32, kind: io::ErrorKind) -> Retrying<Flaky> {
+        let mut writer = Retrying::new(Flaky {
+            failures,
+            kind,
+            failed: 0,
+            written: vec![],
+        });
+        writer.set_retry(Retry {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        });
+        writer
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let mut writer = flaky(2, io::ErrorKind::WouldBlock);
+        writer.write_all(b"record\n").unwrap();
+        assert_eq!(writer.get_ref().written, b"record\n");
+
+        let mut writer = flaky(3, io::ErrorKind::TimedOut);
+        assert_eq!(
+            writer.write_all(b"record\n").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(writer.get_ref().failed, 3);
+
+        let mut writer = flaky(1, io::ErrorKind::StorageFull);
+        assert!(writer.write_all(b"record\n").is_err());
+        assert_eq!(writer.get_ref().failed, 1);
+
+        assert!(!transaction::Error::InsufficientFunds.is_transient());
+        assert!(wal::Error::Io(io::ErrorKind::Interrupted.into()).is_transient());
+    }
+}
@@ -0,0 +1,482 @@
+//! This module contains a [Bank](../struct.Bank.html) split across worker threads by client.
+//!
+//! Instructions for one client must be applied in order, but different clients don't affect each other.  A
+//! [`ShardedBank`](struct.ShardedBank.html) hashes each instruction's client onto one of several worker threads, each
+//! owning a bank holding only its clients, and merges the shards back into a single bank when input is finished.
+//!
+//! Instructions for one client are applied in the order they were submitted, even when several threads submit them.
+//! Each is given the next [`Sequence`](struct.Sequence.html) for its client as it's submitted, and a worker holds back
+//! any instruction that reaches it ahead of one submitted before it, until the earlier one arrives.  Instructions for
+//! different clients have no order between them.
+//!
+//! Transaction id uniqueness is only enforced within a shard: two clients on different shards can reuse a
+//! transaction id without either instruction being rejected.
+//!
+//! Each shard's queue is bounded, so a reader that outpaces the workers is made to wait rather than buffering the
+//! input in memory.  [`QueueStats`](struct.QueueStats.html) say how full the queues are and how long submitting has
+//! waited on them.
+
+use super::account::AccountId;
+use super::transaction::instruction::TransactionInstruction;
+use super::{Bank, Map};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of instructions that can be queued for each shard before `submit` blocks, unless set otherwise.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// Errors related to running shards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// A worker thread stopped unexpectedly, most likely because it panicked.
+    WorkerStopped,
+}
+
+/// A bank split across worker threads by client.
+#[derive(Debug)]
+pub struct ShardedBank {
+    shards: Vec<Shard>,
+    stats: Arc<QueueStats>,
+    /// The next sequence number for each client.
+    sequences: Mutex<Map<AccountId, u64>>,
+}
+
+/// Where a submitted instruction falls in its client's order: the number of instructions submitted for the client
+/// before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sequence(pub u64);
+
+/// How full a sharded bank's queues are, and how long submitting has waited for room in them.  Updated as
+/// instructions are submitted and applied, so they can be read while the bank is running.
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    capacity: usize,
+    depth: AtomicUsize,
+    peak: AtomicUsize,
+    stalled_nanos: AtomicU64,
+}
+
+impl QueueStats {
+    /// Number of instructions each shard can queue.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of instructions queued on all shards and not yet applied.
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// The largest [`depth`](#method.depth) so far.
+    #[must_use]
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Total time `submit` has blocked on a full queue.
+    #[must_use]
+    pub fn stalled(&self) -> Duration {
+        Duration::from_nanos(self.stalled_nanos.load(Ordering::Relaxed))
+    }
+
+    fn queued(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn dequeued(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn stall(&self, waited: Duration) {
+        let nanos = u64::try_from(waited.as_nanos()).unwrap_or(u64::MAX);
+        self.stalled_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+struct Shard {
+    sender: mpsc::SyncSender<(Sequence, TransactionInstruction)>,
+    handle: thread::JoinHandle<Bank>,
+}
+
+/// Instructions that reached a worker ahead of their turn, with the sequence each client is up to.
+#[derive(Debug, Default)]
+struct Sequencer {
+    next: Map<AccountId, u64>,
+    held: Map<AccountId, BTreeMap<u64, TransactionInstruction>>,
+}
+
+impl Sequencer {
+    /// Take `ti` as instruction `seq` of its client, returning the instructions that are now due, in order.
+    fn arrive(&mut self, seq: Sequence, ti: TransactionInstruction) -> Vec<TransactionInstruction> {
+        let client = ti.client;
+        let next = self.next.entry(client).or_default();
+        if seq.0 != *next {
+            self.held.entry(client).or_default().insert(seq.0, ti);
+            return vec![];
+        }
+        let mut due = vec![ti];
+        *next += 1;
+        if let Some(held) = self.held.get_mut(&client) {
+            while let Some(ti) = held.remove(next) {
+                due.push(ti);
+                *next += 1;
+            }
+            if held.is_empty() {
+                self.held.remove(&client);
+            }
+        }
+        due
+    }
+
+    /// Number of instructions still waiting for an earlier one.
+    fn held(&self) -> usize {
+        self.held.values().map(BTreeMap::len).sum()
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::WorkerStopped => write!(f, "shard worker stopped unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The shard that owns `client` when there are `shards` shards.  Codes are hashed with FNV-1a, so the shard doesn't
+/// change between runs.
+#[must_use]
+pub fn shard_for(client: AccountId, shards: usize) -> usize {
+    match client {
+        AccountId::Number(number) => usize::from(number) % shards,
+        AccountId::Code(code) => {
+            let hash = code
+                .as_str()
+                .bytes()
+                .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                    (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+                });
+            let shards = u64::try_from(shards).unwrap_or(u64::MAX);
+            usize::try_from(hash % shards).unwrap_or_default()
+        }
+    }
+}
+
+impl ShardedBank {
+    /// Start `shards` worker threads, each with an empty bank.  At least one shard is always started.
+    #[must_use]
+    pub fn new(shards: usize) -> Self {
+        Self::with_banks((0..shards.max(1)).map(|_| Bank::new()))
+    }
+
+    /// Start one worker thread per bank, for example banks with observers or hooks registered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `banks` is empty.
+    pub fn with_banks<I: IntoIterator<Item = Bank>>(banks: I) -> Self {
+        Self::with_banks_and_capacity(banks, QUEUE_CAPACITY)
+    }
+
+    /// Like [`with_banks`](#method.with_banks), queueing at most `capacity` instructions for each shard, at least
+    /// one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `banks` is empty.
+    pub fn with_banks_and_capacity<I: IntoIterator<Item = Bank>>(
+        banks: I,
+        capacity: usize,
+    ) -> Self {
+        let capacity = capacity.max(1);
+        let stats = Arc::new(QueueStats {
+            capacity,
+            ..QueueStats::default()
+        });
+        let shards: Vec<Shard> = banks
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut bank)| {
+                let (sender, receiver) = mpsc::sync_channel(capacity);
+                let stats = Arc::clone(&stats);
+                let handle = thread::Builder::new()
+                    .name(format!("shard-{i}"))
+                    .spawn(move || {
+                        let mut sequencer = Sequencer::default();
+                        for (seq, ti) in receiver {
+                            stats.dequeued();
+                            for ti in sequencer.arrive(seq, ti) {
+                                // Errors are to be dropped according to spec
+                                if let Err(err) = bank.perform_transaction(ti) {
+                                    tracing::error!(?err, shard = i, "error applying transaction");
+                                }
+                            }
+                        }
+                        if sequencer.held() > 0 {
+                            tracing::error!(
+                                held = sequencer.held(),
+                                shard = i,
+                                "instructions never applied, waiting for earlier ones that weren't sent"
+                            );
+                        }
+                        bank
+                    })
+                    .expect("could not start shard worker");
+                Shard { sender, handle }
+            })
+            .collect();
+        assert!(
+            !shards.is_empty(),
+            "a sharded bank needs at least one shard"
+        );
+        Self {
+            shards,
+            stats,
+            sequences: Mutex::new(Map::default()),
+        }
+    }
+
+    /// Statistics about the shards' queues, which keep being updated.
+    #[must_use]
+    pub fn stats(&self) -> Arc<QueueStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Queue an instruction on the shard owning its client.  Blocks if that shard's queue is full.
+    ///
+    /// Returns the instruction's place in its client's order.  It is applied after every instruction for the client
+    /// with an earlier sequence, and before every one with a later sequence, whichever thread submitted them.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the shard's worker has stopped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another thread panicked while submitting.
+    pub fn submit(&self, ti: TransactionInstruction) -> Result<Sequence, Error> {
+        let seq = self.sequence(ti.client);
+        self.send(seq, ti)?;
+        Ok(seq)
+    }
+
+    /// The next place in `client`'s order.
+    fn sequence(&self, client: AccountId) -> Sequence {
+        let mut sequences = self.sequences.lock().expect("sequence lock poisoned");
+        let next = sequences.entry(client).or_default();
+        *next += 1;
+        Sequence(*next - 1)
+    }
+
+    /// Queue `ti`, as instruction `seq` of its client, on the shard owning the client.
+    fn send(&self, seq: Sequence, ti: TransactionInstruction) -> Result<(), Error> {
+        let shard = &self.shards[shard_for(ti.client, self.shards.len())];
+        // Counted before sending, so the worker can't take it off the queue before it's counted.
+        self.stats.queued();
+        let sequenced = match shard.sender.try_send((seq, ti)) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::TrySendError::Full(sequenced)) => sequenced,
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                self.stats.dequeued();
+                return Err(Error::WorkerStopped);
+            }
+        };
+        let started = Instant::now();
+        let sent = shard.sender.send(sequenced);
+        self.stats.stall(started.elapsed());
+        sent.map_err(|_| {
+            self.stats.dequeued();
+            Error::WorkerStopped
+        })
+    }
+
+    /// Wait for every queued instruction to be applied and merge the shards into one bank.
+    ///
+    /// The other shards are merged into the first shard's bank, which keeps its settings, such as transaction
+    /// spilling.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if any worker panicked.
+    pub fn finish(self) -> Result<Bank, Error> {
+        let mut banks = self.shards.into_iter().map(|shard| {
+            drop(shard.sender);
+            shard.handle.join().map_err(|_| Error::WorkerStopped)
+        });
+        let mut merged = banks.next().unwrap_or_else(|| Ok(Bank::new()))?;
+        for bank in banks {
+            merged.absorb(bank?);
+        }
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::amount::Amount;
+    use crate::bank::{
+        account::AccountSummary,
+        transaction::{instruction::TransactionInstructionKind, TransactionId},
+    };
+    use std::convert::TryFrom;
+
+    fn instructions() -> Vec<TransactionInstruction> {
+        let mut instructions = vec![];
+        for tx in 0..500_u32 {
+            let client = AccountId::Number(u16::try_from(tx % 17).unwrap());
+            let (kind, amount, tx) = match tx % 5 {
+                0..=2 => (TransactionInstructionKind::Deposit, Some(tx), tx),
+                3 => (TransactionInstructionKind::Withdrawal, Some(tx / 2), tx),
+                _ => (
+                    TransactionInstructionKind::Dispute,
+                    None,
+                    tx.saturating_sub(17),
+                ),
+            };
+            instructions.push(TransactionInstruction {
+                kind,
+                client,
+                tx: TransactionId(tx.into()),
+                amount: amount.map(Amount::from),
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            });
+        }
+        instructions
+    }
+
+    fn summaries(bank: &Bank) -> Vec<AccountSummary> {
+        let mut summaries: Vec<AccountSummary> =
+            bank.accounts().map(AccountSummary::from).collect();
+        summaries.sort_unstable_by_key(|s| s.client);
+        summaries
+    }
+
+    #[test]
+    fn matches_single_threaded() {
+        let mut sequential = Bank::new();
+        let sharded = ShardedBank::new(4);
+        for ti in instructions() {
+            let _ = sequential.perform_transaction(ti.clone());
+            sharded.submit(ti).unwrap();
+        }
+        let merged = sharded.finish().unwrap();
+
+        assert_eq!(summaries(&merged), summaries(&sequential));
+        assert_eq!(
+            merged.transactions().count(),
+            sequential.transactions().count()
+        );
+    }
+
+    #[test]
+    fn bounded_queues() {
+        let mut sequential = Bank::new();
+        let mut slow = Bank::new();
+        slow.register_observer(|_: &crate::bank::event::Event| {
+            thread::sleep(Duration::from_millis(1));
+        });
+        let sharded = ShardedBank::with_banks_and_capacity(vec![slow], 4);
+        let stats = sharded.stats();
+        for ti in instructions().into_iter().take(20) {
+            let _ = sequential.perform_transaction(ti.clone());
+            sharded.submit(ti).unwrap();
+            // The worker may not have counted the instruction it took off the queue yet.
+            assert!(stats.depth() <= 5);
+        }
+        let merged = sharded.finish().unwrap();
+
+        assert_eq!(summaries(&merged), summaries(&sequential));
+        assert_eq!(stats.capacity(), 4);
+        assert_eq!(stats.depth(), 0);
+        assert!(stats.peak() >= 4);
+        assert!(stats.stalled() > Duration::ZERO);
+    }
+
+    #[test]
+    fn applies_clients_in_submission_order() {
+        let deposit = |client, tx| TransactionInstruction {
+            kind: TransactionInstructionKind::Deposit,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(5)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+        // The withdrawal reaches the worker first, but waits for the deposit submitted before it.
+        let sharded = ShardedBank::new(1);
+        let first = sharded.sequence(AccountId::Number(1));
+        let second = sharded.sequence(AccountId::Number(1));
+        let withdrawal = TransactionInstruction {
+            kind: TransactionInstructionKind::Withdrawal,
+            ..deposit(1, 2)
+        };
+        sharded.send(second, withdrawal).unwrap();
+        sharded.submit(deposit(2, 3)).unwrap();
+        sharded.send(first, deposit(1, 1)).unwrap();
+        let bank = sharded.finish().unwrap();
+        assert_eq!(
+            bank.account(&AccountId::Number(1)).unwrap().available,
+            Amount::from(0)
+        );
+        assert_eq!(bank.transactions().count(), 3);
+
+        let applied = Arc::new(Mutex::new(vec![]));
+        let banks = (0..2).map(|_| {
+            let applied = Arc::clone(&applied);
+            let mut bank = Bank::new();
+            bank.register_observer(move |event: &crate::bank::event::Event| {
+                if let crate::bank::event::Event::DepositApplied { client, tx, .. } = event {
+                    applied.lock().unwrap().push((*client, *tx));
+                }
+            });
+            bank
+        });
+        let sharded = ShardedBank::with_banks_and_capacity(banks, 4);
+        let submitted = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for submitter in 0..4_u64 {
+                let (sharded, submitted) = (&sharded, &submitted);
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        let ti = deposit(u16::try_from(i % 3).unwrap(), submitter * 1000 + i);
+                        let (client, tx) = (ti.client, ti.tx);
+                        let seq = sharded.submit(ti).unwrap();
+                        submitted.lock().unwrap().push((client, seq, tx));
+                    }
+                });
+            }
+        });
+        sharded.finish().unwrap();
+
+        let mut submitted = submitted.into_inner().unwrap();
+        submitted.sort_unstable();
+        let applied = applied.lock().unwrap();
+        for client in 0..3 {
+            let client = AccountId::Number(client);
+            let expected: Vec<_> = submitted
+                .iter()
+                .filter(|(c, ..)| *c == client)
+                .map(|(.., tx)| *tx)
+                .collect();
+            let actual: Vec<_> = applied
+                .iter()
+                .filter(|(c, _)| *c == client)
+                .map(|(_, tx)| *tx)
+                .collect();
+            assert_eq!(actual, expected);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! This module contains the on-disk representation of a [Bank](../struct.Bank.html).
+//!
+//! A snapshot is a JSON document holding every account, [archived](../archive/index.html) ones included, and every
+//! transaction, including each transaction's amendment history.  Loading a snapshot and then applying more
+//! instructions gives the same result as applying all of the instructions from the beginning.
+//!
+//! Every snapshot carries a format version, and is always written in the latest, [`VERSION`](constant.VERSION.html).
+//! Snapshots written in an earlier version by an older version of the crate are upgraded as they're loaded, one
+//! version at a time, so they load the same as if they'd just been written.  Loading a snapshot with a version this
+//! crate doesn't know about, such as one written by a newer version, fails with
+//! [`Error::UnsupportedVersion`](enum.Error.html#variant.UnsupportedVersion) rather than guessing.
+//!
+//! | Version | Change |
+//! |---|---|
+//! | 1 | The first version.  Clients' transaction histories were added later and may be missing. |
+//! | 2 | Clients' transaction histories are always present. |
+//!
+//! `Bank` implements `Serialize` and `Deserialize` using the same format, so it can also be stored with any other
+//! serde data format.
+
+use super::account::{Account, AccountId};
+use super::amount::Amount;
+use super::transaction::{IdScope, Transaction, TransactionId};
+use super::Bank;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+
+/// The snapshot format version written by this version of the crate.
+pub const VERSION: u32 = 2;
+
+/// Upgrades from each earlier version to the next: the first upgrades version 1 snapshots to version 2, and so on.
+const MIGRATIONS: &[fn(&mut Snapshot<Account, Transaction>)] = &[from_v1];
+
+/// Errors related to saving or loading a snapshot.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+/// Versioned container for the state of a Bank.
+///
+/// Generic over the account and transaction types so that saving can borrow them instead of cloning.
+#[derive(Debug, Deserialize, Serialize)]
+struct Snapshot<A, T> {
+    version: u32,
+    accounts: Vec<A>,
+    transactions: Vec<T>,
+    /// Each client's transaction ids in the order they were applied.
+    #[serde(default)]
+    history: Vec<(AccountId, Vec<TransactionId>)>,
+    /// Where transaction ids are unique.  Left out when it's the whole bank.
+    #[serde(default, skip_serializing_if = "IdScope::is_global")]
+    id_scope: IdScope,
+    /// Ids of transactions retired by the [retention policy](../retention/index.html).
+    #[serde(default)]
+    retired: Vec<TransactionId>,
+    /// Clients and ids of retired transactions when ids are unique per client.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    retired_by_client: Vec<(AccountId, TransactionId)>,
+    /// Available funds of [purged](../purge/index.html) accounts.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    purged: Amount,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // `skip_serializing_if` passes a reference.
+fn is_zero(amount: &Amount) -> bool {
+    *amount == Amount::default()
+}
+
+/// Only the version is read first so that a snapshot from a different version can be rejected with a useful error
+/// instead of a deserialization failure.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl<A, T> Snapshot<A, T> {
+    /// Whether the snapshot's version can be loaded.
+    fn check_version(version: u32) -> Result<(), Error> {
+        if version == 0 || version > VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot<Account, Transaction> {
+    /// Bring the snapshot up to the current version.
+    fn upgrade(mut self) -> Result<Self, Error> {
+        Self::check_version(self.version)?;
+        for (from, migrate) in (1..).zip(MIGRATIONS) {
+            if self.version == from {
+                migrate(&mut self);
+                self.version += 1;
+            }
+        }
+        debug_assert_eq!(self.version, VERSION, "a migration is missing");
+        Ok(self)
+    }
+}
+
+/// Version 1 snapshots from before clients' histories were kept don't have them, so they're rebuilt in transaction id
+/// order.
+fn from_v1(snapshot: &mut Snapshot<Account, Transaction>) {
+    if !snapshot.history.is_empty() {
+        return;
+    }
+    let mut history: BTreeMap<AccountId, Vec<TransactionId>> = BTreeMap::new();
+    for txn in &snapshot.transactions {
+        history.entry(txn.client).or_default().push(txn.tx);
+    }
+    snapshot.history = history.into_iter().collect();
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading or writing snapshot: {err}"),
+            Error::Format(err) => write!(f, "invalid snapshot: {err}"),
+            Error::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported snapshot version {v}, this version of transactomatic loads versions 1 to {VERSION}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Format(err) => Some(err),
+            Error::UnsupportedVersion(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Error::Io(err.into())
+        } else {
+            Error::Format(err)
+        }
+    }
+}
+
+impl From<Snapshot<Account, Transaction>> for Bank {
+    fn from(snapshot: Snapshot<Account, Transaction>) -> Self {
+        let mut bank = Bank::new();
+        bank.set_id_scope(snapshot.id_scope);
+        for account in snapshot.accounts {
+            bank.accounts.insert(account.client, account);
+        }
+        for txn in snapshot.transactions {
+            bank.transactions.insert(txn);
+        }
+        bank.history.extend(snapshot.history);
+        for tx in snapshot.retired {
+            bank.retention.retire((None, tx));
+        }
+        for (client, tx) in snapshot.retired_by_client {
+            bank.retention.retire((Some(client), tx));
+        }
+        bank.purged = snapshot.purged;
+        bank.recount_totals();
+        bank
+    }
+}
+
+/// Accounts and transactions are written in id order so that snapshots of the same state are identical.
+impl Serialize for Bank {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut accounts: Vec<Cow<'_, Account>> =
+            self.accounts.values().map(Cow::Borrowed).collect();
+        let mut history: Vec<(AccountId, Vec<TransactionId>)> = self
+            .history
+            .iter()
+            .map(|(client, txs)| (*client, txs.clone()))
+            .collect();
+        // Archived accounts are written as if they were in memory.
+        for archived in self.archive.iter() {
+            if !archived.history.is_empty() {
+                history.push((archived.account.client, archived.history));
+            }
+            accounts.push(Cow::Owned(archived.account));
+        }
+        accounts.sort_unstable_by_key(|a| a.client);
+        let mut transactions: Vec<Cow<'_, Transaction>> = self.transactions.iter().collect();
+        transactions.sort_unstable_by_key(|t| (t.tx, t.client));
+        history.sort_unstable_by_key(|(client, _)| *client);
+        let mut retired = vec![];
+        let mut retired_by_client = vec![];
+        for key in self.retention.retired() {
+            match key {
+                (None, tx) => retired.push(tx),
+                (Some(client), tx) => retired_by_client.push((client, tx)),
+            }
+        }
+        retired.sort_unstable();
+        retired_by_client.sort_unstable();
+
+        Snapshot {
+            version: VERSION,
+            accounts,
+            transactions,
+            history,
+            id_scope: self.transactions.scope(),
+            retired,
+            retired_by_client,
+            purged: self.purged,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bank {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = Snapshot::<Account, Transaction>::deserialize(deserializer)?;
+        Ok(Bank::from(snapshot.upgrade().map_err(de::Error::custom)?))
+    }
+}
+
+impl Bank {
+    /// Write the complete state of the bank to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot can't be written.
+    pub fn save_snapshot<W: io::Write>(&self, writer: W) -> Result<(), Error> {
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Restore a bank from a snapshot previously written by [`save_snapshot`](#method.save_snapshot).
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot can't be read, is malformed, or was written in an unsupported version.
+    pub fn load_snapshot<R: io::Read>(reader: R) -> Result<Self, Error> {
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        let header = Header::deserialize(&value)?;
+        Snapshot::<Account, Transaction>::check_version(header.version)?;
+        let snapshot: Snapshot<Account, Transaction> = Snapshot::deserialize(value)?;
+        Ok(Bank::from(snapshot.upgrade()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::transaction::{
+        instruction::{TransactionInstruction, TransactionInstructionKind},
+        TransactionAmendment, TransactionId,
+    };
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        client: u16,
+        tx: u64,
+        amount: Option<Amount>,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bank = Bank::new();
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            1,
+            1,
+            Some(Amount::new(15, 1)),
+        ))
+        .unwrap();
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            1,
+            0,
+            Some(Amount::new(1, 1)),
+        ))
+        .unwrap();
+        bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 1, 1, None))
+            .unwrap();
+
+        let mut buf = vec![];
+        bank.save_snapshot(&mut buf).unwrap();
+        let mut restored = Bank::load_snapshot(buf.as_slice()).unwrap();
+
+        let account = &restored.accounts[&AccountId::Number(1)];
+        assert_eq!(account.available, Amount::new(1, 1));
+        assert_eq!(account.held, Amount::new(15, 1));
+        assert_eq!(
+            restored
+                .transaction(&TransactionId(1))
+                .unwrap()
+                .amendment_history(),
+            [TransactionAmendment::Dispute]
+        );
+
+        let history: Vec<_> = restored
+            .history(AccountId::Number(1))
+            .map(|t| t.tx)
+            .collect();
+        assert_eq!(history, [TransactionId(1), TransactionId(0)]);
+
+        // The restored dispute can still be resolved.
+        let account = restored
+            .perform_transaction(instruction(TransactionInstructionKind::Resolve, 1, 1, None))
+            .unwrap();
+        assert_eq!(account.available, Amount::new(16, 1));
+        assert_eq!(account.held, Amount::from(0));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let input = r#"{"version":0,"accounts":[],"transactions":[]}"#;
+        assert!(matches!(
+            Bank::load_snapshot(input.as_bytes()),
+            Err(Error::UnsupportedVersion(0))
+        ));
+    }
+
+    #[test]
+    fn upgrades_version_1() {
+        let mut bank = Bank::new();
+        for tx in [2, 1] {
+            let deposit = TransactionInstructionKind::Deposit;
+            bank.perform_transaction(instruction(deposit, 1, tx, Some(Amount::from(1))))
+                .unwrap();
+        }
+        // A version 1 snapshot from before histories were kept.
+        let mut old = serde_json::to_value(&bank).unwrap();
+        old["version"] = 1.into();
+        old.as_object_mut().unwrap().remove("history");
+
+        let bank = Bank::load_snapshot(old.to_string().as_bytes()).unwrap();
+        let history: Vec<_> = bank.history(AccountId::Number(1)).map(|t| t.tx).collect();
+        assert_eq!(history, [TransactionId(1), TransactionId(2)]);
+        let bank: Bank = serde_json::from_value(old).unwrap();
+        assert_eq!(bank.history(AccountId::Number(1)).count(), 2);
+
+        let mut buf = vec![];
+        bank.save_snapshot(&mut buf).unwrap();
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .starts_with(r#"{"version":2,"#));
+    }
+
+    #[test]
+    fn bank_serde() {
+        let mut bank = Bank::new();
+        bank.perform_transaction(instruction(
+            TransactionInstructionKind::Deposit,
+            2,
+            1,
+            Some(Amount::from(1)),
+        ))
+        .unwrap();
+
+        let json = serde_json::to_string(&bank).unwrap();
+        let restored: Bank = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.accounts, bank.accounts);
+
+        let clone = bank.clone();
+        assert_eq!(clone.accounts, bank.accounts);
+        assert_eq!(clone.history, bank.history);
+
+        let err = serde_json::from_str::<Bank>(r#"{"version":9,"accounts":[],"transactions":[]}"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("unsupported snapshot version 9"));
+    }
+
+    #[test]
+    fn malformed() {
+        let input = r#"{"version":1,"accounts":{}}"#;
+        assert!(matches!(
+            Bank::load_snapshot(input.as_bytes()),
+            Err(Error::Format(_))
+        ));
+    }
+}
@@ -0,0 +1,327 @@
+//! This module contains the storage for a [Bank's](../struct.Bank.html) transactions.
+//!
+//! Transactions are only kept so that they can be disputed later, but by default every one of them stays in memory.
+//! [`Bank::spill_transactions`](../struct.Bank.html#method.spill_transactions) caps how many are held in memory; the
+//! oldest are written to a temporary file and read back if they are ever needed again.  Only a small index entry per
+//! spilled transaction stays in memory.
+//!
+//! Transactions read back from disk are returned as [`Cow::Owned`](https://doc.rust-lang.org/std/borrow/enum.Cow.html),
+//! so callers of [`Bank::transaction`](../struct.Bank.html#method.transaction) don't need to care where a transaction
+//! lives.
+
+use super::account::AccountId;
+use super::transaction::{IdScope, Transaction, TransactionId};
+use super::{BuildHasher, Map};
+use std::borrow::Cow;
+use std::collections::{hash_map::Entry, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Distinguishes spill files created by one process.
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// What a transaction is filed under: its id, and its client too if ids are only unique per client.
+pub(crate) type Key = (Option<AccountId>, TransactionId);
+
+/// The transactions held by a bank.
+#[derive(Debug, Default)]
+pub(crate) struct TransactionStore {
+    scope: IdScope,
+    hot: Map<Key, Transaction>,
+    spill: Option<Spill>,
+}
+
+/// Transactions that have been moved out of memory.
+#[derive(Debug)]
+struct Spill {
+    /// Largest number of transactions kept in memory.
+    capacity: usize,
+    /// Keys of in-memory transactions, oldest first.
+    order: VecDeque<Key>,
+    /// Where each spilled transaction is in the file: offset and length.
+    index: Map<Key, (u64, usize)>,
+    path: PathBuf,
+    /// Locked so that transactions can be read back through a shared reference.
+    file: Mutex<File>,
+}
+
+impl TransactionStore {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            scope: IdScope::default(),
+            hot: Map::with_capacity_and_hasher(capacity, BuildHasher::default()),
+            spill: None,
+        }
+    }
+
+    pub(crate) fn scope(&self) -> IdScope {
+        self.scope
+    }
+
+    /// File transactions by id alone or by client and id.  Transactions already held are refiled.
+    pub(crate) fn set_scope(&mut self, scope: IdScope) {
+        if scope == self.scope {
+            return;
+        }
+        self.scope = scope;
+        let hot = std::mem::take(&mut self.hot);
+        self.hot = hot
+            .into_values()
+            .map(|txn| (self.key(txn.client, txn.tx), txn))
+            .collect();
+        if let Some(spill) = &mut self.spill {
+            spill.order = self.hot.keys().copied().collect();
+            // The key doesn't always have the client, so it's read back from the file.
+            spill.index = spill
+                .index
+                .iter()
+                .filter_map(|(key, location)| {
+                    let txn = spill.read(*key)?;
+                    Some(((scope.client(txn.client), txn.tx), *location))
+                })
+                .collect();
+        }
+    }
+
+    /// What the transaction `tx` of `client` is filed under.
+    pub(crate) fn key(&self, client: AccountId, tx: TransactionId) -> Key {
+        (self.scope.client(client), tx)
+    }
+
+    /// Keep at most `capacity` transactions in memory, spilling the rest to a new file in `dir`.
+    pub(crate) fn enable_spill(&mut self, capacity: usize, dir: &Path) -> io::Result<()> {
+        let path = dir.join(format!(
+            "transactomatic-spill-{}-{}",
+            std::process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        tracing::info!(?path, capacity, "spilling transactions to disk");
+
+        let mut spill = Spill {
+            capacity: capacity.max(1),
+            order: self.hot.keys().copied().collect(),
+            index: Map::default(),
+            path,
+            file: Mutex::new(file),
+        };
+        // Anything already held is spilled as if it had just been inserted.
+        if let Some(old) = self.spill.take() {
+            for key in old.index.keys() {
+                if let Some(txn) = old.read(*key) {
+                    spill.write(*key, &txn)?;
+                }
+            }
+        }
+        spill.evict(&mut self.hot);
+        self.spill = Some(spill);
+        Ok(())
+    }
+
+    pub(crate) fn contains(&self, key: Key) -> bool {
+        self.hot.contains_key(&key)
+            || self
+                .spill
+                .as_ref()
+                .is_some_and(|spill| spill.index.contains_key(&key))
+    }
+
+    pub(crate) fn insert(&mut self, txn: Transaction) {
+        let key = self.key(txn.client, txn.tx);
+        self.hot.insert(key, txn);
+        if let Some(spill) = &mut self.spill {
+            spill.order.push_back(key);
+            spill.evict(&mut self.hot);
+        }
+    }
+
+    pub(crate) fn get(&self, key: Key) -> Option<Cow<'_, Transaction>> {
+        if let Some(txn) = self.hot.get(&key) {
+            return Some(Cow::Borrowed(txn));
+        }
+        self.spill.as_ref()?.read(key).map(Cow::Owned)
+    }
+
+    /// Look up a transaction for modification, moving it back into memory if it was spilled.
+    pub(crate) fn get_mut(&mut self, key: Key) -> Option<&mut Transaction> {
+        if let Some(spill) = &mut self.spill {
+            if let Entry::Vacant(entry) = self.hot.entry(key) {
+                entry.insert(spill.read(key)?);
+                spill.index.remove(&key);
+                spill.order.push_back(key);
+                spill.evict(&mut self.hot);
+            }
+        }
+        self.hot.get_mut(&key)
+    }
+
+    pub(crate) fn remove(&mut self, key: Key) {
+        if self.hot.remove(&key).is_none() {
+            if let Some(spill) = &mut self.spill {
+                spill.index.remove(&key);
+            }
+        }
+    }
+
+    /// Return an iterator over all transactions, in no particular order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Cow<'_, Transaction>> {
+        let spilled = self
+            .spill
+            .iter()
+            .flat_map(|spill| spill.index.keys().filter_map(move |key| spill.read(*key)));
+        self.hot
+            .values()
+            .map(Cow::Borrowed)
+            .chain(spilled.map(Cow::Owned))
+    }
+}
+
+/// Clones are held entirely in memory.
+impl Clone for TransactionStore {
+    fn clone(&self) -> Self {
+        Self {
+            scope: self.scope,
+            hot: self
+                .iter()
+                .map(|txn| (self.key(txn.client, txn.tx), txn.into_owned()))
+                .collect(),
+            spill: None,
+        }
+    }
+}
+
+impl IntoIterator for TransactionStore {
+    type Item = Transaction;
+    type IntoIter = std::vec::IntoIter<Transaction>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        let mut transactions: Vec<Transaction> = self.hot.drain().map(|(_, txn)| txn).collect();
+        if let Some(spill) = &self.spill {
+            transactions.extend(spill.index.keys().filter_map(|key| spill.read(*key)));
+        }
+        transactions.into_iter()
+    }
+}
+
+impl Spill {
+    /// Write the oldest in-memory transactions to disk until no more than `capacity` are left.
+    fn evict(&mut self, hot: &mut Map<Key, Transaction>) {
+        while hot.len() > self.capacity {
+            let Some(key) = self.order.pop_front() else {
+                break;
+            };
+            // Keys of transactions removed since they were inserted are skipped.
+            let Some(txn) = hot.remove(&key) else {
+                continue;
+            };
+            if let Err(err) = self.write(key, &txn) {
+                // Keeping the transaction in memory is better than losing it.
+                tracing::error!(?err, tx = ?key.1, "error spilling transaction");
+                hot.insert(key, txn);
+                self.order.push_front(key);
+                break;
+            }
+        }
+    }
+
+    fn write(&mut self, key: Key, txn: &Transaction) -> io::Result<()> {
+        let bytes = serde_json::to_vec(txn)?;
+        let file = self.file.get_mut().expect("spill file lock poisoned");
+        let offset = file.seek(SeekFrom::End(0))?;
+        file.write_all(&bytes)?;
+        self.index.insert(key, (offset, bytes.len()));
+        Ok(())
+    }
+
+    fn read(&self, key: Key) -> Option<Transaction> {
+        let (offset, len) = *self.index.get(&key)?;
+        let mut buf = vec![0; len];
+        let result = {
+            let mut file = self.file.lock().expect("spill file lock poisoned");
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut buf))
+        };
+        match result.and_then(|()| Ok(serde_json::from_slice(&buf)?)) {
+            Ok(txn) => Some(txn),
+            Err(err) => {
+                tracing::error!(?err, tx = ?key.1, "error reading spilled transaction");
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            tracing::warn!(?err, path = ?self.path, "error removing spill file");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::{
+        account::AccountId,
+        transaction::{TransactionAmendment, TransactionKind},
+    };
+    use std::convert::TryFrom;
+
+    fn transaction(tx: u64) -> Transaction {
+        Transaction::new(
+            AccountId::Number(1),
+            TransactionId(tx),
+            TransactionKind::Deposit,
+            u32::try_from(tx).unwrap(),
+        )
+    }
+
+    #[test]
+    fn spills_oldest() {
+        let mut store = TransactionStore::default();
+        store.enable_spill(2, &std::env::temp_dir()).unwrap();
+        for tx in 0..5 {
+            store.insert(transaction(tx));
+        }
+
+        assert_eq!(store.iter().count(), 5);
+        assert_eq!(store.hot.len(), 2);
+        let key = |tx| store.key(AccountId::Number(1), TransactionId(tx));
+        assert!(store.contains(key(0)));
+        assert!(matches!(store.get(key(0)), Some(Cow::Owned(_))));
+        assert!(matches!(store.get(key(4)), Some(Cow::Borrowed(_))));
+
+        // Amending a spilled transaction brings it back into memory.
+        let (zero, one) = (key(0), key(1));
+        store
+            .get_mut(zero)
+            .unwrap()
+            .amend(TransactionAmendment::Dispute);
+        assert_eq!(store.hot.len(), 2);
+        assert!(store.get(zero).unwrap().is_disputed());
+
+        // Refiling by client keeps spilled transactions where they are.
+        store.set_scope(IdScope::PerClient);
+        let key = |tx| store.key(AccountId::Number(1), TransactionId(tx));
+        assert!(matches!(store.get(key(1)), Some(Cow::Owned(_))));
+        assert!(!store.contains(one));
+        let one = key(1);
+
+        store.remove(one);
+        let mut txs: Vec<u64> = store.iter().map(|txn| txn.tx.0).collect();
+        txs.sort_unstable();
+        assert_eq!(txs, [0, 2, 3, 4]);
+
+        let path = store.spill.as_ref().unwrap().path.clone();
+        drop(store);
+        assert!(!path.exists());
+    }
+}
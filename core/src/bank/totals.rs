@@ -0,0 +1,201 @@
+//! This module contains the bank-wide totals returned by [`Bank::totals`](../struct.Bank.html#method.totals).
+//!
+//! The totals are kept up to date as instructions are applied, rolled back, and accounts are unlocked, so reading them
+//! doesn't take a pass over every account.  Changes made through
+//! [`Bank::account_mut`](../struct.Bank.html#method.account_mut) can't be followed, so the account totals are counted
+//! again after one.
+
+use super::account::Account;
+use super::amount::Amount;
+use super::Bank;
+use serde::Serialize;
+
+/// Sums and counts over every account in a bank.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Totals {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// Number of accounts.
+    pub accounts: usize,
+    /// Number of locked accounts.
+    pub locked_accounts: usize,
+    /// Number of transactions in dispute.
+    pub open_disputes: usize,
+}
+
+impl Totals {
+    /// Add an account, as it is now.
+    pub(crate) fn add(&mut self, account: &Account) {
+        self.available += account.available;
+        self.held += account.held;
+        self.total += account.available + account.held;
+        self.accounts += 1;
+        self.locked_accounts += usize::from(account.locked);
+    }
+
+    /// Take away an account, as it was when it was added.
+    pub(crate) fn remove(&mut self, account: &Account) {
+        self.available -= account.available;
+        self.held -= account.held;
+        self.total -= account.available + account.held;
+        self.accounts -= 1;
+        self.locked_accounts -= usize::from(account.locked);
+    }
+
+    /// The totals of `accounts`, with `open_disputes` disputes.
+    pub(crate) fn count<'a>(
+        accounts: impl Iterator<Item = &'a Account>,
+        open_disputes: usize,
+    ) -> Self {
+        let mut totals = Totals {
+            open_disputes,
+            ..Totals::default()
+        };
+        for account in accounts {
+            totals.add(account);
+        }
+        totals
+    }
+
+    /// Add the totals of a bank with none of the same accounts.
+    pub(crate) fn absorb(&mut self, other: Totals) {
+        self.available += other.available;
+        self.held += other.held;
+        self.total += other.total;
+        self.accounts += other.accounts;
+        self.locked_accounts += other.locked_accounts;
+        self.open_disputes += other.open_disputes;
+    }
+}
+
+impl Bank {
+    /// Sums of every account's available, held, and total funds, and the numbers of accounts, locked accounts, and
+    /// transactions in dispute.  The funds of [purged](../purge/index.html) accounts are still included in the sums.
+    ///
+    /// Totals are rescaled to four decimal places, like the account report.
+    #[must_use]
+    pub fn totals(&self) -> Totals {
+        let mut totals = if self.totals_stale {
+            Totals::count(self.accounts.values(), self.totals.open_disputes)
+        } else {
+            self.totals
+        };
+        totals.available += self.purged;
+        totals.total += self.purged;
+        totals.available.rescale(4);
+        totals.held.rescale(4);
+        totals.total.rescale(4);
+        totals
+    }
+
+    /// Count the account totals again if an account has been changed directly.
+    pub(crate) fn refresh_totals(&mut self) {
+        if self.totals_stale {
+            self.totals = Totals::count(self.accounts.values(), self.totals.open_disputes);
+            self.totals_stale = false;
+        }
+    }
+
+    /// Count every total again, including disputes.
+    pub(crate) fn recount_totals(&mut self) {
+        let open_disputes = self
+            .transactions
+            .iter()
+            .filter(|txn| txn.is_disputed())
+            .count();
+        self.totals = Totals::count(self.accounts.values(), open_disputes);
+        self.totals_stale = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::shard::ShardedBank;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind as Kind,
+    };
+    use crate::bank::transaction::TransactionId;
+
+    fn instruction(
+        kind: Kind,
+        client: u16,
+        tx: u64,
+        amount: Option<Amount>,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    /// The totals counted from scratch.
+    fn counted(bank: &Bank) -> Totals {
+        let mut bank = bank.clone();
+        bank.recount_totals();
+        bank.totals()
+    }
+
+    #[test]
+    fn kept_incrementally() {
+        let mut bank = Bank::new();
+        bank.enable_journal(10);
+        let instructions = [
+            instruction(Kind::Deposit, 1, 1, Some(Amount::from(100))),
+            instruction(Kind::Withdrawal, 1, 2, Some(Amount::new(305, 1))),
+            instruction(Kind::Withdrawal, 1, 3, Some(Amount::from(1000))),
+            instruction(Kind::Dispute, 1, 1, None),
+            instruction(Kind::Deposit, 2, 4, Some(Amount::from(5))),
+            instruction(Kind::Dispute, 2, 4, None),
+            instruction(Kind::Resolve, 2, 4, None),
+            instruction(Kind::Deposit, 3, 5, Some(Amount::from(7))),
+            instruction(Kind::Dispute, 3, 5, None),
+            instruction(Kind::Chargeback, 3, 5, None),
+        ];
+        for ti in instructions.clone() {
+            let _ = bank.perform_transaction(ti.clone());
+            assert_eq!(bank.totals(), counted(&bank), "{ti:?}");
+        }
+        let totals = bank.totals();
+        assert_eq!(totals.available, Amount::new(-255, 1));
+        assert_eq!(totals.held, Amount::from(100));
+        assert_eq!(totals.total, Amount::new(745, 1));
+        assert_eq!(
+            (
+                totals.accounts,
+                totals.locked_accounts,
+                totals.open_disputes
+            ),
+            (3, 1, 1)
+        );
+
+        bank.unlock(&AccountId::Number(3));
+        assert_eq!(bank.totals().locked_accounts, 0);
+        assert_eq!(bank.rollback(7), 7);
+        assert_eq!(bank.totals(), counted(&bank));
+        assert_eq!(bank.totals().open_disputes, 0);
+
+        bank.account_mut(&AccountId::Number(1)).unwrap().held = Amount::from(1);
+        assert_eq!(bank.totals(), counted(&bank));
+        let _ = bank.perform_transaction(instruction(Kind::Deposit, 1, 6, Some(Amount::from(1))));
+        assert_eq!(bank.totals(), counted(&bank));
+
+        let restored: Bank = serde_json::from_str(&serde_json::to_string(&bank).unwrap()).unwrap();
+        assert_eq!(restored.totals(), bank.totals());
+
+        let sharded = ShardedBank::new(3);
+        for ti in instructions {
+            sharded.submit(ti).unwrap();
+        }
+        let merged = sharded.finish().unwrap();
+        assert_eq!(merged.totals(), counted(&merged));
+        assert_eq!(merged.totals(), totals);
+    }
+}
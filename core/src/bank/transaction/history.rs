@@ -0,0 +1,129 @@
+//! Compact storage for a transaction's amendments.
+//!
+//! Every transaction the bank keeps carries its amendment history, but almost all of them are never amended, and
+//! the rest usually go through one dispute that is then resolved or charged back.  Histories of up to two amendments
+//! are held inline, so only the rare transaction disputed over and over allocates, and the whole history takes two
+//! words rather than the three of an empty `Vec`.
+//!
+//! Serialized as a plain list, the same as the `Vec` it replaced, so existing snapshots still load.
+
+use super::TransactionAmendment;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::iter::FromIterator;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) enum AmendmentHistory {
+    #[default]
+    Empty,
+    One([TransactionAmendment; 1]),
+    Two([TransactionAmendment; 2]),
+    /// Boxed to keep the enum two words wide; a `Vec` alone is three.
+    #[allow(clippy::box_collection)]
+    Many(Box<Vec<TransactionAmendment>>),
+}
+
+impl AmendmentHistory {
+    pub(crate) fn as_slice(&self) -> &[TransactionAmendment] {
+        match self {
+            AmendmentHistory::Empty => &[],
+            AmendmentHistory::One(amendments) => amendments,
+            AmendmentHistory::Two(amendments) => amendments,
+            AmendmentHistory::Many(amendments) => amendments,
+        }
+    }
+
+    pub(crate) fn last(&self) -> Option<TransactionAmendment> {
+        self.as_slice().last().copied()
+    }
+
+    pub(crate) fn push(&mut self, amendment: TransactionAmendment) {
+        *self = match std::mem::take(self) {
+            AmendmentHistory::Empty => AmendmentHistory::One([amendment]),
+            AmendmentHistory::One([first]) => AmendmentHistory::Two([first, amendment]),
+            AmendmentHistory::Two([first, second]) => {
+                AmendmentHistory::Many(Box::new(vec![first, second, amendment]))
+            }
+            AmendmentHistory::Many(mut amendments) => {
+                amendments.push(amendment);
+                AmendmentHistory::Many(amendments)
+            }
+        };
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<TransactionAmendment> {
+        let (rest, last) = match std::mem::take(self) {
+            AmendmentHistory::Empty => (AmendmentHistory::Empty, None),
+            AmendmentHistory::One([only]) => (AmendmentHistory::Empty, Some(only)),
+            AmendmentHistory::Two([first, second]) => {
+                (AmendmentHistory::One([first]), Some(second))
+            }
+            AmendmentHistory::Many(mut amendments) => {
+                let last = amendments.pop();
+                (amendments.iter().copied().collect(), last)
+            }
+        };
+        *self = rest;
+        last
+    }
+}
+
+impl FromIterator<TransactionAmendment> for AmendmentHistory {
+    fn from_iter<I: IntoIterator<Item = TransactionAmendment>>(iter: I) -> Self {
+        let mut history = AmendmentHistory::Empty;
+        for amendment in iter {
+            history.push(amendment);
+        }
+        history
+    }
+}
+
+impl Serialize for AmendmentHistory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AmendmentHistory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<TransactionAmendment>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pop() {
+        assert_eq!(std::mem::size_of::<AmendmentHistory>(), 16);
+
+        let sequence = [
+            TransactionAmendment::Dispute,
+            TransactionAmendment::Resolve,
+            TransactionAmendment::Dispute,
+            TransactionAmendment::Chargeback,
+        ];
+        let mut history = AmendmentHistory::default();
+        for (n, amendment) in sequence.iter().enumerate() {
+            history.push(*amendment);
+            assert_eq!(history.as_slice(), &sequence[..=n]);
+        }
+        assert!(matches!(history, AmendmentHistory::Many(_)));
+
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(json, r#"["Dispute","Resolve","Dispute","Chargeback"]"#);
+        assert_eq!(
+            serde_json::from_str::<AmendmentHistory>(&json).unwrap(),
+            history
+        );
+
+        for n in (0..sequence.len()).rev() {
+            assert_eq!(history.pop(), Some(sequence[n]));
+            assert_eq!(history.as_slice(), &sequence[..n]);
+        }
+        assert_eq!(history, AmendmentHistory::Empty);
+        assert_eq!(history.pop(), None);
+    }
+}
@@ -0,0 +1,314 @@
+//! This module contains types for handling transaction instructions.
+
+use crate::bank::amount::Amount;
+use crate::bank::transaction::Error;
+use crate::bank::{AccountId, TransactionId};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "csv")]
+use std::str::FromStr;
+
+/// Columns of the CSV input, in order.
+#[cfg(feature = "csv")]
+pub const FIELDS: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "correlation_id",
+    "operator_reference",
+    "timestamp",
+];
+
+/// A transaction instruction from an outside source.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct TransactionInstruction {
+    #[serde(rename = "type")]
+    pub kind: TransactionInstructionKind,
+    pub client: AccountId,
+    pub tx: TransactionId,
+    pub amount: Option<Amount>,
+    /// Id of the upstream request that produced the instruction, for tracing it through logs and rejections.
+    /// Optional in every input format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Who authorized a reinstatement and under what ticket, for the audit trail.  Required by `reinstate`
+    /// instructions and ignored by the others.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator_reference: Option<String>,
+    /// When the instruction was made, in seconds since the Unix epoch.  Optional, but statements and balance history
+    /// need it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+/// Transaction input type.  Covers all Transaction and amendment types.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionInstructionKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Unlock an account that a chargeback locked.  `tx` is the charged back transaction.
+    Reinstate,
+}
+
+impl TransactionInstruction {
+    /// Check that the instruction is well-formed on its own: deposits and withdrawals need an amount, amounts can't
+    /// be negative, and reinstatements need an operator reference.  Whether it can be applied depends on the bank.
+    ///
+    /// # Errors
+    ///
+    /// Will return [`Error::MissingAmount`](../enum.Error.html), [`Error::NegativeAmount`](../enum.Error.html), or
+    /// [`Error::MissingOperatorReference`](../enum.Error.html).
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.kind == TransactionInstructionKind::Reinstate
+            && self
+                .operator_reference
+                .as_deref()
+                .is_none_or(|reference| reference.trim().is_empty())
+        {
+            return Err(Error::MissingOperatorReference);
+        }
+        match (self.kind, &self.amount) {
+            (
+                TransactionInstructionKind::Deposit | TransactionInstructionKind::Withdrawal,
+                None,
+            ) => Err(Error::MissingAmount),
+            (_, Some(amount)) if amount.is_sign_negative() => Err(Error::NegativeAmount),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses one line of CSV input without a header, like `deposit, 1, 1, 2.5`.  Whitespace around fields is ignored and
+/// the amount and correlation id can be left off.  Needs the `csv` feature.
+#[cfg(feature = "csv")]
+impl FromStr for TransactionInstruction {
+    type Err = csv::Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut record = csv::StringRecord::new();
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(line.as_bytes())
+            .read_record(&mut record)?;
+        while record.len() < FIELDS.len() {
+            record.push_field("");
+        }
+        record.deserialize(Some(&csv::StringRecord::from(FIELDS.to_vec())))
+    }
+}
+
+/// Arbitrary instructions for fuzzing.  Any kind can have an amount or not, and amounts can be negative, so that
+/// fuzzers reach the bank's handling of malformed instructions.  Needs the `arbitrary` feature.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for TransactionInstruction {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let amount = if u.arbitrary()? {
+            Some(Amount::new(
+                i64::from(u.arbitrary::<i32>()?),
+                u.int_in_range(0..=8)?,
+            ))
+        } else {
+            None
+        };
+        Ok(Self {
+            kind: u.arbitrary()?,
+            client: AccountId::Number(u.arbitrary()?),
+            tx: TransactionId(u.arbitrary()?),
+            amount,
+            correlation_id: u.arbitrary()?,
+            operator_reference: u.arbitrary()?,
+            timestamp: u.arbitrary()?,
+        })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for TransactionInstructionKind {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(*u.choose(&[
+            TransactionInstructionKind::Deposit,
+            TransactionInstructionKind::Withdrawal,
+            TransactionInstructionKind::Dispute,
+            TransactionInstructionKind::Resolve,
+            TransactionInstructionKind::Chargeback,
+            TransactionInstructionKind::Reinstate,
+        ])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPOSIT: &str = r"type, client, tx, amount
+deposit, 1, 1, 1.0
+";
+
+    const WITHDRAWAL: &str = r"type, client, tx, amount
+withdrawal, 1, 1, 1.0
+";
+
+    const DISPUTE: &str = r"type, client, tx, amount
+dispute, 1, 1,
+";
+
+    const RESOLVE: &str = r"type, client, tx, amount
+resolve, 1, 1,
+";
+
+    const CHARGEBACK: &str = r"type, client, tx, amount
+chargeback, 1, 1
+";
+
+    const CORRELATED: &str = r"type, client, tx, amount, correlation_id
+deposit, 1, 1, 1.0, req-1
+";
+
+    const LARGE_TX: &str = r"type, client, tx, amount
+deposit, 1, 18446744073709551615, 1.0
+";
+
+    macro_rules! test_parse {
+        ($(($name:tt, $input:expr, $output:expr)),*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let mut r = csv::ReaderBuilder::new()
+                        .trim(csv::Trim::All)
+                        .flexible(true)
+                        .from_reader($input.as_bytes());
+                    for record in r.deserialize() {
+                        let tx: TransactionInstruction = record.unwrap();
+                        assert_eq!($output, tx);
+                    }
+                }
+            )*
+        };
+    }
+
+    test_parse!(
+        (
+            deposit,
+            DEPOSIT,
+            TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(1),
+                amount: Some(Amount::from(1)),
+                kind: TransactionInstructionKind::Deposit,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            }
+        ),
+        (
+            withdrawal,
+            WITHDRAWAL,
+            TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(1),
+                amount: Some(Amount::from(1)),
+                kind: TransactionInstructionKind::Withdrawal,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            }
+        ),
+        (
+            dispute,
+            DISPUTE,
+            TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(1),
+                amount: None,
+                kind: TransactionInstructionKind::Dispute,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            }
+        ),
+        (
+            resolve,
+            RESOLVE,
+            TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(1),
+                amount: None,
+                kind: TransactionInstructionKind::Resolve,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            }
+        ),
+        (
+            chargeback,
+            CHARGEBACK,
+            TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(1),
+                amount: None,
+                kind: TransactionInstructionKind::Chargeback,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            }
+        ),
+        (
+            correlated,
+            CORRELATED,
+            TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(1),
+                amount: Some(Amount::from(1)),
+                kind: TransactionInstructionKind::Deposit,
+                correlation_id: Some("req-1".to_string()),
+                operator_reference: None,
+                timestamp: None,
+            }
+        ),
+        (
+            large_tx,
+            LARGE_TX,
+            TransactionInstruction {
+                client: AccountId::Number(1),
+                tx: TransactionId(u64::MAX),
+                amount: Some(Amount::from(1)),
+                kind: TransactionInstructionKind::Deposit,
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            }
+        )
+    );
+    #[cfg(feature = "csv")]
+    #[test]
+    fn from_line() {
+        let ti: TransactionInstruction = "deposit, 2, 1, 2.5".parse().unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Deposit);
+        assert_eq!(ti.client, AccountId::Number(2));
+        assert_eq!(ti.amount, Some(Amount::new(25, 1)));
+
+        let ti: TransactionInstruction = "dispute,2,1".parse().unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Dispute);
+        assert_eq!(ti.amount, None);
+
+        let ti: TransactionInstruction = "withdrawal,2,3,1,req-42".parse().unwrap();
+        assert_eq!(ti.correlation_id.as_deref(), Some("req-42"));
+
+        let ti: TransactionInstruction = "reinstate,2,3,,,OPS-7".parse().unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Reinstate);
+        assert_eq!(ti.operator_reference.as_deref(), Some("OPS-7"));
+
+        let ti: TransactionInstruction = "deposit,CUST-2,1,2".parse().unwrap();
+        assert_eq!(ti.client, "CUST-2".parse().unwrap());
+        assert_eq!(ti.client.to_string(), "CUST-2");
+
+        assert!("deposit,2,two,2".parse::<TransactionInstruction>().is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! This module contains types for dealing with realized transactions.
+//!
+//! Three scenarios are covered by this module:
+//! 1. A new transaction input has come in.
+//! 2. A new transaction is created.
+//! 3. An existing transaction is adjusted.
+//!
+//! It's important to note with Number 3 that the original transaction keeps its original data and amendment are added to history.
+//! Once a transaction has been created its initial data is not modified.
+
+mod history;
+pub mod instruction;
+
+use super::account::AccountId;
+use crate::bank::amount::Amount;
+use history::AmendmentHistory;
+use instruction::{TransactionInstruction, TransactionInstructionKind};
+use serde::{Deserialize, Serialize};
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct TransactionId(pub u64);
+
+/// Where transaction ids have to be unique.  Set with
+/// [`Bank::set_id_scope`](../struct.Bank.html#method.set_id_scope).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdScope {
+    /// Across the whole bank, as in the original input format.
+    #[default]
+    Global,
+    /// Within each client.  Different clients' transactions can share an id, and disputes and the like refer to a
+    /// transaction of the client they are for.
+    PerClient,
+}
+
+impl IdScope {
+    #[must_use]
+    pub fn is_global(&self) -> bool {
+        *self == IdScope::Global
+    }
+
+    /// The client part of a transaction's key in this scope.
+    pub(crate) fn client(self, client: AccountId) -> Option<AccountId> {
+        match self {
+            IdScope::Global => None,
+            IdScope::PerClient => Some(client),
+        }
+    }
+}
+
+/// Errors related to performing transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Error {
+    InsufficientFunds,
+    AccountFrozen,
+    NegativeAmount,
+    /// A deposit or withdrawal reused the id of an existing transaction.
+    DuplicateTransaction,
+    /// The transaction referenced by a dispute, resolve, or chargeback doesn't exist.
+    TransactionNotFound,
+    /// The transaction referenced by a dispute or resolve belongs to a different client.
+    ClientMismatch,
+    /// A resolve or chargeback referenced a transaction that isn't in dispute.
+    NotDisputed,
+    /// A registered [`Hook`](../hook/trait.Hook.html) rejected the instruction.
+    RejectedByHook,
+    /// The transaction referenced by a dispute, resolve, or chargeback was retired by the
+    /// [retention policy](../retention/index.html).
+    TransactionRetired,
+    /// A deposit or withdrawal didn't have an amount.
+    MissingAmount,
+    /// A withdrawal was made from an account whose type doesn't allow them, such as escrow.
+    WithdrawalNotAllowed,
+    /// A withdrawal would have left less than the account's minimum balance.
+    BelowMinimumBalance,
+    /// A reinstatement didn't say which operator authorized it.
+    MissingOperatorReference,
+    /// A reinstatement referenced a transaction that wasn't charged back.
+    NotChargedBack,
+    /// The instruction would have taken a balance, or the bank's totals, out of the range an amount can hold.
+    AmountOverflow,
+}
+
+/// Errors related to creating a transaction from an input.
+#[derive(Debug, PartialEq)]
+pub struct TryFromError(TransactionInstructionKind);
+
+/// A realized transaction.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Transaction {
+    pub client: AccountId,
+    pub tx: TransactionId,
+    pub kind: TransactionKind,
+    pub amount: Amount,
+    /// Correlation id of the instruction that created the transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    amendment_history: AmendmentHistory,
+}
+
+/// Type of original transaction
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// An amendment/adjustment to an existing Transaction.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TransactionAmendment {
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// The account locked by the chargeback was unlocked by an operator.
+    Reinstate,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InsufficientFunds => write!(f, "insufficient funds"),
+            Error::AccountFrozen => write!(f, "account is frozen"),
+            Error::NegativeAmount => write!(f, "amount is negative"),
+            Error::DuplicateTransaction => write!(f, "transaction id already exists"),
+            Error::TransactionNotFound => write!(f, "original transaction not found"),
+            Error::ClientMismatch => write!(f, "transaction belongs to a different client"),
+            Error::NotDisputed => write!(f, "transaction is not in dispute"),
+            Error::RejectedByHook => write!(f, "rejected by hook"),
+            Error::TransactionRetired => write!(f, "transaction is no longer kept"),
+            Error::MissingAmount => write!(f, "amount is missing"),
+            Error::WithdrawalNotAllowed => write!(f, "account doesn't allow withdrawals"),
+            Error::BelowMinimumBalance => write!(f, "balance would fall below the minimum"),
+            Error::MissingOperatorReference => write!(f, "operator reference is missing"),
+            Error::NotChargedBack => write!(f, "transaction is not charged back"),
+            Error::AmountOverflow => write!(f, "balance would be out of range"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for TryFromError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "can't create transaction from input kind {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TryFromError {}
+
+impl Transaction {
+    pub fn new<D: Into<Amount>>(
+        client: AccountId,
+        tx: TransactionId,
+        kind: TransactionKind,
+        amount: D,
+    ) -> Self {
+        Self {
+            client,
+            tx,
+            kind,
+            amount: amount.into(),
+            correlation_id: None,
+            amendment_history: AmendmentHistory::Empty,
+        }
+    }
+
+    /// Returns `true` if the transaction is in dispute.  That is, its last amendment is Dispute.
+    #[must_use]
+    pub fn is_disputed(&self) -> bool {
+        self.amendment_history.last() == Some(TransactionAmendment::Dispute)
+    }
+
+    pub fn amend(&mut self, amendment: TransactionAmendment) {
+        self.amendment_history.push(amendment);
+    }
+
+    /// Remove the most recent amendment.  Only used to roll back instructions.
+    pub(crate) fn revert_amendment(&mut self) -> Option<TransactionAmendment> {
+        self.amendment_history.pop()
+    }
+
+    #[must_use]
+    /// Returns a read-only view into the transaction's history.
+    pub fn amendment_history(&self) -> &[TransactionAmendment] {
+        self.amendment_history.as_slice()
+    }
+}
+
+impl std::convert::TryFrom<TransactionInstruction> for Transaction {
+    type Error = TryFromError;
+
+    /// Attempt to build a transaction from the input.  This only works if the
+    /// input type is a [`TransactionKind`](TransactionKind) and not a
+    /// [`TransactionAmendment`](TransactionAmendment), and the input has an amount.
+    fn try_from(ti: TransactionInstruction) -> Result<Self, Self::Error> {
+        let kind = match ti.kind {
+            TransactionInstructionKind::Deposit => TransactionKind::Deposit,
+            TransactionInstructionKind::Withdrawal => TransactionKind::Withdrawal,
+            _ => return Err(TryFromError(ti.kind)),
+        };
+        let amount = ti.amount.ok_or(TryFromError(ti.kind))?;
+        Ok(Transaction {
+            correlation_id: ti.correlation_id,
+            ..Transaction::new(ti.client, ti.tx, kind, amount)
+        })
+    }
+}
@@ -0,0 +1,502 @@
+//! This module contains the write-ahead log (event log) of transaction instructions.
+//!
+//! Every instruction is written to the log, with a sequence number and a timestamp, before it is applied to the
+//! [Bank](../struct.Bank.html).  Because applying instructions is deterministic, replaying the log into an empty bank
+//! reproduces the state at any point in the log.  The log is JSON Lines so it can be inspected with ordinary tools.
+//!
+//! The same property makes a log a check on a [snapshot](../snapshot/index.html): [`verify`](fn.verify.html) replays
+//! it and reports the first record after which the replayed state stops matching the snapshot.
+//!
+//! A new log starts with a line giving its format version, `{"version":1}`, before the first record.  Logs written
+//! before there was a version line are read as version 1, and appending to one leaves it without.  Reading a log with
+//! a version this crate doesn't know about fails with
+//! [`Error::UnsupportedVersion`](enum.Error.html#variant.UnsupportedVersion).
+
+use super::account::{AccountId, AccountSummary};
+use super::retry::{Retry, Retrying};
+use super::transaction::{instruction::TransactionInstruction, TransactionId};
+use super::{Bank, Map};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The log format version written by this version of the crate.
+pub const VERSION: u32 = 1;
+
+/// Errors related to reading or writing the log.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+/// The first line of a log.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    version: u32,
+}
+
+/// A single entry in the log.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Record {
+    /// Position in the log, starting at 1.
+    pub seq: u64,
+    /// Seconds since the Unix epoch when the record was written.
+    pub timestamp: u64,
+    pub instruction: TransactionInstruction,
+}
+
+/// How far to replay a log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    /// Replay every record.
+    End,
+    /// Replay records up to and including this sequence number.
+    Seq(u64),
+    /// Replay records written at or before this timestamp.
+    Timestamp(u64),
+}
+
+/// Where a replayed log stopped matching a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// Replaying record `seq` left the client's account different from the snapshot's, and no later record brought
+    /// them back in line.  Either account is `None` if it doesn't exist.
+    Account {
+        seq: u64,
+        client: AccountId,
+        replayed: Option<AccountSummary>,
+        snapshot: Option<AccountSummary>,
+    },
+    /// The snapshot has an account for a client the log never mentions.
+    Unlogged { client: AccountId },
+    /// The accounts match, but one of the client's transactions, or its amendment history, doesn't.
+    Transaction {
+        client: AccountId,
+        tx: TransactionId,
+    },
+}
+
+/// Appends records to a log file.
+#[derive(Debug)]
+pub struct Writer {
+    writer: io::BufWriter<Retrying<fs::File>>,
+    seq: u64,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading or writing log: {err}"),
+            Error::Format(err) => write!(f, "invalid log record: {err}"),
+            Error::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported log version {v}, this version of transactomatic reads versions 1 to {VERSION}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Format(err) => Some(err),
+            Error::UnsupportedVersion(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Error::Io(err.into())
+        } else {
+            Error::Format(err)
+        }
+    }
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        /// An account's balances, or that there isn't one.
+        struct Balances(Option<AccountSummary>);
+
+        impl std::fmt::Display for Balances {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self.0 {
+                    Some(account) => write!(
+                        f,
+                        "available {}, held {}, locked {}",
+                        account.available, account.held, account.locked
+                    ),
+                    None => write!(f, "no account"),
+                }
+            }
+        }
+
+        match self {
+            Divergence::Account {
+                seq,
+                client,
+                replayed,
+                snapshot,
+            } => write!(
+                f,
+                "record {seq} left client {client}'s account different from the snapshot: replayed {}, snapshot {}",
+                Balances(*replayed),
+                Balances(*snapshot)
+            ),
+            Divergence::Unlogged { client } => {
+                write!(f, "the snapshot has an account for client {client}, who isn't in the log")
+            }
+            Divergence::Transaction { client, tx } => write!(
+                f,
+                "transaction {} of client {client} differs from the snapshot",
+                tx.0
+            ),
+        }
+    }
+}
+
+impl Until {
+    fn includes(self, record: &Record) -> bool {
+        match self {
+            Until::End => true,
+            Until::Seq(seq) => record.seq <= seq,
+            Until::Timestamp(timestamp) => record.timestamp <= timestamp,
+        }
+    }
+}
+
+impl Writer {
+    /// Open a log for appending, creating it if necessary.  Sequence numbers continue from the last record in an
+    /// existing log.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the log can't be opened or an existing log can't be read.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut seq = 0;
+        if path.exists() {
+            for record in records(fs::File::open(path)?) {
+                seq = record?.seq;
+            }
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        let mut writer = io::BufWriter::new(Retrying::new(file));
+        if empty {
+            serde_json::to_writer(&mut writer, &Header { version: VERSION })?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(Self { writer, seq })
+    }
+
+    /// Retry writes to the log after transient errors.
+    #[must_use]
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.writer.get_mut().set_retry(retry);
+        self
+    }
+
+    /// Append an instruction to the log.
+    ///
+    /// Records are buffered; call [`flush`](#method.flush) to make sure they have reached the file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the record can't be written.
+    pub fn append(&mut self, instruction: &TransactionInstruction) -> Result<(), Error> {
+        #[derive(Serialize)]
+        struct RecordRef<'a> {
+            seq: u64,
+            timestamp: u64,
+            instruction: &'a TransactionInstruction,
+        }
+
+        self.seq += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        serde_json::to_writer(
+            &mut self.writer,
+            &RecordRef {
+                seq: self.seq,
+                timestamp,
+                instruction,
+            },
+        )?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if buffered records can't be written.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Iterate over the records in a log, after checking its version.
+pub fn records<R: io::Read>(reader: R) -> impl Iterator<Item = Result<Record, Error>> {
+    let mut first = true;
+    io::BufReader::new(reader)
+        .lines()
+        .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .filter_map(move |line| {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            if std::mem::take(&mut first) {
+                if let Ok(Header { version }) = serde_json::from_str(&line) {
+                    return (version == 0 || version > VERSION)
+                        .then_some(Err(Error::UnsupportedVersion(version)));
+                }
+            }
+            Some(serde_json::from_str(&line).map_err(Error::from))
+        })
+}
+
+/// Rebuild a bank by applying the instructions in a log, stopping once `until` is passed.
+///
+/// Instructions that were rejected when they were first applied are rejected again, so the result matches the state
+/// of the original bank at that point in the log.
+///
+/// # Errors
+///
+/// Will return `Err` if the log can't be read.
+pub fn replay<R: io::Read>(reader: R, until: Until) -> Result<Bank, Error> {
+    let mut bank = Bank::new();
+    replay_into(&mut bank, reader, until)?;
+    Ok(bank)
+}
+
+/// Like [`replay`](fn.replay.html), applying the instructions to an existing bank, such as one with observers
+/// registered.
+///
+/// # Errors
+///
+/// Will return `Err` if the log can't be read.
+pub fn replay_into<R: io::Read>(bank: &mut Bank, reader: R, until: Until) -> Result<(), Error> {
+    for record in records(reader) {
+        let record = record?;
+        if !until.includes(&record) {
+            break;
+        }
+        if let Err(err) = bank.perform_transaction(record.instruction) {
+            tracing::debug!(seq = record.seq, ?err, "replayed instruction rejected");
+        }
+    }
+    Ok(())
+}
+
+/// Replay a log and check that it reproduces `snapshot`.  Returns the number of records replayed, or where the
+/// replayed state first diverges from the snapshot.
+///
+/// After each record the client's account, and the transaction the record refers to, are compared with the snapshot.
+/// A client that doesn't match after its last record diverged at the first record since it last matched, and the
+/// earliest such record is reported.  Only if every account matches are the transactions compared as a whole.
+///
+/// Metadata, account types, and other administrative changes aren't in the log, so they aren't compared.
+///
+/// # Errors
+///
+/// Will return `Err` if the log can't be read.
+pub fn verify<R: io::Read>(reader: R, snapshot: &Bank) -> Result<Result<u64, Divergence>, Error> {
+    let mut bank = Bank::new();
+    bank.set_id_scope(snapshot.transactions.scope());
+    // The first record since which each client hasn't matched the snapshot, if it doesn't now.
+    let mut diverged: Map<AccountId, Option<u64>> = Map::default();
+    let mut count = 0;
+    for record in records(reader) {
+        let record = record?;
+        let (client, tx) = (record.instruction.client, record.instruction.tx);
+        if let Err(err) = bank.perform_transaction(record.instruction) {
+            tracing::debug!(seq = record.seq, ?err, "replayed instruction rejected");
+        }
+        count += 1;
+        let matches = bank.account(&client).map(AccountSummary::from)
+            == snapshot.account(&client).map(AccountSummary::from)
+            && bank.client_transaction(client, tx) == snapshot.client_transaction(client, tx);
+        let since = diverged.entry(client).or_default();
+        if matches {
+            *since = None;
+        } else if since.is_none() {
+            *since = Some(record.seq);
+        }
+    }
+
+    let first = diverged
+        .iter()
+        .filter_map(|(client, since)| since.map(|seq| (seq, *client)))
+        .min();
+    if let Some((seq, client)) = first {
+        return Ok(Err(Divergence::Account {
+            seq,
+            client,
+            replayed: bank.account(&client).map(AccountSummary::from),
+            snapshot: snapshot.account(&client).map(AccountSummary::from),
+        }));
+    }
+    let mut unlogged: Vec<AccountId> = snapshot
+        .accounts()
+        .map(|account| account.client)
+        .filter(|client| !diverged.contains_key(client))
+        .collect();
+    unlogged.sort_unstable();
+    if let Some(client) = unlogged.first() {
+        return Ok(Err(Divergence::Unlogged { client: *client }));
+    }
+    let mut transactions: Vec<_> = snapshot
+        .transactions()
+        .map(|txn| (txn.client, txn.tx))
+        .chain(bank.transactions().map(|txn| (txn.client, txn.tx)))
+        .collect();
+    transactions.sort_unstable_by_key(|(client, tx)| (*tx, *client));
+    for (client, tx) in transactions {
+        if bank.client_transaction(client, tx) != snapshot.client_transaction(client, tx) {
+            return Ok(Err(Divergence::Transaction { client, tx }));
+        }
+    }
+    Ok(Ok(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::{instruction::TransactionInstructionKind, TransactionId};
+
+    const LOG: &str = r#"{"seq":1,"timestamp":100,"instruction":{"type":"deposit","client":1,"tx":1,"amount":"5"}}
+{"seq":2,"timestamp":100,"instruction":{"type":"deposit","client":1,"tx":2,"amount":"3"}}
+{"seq":3,"timestamp":200,"instruction":{"type":"dispute","client":1,"tx":1,"amount":null}}
+{"seq":4,"timestamp":300,"instruction":{"type":"chargeback","client":1,"tx":1,"amount":null}}
+"#;
+
+    fn account(bank: &Bank) -> (Amount, Amount, bool) {
+        let account = bank.accounts().next().unwrap();
+        (account.available, account.held, account.locked)
+    }
+
+    #[test]
+    fn replay_to_end() {
+        let bank = replay(LOG.as_bytes(), Until::End).unwrap();
+        assert_eq!(account(&bank), (Amount::from(3), Amount::from(0), true));
+    }
+
+    #[test]
+    fn replay_to_seq() {
+        let bank = replay(LOG.as_bytes(), Until::Seq(3)).unwrap();
+        assert_eq!(account(&bank), (Amount::from(3), Amount::from(5), false));
+    }
+
+    #[test]
+    fn replay_to_timestamp() {
+        let bank = replay(LOG.as_bytes(), Until::Timestamp(100)).unwrap();
+        assert_eq!(account(&bank), (Amount::from(8), Amount::from(0), false));
+    }
+
+    #[test]
+    fn verify_against_snapshot() {
+        let snapshot = replay(LOG.as_bytes(), Until::End).unwrap();
+        assert_eq!(verify(LOG.as_bytes(), &snapshot).unwrap(), Ok(4));
+
+        // A snapshot taken part way through diverges at the first record after it.
+        let earlier = replay(LOG.as_bytes(), Until::Seq(2)).unwrap();
+        assert!(matches!(
+            verify(LOG.as_bytes(), &earlier).unwrap(),
+            Err(Divergence::Account {
+                seq: 3,
+                client: AccountId::Number(1),
+                ..
+            })
+        ));
+
+        let mut extra = replay(LOG.as_bytes(), Until::End).unwrap();
+        extra
+            .perform_transaction(TransactionInstruction {
+                kind: TransactionInstructionKind::Deposit,
+                client: AccountId::Number(2),
+                tx: TransactionId(9),
+                amount: Some(Amount::from(1)),
+                correlation_id: None,
+                operator_reference: None,
+                timestamp: None,
+            })
+            .unwrap();
+        assert_eq!(
+            verify(LOG.as_bytes(), &extra).unwrap(),
+            Err(Divergence::Unlogged {
+                client: AccountId::Number(2)
+            })
+        );
+    }
+
+    #[test]
+    fn writer_continues_sequence() {
+        let path = std::env::temp_dir().join(format!("transactomatic-wal-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let instruction = TransactionInstruction {
+            kind: TransactionInstructionKind::Deposit,
+            client: AccountId::Number(1),
+            tx: TransactionId(1),
+            amount: Some(Amount::from(1)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        };
+
+        let mut writer = Writer::open(&path).unwrap();
+        writer.append(&instruction).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        let mut writer = Writer::open(&path).unwrap();
+        writer.append(&instruction).unwrap();
+        writer.flush().unwrap();
+
+        let seqs: Vec<u64> = records(fs::File::open(&path).unwrap())
+            .map(|r| r.unwrap().seq)
+            .collect();
+        assert_eq!(seqs, [1, 2]);
+        assert_eq!(
+            records(fs::File::open(&path).unwrap())
+                .next()
+                .unwrap()
+                .unwrap()
+                .instruction,
+            instruction
+        );
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log.starts_with("{\"version\":1}\n{\"seq\":1,"));
+        assert_eq!(log.matches("version").count(), 1);
+
+        // Logs from before the version line are still read, but not ones from a later version.
+        let legacy = log.split_once('\n').unwrap().1;
+        assert_eq!(records(legacy.as_bytes()).count(), 2);
+        let later = log.replacen("\"version\":1", "\"version\":2", 1);
+        assert!(matches!(
+            records(later.as_bytes()).next(),
+            Some(Err(Error::UnsupportedVersion(2)))
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
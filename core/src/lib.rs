@@ -0,0 +1,47 @@
+//! The transactomatic engine: a [Bank](bank/struct.Bank.html) that applies deposits, withdrawals, disputes, resolves,
+//! and chargebacks to client accounts.
+//!
+//! This crate has no opinion about where instructions come from or where accounts go.  It doesn't read files, parse
+//! command lines, or install a logger; it only emits [tracing](https://docs.rs/tracing) events, which go nowhere unless
+//! the embedding program installs a subscriber.  The `transactomatic` crate is the command line application and the
+//! other frontends built on top of it.
+//!
+//! The types most embedders need are re-exported at the top level:
+//!
+//! ```
+//! use transactomatic_core::{Bank, AccountId, Amount, TransactionId, TransactionInstruction, TransactionInstructionKind};
+//!
+//! let mut bank = Bank::new();
+//! let account = bank
+//!     .perform_transaction(TransactionInstruction {
+//!         kind: TransactionInstructionKind::Deposit,
+//!         client: AccountId::Number(1),
+//!         tx: TransactionId(1),
+//!         amount: Some(Amount::from(5)),
+//!         correlation_id: None,
+//!         operator_reference: None,
+//!         timestamp: None,
+//!     })
+//!     .unwrap();
+//! assert_eq!(account.available, Amount::from(5));
+//! ```
+//!
+//! # Features
+//!
+//! - `csv`: parse instructions from lines of CSV input with `str::parse`.
+//! - `proptest`: [proptest](https://docs.rs/proptest) strategies for instructions and histories, in
+//!   [`bank::arbitrary`](bank/arbitrary/index.html).
+//! - `arbitrary`: `arbitrary::Arbitrary` for instructions, for fuzzing.
+//! - `fxhash`: use `FxHash` for the bank's maps.
+//! - `fixed-point`: use a fixed-point `i64` for amounts instead of `rust_decimal`.
+
+#![warn(clippy::all, rust_2018_idioms, clippy::pedantic)]
+
+pub mod bank;
+
+pub use bank::account::{Account, AccountId, AccountSummary};
+pub use bank::amount::Amount;
+pub use bank::event::{Event, Observer};
+pub use bank::transaction::instruction::{TransactionInstruction, TransactionInstructionKind};
+pub use bank::transaction::{Error, Transaction, TransactionId};
+pub use bank::Bank;
@@ -0,0 +1,114 @@
+/*
+ * C interface to the transactomatic bank engine.
+ *
+ * Link against libtransactomatic_ffi, built with `cargo build --release -p transactomatic-ffi`.
+ *
+ *     TmBank *bank = tm_bank_new();
+ *     TmInstruction deposit = {TM_KIND_DEPOSIT, 1, 1, "2.5"};
+ *     TmStatus status = tm_bank_apply(bank, &deposit);
+ *     if (status != TM_OK) fprintf(stderr, "%s\n", tm_status_message(status));
+ *     TmBalances balances;
+ *     if (tm_bank_balances(bank, 1, &balances) == TM_OK) printf("%s\n", balances.available);
+ *     tm_bank_free(bank);
+ *
+ * Amounts are NUL-terminated decimal strings.  Clients are numbered; alphanumeric client codes can be used through
+ * tm_bank_apply_csv, but their balances can't be read here.  A bank isn't thread-safe; serialize calls on one bank.
+ */
+
+#ifndef TRANSACTOMATIC_H
+#define TRANSACTOMATIC_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Size of the amount buffers in TmBalances, including the terminating NUL. */
+#define TM_AMOUNT_LEN 40
+
+#define TM_KIND_DEPOSIT 0
+#define TM_KIND_WITHDRAWAL 1
+#define TM_KIND_DISPUTE 2
+#define TM_KIND_RESOLVE 3
+#define TM_KIND_CHARGEBACK 4
+
+/* Zero on success, positive when the bank rejects an instruction, negative when the call is invalid. */
+typedef enum TmStatus {
+    TM_OK = 0,
+    TM_INSUFFICIENT_FUNDS = 1,
+    TM_ACCOUNT_FROZEN = 2,
+    TM_NEGATIVE_AMOUNT = 3,
+    TM_DUPLICATE_TRANSACTION = 4,
+    TM_TRANSACTION_NOT_FOUND = 5,
+    TM_CLIENT_MISMATCH = 6,
+    TM_NOT_DISPUTED = 7,
+    TM_REJECTED_BY_HOOK = 8,
+    TM_TRANSACTION_RETIRED = 9,
+    /* The client has no account. */
+    TM_NO_ACCOUNT = 10,
+    /* A deposit or withdrawal had no amount. */
+    TM_MISSING_AMOUNT = 11,
+    /* The account doesn't allow withdrawals. */
+    TM_WITHDRAWAL_NOT_ALLOWED = 12,
+    /* The withdrawal would have left less than the minimum balance. */
+    TM_BELOW_MINIMUM_BALANCE = 13,
+    /* A reinstatement had no operator reference. */
+    TM_MISSING_OPERATOR_REFERENCE = 14,
+    /* A reinstatement referenced a transaction that wasn't charged back. */
+    TM_NOT_CHARGED_BACK = 15,
+    /* The instruction would have taken a balance out of range. */
+    TM_AMOUNT_OVERFLOW = 16,
+    /* A required pointer was null. */
+    TM_NULL_POINTER = -1,
+    /* An unknown kind, a malformed amount, or an invalid CSV line. */
+    TM_INVALID_INSTRUCTION = -2,
+    /* The engine panicked.  The bank should not be used again. */
+    TM_PANIC = -3,
+} TmStatus;
+
+/* An instruction, with the same fields as the CSV input. */
+typedef struct TmInstruction {
+    /* One of the TM_KIND_* constants. */
+    uint32_t kind;
+    uint16_t client;
+    uint64_t tx;
+    /* A decimal string such as "1.5", or NULL for instructions without an amount. */
+    const char *amount;
+} TmInstruction;
+
+/* An account's balances, formatted to four decimal places like the account report. */
+typedef struct TmBalances {
+    uint16_t client;
+    char available[TM_AMOUNT_LEN];
+    char held[TM_AMOUNT_LEN];
+    char total[TM_AMOUNT_LEN];
+    bool locked;
+} TmBalances;
+
+typedef struct TmBank TmBank;
+
+/* Create an empty bank.  Release it with tm_bank_free. */
+TmBank *tm_bank_new(void);
+
+/* Release a bank.  Does nothing if bank is NULL. */
+void tm_bank_free(TmBank *bank);
+
+/* Apply an instruction. */
+TmStatus tm_bank_apply(TmBank *bank, const TmInstruction *instruction);
+
+/* Apply one line of CSV input without a header, like "deposit, 1, 1, 2.5". */
+TmStatus tm_bank_apply_csv(TmBank *bank, const char *line);
+
+/* Read a client's balances into out.  Returns TM_NO_ACCOUNT if the client has no account. */
+TmStatus tm_bank_balances(const TmBank *bank, uint16_t client, TmBalances *out);
+
+/* A static description of status.  Unknown values are described as such. */
+const char *tm_status_message(int32_t status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TRANSACTOMATIC_H */
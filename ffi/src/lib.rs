@@ -0,0 +1,372 @@
+//! A C interface to the transactomatic [Bank](../transactomatic_core/bank/struct.Bank.html), so other languages can embed
+//! the same engine.  The functions here are declared in `include/transactomatic.h`.
+//!
+//! A bank is an opaque pointer from `tm_bank_new` that must be released with `tm_bank_free`.  Instructions are applied
+//! from a `TmInstruction` or a line of CSV input, and balances are read back into a `TmBalances`.  Amounts cross the
+//! boundary as decimal strings so that no precision is lost.
+//!
+//! Every function returns a `TmStatus`: zero on success, a positive code when the bank rejects an instruction, and a
+//! negative code when the call itself is invalid.  `tm_status_message` describes a status.  A bank isn't thread-safe;
+//! callers sharing one between threads must serialize calls.
+
+#![warn(clippy::all, rust_2018_idioms, clippy::pedantic)]
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use transactomatic_core::bank::account::{Account, AccountId};
+use transactomatic_core::bank::amount::Amount;
+use transactomatic_core::bank::transaction::instruction::{
+    TransactionInstruction, TransactionInstructionKind,
+};
+use transactomatic_core::bank::transaction::{Error, TransactionId};
+use transactomatic_core::bank::Bank;
+
+/// Size of the amount buffers in `TmBalances`, including the terminating NUL.
+pub const TM_AMOUNT_LEN: usize = 40;
+
+/// The result of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TmStatus {
+    Ok = 0,
+    InsufficientFunds = 1,
+    AccountFrozen = 2,
+    NegativeAmount = 3,
+    DuplicateTransaction = 4,
+    TransactionNotFound = 5,
+    ClientMismatch = 6,
+    NotDisputed = 7,
+    RejectedByHook = 8,
+    TransactionRetired = 9,
+    /// The client has no account.
+    NoAccount = 10,
+    /// A deposit or withdrawal had no amount.
+    MissingAmount = 11,
+    /// The account doesn't allow withdrawals.
+    WithdrawalNotAllowed = 12,
+    /// The withdrawal would have left less than the minimum balance.
+    BelowMinimumBalance = 13,
+    /// A reinstatement had no operator reference.
+    MissingOperatorReference = 14,
+    /// A reinstatement referenced a transaction that wasn't charged back.
+    NotChargedBack = 15,
+    /// The instruction would have taken a balance out of range.
+    AmountOverflow = 16,
+    /// A required pointer was null.
+    NullPointer = -1,
+    /// The instruction couldn't be parsed: an unknown kind, a malformed amount, or an invalid CSV line.
+    InvalidInstruction = -2,
+    /// The engine panicked.  The bank should not be used again.
+    Panic = -3,
+}
+
+/// An instruction, with the same fields as the CSV input.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TmInstruction {
+    /// One of the `TM_KIND_*` constants.
+    pub kind: u32,
+    pub client: u16,
+    pub tx: u64,
+    /// A NUL-terminated decimal string such as `"1.5"`, or null for instructions without an amount.
+    pub amount: *const c_char,
+}
+
+pub const TM_KIND_DEPOSIT: u32 = 0;
+pub const TM_KIND_WITHDRAWAL: u32 = 1;
+pub const TM_KIND_DISPUTE: u32 = 2;
+pub const TM_KIND_RESOLVE: u32 = 3;
+pub const TM_KIND_CHARGEBACK: u32 = 4;
+
+/// An account's balances, in the same form as the account report.  Amounts are NUL-terminated decimal strings.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TmBalances {
+    pub client: u16,
+    pub available: [c_char; TM_AMOUNT_LEN],
+    pub held: [c_char; TM_AMOUNT_LEN],
+    pub total: [c_char; TM_AMOUNT_LEN],
+    pub locked: bool,
+}
+
+/// A bank, opaque to C.
+pub struct TmBank(Bank);
+
+impl From<Error> for TmStatus {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::InsufficientFunds => TmStatus::InsufficientFunds,
+            Error::AccountFrozen => TmStatus::AccountFrozen,
+            Error::NegativeAmount => TmStatus::NegativeAmount,
+            Error::DuplicateTransaction => TmStatus::DuplicateTransaction,
+            Error::TransactionNotFound => TmStatus::TransactionNotFound,
+            Error::ClientMismatch => TmStatus::ClientMismatch,
+            Error::NotDisputed => TmStatus::NotDisputed,
+            Error::RejectedByHook => TmStatus::RejectedByHook,
+            Error::TransactionRetired => TmStatus::TransactionRetired,
+            Error::MissingAmount => TmStatus::MissingAmount,
+            Error::WithdrawalNotAllowed => TmStatus::WithdrawalNotAllowed,
+            Error::BelowMinimumBalance => TmStatus::BelowMinimumBalance,
+            Error::MissingOperatorReference => TmStatus::MissingOperatorReference,
+            Error::NotChargedBack => TmStatus::NotChargedBack,
+            Error::AmountOverflow => TmStatus::AmountOverflow,
+        }
+    }
+}
+
+/// Create an empty bank.  Release it with `tm_bank_free`.
+#[no_mangle]
+pub extern "C" fn tm_bank_new() -> *mut TmBank {
+    Box::into_raw(Box::new(TmBank(Bank::new())))
+}
+
+/// Release a bank.  Does nothing if `bank` is null.
+///
+/// # Safety
+///
+/// `bank` must be null or a pointer from `tm_bank_new` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tm_bank_free(bank: *mut TmBank) {
+    if !bank.is_null() {
+        drop(Box::from_raw(bank));
+    }
+}
+
+/// Apply an instruction.
+///
+/// # Safety
+///
+/// `bank` must be a live pointer from `tm_bank_new`.  `instruction` must point to a `TmInstruction` whose `amount` is
+/// null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tm_bank_apply(
+    bank: *mut TmBank,
+    instruction: *const TmInstruction,
+) -> TmStatus {
+    let (Some(bank), Some(instruction)) = (bank.as_mut(), instruction.as_ref()) else {
+        return TmStatus::NullPointer;
+    };
+    let amount = if instruction.amount.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(instruction.amount).to_str().map(str::parse) {
+            Ok(Ok(amount)) => Some(amount),
+            _ => return TmStatus::InvalidInstruction,
+        }
+    };
+    let Some(kind) = kind(instruction.kind) else {
+        return TmStatus::InvalidInstruction;
+    };
+    perform(
+        bank,
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(instruction.client),
+            tx: TransactionId(instruction.tx),
+            amount,
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        },
+    )
+}
+
+/// Apply one line of CSV input without a header, like `"deposit, 1, 1, 2.5"`.
+///
+/// # Safety
+///
+/// `bank` must be a live pointer from `tm_bank_new` and `line` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tm_bank_apply_csv(bank: *mut TmBank, line: *const c_char) -> TmStatus {
+    let Some(bank) = bank.as_mut() else {
+        return TmStatus::NullPointer;
+    };
+    if line.is_null() {
+        return TmStatus::NullPointer;
+    }
+    match CStr::from_ptr(line).to_str().map(str::parse) {
+        Ok(Ok(ti)) => perform(bank, ti),
+        _ => TmStatus::InvalidInstruction,
+    }
+}
+
+/// Read a client's balances into `out`.  Returns `TmStatus::NoAccount` if the client has no account.
+///
+/// # Safety
+///
+/// `bank` must be a live pointer from `tm_bank_new` and `out` must point to writable memory for a `TmBalances`.
+#[no_mangle]
+pub unsafe extern "C" fn tm_bank_balances(
+    bank: *const TmBank,
+    client: u16,
+    out: *mut TmBalances,
+) -> TmStatus {
+    let Some(bank) = bank.as_ref() else {
+        return TmStatus::NullPointer;
+    };
+    if out.is_null() {
+        return TmStatus::NullPointer;
+    }
+    guard(|| match bank.0.account(&AccountId::Number(client)) {
+        Some(account) => {
+            ptr::write(out, balances(account));
+            TmStatus::Ok
+        }
+        None => TmStatus::NoAccount,
+    })
+}
+
+/// A static, NUL-terminated description of `status`.  Takes the status as a plain integer so that any value from C is
+/// safe to pass.
+#[no_mangle]
+pub extern "C" fn tm_status_message(status: i32) -> *const c_char {
+    let message: &'static [u8] = match status {
+        0 => b"ok\0",
+        1 => b"insufficient funds\0",
+        2 => b"account frozen\0",
+        3 => b"negative amount\0",
+        4 => b"duplicate transaction\0",
+        5 => b"transaction not found\0",
+        6 => b"client mismatch\0",
+        7 => b"transaction not disputed\0",
+        8 => b"rejected by hook\0",
+        9 => b"transaction retired\0",
+        10 => b"no account\0",
+        11 => b"missing amount\0",
+        12 => b"withdrawal not allowed\0",
+        13 => b"below minimum balance\0",
+        14 => b"missing operator reference\0",
+        15 => b"transaction not charged back\0",
+        -1 => b"null pointer\0",
+        -2 => b"invalid instruction\0",
+        -3 => b"internal error\0",
+        _ => b"unknown status\0",
+    };
+    message.as_ptr().cast()
+}
+
+fn kind(kind: u32) -> Option<TransactionInstructionKind> {
+    match kind {
+        TM_KIND_DEPOSIT => Some(TransactionInstructionKind::Deposit),
+        TM_KIND_WITHDRAWAL => Some(TransactionInstructionKind::Withdrawal),
+        TM_KIND_DISPUTE => Some(TransactionInstructionKind::Dispute),
+        TM_KIND_RESOLVE => Some(TransactionInstructionKind::Resolve),
+        TM_KIND_CHARGEBACK => Some(TransactionInstructionKind::Chargeback),
+        _ => None,
+    }
+}
+
+fn perform(bank: &mut TmBank, ti: TransactionInstruction) -> TmStatus {
+    guard(|| match bank.0.perform_transaction(ti) {
+        Ok(_) => TmStatus::Ok,
+        Err(err) => err.into(),
+    })
+}
+
+/// Run `f`, turning a panic into `TmStatus::Panic` instead of unwinding into C.
+fn guard<F: FnOnce() -> TmStatus>(f: F) -> TmStatus {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(TmStatus::Panic)
+}
+
+fn balances(account: &Account) -> TmBalances {
+    TmBalances {
+        // Accounts are only looked up by number here.
+        client: account.client.number().unwrap_or_default(),
+        available: amount(account.available),
+        held: amount(account.held),
+        total: amount(account.total()),
+        locked: account.locked,
+    }
+}
+
+/// Format an amount to four decimal places, like the account report, as a NUL-terminated string.
+fn amount(mut amount: Amount) -> [c_char; TM_AMOUNT_LEN] {
+    amount.rescale(4);
+    let mut buffer = [0; TM_AMOUNT_LEN];
+    // An amount is at most 31 characters, so there is always room for the NUL.
+    for (slot, byte) in buffer
+        .iter_mut()
+        .zip(amount.to_string().bytes().take(TM_AMOUNT_LEN - 1))
+    {
+        *slot = c_char::from_ne_bytes([byte]);
+    }
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    fn text(buffer: &[c_char; TM_AMOUNT_LEN]) -> &str {
+        unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_str().unwrap()
+    }
+
+    #[test]
+    fn apply_and_query() {
+        unsafe {
+            let bank = tm_bank_new();
+            let amount = CString::new("2.5").unwrap();
+            let mut instruction = TmInstruction {
+                kind: TM_KIND_DEPOSIT,
+                client: 1,
+                tx: 1,
+                amount: amount.as_ptr(),
+            };
+            assert_eq!(tm_bank_apply(bank, &raw const instruction), TmStatus::Ok);
+
+            let line = CString::new("withdrawal, 1, 2, 5").unwrap();
+            assert_eq!(
+                tm_bank_apply_csv(bank, line.as_ptr()),
+                TmStatus::InsufficientFunds
+            );
+            let line = CString::new("withdrawal,1,two,5").unwrap();
+            assert_eq!(
+                tm_bank_apply_csv(bank, line.as_ptr()),
+                TmStatus::InvalidInstruction
+            );
+
+            instruction.kind = TM_KIND_DISPUTE;
+            instruction.amount = ptr::null();
+            assert_eq!(tm_bank_apply(bank, &raw const instruction), TmStatus::Ok);
+            instruction.kind = TM_KIND_WITHDRAWAL;
+            assert_eq!(
+                tm_bank_apply(bank, &raw const instruction),
+                TmStatus::MissingAmount
+            );
+            instruction.kind = 9;
+            assert_eq!(
+                tm_bank_apply(bank, &raw const instruction),
+                TmStatus::InvalidInstruction
+            );
+
+            let mut balances = std::mem::MaybeUninit::<TmBalances>::uninit();
+            assert_eq!(
+                tm_bank_balances(bank, 1, balances.as_mut_ptr()),
+                TmStatus::Ok
+            );
+            let balances = balances.assume_init();
+            assert_eq!(text(&balances.available), "0.0000");
+            assert_eq!(text(&balances.held), "2.5000");
+            assert_eq!(text(&balances.total), "2.5000");
+            assert!(!balances.locked);
+
+            let mut other = std::mem::MaybeUninit::<TmBalances>::uninit();
+            assert_eq!(
+                tm_bank_balances(bank, 2, other.as_mut_ptr()),
+                TmStatus::NoAccount
+            );
+            assert_eq!(
+                tm_bank_apply(ptr::null_mut(), &raw const instruction),
+                TmStatus::NullPointer
+            );
+            assert_eq!(
+                CStr::from_ptr(tm_status_message(TmStatus::InsufficientFunds as i32)),
+                CStr::from_bytes_with_nul(b"insufficient funds\0").unwrap()
+            );
+            tm_bank_free(bank);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Arbitrary instruction sequences applied to a bank, checking its invariants after every instruction.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactomatic_core::{Bank, TransactionInstruction};
+
+fuzz_target!(|history: Vec<TransactionInstruction>| {
+    let mut bank = Bank::new();
+    bank.enable_invariant_checks();
+    for ti in history {
+        let _ = bank.perform_transaction(ti);
+    }
+});
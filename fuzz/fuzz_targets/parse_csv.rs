@@ -0,0 +1,14 @@
+//! Arbitrary bytes as CSV input, applied to a bank the way the command line application does.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactomatic::cli;
+use transactomatic_core::Bank;
+
+fuzz_target!(|data: &[u8]| {
+    let mut bank = Bank::new();
+    for ti in cli::instructions(data).flatten() {
+        let _ = bank.perform_transaction(ti);
+    }
+});
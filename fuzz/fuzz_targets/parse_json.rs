@@ -0,0 +1,15 @@
+//! Arbitrary bytes as JSON Lines instructions, as read by the stream and HTTP frontends, applied to a bank.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactomatic_core::{Bank, TransactionInstruction};
+
+fuzz_target!(|data: &[u8]| {
+    let mut bank = Bank::new();
+    for line in data.split(|&b| b == b'\n') {
+        if let Ok(ti) = serde_json::from_slice::<TransactionInstruction>(line) {
+            let _ = bank.perform_transaction(ti);
+        }
+    }
+});
@@ -0,0 +1,148 @@
+//! This module contains alerts: what the [fraud](../fraud/index.html) and [anomaly](../anomaly/index.html) screens
+//! raise for a person to look at, and where they're sent.
+//!
+//! An [`AlertSink`](trait.AlertSink.html) receives every alert as it's raised, so alerts can be routed to a pager or a
+//! chat channel by implementing one method, without touching the screens.  Any closure taking an `&Alert` is a sink.
+//! Two sinks are built in:
+//!
+//! | Sink | Sends |
+//! |---|---|
+//! | [`Stderr`](struct.Stderr.html) | Each alert as a line of JSON on standard error. |
+//! | [`Notifier`](../webhook/struct.Notifier.html) | Each alert to the webhooks with the `alert` trigger, with the `webhooks` feature. |
+//!
+//! Sinks are called from the observer that raised the alert, inside the bank, so like observers they should be quick,
+//! and hand anything slow to another thread the way the webhook notifier does.
+
+use crate::bank::account::AccountId;
+use crate::bank::amount::Amount;
+use crate::bank::transaction::TransactionId;
+use crate::fraud::Rule;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Which screen raised an alert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Fraud,
+    Anomaly,
+}
+
+/// Something a screen noticed about a client's transaction.
+///
+/// Serializes as e.g. `{"source":"fraud","client":1,"tx":7,"rule":"chargebacks","detail":"2 chargebacks"}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub source: Source,
+    pub client: AccountId,
+    pub tx: TransactionId,
+    /// The fraud rule that was broken, for fraud alerts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<Rule>,
+    /// A human-readable explanation.
+    pub detail: String,
+}
+
+/// `amount` as alert details show it: to the four decimal places of the account report, whichever amount type the
+/// bank uses.
+pub(crate) fn detail_amount(mut amount: Amount) -> Amount {
+    amount.rescale(4);
+    amount
+}
+
+/// Receives alerts.
+pub trait AlertSink: Send + Sync {
+    fn alert(&self, alert: &Alert);
+}
+
+impl<F: Fn(&Alert) + Send + Sync> AlertSink for F {
+    fn alert(&self, alert: &Alert) {
+        self(alert);
+    }
+}
+
+/// Writes each alert as a line of JSON on standard error.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stderr;
+
+impl AlertSink for Stderr {
+    fn alert(&self, alert: &Alert) {
+        match serde_json::to_string(alert) {
+            Ok(line) => {
+                let _ = writeln!(std::io::stderr().lock(), "{line}");
+            }
+            Err(err) => tracing::error!(%err, ?alert, "can't serialize alert"),
+        }
+    }
+}
+
+/// The sinks a screen sends its alerts to.
+#[derive(Clone, Default)]
+pub struct Sinks(Vec<Arc<dyn AlertSink>>);
+
+impl Sinks {
+    /// Also send alerts to `sink`.
+    pub fn add(&mut self, sink: Arc<dyn AlertSink>) {
+        self.0.push(sink);
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn alert(&self, alert: &Alert) {
+        for sink in &self.0 {
+            sink.alert(alert);
+        }
+    }
+}
+
+impl std::fmt::Debug for Sinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sinks({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anomaly::Detector;
+    use crate::bank::Bank;
+    use crate::fraud::{Rules, Screener};
+    use std::sync::Mutex;
+
+    #[test]
+    fn screens_alert_sinks() {
+        let alerts = Arc::new(Mutex::new(vec![]));
+        let mut sinks = Sinks::default();
+        let received = Arc::clone(&alerts);
+        sinks.add(Arc::new(move |alert: &Alert| {
+            received.lock().unwrap().push(alert.clone());
+        }));
+        let mut bank = Bank::new();
+        Screener::with_sinks(Rules::default(), sinks.clone()).install(&mut bank);
+        Detector::with_sinks(3.0, sinks).install(&mut bank);
+        let mut tx = 0;
+        let mut perform = |kind: &str, amount: &str| {
+            tx += 1;
+            bank.perform_transaction(format!("{kind},1,{tx},{amount}").parse().unwrap())
+                .unwrap();
+        };
+        for amount in ["10", "11", "9", "10", "12"] {
+            perform("deposit", amount);
+        }
+        perform("deposit", "500");
+        perform("withdrawal", "500");
+
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 2, "{alerts:?}");
+        assert_eq!(alerts[0].source, Source::Anomaly);
+        assert_eq!(alerts[0].tx, TransactionId(6));
+        assert_eq!(
+            serde_json::to_string(&alerts[1]).unwrap(),
+            r#"{"source":"fraud","client":1,"tx":7,"rule":"rapid_withdrawal","detail":"withdrew 500.0000 soon after depositing 10.0000 in tx 4"}"#
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! This module contains statistical screening of transaction amounts on a [Bank](../bank/struct.Bank.html).
+//!
+//! [`Detector::install`](struct.Detector.html#method.install) registers an observer that keeps a running mean and
+//! variance of each client's deposit amounts and, separately, their withdrawal amounts, using Welford's online
+//! algorithm.  A transaction whose amount is more than `sigmas` standard deviations from the client's mean is
+//! reported as an anomaly.  Nothing is blocked.
+//!
+//! A client needs [`MIN_SAMPLES`](constant.MIN_SAMPLES.html) earlier transactions of a kind before its transactions of
+//! that kind are judged, and anomalies are still added to the statistics afterwards.  Memory use is a few numbers per
+//! client, so screening is cheap enough to leave on while replaying long logs.  Anomalies are also raised as
+//! [alerts](../alert/index.html) to the detector's sinks as they're found.
+
+use crate::alert::{self, Alert, Sinks};
+use crate::bank::account::AccountId;
+use crate::bank::amount::{self, Amount};
+use crate::bank::event::Event;
+use crate::bank::transaction::instruction::TransactionInstructionKind;
+use crate::bank::transaction::TransactionId;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Number of a client's earlier deposits (or withdrawals) needed before another one can be an anomaly.
+pub const MIN_SAMPLES: u64 = 5;
+
+/// A transaction whose amount was unusual for the client.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Anomaly {
+    pub client: AccountId,
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub kind: TransactionInstructionKind,
+    pub amount: Amount,
+    /// The client's mean amount for this kind of transaction before this one.
+    pub mean: f64,
+    pub std_dev: f64,
+    /// How many standard deviations the amount was from the mean.
+    pub deviations: f64,
+}
+
+/// Screens the amounts of one or more banks' transactions and collects the anomalies found.
+#[derive(Debug)]
+pub struct Detector {
+    sigmas: f64,
+    sinks: Sinks,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Deposit and withdrawal statistics for each client.
+    clients: HashMap<AccountId, [Stats; 2]>,
+    anomalies: Vec<Anomaly>,
+}
+
+/// Running statistics for one distribution.
+#[derive(Debug, Clone, Copy, Default)]
+struct Stats {
+    count: u64,
+    mean: f64,
+    /// Sum of squared differences from the mean.
+    m2: f64,
+}
+
+impl Stats {
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.count as f64;
+        self.mean += delta / count;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Sample standard deviation.
+    fn std_dev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.count as f64;
+        (self.m2 / (count - 1.0)).sqrt()
+    }
+}
+
+impl Detector {
+    /// A detector that reports amounts more than `sigmas` standard deviations from a client's mean.
+    #[must_use]
+    pub fn new(sigmas: f64) -> Arc<Self> {
+        Self::with_sinks(sigmas, Sinks::default())
+    }
+
+    /// A detector that also sends each anomaly to `sinks` as an alert.
+    #[must_use]
+    pub fn with_sinks(sigmas: f64, sinks: Sinks) -> Arc<Self> {
+        Arc::new(Self {
+            sigmas,
+            sinks,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Screen every deposit and withdrawal `bank` applies from now on.
+    pub fn install(self: &Arc<Self>, bank: &mut Bank) {
+        let detector = Arc::clone(self);
+        bank.register_observer(move |event: &Event| detector.screen(event));
+    }
+
+    /// The anomalies found so far, by client and then in the order they were found.
+    ///
+    /// # Panics
+    ///
+    /// Panics if screening panicked on another thread.
+    #[must_use]
+    pub fn anomalies(&self) -> Vec<Anomaly> {
+        let mut anomalies = self
+            .state
+            .lock()
+            .expect("detector lock poisoned")
+            .anomalies
+            .clone();
+        anomalies.sort_by_key(|anomaly| anomaly.client);
+        anomalies
+    }
+
+    /// Write the anomalies as CSV.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the report can't be written.
+    pub fn write_report<W: io::Write>(&self, output: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(output);
+        for mut anomaly in self.anomalies() {
+            anomaly.amount.rescale(4);
+            anomaly.mean = round(anomaly.mean);
+            anomaly.std_dev = round(anomaly.std_dev);
+            anomaly.deviations = round(anomaly.deviations);
+            writer.serialize(anomaly)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn screen(&self, event: &Event) {
+        let (client, tx, amount, kind) = match *event {
+            Event::DepositApplied { client, tx, amount } => {
+                (client, tx, amount, TransactionInstructionKind::Deposit)
+            }
+            Event::WithdrawalApplied { client, tx, amount } => {
+                (client, tx, amount, TransactionInstructionKind::Withdrawal)
+            }
+            _ => return,
+        };
+        let mut guard = self.state.lock().expect("detector lock poisoned");
+        let State { clients, anomalies } = &mut *guard;
+        let stats = &mut clients.entry(client).or_default()
+            [usize::from(kind == TransactionInstructionKind::Withdrawal)];
+
+        let x = amount::to_f64(amount);
+        let std_dev = stats.std_dev();
+        if stats.count >= MIN_SAMPLES && std_dev > 0.0 {
+            let deviations = (x - stats.mean).abs() / std_dev;
+            if deviations > self.sigmas {
+                tracing::warn!(?client, ?tx, %amount, deviations, "anomalous amount");
+                self.sinks.alert(&Alert {
+                    source: alert::Source::Anomaly,
+                    client,
+                    tx,
+                    rule: None,
+                    detail: format!(
+                        "{} of {} is {:.1} standard deviations from the mean of {:.4}",
+                        crate::cli::kind_name(kind),
+                        alert::detail_amount(amount),
+                        deviations,
+                        stats.mean
+                    ),
+                });
+                anomalies.push(Anomaly {
+                    client,
+                    tx,
+                    kind,
+                    amount,
+                    mean: stats.mean,
+                    std_dev,
+                    deviations,
+                });
+            }
+        }
+        stats.add(x);
+    }
+}
+
+/// Round to four decimal places for the report.
+fn round(x: f64) -> f64 {
+    (x * 10_000.0).round() / 10_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_outliers() {
+        let detector = Detector::new(3.0);
+        let mut bank = Bank::new();
+        detector.install(&mut bank);
+        let mut tx = 0;
+        let mut perform = |kind: &str, client: u16, amount: &str| {
+            tx += 1;
+            bank.perform_transaction(format!("{kind},{client},{tx},{amount}").parse().unwrap())
+                .unwrap();
+        };
+        for amount in ["10", "11", "9", "10", "12"] {
+            perform("deposit", 1, amount);
+        }
+        // Client 2's history is too short to judge.
+        perform("deposit", 2, "1");
+        perform("deposit", 2, "1000");
+        // Within three standard deviations of client 1's deposits, then well outside them.
+        perform("deposit", 1, "13");
+        perform("deposit", 1, "500");
+        // Withdrawals are judged separately, and client 1 hasn't made any.
+        perform("withdrawal", 1, "400");
+
+        let anomalies = detector.anomalies();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].tx, TransactionId(9));
+        assert!((anomalies[0].mean - 65.0 / 6.0).abs() < 1e-9);
+
+        let mut report = vec![];
+        detector.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,tx,type,amount,mean,std_dev,deviations\n\
+             1,9,deposit,500.0000,10.8333,1.472,332.3233\n"
+        );
+    }
+}
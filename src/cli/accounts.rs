@@ -0,0 +1,162 @@
+//! Accounts files: clients' account types and details, loaded into the bank before any instructions are applied.
+//!
+//! An accounts file is a CSV with a `client` column and any of `type`, `credit_limit`, `minimum_balance`, `name`,
+//! `reference`, and `tags`.  The type is `standard` (the default), `credit`, or `escrow`; `credit_limit` is how far
+//! below zero a credit account's available funds can go, zero if left empty.  A minimum balance overrides the bank's.
+//! Tags are separated by `;`.  Clients listed in the file get an account even if no instruction mentions them.
+//!
+//! ```text
+//! client,type,credit_limit,minimum_balance,name,reference,tags
+//! 1,credit,500,,Ada Lovelace,CUST-0001,vip;early-adopter
+//! 2,escrow,,,Charles Babbage,,
+//! 3,,,100,,,
+//! ```
+
+use crate::bank::account::{AccountId, AccountType, Metadata};
+use crate::bank::amount::Amount;
+use crate::bank::shard;
+use crate::bank::Bank;
+use serde::Deserialize;
+use std::io;
+
+/// A client's account as described by an accounts file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub client: AccountId,
+    pub account_type: AccountType,
+    pub minimum_balance: Option<Amount>,
+    pub metadata: Metadata,
+}
+
+/// The `type` column.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Type {
+    Standard,
+    Credit,
+    Escrow,
+}
+
+/// A row of an accounts file.
+#[derive(Debug, Deserialize)]
+struct Row {
+    client: AccountId,
+    #[serde(rename = "type", default)]
+    kind: Option<Type>,
+    #[serde(default)]
+    credit_limit: Option<Amount>,
+    #[serde(default)]
+    minimum_balance: Option<Amount>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    reference: Option<String>,
+    #[serde(default)]
+    tags: Option<String>,
+}
+
+impl From<Row> for Entry {
+    fn from(row: Row) -> Self {
+        let account_type = match row.kind {
+            None | Some(Type::Standard) => AccountType::Standard,
+            Some(Type::Credit) => AccountType::Credit {
+                limit: row.credit_limit.unwrap_or_default(),
+            },
+            Some(Type::Escrow) => AccountType::Escrow,
+        };
+        let tags = row.tags.as_deref().map_or_else(Vec::new, |tags| {
+            tags.split(';')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(String::from)
+                .collect()
+        });
+        Self {
+            client: row.client,
+            account_type,
+            minimum_balance: row.minimum_balance,
+            metadata: Metadata {
+                name: row.name,
+                reference: row.reference,
+                tags,
+            },
+        }
+    }
+}
+
+/// Read an accounts file.
+///
+/// # Errors
+///
+/// Will return `Err` if the input can't be read or parsed.
+pub fn read<R: io::Read>(input: R) -> Result<Vec<Entry>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input)
+        .deserialize()
+        .map(|row: Result<Row, _>| row.map(Entry::from))
+        .collect()
+}
+
+/// Set up the accounts of the clients that `shard` of `shards` owns.
+pub fn seed(bank: &mut Bank, accounts: &[Entry], shard: usize, shards: usize) {
+    for entry in accounts {
+        if shard::shard_for(entry.client, shards) == shard {
+            bank.set_account_type(entry.client, entry.account_type);
+            bank.set_account_minimum_balance(entry.client, entry.minimum_balance);
+            bank.set_metadata(entry.client, entry.metadata.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::transaction::Error;
+
+    #[test]
+    fn reads_and_seeds() {
+        let accounts = read(
+            "client, type, credit_limit, minimum_balance, name, reference, tags
+             1, credit, 50, -20, Ada, CUST-1, vip; early
+             2,,,,,,
+             3, escrow,,, Charles,,
+             5, credit,,,,,
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(accounts.len(), 4);
+        assert_eq!(accounts[0].metadata.tags, ["vip", "early"]);
+        assert!(accounts[1].metadata.is_empty());
+        assert_eq!(accounts[1].account_type, AccountType::Standard);
+        assert_eq!(
+            accounts[3].account_type,
+            AccountType::Credit {
+                limit: Amount::from(0)
+            }
+        );
+
+        let mut bank = Bank::new();
+        seed(&mut bank, &accounts, 1, 2);
+        assert_eq!(bank.accounts().count(), 3, "client 2 is on the other shard");
+        let check = |client, amount| {
+            bank.account(&AccountId::Number(client))
+                .unwrap()
+                .check_withdrawal(Amount::from(amount), None)
+        };
+        assert_eq!(check(1, 20), Ok(()));
+        assert_eq!(check(1, 21), Err(Error::BelowMinimumBalance));
+        assert_eq!(check(1, 51), Err(Error::InsufficientFunds));
+        assert_eq!(check(3, 0), Err(Error::WithdrawalNotAllowed));
+        assert_eq!(check(5, 0), Ok(()));
+        assert_eq!(
+            bank.account(&AccountId::Number(1))
+                .unwrap()
+                .metadata
+                .reference
+                .as_deref(),
+            Some("CUST-1")
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Amounts as they are written in the input.
+//!
+//! Amounts are parsed strictly by default: a plain decimal number like `-1234.5`.  Spreadsheets exported by people
+//! tend to format them for reading instead, like `$1,234.50` or `(12.00)` for a negative amount.  Lenient parsing
+//! strips that formatting from the `amount` column before the instruction is deserialized, so the amount then has to
+//! be a plain decimal number like any other.
+//!
+//! European exports write the decimal separator as a comma, like `1,50` or `1.234,50`, which needs the field quoted
+//! in CSV.  The locale says which separator amounts use, or detects it per amount: the last `.` or `,` is the decimal
+//! separator unless it appears more than once.  A lone comma is therefore read as a decimal separator, so `1,500` is
+//! one and a half; set the locale explicitly for inputs with thousands separators and no decimals.
+
+use std::borrow::Cow;
+
+/// How amounts are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    /// Plain decimal numbers only.
+    #[default]
+    Strict,
+    /// Also allow a `$` sign, `,` thousands separators, and negative amounts in parentheses.
+    Lenient,
+}
+
+/// Which decimal separator amounts use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Locale {
+    /// `1234.50`, or `1,234.50` with thousands separators.
+    #[default]
+    Point,
+    /// `1234,50`, or `1.234,50` with thousands separators.
+    Comma,
+    /// Detect the separator of each amount.
+    Auto,
+}
+
+impl Locale {
+    /// The decimal separator of `amount`.
+    fn decimal_separator(self, amount: &str) -> char {
+        match self {
+            Locale::Point => '.',
+            Locale::Comma => ',',
+            Locale::Auto => match amount.rfind(['.', ',']).map(|i| &amount[i..=i]) {
+                Some(",") if amount.matches(',').count() == 1 => ',',
+                Some(".") if amount.matches('.').count() > 1 => ',',
+                _ => '.',
+            },
+        }
+    }
+}
+
+/// How amounts are written: their format and locale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Notation {
+    pub format: Format,
+    pub locale: Locale,
+}
+
+/// `amount` as a plain decimal number, or unchanged if it already is one or if there's nothing to strip or convert
+/// in `notation`.  Anything that still isn't a number is left for deserialization to reject.
+#[must_use]
+pub fn normalize(amount: &str, notation: Notation) -> Cow<'_, str> {
+    let lenient = notation.format == Format::Lenient;
+    let decimal = notation.locale.decimal_separator(amount);
+    if decimal == '.' && !(lenient && amount.contains(['$', ',', '('])) {
+        return Cow::Borrowed(amount);
+    }
+    let thousands = if decimal == '.' { ',' } else { '.' };
+    let mut amount = amount.trim();
+    let negative = match amount
+        .strip_prefix('(')
+        .and_then(|inner| inner.strip_suffix(')'))
+    {
+        Some(inner) if lenient => {
+            amount = inner.trim();
+            true
+        }
+        _ => false,
+    };
+    let mut normalized = String::with_capacity(amount.len() + 1);
+    if negative {
+        normalized.push('-');
+    }
+    for c in amount.chars() {
+        if c == decimal {
+            normalized.push('.');
+        } else if !(lenient && (c == '$' || c == thousands)) {
+            normalized.push(c);
+        }
+    }
+    Cow::Owned(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize_as(amount: &str, format: Format, locale: Locale) -> Cow<'_, str> {
+        normalize(amount, Notation { format, locale })
+    }
+
+    #[test]
+    fn strips_formatting() {
+        for (amount, lenient) in [
+            ("1234.5", "1234.5"),
+            ("$1,234.50", "1234.50"),
+            (" (12.00) ", "-12.00"),
+            ("($1,000)", "-1000"),
+            ("-$3", "-3"),
+            ("(oops", "(oops"),
+        ] {
+            assert_eq!(
+                normalize_as(amount, Format::Lenient, Locale::Point),
+                lenient
+            );
+        }
+        assert_eq!(
+            normalize_as("$1,234.50", Format::Strict, Locale::Point),
+            "$1,234.50"
+        );
+    }
+
+    #[test]
+    fn decimal_commas() {
+        for (amount, locale, format, normalized) in [
+            ("1,50", Locale::Comma, Format::Strict, "1.50"),
+            ("1.234,50", Locale::Comma, Format::Strict, "1.234.50"),
+            ("1.234,50", Locale::Comma, Format::Lenient, "1234.50"),
+            ("1,50", Locale::Auto, Format::Strict, "1.50"),
+            ("1.50", Locale::Auto, Format::Strict, "1.50"),
+            ("1.234,50", Locale::Auto, Format::Lenient, "1234.50"),
+            ("1,234.50", Locale::Auto, Format::Lenient, "1234.50"),
+            ("1.234.567", Locale::Auto, Format::Lenient, "1234567"),
+            ("1,234,567", Locale::Auto, Format::Lenient, "1234567"),
+            ("(1,5)", Locale::Auto, Format::Lenient, "-1.5"),
+        ] {
+            assert_eq!(normalize_as(amount, format, locale), normalized, "{amount}");
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Applied-events files: a JSON Lines stream of every change the bank made, for systems that consume deltas instead of
+//! diffing account reports.
+//!
+//! Each line is an [event](../../bank/event/enum.Event.html) of an instruction that was applied, with its `event` name
+//! and fields, a `seq` number starting at 1, and the account as it was `before` and `after` the instruction.  Amounts
+//! are rescaled to the four decimal places of the account report, whichever amount type the bank uses.  `before`
+//! is `null` when the instruction opened the account, which isn't listed as an event of its own.  Rejected instructions
+//! aren't listed; they're in the rejects file.  Lines are written as instructions are applied, so the file can be
+//! followed while the run goes on.
+
+use crate::bank::account::AccountSummary;
+use crate::bank::event::Event;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A line of an applied-events file.
+#[derive(Debug, Serialize)]
+struct Line {
+    seq: u64,
+    #[serde(flatten)]
+    event: Event,
+    before: Option<AccountSummary>,
+    after: AccountSummary,
+}
+
+/// Writes an applied-events file.
+#[derive(Debug)]
+pub struct Writer {
+    file: io::BufWriter<fs::File>,
+    seq: u64,
+    /// Events of the instruction being applied, collected by an observer on the bank.
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl Writer {
+    /// Create an applied-events file at `path`, replacing any existing one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be created.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: io::BufWriter::new(fs::File::create(path)?),
+            seq: 0,
+            events: Arc::default(),
+        })
+    }
+
+    /// Collect the events of `bank`.
+    pub(super) fn install(&self, bank: &mut Bank) {
+        let events = Arc::clone(&self.events);
+        bank.register_observer(move |event: &Event| {
+            events
+                .lock()
+                .expect("events lock poisoned")
+                .push(event.clone());
+        });
+    }
+
+    /// Write the events of the instruction just applied to `bank`, given the account as it was `before`.
+    pub(super) fn record(&mut self, bank: &Bank, before: Option<AccountSummary>) -> io::Result<()> {
+        let events = std::mem::take(&mut *self.events.lock().expect("events lock poisoned"));
+        for event in &events {
+            if matches!(
+                event,
+                Event::AccountCreated { .. } | Event::InstructionRejected { .. }
+            ) {
+                continue;
+            }
+            let Some(account) = bank.account(&event.client()) else {
+                continue;
+            };
+            self.seq += 1;
+            let line = Line {
+                seq: self.seq,
+                event: event.rescaled(),
+                before,
+                after: AccountSummary::from(account),
+            };
+            serde_json::to_writer(&mut self.file, &line)?;
+            self.file.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Flush the file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
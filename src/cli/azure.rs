@@ -0,0 +1,364 @@
+//! Reading inputs straight from Azure Blob Storage.
+//!
+//! A blob is given either as `az://container/blob`, with the account and its credentials in the connection string in
+//! `AZURE_STORAGE_CONNECTION_STRING`, or as a SAS URL, the blob's URL with a shared access signature in the query,
+//! which carries its own authorization.  Connection strings with an `AccountKey` sign each request with the key, and
+//! ones with a `SharedAccessSignature` use it as a SAS.
+//!
+//! A [`Blob`](struct.Blob.html) streams the blob's body into the CSV reader as it is read, so nothing is copied to local
+//! disk.  Seeking, for slices, checkpoints, and chunks, starts a new ranged `GET` at the new offset, and so does a
+//! request that fails part way through.  Every request is conditional on the `ETag` of the blob that was opened, so a
+//! blob replaced mid-read fails rather than mixing the bytes of two versions.
+
+use super::ranged;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::Write;
+use std::io;
+use url::Url;
+
+/// URL scheme of blobs named by container and blob.
+pub const SCHEME: &str = "az://";
+
+/// Environment variable holding the connection string used for `az://` inputs.
+const CONNECTION_STRING: &str = "AZURE_STORAGE_CONNECTION_STRING";
+
+/// Version of the Blob service REST API that requests are made against.
+const API_VERSION: &str = "2021-08-06";
+
+/// Errors related to opening a blob.
+#[derive(Debug)]
+pub enum Error {
+    /// The input isn't an `az://container/blob` or SAS URL, or the endpoint isn't valid.
+    InvalidUrl(String),
+    /// The connection string is missing or lacks what's needed to reach the account.
+    InvalidConnectionString(String),
+    /// The service couldn't be reached or refused the request.
+    Request(Box<ureq::Error>),
+}
+
+/// An account key, which requests are signed with.
+struct SharedKey {
+    account: String,
+    key: Vec<u8>,
+}
+
+/// Where a blob is and how to authorize requests for it.
+struct Location {
+    /// URL of the blob, including the SAS if there is one.
+    url: Url,
+    shared_key: Option<SharedKey>,
+    /// `ETag` of the blob when it was opened.
+    etag: Option<String>,
+}
+
+/// A blob being read.
+pub struct Blob {
+    reader: ranged::Reader<Location>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "invalid Azure blob URL: {url}"),
+            Error::InvalidConnectionString(reason) => {
+                write!(f, "invalid Azure connection string: {reason}")
+            }
+            Error::Request(err) => write!(f, "Azure request failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUrl(_) | Error::InvalidConnectionString(_) => None,
+            Error::Request(err) => Some(&**err),
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(err: ureq::Error) -> Self {
+        Error::Request(Box::new(err))
+    }
+}
+
+impl std::fmt::Debug for Blob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = self.reader.source();
+        let mut url = location.url.clone();
+        // Leave out the SAS, which is a credential.
+        url.set_query(None);
+        f.debug_struct("Blob")
+            .field("url", &url.as_str())
+            .field("etag", &location.etag)
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether `input` names a blob rather than a local file: an `az://` URL, or an HTTP URL with a shared access
+/// signature.
+#[must_use]
+pub fn is_azure(input: &str) -> bool {
+    input.starts_with(SCHEME) || sas_url(input).is_some()
+}
+
+/// `input` as a URL if it is an HTTP URL with a shared access signature.
+fn sas_url(input: &str) -> Option<Url> {
+    let url = Url::parse(input).ok()?;
+    let http = matches!(url.scheme(), "http" | "https");
+    let signed = url.query_pairs().any(|(name, _)| name == "sig");
+    (http && signed).then_some(url)
+}
+
+/// Split an `az://container/blob` URL into the container and blob name.
+fn parse(url: &str) -> Option<(&str, &str)> {
+    let (container, blob) = url.strip_prefix(SCHEME)?.split_once('/')?;
+    if container.is_empty() || blob.is_empty() {
+        return None;
+    }
+    Some((container, blob))
+}
+
+/// The URL of `blob` in `container`, and how to authorize requests for it, given by `connection_string`.
+fn connect(
+    connection_string: &str,
+    container: &str,
+    blob: &str,
+) -> Result<(Url, Option<SharedKey>), Error> {
+    let setting = |name: &str| {
+        connection_string
+            .split(';')
+            .filter_map(|setting| setting.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    };
+    let invalid = |reason: &str| Error::InvalidConnectionString(reason.to_string());
+    let account = setting("AccountName");
+    let endpoint = match (setting("BlobEndpoint"), account) {
+        (Some(endpoint), _) => endpoint.to_string(),
+        (None, Some(account)) => format!(
+            "{}://{account}.blob.{}",
+            setting("DefaultEndpointsProtocol").unwrap_or("https"),
+            setting("EndpointSuffix").unwrap_or("core.windows.net")
+        ),
+        (None, None) => return Err(invalid("no AccountName or BlobEndpoint")),
+    };
+    let mut url = Url::parse(&endpoint).map_err(|_| Error::InvalidUrl(endpoint.clone()))?;
+    url.path_segments_mut()
+        .map_err(|()| Error::InvalidUrl(endpoint.clone()))?
+        .pop_if_empty()
+        .push(container)
+        .extend(blob.split('/'));
+    if let Some(sas) = setting("SharedAccessSignature") {
+        url.set_query(Some(sas.trim_start_matches('?')));
+        return Ok((url, None));
+    }
+    let (Some(account), Some(key)) = (account, setting("AccountKey")) else {
+        return Err(invalid(
+            "no AccountName and AccountKey, or SharedAccessSignature",
+        ));
+    };
+    let key = base64::engine::general_purpose::STANDARD
+        .decode(key)
+        .map_err(|_| invalid("AccountKey isn't base64"))?;
+    let shared_key = SharedKey {
+        account: account.to_string(),
+        key,
+    };
+    Ok((url, Some(shared_key)))
+}
+
+impl SharedKey {
+    /// The string signed for a request to `url`, which has no query, with no body, an `If-Match` of `if_match`, and
+    /// the `x-ms-` headers in `headers`, sorted by name.
+    fn string_to_sign(
+        &self,
+        method: &str,
+        url: &Url,
+        if_match: &str,
+        headers: &[(&str, &str)],
+    ) -> String {
+        // Content-Encoding, -Language, -Length, -MD5, and -Type, Date, and If-Modified-Since are all empty.
+        let mut signed = format!("{method}\n{}{if_match}\n", "\n".repeat(7));
+        // If-None-Match, If-Unmodified-Since, and Range are also empty; the range is sent as `x-ms-range`.
+        signed.push_str("\n\n\n");
+        for (name, value) in headers {
+            let _ = writeln!(signed, "{name}:{value}");
+        }
+        let _ = write!(signed, "/{}{}", self.account, url.path());
+        signed
+    }
+
+    /// The `Authorization` header for a request that signs as `string_to_sign`.
+    fn authorization(&self, string_to_sign: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(string_to_sign.as_bytes());
+        let signature =
+            base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+        format!("SharedKey {}:{signature}", self.account)
+    }
+}
+
+impl Location {
+    /// A request to the blob, from `offset` if there is one, authorized and conditional on the blob being unchanged.
+    fn request(&self, method: &str, offset: Option<u64>) -> ureq::Request {
+        let date = httpdate::fmt_http_date(std::time::SystemTime::now());
+        let range = offset.map(|offset| format!("bytes={offset}-"));
+        let mut headers = vec![("x-ms-date", date.as_str())];
+        if let Some(range) = &range {
+            headers.push(("x-ms-range", range));
+        }
+        headers.push(("x-ms-version", API_VERSION));
+
+        let mut request = ureq::request_url(method, &self.url);
+        for (name, value) in &headers {
+            request = request.set(name, value);
+        }
+        if let Some(etag) = &self.etag {
+            request = request.set("If-Match", etag);
+        }
+        if let Some(shared_key) = &self.shared_key {
+            let if_match = self.etag.as_deref().unwrap_or_default();
+            let signed = shared_key.string_to_sign(method, &self.url, if_match, &headers);
+            request = request.set("Authorization", &shared_key.authorization(&signed));
+        }
+        request
+    }
+}
+
+impl Blob {
+    /// Open the blob given by `input`, an `az://` or SAS URL, and look up its length.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the URL or connection string is invalid or the blob can't be found.
+    pub fn open(input: &str) -> Result<Self, Error> {
+        let (url, shared_key) = if let Some(url) = sas_url(input) {
+            (url, None)
+        } else {
+            let (container, blob) =
+                parse(input).ok_or_else(|| Error::InvalidUrl(input.to_string()))?;
+            let connection_string = std::env::var(CONNECTION_STRING).map_err(|_| {
+                Error::InvalidConnectionString(format!("{CONNECTION_STRING} isn't set"))
+            })?;
+            connect(&connection_string, container, blob)?
+        };
+        let mut location = Location {
+            url,
+            shared_key,
+            etag: None,
+        };
+        let response = location.request("HEAD", None).call()?;
+        let len = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .unwrap_or_default();
+        location.etag = response.header("ETag").map(str::to_string);
+        Ok(Self {
+            reader: ranged::Reader::new(location, len),
+        })
+    }
+
+    /// Length of the blob in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.reader.len()
+    }
+
+    /// Whether the blob is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ranged::Source for Location {
+    fn read_from(&self, offset: u64) -> io::Result<ranged::Body> {
+        let response = self
+            .request("GET", Some(offset))
+            .call()
+            .map_err(io::Error::other)?;
+        Ok(response.into_reader())
+    }
+}
+
+impl io::Read for Blob {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl io::Seek for Blob {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inputs() {
+        assert_eq!(
+            parse("az://settlements/2024/05/01.csv"),
+            Some(("settlements", "2024/05/01.csv"))
+        );
+        assert!(is_azure("az://settlements/01.csv"));
+        assert!(is_azure(
+            "https://acme.blob.core.windows.net/settlements/01.csv?sv=2021-08-06&sig=abc"
+        ));
+        assert!(!is_azure(
+            "https://acme.blob.core.windows.net/settlements/01.csv"
+        ));
+        assert!(!is_azure("settlements/01.csv"));
+
+        let (url, shared_key) = connect(
+            "DefaultEndpointsProtocol=https;AccountName=acme;AccountKey=a2V5;EndpointSuffix=core.windows.net",
+            "settlements",
+            "2024/05/01 final.csv",
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://acme.blob.core.windows.net/settlements/2024/05/01%20final.csv"
+        );
+        let shared_key = shared_key.unwrap();
+        assert_eq!(shared_key.key, b"key");
+        assert_eq!(
+            shared_key.string_to_sign(
+                "GET",
+                &url,
+                "\"0x1\"",
+                &[
+                    ("x-ms-date", "Wed, 01 May 2024 00:00:00 GMT"),
+                    ("x-ms-version", API_VERSION)
+                ]
+            ),
+            "GET\n\n\n\n\n\n\n\n\"0x1\"\n\n\n\n\
+             x-ms-date:Wed, 01 May 2024 00:00:00 GMT\nx-ms-version:2021-08-06\n\
+             /acme/settlements/2024/05/01%20final.csv"
+        );
+
+        let (url, shared_key) = connect(
+            "BlobEndpoint=http://127.0.0.1:10000/devstoreaccount1;SharedAccessSignature=sv=2021-08-06&sig=abc",
+            "settlements",
+            "01.csv",
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://127.0.0.1:10000/devstoreaccount1/settlements/01.csv?sv=2021-08-06&sig=abc"
+        );
+        assert!(shared_key.is_none());
+        assert!(matches!(
+            connect("AccountName=acme", "settlements", "01.csv"),
+            Err(Error::InvalidConnectionString(_))
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+//! Export of balance history: each client's balances at the end of every period, written when the run finishes.
+
+use crate::bank::account::AccountId;
+use crate::bank::amount::Amount;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::io;
+
+/// A row of the balance history.
+#[derive(Debug, Serialize)]
+struct Row {
+    client: AccountId,
+    /// Start of the period, in seconds since the Unix epoch.
+    period: u64,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+/// Write the bank's balance history as CSV, in client id order and then oldest period first.
+///
+/// # Errors
+///
+/// Will return `Err` if the history can't be written.
+pub fn write<W: io::Write>(bank: &Bank, output: W) -> Result<(), csv::Error> {
+    let mut histories: Vec<_> = bank.balance_histories().collect();
+    histories.sort_unstable_by_key(|(client, _)| *client);
+    let mut writer = csv::Writer::from_writer(output);
+    for (client, history) in histories {
+        for balance in history {
+            writer.serialize(Row {
+                client,
+                period: balance.period,
+                available: balance.available,
+                held: balance.held,
+                total: balance.total,
+                locked: balance.locked,
+            })?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU64;
+
+    #[test]
+    fn writes_history() {
+        let mut bank = Bank::new();
+        bank.set_balance_period(NonZeroU64::new(100));
+        let mut reader = super::super::reader_builder().from_reader(
+            "type, client, tx, amount, correlation_id, operator_reference, timestamp
+deposit, 2, 1, 10,,, 150
+deposit, 1, 2, 5,,, 120
+withdrawal, 2, 3, 4,,, 199
+dispute, 2, 1,,,, 200
+"
+            .as_bytes(),
+        );
+        for ti in reader.deserialize() {
+            bank.perform_transaction(ti.unwrap()).unwrap();
+        }
+
+        let mut output = vec![];
+        write(&bank, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,period,available,held,total,locked
+1,100,5.0000,0.0000,5.0000,false
+2,100,6.0000,0.0000,6.0000,false
+2,200,-4.0000,10.0000,6.0000,false
+"
+        );
+    }
+}
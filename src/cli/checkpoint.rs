@@ -0,0 +1,148 @@
+//! Checkpoints let an interrupted run resume instead of reprocessing its input from the start.
+//!
+//! A checkpoint is a single file in the state directory.  The first line records the input position reached when the
+//! checkpoint was taken and the remainder is a [Bank snapshot](../../bank/snapshot/index.html).  Keeping both in one
+//! file means they can be replaced together atomically: the new checkpoint is written to a temporary file which is
+//! then renamed over the old one.
+
+use crate::bank::Bank;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+const CHECKPOINT_FILE: &str = "checkpoint";
+const CHECKPOINT_TMP_FILE: &str = "checkpoint.tmp";
+
+/// Takes and restores checkpoints in a state directory.
+#[derive(Debug)]
+pub struct Checkpointer {
+    dir: PathBuf,
+    interval: u64,
+    since_last: u64,
+}
+
+/// A restored checkpoint.
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub bank: Bank,
+    /// Input position of the first record not yet applied to `bank`.
+    pub position: csv::Position,
+}
+
+/// Serializable form of `csv::Position`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(super) struct Position {
+    byte: u64,
+    line: u64,
+    record: u64,
+}
+
+impl From<&csv::Position> for Position {
+    fn from(pos: &csv::Position) -> Self {
+        Self {
+            byte: pos.byte(),
+            line: pos.line(),
+            record: pos.record(),
+        }
+    }
+}
+
+impl From<Position> for csv::Position {
+    fn from(pos: Position) -> Self {
+        let mut p = csv::Position::new();
+        p.set_byte(pos.byte)
+            .set_line(pos.line)
+            .set_record(pos.record);
+        p
+    }
+}
+
+impl Checkpointer {
+    /// Create a checkpointer that keeps its state in `dir`, checkpointing every `interval` records.
+    ///
+    /// An interval of 0 disables periodic checkpoints; only the final checkpoint is taken.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `dir` doesn't exist and can't be created.
+    pub fn new<P: Into<PathBuf>>(dir: P, interval: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            interval,
+            since_last: 0,
+        })
+    }
+
+    /// Whether a checkpoint has been taken.
+    #[must_use]
+    pub fn exists(&self) -> bool {
+        self.dir.join(CHECKPOINT_FILE).exists()
+    }
+
+    /// Load the most recent checkpoint, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a checkpoint exists but can't be read.
+    pub fn load(&self) -> Result<Option<Checkpoint>, Box<dyn std::error::Error>> {
+        let file = match fs::File::open(self.dir.join(CHECKPOINT_FILE)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut reader = io::BufReader::new(file);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let position: Position = serde_json::from_str(&line)?;
+        let bank = Bank::load_snapshot(reader)?;
+        Ok(Some(Checkpoint {
+            bank,
+            position: position.into(),
+        }))
+    }
+
+    /// Note that a record has been processed, taking a checkpoint if the interval has been reached.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a checkpoint is due and can't be written.
+    pub fn record_processed(
+        &mut self,
+        bank: &Bank,
+        position: &csv::Position,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.since_last += 1;
+        if self.interval > 0 && self.since_last >= self.interval {
+            self.save(bank, position)?;
+        }
+        Ok(())
+    }
+
+    /// Take a checkpoint now.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the checkpoint can't be written.
+    pub fn save(
+        &mut self,
+        bank: &Bank,
+        position: &csv::Position,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        tracing::debug!(?position, "writing checkpoint");
+        let tmp = self.dir.join(CHECKPOINT_TMP_FILE);
+        let mut writer = io::BufWriter::new(fs::File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, &Position::from(position))?;
+        writer.write_all(b"\n")?;
+        bank.save_snapshot(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        fs::rename(tmp, self.dir.join(CHECKPOINT_FILE))?;
+        self.since_last = 0;
+        Ok(())
+    }
+}
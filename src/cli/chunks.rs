@@ -0,0 +1,289 @@
+//! Chunked deserialization of a single seekable input.
+//!
+//! The input after the header row is split into byte ranges that start and end on record boundaries, found with one
+//! pass over the raw bytes that follows quoting and comment lines without deserializing anything.  A pool of threads
+//! then deserializes whole chunks at once, each with its own CSV reader over its range, and chunks are put back into
+//! input order before they are consumed.  With a sharded bank that means the input is read, parsed, and applied in
+//! parallel while every client's instructions are still applied in input order.
+
+use super::{Record, RecordParser};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Number of parsed chunks that can be waiting to be consumed, per parser thread.
+const QUEUE_DEPTH: usize = 2;
+
+/// Size of each chunk reader's buffer, so that the shared input is locked once per 64 KiB.
+const BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// A byte range of an input holding whole records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Chunk {
+    start: u64,
+    end: u64,
+    /// Line and record number of the first byte.
+    line: u64,
+    record: u64,
+}
+
+/// Where a scan over raw bytes is within the CSV grammar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    RecordStart,
+    FieldStart,
+    Unquoted,
+    Quoted,
+    /// A quote inside a quoted field, which either ends it or escapes another quote.
+    QuoteInQuoted,
+    Comment,
+}
+
+/// Split `input` from `start` to its end into chunks of about `size` bytes that start and end on record boundaries.
+fn split<R: Read + Seek>(
+    input: &mut R,
+    start: &csv::Position,
+    size: u64,
+) -> io::Result<Vec<Chunk>> {
+    input.seek(io::SeekFrom::Start(start.byte()))?;
+    let mut chunks = vec![];
+    let mut chunk = Chunk {
+        start: start.byte(),
+        end: start.byte(),
+        line: start.line(),
+        record: start.record(),
+    };
+    let (mut offset, mut line, mut record) = (start.byte(), start.line(), start.record());
+    let mut state = State::RecordStart;
+    // Chunks only start right after a record's terminator, so that blank and comment lines, and the `\n` of a `\r\n`,
+    // go with the record after them as when reading sequentially.
+    let mut after_record = false;
+    let mut buffer = vec![0; BUFFER_CAPACITY];
+    loop {
+        let n = input.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        for &byte in &buffer[..n] {
+            if state == State::RecordStart {
+                if after_record && offset - chunk.start >= size {
+                    chunk.end = offset;
+                    let next = Chunk {
+                        start: offset,
+                        end: offset,
+                        line,
+                        record,
+                    };
+                    chunks.push(std::mem::replace(&mut chunk, next));
+                }
+                if byte == b'#' {
+                    state = State::Comment;
+                }
+                after_record = false;
+            }
+            let ends_record = matches!(byte, b'\n' | b'\r');
+            state = match (state, byte) {
+                (State::Quoted, b'"') => State::QuoteInQuoted,
+                (State::Quoted, _) | (State::QuoteInQuoted, b'"') => State::Quoted,
+                (State::Comment | State::RecordStart, _) if ends_record => State::RecordStart,
+                (State::Comment, _) => State::Comment,
+                (_, _) if ends_record => {
+                    record += 1;
+                    after_record = true;
+                    State::RecordStart
+                }
+                (State::RecordStart | State::FieldStart, b'"') => State::Quoted,
+                (_, b',') => State::FieldStart,
+                (_, _) => State::Unquoted,
+            };
+            if byte == b'\n' {
+                line += 1;
+            }
+            offset += 1;
+        }
+    }
+    chunk.end = offset;
+    if chunk.end > chunk.start {
+        chunks.push(chunk);
+    }
+    Ok(chunks)
+}
+
+/// Reads one chunk of an input that is shared between threads.
+struct ChunkReader<'a, R> {
+    input: &'a Mutex<R>,
+    offset: u64,
+    end: u64,
+}
+
+impl<R: Read + Seek> Read for ChunkReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = usize::try_from(self.end - self.offset).unwrap_or(usize::MAX);
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(remaining);
+        let mut input = self.input.lock().expect("parser thread panicked");
+        input.seek(io::SeekFrom::Start(self.offset))?;
+        let n = input.read(&mut buf[..len])?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R> Seek for ChunkReader<'_, R> {
+    /// Only used by the CSV reader to go back to the start of the chunk, by offset in the whole input.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        match pos {
+            io::SeekFrom::Start(offset) => {
+                self.offset = offset;
+                Ok(offset)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "chunks can only be seeked from the start",
+            )),
+        }
+    }
+}
+
+/// Deserialize every record of `chunk` with `parser`, along with the position following it.
+fn parse_chunk<R: Read + Seek>(
+    input: &Mutex<R>,
+    chunk: &Chunk,
+    parser: &RecordParser,
+) -> Result<Vec<(Record, csv::Position)>, csv::Error> {
+    let mut reader = super::reader_builder()
+        .has_headers(false)
+        .buffer_capacity(BUFFER_CAPACITY)
+        .from_reader(ChunkReader {
+            input,
+            offset: chunk.start,
+            end: chunk.end,
+        });
+    // Seeking makes the reader's positions those of the whole input rather than the chunk.
+    let mut position = csv::Position::new();
+    position
+        .set_byte(chunk.start)
+        .set_line(chunk.line)
+        .set_record(chunk.record);
+    reader.seek_raw(io::SeekFrom::Start(chunk.start), position)?;
+
+    let mut records = vec![];
+    let mut record = csv::StringRecord::new();
+    loop {
+        let start = reader.position().clone();
+        let parsed = match reader.read_record(&mut record) {
+            Ok(false) => return Ok(records),
+            Ok(true) => parser.parse(&record),
+            Err(err) if err.is_io_error() => return Err(err),
+            Err(err) => Record::unreadable(err, &start),
+        };
+        records.push((parsed, reader.position().clone()));
+    }
+}
+
+/// Call `consume` with every record in `reader` and the position following it, in input order, splitting the rest of
+/// the input into chunks of about `size` bytes and deserializing them with `parser` on `threads` background threads.
+pub(crate) fn read_records<R, F>(
+    reader: &mut csv::Reader<R>,
+    parser: &RecordParser,
+    threads: usize,
+    size: u64,
+    limit: Option<u64>,
+    mut consume: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    R: Read + Seek + Send,
+    F: FnMut(Record, &csv::Position) -> Result<(), Box<dyn std::error::Error>>,
+{
+    let threads = threads.max(1);
+    let start = reader.position().clone();
+    let chunks = split(reader.get_mut(), &start, size.max(1))?;
+    let input = Mutex::new(reader.get_mut());
+    let next_chunk = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(threads * QUEUE_DEPTH);
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (input, chunks, next_chunk) = (&input, &chunks, &next_chunk);
+            scope.spawn(move || {
+                // Chunks are taken in order, so the one to be consumed next is always being parsed.
+                while let Some(chunk) = chunks.get(next_chunk.fetch_add(1, Ordering::Relaxed)) {
+                    let parsed = parse_chunk(input, chunk, parser).map_err(|err| err.to_string());
+                    if sender.send((chunk.start, parsed)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending = BTreeMap::new();
+        let mut next = chunks.iter().map(|chunk| chunk.start);
+        let mut expected = next.next();
+        let mut read = 0;
+        for (start, parsed) in receiver {
+            pending.insert(start, parsed);
+            while let Some(parsed) = expected.and_then(|start| pending.remove(&start)) {
+                expected = next.next();
+                for (record, position) in parsed? {
+                    if limit.is_some_and(|limit| read == limit) {
+                        return Ok(());
+                    }
+                    read += 1;
+                    consume(record, &position)?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    #[test]
+    fn splits_on_record_boundaries() {
+        let mut input = String::from("type,client,tx,amount,note\n");
+        for tx in 0..500 {
+            let end = if tx % 2 == 0 { "\n" } else { "\r\n" };
+            write!(input, "deposit,{},{tx},1.0,\"a\nb,\"\"c\"\"\"{end}", tx % 7).unwrap();
+            if tx % 50 == 0 {
+                input.push_str("# a \"comment\n\n");
+            }
+        }
+        input.push_str("bogus,1,1,1.0,x\n");
+
+        let sequential: Vec<_> = super::super::reader_builder()
+            .from_reader(input.as_bytes())
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                (record[2].to_string(), record.position().unwrap().line())
+            })
+            .collect();
+
+        for size in [1, 100, 1000, 1 << 20] {
+            let mut chunked = vec![];
+            let mut reader = super::super::reader_builder().from_reader(io::Cursor::new(&input));
+            let parser =
+                RecordParser::new(&mut reader, super::super::amounts::Notation::default()).unwrap();
+            read_records(&mut reader, &parser, 3, size, None, |record, _| {
+                let tx = record
+                    .instruction
+                    .map_or_else(|_| "1".to_string(), |ti| ti.tx.0.to_string());
+                chunked.push((tx, record.location.line));
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(chunked, sequential, "chunks of {size} bytes");
+        }
+    }
+}
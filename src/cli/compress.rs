@@ -0,0 +1,122 @@
+//! Compressing the account report and the report files as they're written.
+//!
+//! Reports of millions of accounts are large but compress well, and compressing them as they're written saves reading
+//! them back for a second pass.  The whole output is one gzip member or zstd frame, which `gunzip` and `zstd -d`
+//! decompress as usual.
+
+use std::io;
+
+/// Default zstd level, which is also zstd's own default.
+const ZSTD_LEVEL: i32 = 3;
+
+/// How output is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// Writes to `W`, compressing if asked to.  [`finish`](#method.finish) has to be called to end the compressed stream.
+pub enum Writer<W: io::Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: io::Write> std::fmt::Debug for Writer<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Writer::Plain(_) => f.write_str("Plain"),
+            Writer::Gzip(_) => f.write_str("Gzip"),
+            Writer::Zstd(_) => f.write_str("Zstd"),
+        }
+    }
+}
+
+impl<W: io::Write> Writer<W> {
+    /// Write to `output`, compressed with `compression` if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the compressor can't be set up.
+    pub fn new(output: W, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(match compression {
+            None => Writer::Plain(output),
+            Some(Compression::Gzip) => Writer::Gzip(flate2::write::GzEncoder::new(
+                output,
+                flate2::Compression::default(),
+            )),
+            Some(Compression::Zstd) => Writer::Zstd(zstd::Encoder::new(output, ZSTD_LEVEL)?),
+        })
+    }
+
+    /// End the compressed stream, if any, and flush it, returning the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the end of the stream can't be written.
+    pub fn finish(self) -> io::Result<W> {
+        let mut output = match self {
+            Writer::Plain(output) => output,
+            Writer::Gzip(encoder) => encoder.finish()?,
+            Writer::Zstd(encoder) => encoder.finish()?,
+        };
+        output.flush()?;
+        Ok(output)
+    }
+}
+
+impl<W: io::Write> io::Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Writer::Plain(output) => output.write(buf),
+            Writer::Gzip(encoder) => encoder.write(buf),
+            Writer::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Writer::Plain(output) => output.flush(),
+            Writer::Gzip(encoder) => encoder.flush(),
+            Writer::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn round_trips() {
+        let report =
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n".repeat(100);
+        for compression in [None, Some(Compression::Gzip), Some(Compression::Zstd)] {
+            let mut writer = Writer::new(vec![], compression).unwrap();
+            writer.write_all(report.as_bytes()).unwrap();
+            writer.flush().unwrap();
+            let written = writer.finish().unwrap();
+            let mut read = String::new();
+            match compression {
+                None => read = String::from_utf8(written.clone()).unwrap(),
+                Some(Compression::Gzip) => {
+                    flate2::read::GzDecoder::new(&*written)
+                        .read_to_string(&mut read)
+                        .unwrap();
+                }
+                Some(Compression::Zstd) => {
+                    zstd::Decoder::new(&*written)
+                        .unwrap()
+                        .read_to_string(&mut read)
+                        .unwrap();
+                }
+            }
+            assert_eq!(read, report, "{compression:?}");
+            if compression.is_some() {
+                assert!(written.len() < report.len() / 10, "{:?}", compression);
+            }
+        }
+    }
+}
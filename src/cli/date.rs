@@ -0,0 +1,102 @@
+//! Calendar dates in UTC, for subcommands that work with timestamped instructions.
+//!
+//! Timestamps are seconds since the Unix epoch, so only dates from 1970 on are supported.
+
+/// Seconds in a day.
+pub const DAY: u64 = 86_400;
+
+/// Days from 1970-01-01 to a date, which must not be before it.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The year, month, and day `days` after 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Parse a `YYYY-MM-DD` date into the timestamp of its first second.
+///
+/// # Errors
+///
+/// Will return `Err` if `date` isn't a valid date from 1970-01-01 to 9999-12-31.
+pub fn parse(date: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid date {date:?}, expected YYYY-MM-DD");
+    let mut parts = date.trim().splitn(3, '-');
+    let mut next = || -> Result<u64, String> {
+        parts
+            .next()
+            .and_then(|part| part.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (next()?, next()?, next()?);
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let days = days_from_civil(year, month, day);
+    // Days past the end of the month roll over into the next one.
+    if civil_from_days(days) != (year, month, day) {
+        return Err(invalid());
+    }
+    Ok(days * DAY)
+}
+
+/// The timestamp's date, as `YYYY-MM-DD`.
+pub fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / DAY);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// The timestamp's date and time, as `YYYY-MM-DD HH:MM:SS`.
+pub fn format_time(timestamp: u64) -> String {
+    let seconds = timestamp % DAY;
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        format_date(timestamp),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(parse("1970-01-01"), Ok(0));
+        assert_eq!(parse("2000-03-01"), Ok(951_868_800));
+        assert_eq!(parse("2024-02-29"), Ok(1_709_164_800));
+        assert!(parse("2023-02-29").is_err());
+        assert!(parse("2024-13-01").is_err());
+        assert!(parse("1969-12-31").is_err());
+        assert!(parse("yesterday").is_err());
+
+        assert_eq!(format_date(1_709_164_800), "2024-02-29");
+        assert_eq!(format_time(1_709_164_800 + DAY - 1), "2024-02-29 23:59:59");
+        for days in (0..200_000).step_by(97) {
+            assert_eq!(parse(&format_date(days * DAY)), Ok(days * DAY));
+        }
+    }
+}
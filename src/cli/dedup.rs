@@ -0,0 +1,157 @@
+//! A persistent index of instructions already seen, so that a file delivered twice doesn't apply twice.
+//!
+//! Instructions with a `correlation_id` are recognized by it, as an idempotency key.  Deposits and withdrawals without
+//! one are recognized by client and transaction id.  Other instructions without one refer to an existing transaction
+//! and can legitimately repeat, such as a second dispute after a resolve, so they are never suppressed.
+//!
+//! The index is a JSON Lines file of keys that is read in full when opened and appended to as new keys are seen, so
+//! it carries over from one run to the next.
+
+use crate::bank::account::AccountId;
+use crate::bank::transaction::instruction::{TransactionInstruction, TransactionInstructionKind};
+use crate::bank::transaction::TransactionId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Errors related to reading or writing the index.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Format(serde_json::Error),
+}
+
+/// What an instruction is recognized by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Key {
+    Request(String),
+    Transaction(AccountId, TransactionId),
+}
+
+impl Key {
+    fn of(ti: &TransactionInstruction) -> Option<Self> {
+        if let Some(id) = &ti.correlation_id {
+            return Some(Key::Request(id.clone()));
+        }
+        match ti.kind {
+            TransactionInstructionKind::Deposit | TransactionInstructionKind::Withdrawal => {
+                Some(Key::Transaction(ti.client, ti.tx))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The keys seen so far, and the file they are kept in.
+#[derive(Debug)]
+pub struct Index {
+    seen: HashSet<Key>,
+    writer: io::BufWriter<fs::File>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading or writing dedup index: {err}"),
+            Error::Format(err) => write!(f, "invalid dedup index entry: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Format(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Error::Io(err.into())
+        } else {
+            Error::Format(err)
+        }
+    }
+}
+
+impl Index {
+    /// Open the index at `path`, creating it if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or created, or has an invalid entry.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut seen = HashSet::new();
+        match fs::File::open(path) {
+            Ok(file) => {
+                for line in io::BufReader::new(file).lines() {
+                    let line = line?;
+                    if !line.trim().is_empty() {
+                        seen.insert(serde_json::from_str(&line)?);
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            seen,
+            writer: io::BufWriter::new(file),
+        })
+    }
+
+    /// Number of keys in the index.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Whether the index has no keys.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Record `ti` as seen, returning whether it had already been seen, in this run or an earlier one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a new key can't be written.
+    pub fn check(&mut self, ti: &TransactionInstruction) -> Result<bool, Error> {
+        let Some(key) = Key::of(ti) else {
+            return Ok(false);
+        };
+        if self.seen.contains(&key) {
+            return Ok(true);
+        }
+        serde_json::to_writer(&mut self.writer, &key)?;
+        self.writer.write_all(b"\n")?;
+        self.seen.insert(key);
+        Ok(false)
+    }
+
+    /// Flush new keys to the file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,315 @@
+//! Diagnostics: a readable account of every record that was skipped or rejected, printed for the person running
+//! transactomatic rather than for a log collector.
+//!
+//! Each diagnostic has a severity, a message, and where the record starts.  When the input is a local file the line is
+//! quoted with a caret under the field that caused the problem, in the style of a compiler error:
+//!
+//! ```text
+//! warning: withdrawal rejected for client 2, tx 5: insufficient funds
+//!  --> input.csv:7
+//!   |
+//! 7 | withdrawal,2,5,3.0
+//!   |                ^^^ amount
+//! ```
+//!
+//! Records that couldn't be deserialized are errors; instructions the bank rejected are warnings, since rejecting
+//! them is part of normal processing.
+
+use super::{kind_name, Location};
+use crate::bank::account::AccountId;
+use crate::bank::transaction::{instruction::TransactionInstructionKind, Error, TransactionId};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Seek};
+use std::ops::Range;
+
+/// When to print diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum When {
+    /// When standard error is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    /// The record was skipped.
+    Error,
+    /// The instruction was read but not applied.
+    Warning,
+}
+
+impl Severity {
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    /// ANSI escape for the severity's color.
+    fn style(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+const BOLD: &str = "\x1b[1m";
+const GUTTER: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+/// The input, reopened to quote the lines of records.
+#[derive(Debug)]
+struct Source {
+    name: String,
+    file: io::BufReader<fs::File>,
+}
+
+/// Prints diagnostics.
+#[derive(Debug)]
+pub struct Printer<W = io::Stderr> {
+    out: W,
+    color: bool,
+    source: Option<Source>,
+    headers: csv::StringRecord,
+}
+
+impl<W: io::Write> Printer<W> {
+    /// Print diagnostics to `out`, in color if `color` is true.
+    pub fn new(out: W, color: bool) -> Self {
+        Self {
+            out,
+            color,
+            source: None,
+            headers: csv::StringRecord::new(),
+        }
+    }
+
+    /// Quote the lines of records from `file`, a second handle on the input, called `name` in diagnostics.
+    #[must_use]
+    pub fn with_source(mut self, file: fs::File, name: String) -> Self {
+        self.source = Some(Source {
+            name,
+            file: io::BufReader::new(file),
+        });
+        self
+    }
+
+    /// Stop quoting lines, for when records' locations aren't in the input given to
+    /// [`with_source`](#method.with_source).
+    pub(super) fn forget_source(&mut self) {
+        self.source = None;
+    }
+
+    /// Use `headers`, with the input's columns mapped to instruction fields, to find the field a diagnostic is about.
+    pub(super) fn set_headers(&mut self, headers: &csv::StringRecord) {
+        self.headers = headers.clone();
+    }
+
+    /// Print that the record at `location` was skipped because it couldn't be deserialized.
+    pub(super) fn unreadable(&mut self, location: Location, err: &csv::Error) -> io::Result<()> {
+        let (message, field) = match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => (
+                format!("record skipped: {}", err.kind()),
+                err.field().and_then(|field| usize::try_from(field).ok()),
+            ),
+            _ => (format!("record skipped: {err}"), None),
+        };
+        self.print(Severity::Error, &message, location, field)
+    }
+
+    /// Print that the bank rejected the `kind` instruction at `location` for `client` and `tx`.
+    pub(super) fn rejected(
+        &mut self,
+        location: Location,
+        kind: TransactionInstructionKind,
+        client: AccountId,
+        tx: TransactionId,
+        err: Error,
+    ) -> io::Result<()> {
+        let message = format!(
+            "{} rejected for client {client}, tx {}: {err}",
+            kind_name(kind),
+            tx.0
+        );
+        let field = blamed_field(err).and_then(|name| self.headers.iter().position(|h| h == name));
+        self.print(Severity::Warning, &message, location, field)
+    }
+
+    fn print(
+        &mut self,
+        severity: Severity,
+        message: &str,
+        location: Location,
+        field: Option<usize>,
+    ) -> io::Result<()> {
+        let (severity_style, bold, gutter, reset) = if self.color {
+            (severity.style(), BOLD, GUTTER, RESET)
+        } else {
+            ("", "", "", "")
+        };
+        writeln!(
+            self.out,
+            "{severity_style}{}{reset}{bold}: {message}{reset}",
+            severity.label()
+        )?;
+        let line = match &mut self.source {
+            Some(source) => Some((
+                source.name.clone(),
+                read_line(&mut source.file, location.byte)?,
+            )),
+            None => None,
+        };
+        let Some((name, line)) = line else {
+            writeln!(
+                self.out,
+                " {gutter}-->{reset} line {}, byte {}",
+                location.line, location.byte
+            )?;
+            return Ok(());
+        };
+        let number = location.line.to_string();
+        let pad = " ".repeat(number.len());
+        writeln!(self.out, "{pad}{gutter}-->{reset} {name}:{number}")?;
+        writeln!(self.out, "{pad} {gutter}|{reset}")?;
+        writeln!(self.out, "{gutter}{number} |{reset} {line}")?;
+        let span = field.and_then(|field| fields(&line).into_iter().nth(field));
+        if let Some(span) = span {
+            let name = field
+                .and_then(|field| self.headers.get(field))
+                .unwrap_or_default()
+                .to_string();
+            writeln!(
+                self.out,
+                "{pad} {gutter}|{reset} {}{severity_style}{} {name}{reset}",
+                " ".repeat(line[..span.start].chars().count()),
+                "^".repeat(line[span].chars().count().max(1)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The field an instruction was rejected because of, if it's down to one.
+fn blamed_field(err: Error) -> Option<&'static str> {
+    match err {
+        Error::InsufficientFunds
+        | Error::NegativeAmount
+        | Error::MissingAmount
+        | Error::BelowMinimumBalance
+        | Error::AmountOverflow => Some("amount"),
+        Error::AccountFrozen | Error::ClientMismatch | Error::WithdrawalNotAllowed => {
+            Some("client")
+        }
+        Error::DuplicateTransaction
+        | Error::TransactionNotFound
+        | Error::NotDisputed
+        | Error::TransactionRetired
+        | Error::NotChargedBack => Some("tx"),
+        Error::MissingOperatorReference => Some("operator_reference"),
+        Error::RejectedByHook => None,
+    }
+}
+
+/// The line starting at byte `offset` of `file`, without its line ending.
+fn read_line(file: &mut io::BufReader<fs::File>, offset: u64) -> io::Result<String> {
+    file.seek(io::SeekFrom::Start(offset))?;
+    let mut line = vec![];
+    file.read_until(b'\n', &mut line)?;
+    let line = String::from_utf8_lossy(&line);
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Byte ranges of the fields of a CSV line, without surrounding whitespace.
+fn fields(line: &str) -> Vec<Range<usize>> {
+    let mut fields = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(trimmed(line, start..i));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(trimmed(line, start..line.len()));
+    fields
+}
+
+fn trimmed(line: &str, range: Range<usize>) -> Range<usize> {
+    let field = &line[range.clone()];
+    let start = range.start + (field.len() - field.trim_start().len());
+    let end = range.end - (field.len() - field.trim_end().len());
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn quotes_the_field() {
+        let path = std::env::temp_dir().join(format!(
+            "transactomatic-diagnostics-{}.csv",
+            std::process::id()
+        ));
+        let input =
+            "type,client,tx,amount\ndeposit, 1, 1, 1.0\nwithdrawal,1,2,3.0\ndeposit,1,x,1.0\n";
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let mut printer = Printer::new(vec![], false)
+            .with_source(fs::File::open(&path).unwrap(), "in.csv".into());
+        printer.set_headers(reader.headers().unwrap());
+
+        printer
+            .rejected(
+                Location { line: 3, byte: 41 },
+                TransactionInstructionKind::Withdrawal,
+                AccountId::Number(1),
+                TransactionId(2),
+                Error::InsufficientFunds,
+            )
+            .unwrap();
+        let err = reader
+            .deserialize::<crate::bank::transaction::instruction::TransactionInstruction>()
+            .nth(2)
+            .unwrap()
+            .unwrap_err();
+        printer
+            .unreadable(Location { line: 4, byte: 60 }, &err)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let output = String::from_utf8(printer.out).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next(),
+            Some("warning: withdrawal rejected for client 1, tx 2: insufficient funds")
+        );
+        assert_eq!(lines.next(), Some(" --> in.csv:3"));
+        assert_eq!(lines.next(), Some("  |"));
+        assert_eq!(lines.next(), Some("3 | withdrawal,1,2,3.0"));
+        assert_eq!(lines.next(), Some("  |                ^^^ amount"));
+        assert!(lines.next().unwrap().starts_with("error: record skipped: "));
+        assert_eq!(lines.nth(3), Some("  |           ^ tx"));
+    }
+
+    #[test]
+    fn splits_fields() {
+        let line = r#"deposit, 1 ,"2,3",4"#;
+        let fields: Vec<&str> = fields(line).into_iter().map(|range| &line[range]).collect();
+        assert_eq!(fields, ["deposit", "1", r#""2,3""#, "4"]);
+    }
+}
@@ -0,0 +1,163 @@
+//! Comparison of two account reports.
+//!
+//! Either side can be an account report as written by a normal run or a [Bank snapshot](../../bank/snapshot/index.html).
+//! The output is a CSV with one row per client whose account differs, so validating an engine change doesn't need
+//! ad-hoc scripts.
+
+use crate::bank::amount::Amount;
+use crate::bank::{account::AccountId, account::AccountSummary, Bank};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+
+/// How a client's account differs between the two reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Only in the second report.
+    Added,
+    /// Only in the first report.
+    Removed,
+    /// In both reports with different balances or lock status.
+    Changed,
+}
+
+/// One row of diff output.  Deltas are `after - before`, with a missing account counting as zero.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Difference {
+    pub client: AccountId,
+    pub status: Status,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked_before: Option<bool>,
+    pub locked_after: Option<bool>,
+}
+
+/// Read an account report or a snapshot, whichever `reader` contains.
+///
+/// # Errors
+///
+/// Will return `Err` if the input can't be read or parsed.
+pub fn read_accounts<R: io::Read>(
+    mut reader: R,
+) -> Result<BTreeMap<AccountId, AccountSummary>, Box<dyn std::error::Error>> {
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf)?;
+
+    let accounts = if buf.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        Bank::load_snapshot(buf.as_slice())?
+            .accounts()
+            .map(AccountSummary::from)
+            .collect::<Vec<_>>()
+    } else {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(buf.as_slice())
+            .deserialize()
+            .collect::<Result<Vec<AccountSummary>, _>>()?
+    };
+    Ok(accounts.into_iter().map(|a| (a.client, a)).collect())
+}
+
+/// Compare two sets of accounts, returning differences in client order.
+#[must_use]
+pub fn differences(
+    before: &BTreeMap<AccountId, AccountSummary>,
+    after: &BTreeMap<AccountId, AccountSummary>,
+) -> Vec<Difference> {
+    let mut clients: Vec<&AccountId> = before.keys().chain(after.keys()).collect();
+    clients.sort_unstable();
+    clients.dedup();
+
+    clients
+        .into_iter()
+        .filter_map(|client| {
+            let (b, a) = (before.get(client), after.get(client));
+            let status = match (b, a) {
+                (None, Some(_)) => Status::Added,
+                (Some(_), None) => Status::Removed,
+                (Some(b), Some(a)) if b != a => Status::Changed,
+                _ => return None,
+            };
+            // Rescaled to match the precision of the account report.
+            let field = |f: fn(&AccountSummary) -> Amount| {
+                let mut delta = a.map_or(Amount::from(0), f) - b.map_or(Amount::from(0), f);
+                delta.rescale(4);
+                delta
+            };
+            Some(Difference {
+                client: *client,
+                status,
+                available: field(|s| s.available),
+                held: field(|s| s.held),
+                total: field(|s| s.total),
+                locked_before: b.map(|s| s.locked),
+                locked_after: a.map(|s| s.locked),
+            })
+        })
+        .collect()
+}
+
+/// Compare two reports and write the differences as CSV.  Returns `true` if there were any differences.
+///
+/// # Errors
+///
+/// Will return `Err` if either report can't be read or the output can't be written.
+pub fn diff<R1: io::Read, R2: io::Read, W: io::Write>(
+    before: R1,
+    after: R2,
+    output: W,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let differences = differences(&read_accounts(before)?, &read_accounts(after)?);
+    let mut writer = csv::Writer::from_writer(output);
+    for difference in &differences {
+        writer.serialize(difference)?;
+    }
+    writer.flush()?;
+    Ok(!differences.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = "client,available,held,total,locked
+1,1.5000,0.0000,1.5000,false
+2,2.0000,0.0000,2.0000,false
+3,1.0000,0.0000,1.0000,false
+";
+
+    const AFTER: &str = "client,available,held,total,locked
+3,1.0000,0.0000,1.0000,false
+1,1.0000,0.0000,1.0000,true
+4,4.0000,1.0000,5.0000,false
+";
+
+    #[test]
+    fn reports_differences() {
+        let mut output = vec![];
+        assert!(diff(BEFORE.as_bytes(), AFTER.as_bytes(), &mut output).unwrap());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,status,available,held,total,locked_before,locked_after
+1,changed,-0.5000,0.0000,-0.5000,false,true
+2,removed,-2.0000,0.0000,-2.0000,false,
+4,added,4.0000,1.0000,5.0000,,false
+"
+        );
+    }
+
+    #[test]
+    fn identical_reports() {
+        let mut output = vec![];
+        assert!(!diff(BEFORE.as_bytes(), BEFORE.as_bytes(), &mut output).unwrap());
+    }
+
+    #[test]
+    fn snapshot_input() {
+        let snapshot = r#"{"version":1,"accounts":[{"client":3,"available":"1","held":"0","locked":false}],"transactions":[]}"#;
+        let accounts = read_accounts(snapshot.as_bytes()).unwrap();
+        assert_eq!(accounts[&AccountId::Number(3)].total, Amount::from(1));
+    }
+}
@@ -0,0 +1,152 @@
+//! Encrypting the account report and the report files to age recipients as they're written.
+//!
+//! Recipients are X25519 public keys, like `age1...` from `age-keygen`.  Output encrypted to several recipients can be
+//! decrypted by any one of them, with `age --decrypt -i key.txt`.  Encryption comes after
+//! [compression](../compress/index.html), since encrypted data doesn't compress.
+//!
+//! Encryption needs the `age` feature.  Without it, giving a recipient is an error, so nothing is ever written in the
+//! clear by mistake.
+
+use std::io;
+
+/// A recipient that output is encrypted to.
+#[derive(Clone)]
+pub struct Recipient(#[cfg(feature = "age")] age::x25519::Recipient);
+
+/// Writes to `W`, encrypting if there are recipients.  [`finish`](#method.finish) has to be called to end the
+/// encrypted stream, or it can't be decrypted.
+pub enum Writer<W: io::Write> {
+    Plain(W),
+    #[cfg(feature = "age")]
+    Age(age::stream::StreamWriter<W>),
+}
+
+impl std::fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        #[cfg(feature = "age")]
+        return write!(f, "{}", self.0);
+        #[cfg(not(feature = "age"))]
+        return f.write_str("Recipient");
+    }
+}
+
+impl std::str::FromStr for Recipient {
+    type Err = String;
+
+    #[cfg(feature = "age")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Recipient)
+            .map_err(|err| format!("invalid age recipient: {err}"))
+    }
+
+    #[cfg(not(feature = "age"))]
+    fn from_str(_: &str) -> Result<Self, Self::Err> {
+        Err("encrypting output needs the `age` feature".to_string())
+    }
+}
+
+impl<W: io::Write> std::fmt::Debug for Writer<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Writer::Plain(_) => f.write_str("Plain"),
+            #[cfg(feature = "age")]
+            Writer::Age(_) => f.write_str("Age"),
+        }
+    }
+}
+
+impl<W: io::Write> Writer<W> {
+    /// Write to `output`, encrypted to `recipients` if there are any.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the age header can't be written.
+    pub fn new(output: W, recipients: &[Recipient]) -> io::Result<Self> {
+        if recipients.is_empty() {
+            return Ok(Writer::Plain(output));
+        }
+        #[cfg(feature = "age")]
+        {
+            let recipients = recipients
+                .iter()
+                .map(|recipient| &recipient.0 as &dyn age::Recipient);
+            let encryptor =
+                age::Encryptor::with_recipients(recipients).map_err(io::Error::other)?;
+            Ok(Writer::Age(encryptor.wrap_output(output)?))
+        }
+        #[cfg(not(feature = "age"))]
+        unreachable!("recipients can't be parsed without the age feature")
+    }
+
+    /// End the encrypted stream, if any, and flush it, returning the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the end of the stream can't be written.
+    #[cfg_attr(not(feature = "age"), allow(clippy::infallible_destructuring_match))]
+    pub fn finish(self) -> io::Result<W> {
+        let mut output = match self {
+            Writer::Plain(output) => output,
+            #[cfg(feature = "age")]
+            Writer::Age(writer) => writer.finish()?,
+        };
+        output.flush()?;
+        Ok(output)
+    }
+}
+
+impl<W: io::Write> io::Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Writer::Plain(output) => output.write(buf),
+            #[cfg(feature = "age")]
+            Writer::Age(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Writer::Plain(output) => output.flush(),
+            #[cfg(feature = "age")]
+            Writer::Age(writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "age"))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn decrypts_for_each_recipient() {
+        let identities = [
+            age::x25519::Identity::generate(),
+            age::x25519::Identity::generate(),
+        ];
+        let recipients: Vec<Recipient> = identities
+            .iter()
+            .map(|identity| identity.to_public().to_string().parse().unwrap())
+            .collect();
+        let report = "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n";
+        let mut writer = Writer::new(vec![], &recipients).unwrap();
+        writer.write_all(report.as_bytes()).unwrap();
+        let encrypted = writer.finish().unwrap();
+        assert!(!encrypted
+            .windows(report.len())
+            .any(|window| window == report.as_bytes()));
+
+        for identity in &identities {
+            let decryptor = age::Decryptor::new(&*encrypted).unwrap();
+            let mut decrypted = String::new();
+            decryptor
+                .decrypt(std::iter::once(identity as &dyn age::Identity))
+                .unwrap()
+                .read_to_string(&mut decrypted)
+                .unwrap();
+            assert_eq!(decrypted, report);
+        }
+        assert!("age1bogus".parse::<Recipient>().is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//! Reports of accounts that need someone to look at them, written alongside the account report.
+//!
+//! Accounts can go negative when a deposit is withdrawn and then disputed or charged back, and chargebacks lock
+//! accounts until they are reinstated.  Both reports are CSV in client id order.
+
+use crate::bank::account::{Account, AccountId, AccountSummary};
+use crate::bank::amount::Amount;
+use crate::bank::transaction::TransactionId;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::io;
+
+/// A row of the locked accounts report.
+#[derive(Debug, Serialize)]
+struct LockedAccount {
+    client: AccountId,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    /// The chargeback that locked the account, or empty if it was locked some other way.
+    tx: Option<TransactionId>,
+}
+
+/// The bank's accounts matching `filter`, in client id order.
+fn accounts(bank: &Bank, filter: impl Fn(&Account) -> bool) -> Vec<&Account> {
+    let mut accounts: Vec<_> = bank.accounts().filter(|account| filter(account)).collect();
+    accounts.sort_unstable_by_key(|account| account.client);
+    accounts
+}
+
+/// Write every account whose available or total funds are negative, in the same columns as the account report.
+///
+/// # Errors
+///
+/// Will return `Err` if the report can't be written.
+pub fn write_negative<W: io::Write>(bank: &Bank, output: W) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(output);
+    for account in accounts(bank, |account| {
+        account.available.is_sign_negative() || account.total().is_sign_negative()
+    }) {
+        writer.serialize(AccountSummary::from(account))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write every locked account with the chargeback that locked it.
+///
+/// # Errors
+///
+/// Will return `Err` if the report can't be written.
+pub fn write_locked<W: io::Write>(bank: &Bank, output: W) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(output);
+    for account in accounts(bank, |account| account.locked) {
+        let summary = AccountSummary::from(account);
+        writer.serialize(LockedAccount {
+            client: summary.client,
+            available: summary.available,
+            held: summary.held,
+            total: summary.total,
+            tx: account.locked_by,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_and_locked() {
+        let mut bank = Bank::new();
+        for line in [
+            "deposit,1,1,10",
+            "withdrawal,1,2,8",
+            "dispute,1,1",
+            "deposit,2,3,5",
+            "dispute,2,3",
+            "chargeback,2,3",
+            "deposit,3,4,1",
+        ] {
+            bank.perform_transaction(line.parse().unwrap()).unwrap();
+        }
+
+        let mut negative = vec![];
+        write_negative(&bank, &mut negative).unwrap();
+        assert_eq!(
+            String::from_utf8(negative).unwrap(),
+            "client,available,held,total,locked\n1,-8.0000,10.0000,2.0000,false\n"
+        );
+
+        let mut locked = vec![];
+        write_locked(&bank, &mut locked).unwrap();
+        assert_eq!(
+            String::from_utf8(locked).unwrap(),
+            "client,available,held,total,tx\n2,0.0000,0.0000,0.0000,3\n"
+        );
+    }
+}
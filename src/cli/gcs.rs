@@ -0,0 +1,232 @@
+//! Reading inputs straight from Google Cloud Storage, given as `gs://bucket/object`.
+//!
+//! An [`Object`](struct.Object.html) streams the object's media into the CSV reader as it is read, so nothing is
+//! copied to local disk.  Seeking, for slices, checkpoints, and chunks, starts a new ranged download at the new offset,
+//! and so does a download that fails part way through, which is what lets a multi-gigabyte object be read over a
+//! connection that drops.  Every download is pinned to the generation of the object that was opened, so an object
+//! replaced mid-read fails rather than mixing the bytes of two versions.
+//!
+//! Requests are authorized with the OAuth access token in `GOOGLE_OAUTH_ACCESS_TOKEN`, such as one printed by
+//! `gcloud auth print-access-token`, and are anonymous without it.  `STORAGE_EMULATOR_HOST` points at an emulator
+//! instead of the real service.
+
+use super::ranged;
+use serde::Deserialize;
+use std::io;
+use url::Url;
+
+/// URL scheme of GCS inputs.
+pub const SCHEME: &str = "gs://";
+
+/// Endpoint of the JSON API.
+const ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// Errors related to opening an object.
+#[derive(Debug)]
+pub enum Error {
+    /// The input isn't a `gs://bucket/object` URL, or the endpoint isn't valid.
+    InvalidUrl(String),
+    /// The service couldn't be reached or refused the request.
+    Request(Box<ureq::Error>),
+    /// The object's metadata couldn't be read.
+    Io(io::Error),
+    /// The object's metadata isn't what was expected.
+    Format(serde_json::Error),
+}
+
+/// The fields of an object's metadata that are used.  Both are 64-bit integers, which the JSON API writes as strings.
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    size: String,
+    generation: String,
+}
+
+/// Where an object is and how to authorize requests for it.
+struct Location {
+    /// URL of the object's metadata, which the media is downloaded from with `alt=media`.
+    url: Url,
+    generation: String,
+    token: Option<String>,
+}
+
+/// An object being read.
+pub struct Object {
+    reader: ranged::Reader<Location>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "invalid GCS URL: {url}"),
+            Error::Request(err) => write!(f, "GCS request failed: {err}"),
+            Error::Io(err) => write!(f, "error reading GCS object metadata: {err}"),
+            Error::Format(err) => write!(f, "invalid GCS object metadata: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUrl(_) => None,
+            Error::Request(err) => Some(&**err),
+            Error::Io(err) => Some(err),
+            Error::Format(err) => Some(err),
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(err: ureq::Error) -> Self {
+        Error::Request(Box::new(err))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Format(err)
+    }
+}
+
+impl std::fmt::Debug for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = self.reader.source();
+        f.debug_struct("Object")
+            .field("url", &location.url.as_str())
+            .field("generation", &location.generation)
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether `input` names a GCS object rather than a local file.
+#[must_use]
+pub fn is_gcs(input: &str) -> bool {
+    input.starts_with(SCHEME)
+}
+
+/// Split a `gs://bucket/object` URL into the bucket and object name.
+fn parse(url: &str) -> Option<(&str, &str)> {
+    let (bucket, object) = url.strip_prefix(SCHEME)?.split_once('/')?;
+    if bucket.is_empty() || object.is_empty() {
+        return None;
+    }
+    Some((bucket, object))
+}
+
+/// URL of the metadata of `object` in `bucket` under `endpoint`.  The object name is a single path segment, so any
+/// `/` in it is escaped.
+fn metadata_url(endpoint: &str, bucket: &str, object: &str) -> Option<Url> {
+    let mut url = Url::parse(endpoint).ok()?;
+    url.path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .extend(["storage", "v1", "b", bucket, "o", object]);
+    Some(url)
+}
+
+/// A request to `url`, authorized with `token` if there is one.
+fn request(url: &Url, token: Option<&str>) -> ureq::Request {
+    let request = ureq::get(url.as_str());
+    match token {
+        Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+        None => request,
+    }
+}
+
+impl Object {
+    /// Open the object at `url`, configured from the environment, and look up its length and generation.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the URL or endpoint is invalid or the object can't be found.
+    pub fn open(url: &str) -> Result<Self, Error> {
+        let (bucket, object) = parse(url).ok_or_else(|| Error::InvalidUrl(url.to_string()))?;
+        let endpoint =
+            std::env::var("STORAGE_EMULATOR_HOST").unwrap_or_else(|_| ENDPOINT.to_string());
+        let url = metadata_url(&endpoint, bucket, object).ok_or(Error::InvalidUrl(endpoint))?;
+        let token = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN").ok();
+        let metadata = request(&url, token.as_deref()).call()?.into_string()?;
+        let metadata: Metadata = serde_json::from_str(&metadata)?;
+        let location = Location {
+            url,
+            generation: metadata.generation,
+            token,
+        };
+        Ok(Self {
+            reader: ranged::Reader::new(location, metadata.size.parse().unwrap_or_default()),
+        })
+    }
+
+    /// Length of the object in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.reader.len()
+    }
+
+    /// Whether the object is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ranged::Source for Location {
+    fn read_from(&self, offset: u64) -> io::Result<ranged::Body> {
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("alt", "media")
+            .append_pair("generation", &self.generation);
+        let response = request(&url, self.token.as_deref())
+            .set("Range", &format!("bytes={offset}-"))
+            .call()
+            .map_err(io::Error::other)?;
+        Ok(response.into_reader())
+    }
+}
+
+impl io::Read for Object {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl io::Seek for Object {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(
+            parse("gs://exports/2024/05/01.csv"),
+            Some(("exports", "2024/05/01.csv"))
+        );
+        assert!(is_gcs("gs://exports/01.csv"));
+        assert!(!is_gcs("s3://exports/01.csv"));
+        for invalid in ["gs://exports", "gs://exports/", "gs:///01.csv"] {
+            assert_eq!(parse(invalid), None, "{invalid}");
+        }
+        assert_eq!(
+            metadata_url("http://localhost:4443/", "exports", "2024/05/01.csv")
+                .unwrap()
+                .as_str(),
+            "http://localhost:4443/storage/v1/b/exports/o/2024%2F05%2F01.csv"
+        );
+        assert!(matches!(
+            Object::open("gs://exports"),
+            Err(Error::InvalidUrl(_))
+        ));
+    }
+}
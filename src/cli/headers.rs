@@ -0,0 +1,97 @@
+//! Header mapping, for inputs whose columns are named differently from the instruction fields.
+//!
+//! Each mapping names an input column and the field it holds, like `txn_type=type`.  Headers are renamed before any
+//! record is deserialized, so a mapped column behaves exactly as if the file had used the field's name.  Columns
+//! without a mapping keep their names.
+
+use super::tenants::TENANT_COLUMN;
+use crate::bank::transaction::instruction::FIELDS;
+use std::io;
+use std::str::FromStr;
+
+/// An input column read as an instruction field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    /// The column's header in the input.
+    pub column: String,
+    /// The instruction field, one of `type`, `client`, `tx`, `amount`, `correlation_id`, `operator_reference`, and
+    /// `timestamp`, or [`tenant`](../tenants/index.html).
+    pub field: String,
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+
+    /// Parses `COLUMN=FIELD`.
+    fn from_str(mapping: &str) -> Result<Self, Self::Err> {
+        let Some((column, field)) = mapping.split_once('=') else {
+            return Err(format!(
+                "invalid header mapping {mapping:?}, expected COLUMN=FIELD"
+            ));
+        };
+        let (column, field) = (column.trim(), field.trim());
+        if column.is_empty() {
+            return Err(format!("header mapping {mapping:?} has no column"));
+        }
+        if !FIELDS.contains(&field) && field != TENANT_COLUMN {
+            return Err(format!(
+                "unknown field {field:?} in header mapping, expected one of {}, or {TENANT_COLUMN}",
+                FIELDS.join(", ")
+            ));
+        }
+        Ok(Self {
+            column: column.to_string(),
+            field: field.to_string(),
+        })
+    }
+}
+
+/// Rename the headers of `reader` according to `mappings`.  Does nothing if there are no mappings.
+///
+/// # Errors
+///
+/// Will return `Err` if the header row can't be read.
+pub fn apply<R: io::Read>(
+    reader: &mut csv::Reader<R>,
+    mappings: &[Mapping],
+) -> Result<(), csv::Error> {
+    if mappings.is_empty() {
+        return Ok(());
+    }
+    let headers = reader
+        .headers()?
+        .iter()
+        .map(|header| {
+            mappings
+                .iter()
+                .find(|mapping| mapping.column == header)
+                .map_or(header, |mapping| mapping.field.as_str())
+        })
+        .collect();
+    reader.set_headers(headers);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::transaction::instruction::TransactionInstruction;
+
+    #[test]
+    fn renames_columns() {
+        let mappings: Vec<Mapping> = ["txn_type=type", "customer_id = client", "txn_id=tx"]
+            .iter()
+            .map(|mapping| mapping.parse().unwrap())
+            .collect();
+        let input = "txn_type, customer_id, txn_id, amount\ndeposit, 7, 3, 1.5\n";
+        let mut reader = super::super::reader_builder().from_reader(input.as_bytes());
+        apply(&mut reader, &mappings).unwrap();
+        let instructions: Vec<TransactionInstruction> =
+            reader.deserialize().collect::<Result<_, _>>().unwrap();
+        assert_eq!(instructions, ["deposit, 7, 3, 1.5".parse().unwrap()]);
+
+        assert!("txn_type".parse::<Mapping>().is_err());
+        assert!("=type".parse::<Mapping>().is_err());
+        assert!("value=amnt".parse::<Mapping>().is_err());
+    }
+}
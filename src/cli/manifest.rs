@@ -0,0 +1,369 @@
+//! Manifests of output files, so that whatever loads them can check they're complete and intact first.
+//!
+//! A manifest is a JSON file listing each file written with the number of CSV rows in it, not counting the header,
+//! and the SHA-256 of its bytes as written, after any compression or encryption.  It's written once every file it
+//! lists is complete, so a loader can wait for the manifest and then check each file against it.
+//!
+//! Inputs can be checked against a producer's manifest in the same format with a [`Verifier`](struct.Verifier.html),
+//! as they're read.  Rows are counted as lines after the header that aren't blank, and line breaks in quoted fields
+//! don't count.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Name the account report is listed under when it's written to standard output.
+pub const STDOUT: &str = "-";
+
+/// The files written by a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Manifest {
+    pub files: Vec<Entry>,
+}
+
+/// A file in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Entry {
+    /// Path of the file as it was given.
+    pub file: String,
+    #[serde(flatten)]
+    pub digest: Digest,
+}
+
+/// What a file is checked against.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Digest {
+    /// Number of CSV rows, not counting the header.
+    pub rows: u64,
+    /// SHA-256 of the file's bytes, in lowercase hex.
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Add `file` with `digest`.
+    pub fn add(&mut self, file: &Path, digest: Digest) {
+        self.files.push(Entry {
+            file: file.to_string_lossy().into_owned(),
+            digest,
+        });
+    }
+
+    /// Read a manifest.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the manifest can't be read or isn't valid.
+    pub fn read<R: io::Read>(reader: R) -> serde_json::Result<Self> {
+        serde_json::from_reader(reader)
+    }
+
+    /// Write the manifest to `path`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be written.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")
+    }
+}
+
+/// `bytes` in lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// Hashes the bytes written through it.
+pub(crate) struct Hasher<W> {
+    inner: W,
+    sha256: Sha256,
+}
+
+impl<W: io::Write> Hasher<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            sha256: Sha256::new(),
+        }
+    }
+
+    /// The underlying writer and the hash of everything written, in lowercase hex.
+    pub(crate) fn finish(self) -> (W, String) {
+        (self.inner, hex(&self.sha256.finalize()))
+    }
+}
+
+impl<W> std::fmt::Debug for Hasher<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hasher").finish_non_exhaustive()
+    }
+}
+
+impl<W: io::Write> io::Write for Hasher<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.sha256.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Counts the lines of CSV fed to it, leaving out blank lines and line breaks within quoted fields.
+#[derive(Debug, Default)]
+pub(crate) struct Lines {
+    ended: u64,
+    quoted: bool,
+    /// Whether the current line has anything on it.
+    started: bool,
+}
+
+impl Lines {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            match byte {
+                b'\n' if !self.quoted => {
+                    self.ended += u64::from(self.started);
+                    self.started = false;
+                }
+                b'\r' if !self.quoted => {}
+                // An escaped quote toggles twice, so it's left quoted.
+                b'"' => {
+                    self.quoted = !self.quoted;
+                    self.started = true;
+                }
+                _ => self.started = true,
+            }
+        }
+    }
+
+    /// Number of rows after the header, including a last one without a line break.
+    pub(crate) fn rows(&self) -> u64 {
+        (self.ended + u64::from(self.started)).saturating_sub(1)
+    }
+}
+
+/// What to do when an input doesn't match its manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OnMismatch {
+    /// Fail the run before any report is written.
+    #[default]
+    Fail,
+    /// Log a warning and carry on.
+    Warn,
+}
+
+/// An input that doesn't match its manifest.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub file: String,
+    /// What doesn't match, like `rows`.
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} doesn't match its manifest: expected {} {}, read {}",
+            self.file, self.field, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Reads an input, checking it against its manifest entry as it's read.
+///
+/// Too many rows are caught as soon as they're read, and the SHA-256 and too few rows at the end of the input.  With
+/// [`OnMismatch::Fail`](enum.OnMismatch.html#variant.Fail) a mismatch is a read error, which stops the run.  The input
+/// has to be read from start to end, so seeking anywhere but the current position is an error.
+#[derive(Debug)]
+pub struct Verifier<R> {
+    inner: R,
+    entry: Entry,
+    on_mismatch: OnMismatch,
+    sha256: Sha256,
+    lines: Lines,
+    /// Whether a mismatch has been found or the end checked, so that it's reported once.
+    done: bool,
+}
+
+impl Manifest {
+    /// The entry for `input`, matched by file name, since the producer's paths aren't the same as the consumer's.
+    #[must_use]
+    pub fn find(&self, input: &Path) -> Option<&Entry> {
+        let name = input.file_name()?;
+        self.files
+            .iter()
+            .find(|entry| Path::new(&entry.file).file_name() == Some(name))
+    }
+}
+
+impl<R: io::Read> Verifier<R> {
+    #[must_use]
+    pub fn new(inner: R, entry: Entry, on_mismatch: OnMismatch) -> Self {
+        Self {
+            inner,
+            entry,
+            on_mismatch,
+            sha256: Sha256::new(),
+            lines: Lines::default(),
+            done: false,
+        }
+    }
+
+    fn mismatch(
+        &mut self,
+        field: &'static str,
+        actual: String,
+        expected: String,
+    ) -> io::Result<()> {
+        self.done = true;
+        let mismatch = Mismatch {
+            file: self.entry.file.clone(),
+            field,
+            expected,
+            actual,
+        };
+        match self.on_mismatch {
+            OnMismatch::Fail => Err(io::Error::new(io::ErrorKind::InvalidData, mismatch)),
+            OnMismatch::Warn => {
+                tracing::warn!(%mismatch, "input doesn't match manifest");
+                Ok(())
+            }
+        }
+    }
+
+    /// Check what was read against the entry, once the whole input has been.
+    fn check_end(&mut self) -> io::Result<()> {
+        self.done = true;
+        let expected = &self.entry.digest;
+        let rows = self.lines.rows();
+        if rows != expected.rows {
+            return self.mismatch("rows", rows.to_string(), expected.rows.to_string());
+        }
+        let sha256 = hex(&std::mem::take(&mut self.sha256).finalize());
+        if !sha256.eq_ignore_ascii_case(&expected.sha256) {
+            let expected = expected.sha256.clone();
+            return self.mismatch("SHA-256", sha256, expected);
+        }
+        tracing::info!(file = %self.entry.file, rows, "input matches manifest");
+        Ok(())
+    }
+}
+
+impl<R: io::Read> io::Read for Verifier<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.done {
+            return Ok(n);
+        }
+        if n == 0 && !buf.is_empty() {
+            self.check_end()?;
+            return Ok(0);
+        }
+        self.sha256.update(&buf[..n]);
+        self.lines.update(&buf[..n]);
+        let (rows, expected) = (self.lines.rows(), self.entry.digest.rows);
+        if rows > expected {
+            self.mismatch("rows", format!("at least {rows}"), expected.to_string())?;
+        }
+        Ok(n)
+    }
+}
+
+impl<R: io::Seek> io::Seek for Verifier<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        match pos {
+            io::SeekFrom::Current(0) => self.inner.seek(pos),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "an input being verified has to be read from start to end",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn counts_and_hashes() {
+        let report = "client,name\n1,\"two\nlines\"\n2,\"\"\"quoted\"\"\"\n";
+        let mut lines = Lines::default();
+        let mut hasher = Hasher::new(vec![]);
+        for chunk in report.as_bytes().chunks(5) {
+            hasher.write_all(chunk).unwrap();
+            lines.update(chunk);
+        }
+        let (written, sha256) = hasher.finish();
+        assert_eq!(written, report.as_bytes());
+        assert_eq!(lines.rows(), 2);
+        assert_eq!(Lines::default().rows(), 0);
+        // `printf 'client,name\n1,"two\nlines"\n2,"""quoted"""\n' | sha256sum`
+        assert_eq!(
+            sha256,
+            "b739619b61ff6e694d86ed516ee710a5ba04eebd01538ff870b2924dca5df969"
+        );
+
+        let mut manifest = Manifest::default();
+        manifest.add(Path::new(STDOUT), Digest { rows: 2, sha256 });
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.starts_with(r#"{"files":[{"file":"-","rows":2,"sha256":"#));
+        assert_eq!(Manifest::read(json.as_bytes()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn verifies_inputs() {
+        use std::io::Read;
+
+        let input = "type,client,tx,amount\r\ndeposit,1,1,1\r\n\r\ndeposit,1,2,1";
+        let (_, sha256) = {
+            let mut hasher = Hasher::new(vec![]);
+            hasher.write_all(input.as_bytes()).unwrap();
+            hasher.finish()
+        };
+        let manifest = Manifest {
+            files: vec![Entry {
+                file: "exports/2024-05-01.csv".to_string(),
+                digest: Digest { rows: 2, sha256 },
+            }],
+        };
+        assert!(manifest.find(Path::new("2024-05-02.csv")).is_none());
+        let entry = manifest.find(Path::new("/data/in/2024-05-01.csv")).unwrap();
+
+        let read = |input: &str, entry: &Entry, on_mismatch| {
+            let mut verifier = Verifier::new(input.as_bytes(), entry.clone(), on_mismatch);
+            verifier.read_to_end(&mut vec![])
+        };
+        assert!(read(input, entry, OnMismatch::Fail).is_ok());
+        let tampered = input.replace("deposit,1,2,1", "deposit,1,2,9");
+        let err = read(&tampered, entry, OnMismatch::Fail).unwrap_err();
+        assert!(err.to_string().contains("expected SHA-256"), "{}", err);
+        assert!(read(&tampered, entry, OnMismatch::Warn).is_ok());
+        let short = "type,client,tx,amount\ndeposit,1,1,1\n";
+        let err = read(short, entry, OnMismatch::Fail).unwrap_err();
+        assert!(
+            err.to_string().contains("expected rows 2, read 1"),
+            "{}",
+            err
+        );
+        let long = format!("{input}\ndeposit,1,3,1\n");
+        let err = read(&long, entry, OnMismatch::Fail).unwrap_err();
+        assert!(err.to_string().contains("read at least 3"), "{}", err);
+    }
+}
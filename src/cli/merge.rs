@@ -0,0 +1,157 @@
+//! Merging several inputs by timestamp.
+//!
+//! Inputs that are each in time order, such as one file per day or per source system, are read together and their
+//! instructions applied in global time order rather than one input after another, which matters when a dispute in one
+//! input refers to a transaction in another.  This is a k-way merge, so only the next record of each input is held in
+//! memory.
+//!
+//! Records without a timestamp, including ones that couldn't be deserialized, keep their place in their own input by
+//! taking the timestamp of the record before them.  Ties go to the input given first.
+
+use super::{amounts, Record, RecordParser};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+
+/// Reads the next record of an input and the position following it.
+type Next<'a> = Box<dyn FnMut() -> Option<(Record, csv::Position)> + 'a>;
+
+/// An input being merged.
+struct Input<'a> {
+    next: Next<'a>,
+    head: Option<(Record, csv::Position)>,
+    /// Timestamp of the latest record read, which records without one are ordered by.
+    time: u64,
+}
+
+impl Input<'_> {
+    /// Read the next record into `head`, returning the time it is ordered by.
+    fn advance(&mut self, index: usize) -> Option<u64> {
+        let (record, position) = (self.next)()?;
+        if let Ok(ti) = &record.instruction {
+            match ti.timestamp {
+                Some(time) if time < self.time => tracing::warn!(
+                    input = index,
+                    line = record.location.line,
+                    "timestamp goes backwards; keeping the record in input order"
+                ),
+                Some(time) => self.time = time,
+                None => {}
+            }
+        }
+        self.head = Some((record, position));
+        Some(self.time)
+    }
+}
+
+/// A function reading the next record of `reader`, deserialized with `parser`.
+fn records_of<R: io::Read>(reader: &mut csv::Reader<R>, parser: RecordParser) -> Next<'_> {
+    let mut record = csv::StringRecord::new();
+    Box::new(move || {
+        let start = reader.position().clone();
+        let parsed = match reader.read_record(&mut record) {
+            Ok(false) => return None,
+            Ok(true) => parser.parse(&record),
+            Err(err) => Record::unreadable(err, &start),
+        };
+        Some((parsed, reader.position().clone()))
+    })
+}
+
+/// Call `consume` with every record of `first` and `rest` and the position following it within its input, in
+/// timestamp order, with amounts written in `amounts` and stopping after `limit` records if there is a limit.
+pub(crate) fn read_records<R, S, F>(
+    first: &mut csv::Reader<R>,
+    rest: &mut [csv::Reader<S>],
+    amounts: amounts::Notation,
+    limit: Option<u64>,
+    mut consume: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    R: io::Read,
+    S: io::Read,
+    F: FnMut(Record, &csv::Position) -> Result<(), Box<dyn std::error::Error>>,
+{
+    let mut inputs = vec![];
+    let parser = RecordParser::new(first, amounts)?;
+    inputs.push(records_of(first, parser));
+    for reader in rest {
+        let parser = RecordParser::new(reader, amounts)?;
+        inputs.push(records_of(reader, parser));
+    }
+    let mut inputs: Vec<_> = inputs
+        .into_iter()
+        .map(|next| Input {
+            next,
+            head: None,
+            time: 0,
+        })
+        .collect();
+
+    let mut queue = BinaryHeap::new();
+    for (index, input) in inputs.iter_mut().enumerate() {
+        if let Some(time) = input.advance(index) {
+            queue.push(Reverse((time, index)));
+        }
+    }
+    let mut read = 0;
+    while let Some(Reverse((_, index))) = queue.pop() {
+        if limit.is_some_and(|limit| read == limit) {
+            break;
+        }
+        read += 1;
+        let input = &mut inputs[index];
+        let (record, position) = input.head.take().expect("queued inputs have a record");
+        consume(record, &position)?;
+        if let Some(time) = input.advance(index) {
+            queue.push(Reverse((time, index)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaves_by_timestamp() {
+        let first = "type,client,tx,amount,timestamp\n\
+            deposit,1,1,1,10\n\
+            deposit,1,2,1,\n\
+            deposit,1,3,1,30\n\
+            deposit,1,4,1,20\n";
+        let second = "type,client,tx,amount,timestamp\n\
+            deposit,2,5,1,5\n\
+            bogus,2,6,1,15\n\
+            deposit,2,7,1,25\n\
+            deposit,2,8,1,30\n";
+        let mut first = super::super::reader_builder().from_reader(first.as_bytes());
+        let mut rest = [super::super::reader_builder().from_reader(second.as_bytes())];
+        let mut merged = vec![];
+        read_records(
+            &mut first,
+            &mut rest,
+            amounts::Notation::default(),
+            None,
+            |record, _| {
+                merged.push(record.instruction.ok().map(|ti| ti.tx.0));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            [
+                Some(5),
+                None,
+                Some(1),
+                Some(2),
+                Some(7),
+                Some(3),
+                Some(4),
+                Some(8)
+            ]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Writing the account report and report files: [compressed](../compress/index.html) and
+//! [encrypted](../encrypt/index.html) as asked, and counted and hashed for the [manifest](../manifest/index.html).
+
+use super::{compress, encrypt, manifest};
+use std::io;
+
+/// Writes a report to `W`.  [`finish`](#method.finish) has to be called to end any compressed or encrypted stream.
+#[derive(Debug)]
+pub struct Writer<W: io::Write> {
+    inner: compress::Writer<encrypt::Writer<manifest::Hasher<W>>>,
+    lines: manifest::Lines,
+}
+
+impl<W: io::Write> Writer<W> {
+    /// Write to `output`, compressed with `compression` if there is one and then encrypted to `recipients` if there
+    /// are any.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the compressor or encryption can't be set up.
+    pub fn new(
+        output: W,
+        compression: Option<compress::Compression>,
+        recipients: &[encrypt::Recipient],
+    ) -> io::Result<Self> {
+        let encrypted = encrypt::Writer::new(manifest::Hasher::new(output), recipients)?;
+        Ok(Self {
+            inner: compress::Writer::new(encrypted, compression)?,
+            lines: manifest::Lines::default(),
+        })
+    }
+
+    /// End any compressed or encrypted stream and flush it, returning the underlying writer and the digest of what
+    /// was written to it.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the end of a stream can't be written.
+    pub fn finish(self) -> io::Result<(W, manifest::Digest)> {
+        let (output, sha256) = self.inner.finish()?.finish()?.finish();
+        let digest = manifest::Digest {
+            rows: self.lines.rows(),
+            sha256,
+        };
+        Ok((output, digest))
+    }
+}
+
+impl<W: io::Write> io::Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.lines.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,175 @@
+//! Deserialization of input on background threads.
+//!
+//! On wide files deserializing records can cost more than applying them.  One thread reads raw records in batches and
+//! a pool of threads deserializes the batches, while the calling thread only applies instructions.  The channels
+//! between them are bounded so parsing can't run arbitrarily far ahead of the bank, and batches are put back into
+//! input order before they are consumed.
+
+use super::{Record, RecordParser};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Number of records read before a batch is handed to a parser.
+const BATCH_SIZE: usize = 256;
+
+/// Number of batches that can be waiting in each channel, per parser thread.
+const QUEUE_DEPTH: usize = 2;
+
+/// Raw records, or the records that couldn't be read, with the positions following them.
+type RawBatch = (u64, Vec<(Result<csv::StringRecord, Record>, csv::Position)>);
+type ParsedBatch = (u64, Vec<(Record, csv::Position)>);
+
+/// Call `consume` with every record in `reader` and the position following it, in input order, deserializing with
+/// `parser` on `threads` background threads.
+pub(crate) fn read_records<R, F>(
+    reader: &mut csv::Reader<R>,
+    parser: &RecordParser,
+    threads: usize,
+    limit: Option<u64>,
+    mut consume: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    R: io::Read + Send,
+    F: FnMut(Record, &csv::Position) -> Result<(), Box<dyn std::error::Error>>,
+{
+    let threads = threads.max(1);
+
+    thread::scope(|scope| {
+        let (raw_sender, raw_receiver) = mpsc::sync_channel(threads * QUEUE_DEPTH);
+        let (parsed_sender, parsed_receiver) = mpsc::sync_channel(threads * QUEUE_DEPTH);
+
+        scope.spawn(move || read_batches(reader, limit, &raw_sender));
+        // Parsers share the receiver so that it is dropped, stopping the reader, once they have all stopped.
+        let raw_receiver = Arc::new(Mutex::new(raw_receiver));
+        for _ in 0..threads {
+            let raw_receiver = Arc::clone(&raw_receiver);
+            let parsed_sender = parsed_sender.clone();
+            scope.spawn(move || parse_batches(&raw_receiver, parser, &parsed_sender));
+        }
+        drop(raw_receiver);
+        drop(parsed_sender);
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (seq, batch) in parsed_receiver {
+            pending.insert(seq, batch);
+            while let Some(batch) = pending.remove(&next) {
+                next += 1;
+                for (record, position) in batch {
+                    consume(record, &position)?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+fn read_batches<R: io::Read>(
+    reader: &mut csv::Reader<R>,
+    limit: Option<u64>,
+    sender: &mpsc::SyncSender<RawBatch>,
+) {
+    let mut seq = 0;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut read = 0;
+    loop {
+        if limit.is_some_and(|limit| read == limit) {
+            if !batch.is_empty() {
+                let _ = sender.send((seq, batch));
+            }
+            return;
+        }
+        read += 1;
+        let mut record = csv::StringRecord::new();
+        let start = reader.position().clone();
+        let stop = match reader.read_record(&mut record) {
+            Ok(true) => {
+                batch.push((Ok(record), reader.position().clone()));
+                false
+            }
+            Ok(false) => true,
+            Err(err) => {
+                // Other errors only affect the record they occurred in.
+                let stop = err.is_io_error();
+                batch.push((
+                    Err(Record::unreadable(err, &start)),
+                    reader.position().clone(),
+                ));
+                stop
+            }
+        };
+        if stop || batch.len() == BATCH_SIZE {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            if !full.is_empty() && sender.send((seq, full)).is_err() {
+                return;
+            }
+            seq += 1;
+        }
+        if stop {
+            return;
+        }
+    }
+}
+
+fn parse_batches(
+    receiver: &Mutex<mpsc::Receiver<RawBatch>>,
+    parser: &RecordParser,
+    sender: &mpsc::SyncSender<ParsedBatch>,
+) {
+    loop {
+        // The lock is only held while waiting for a batch, not while parsing it.
+        let next = receiver.lock().expect("parser thread panicked").recv();
+        let Ok((seq, batch)) = next else {
+            return;
+        };
+        let parsed = batch
+            .into_iter()
+            .map(|(record, position)| {
+                (
+                    record.map_or_else(|unreadable| unreadable, |record| parser.parse(&record)),
+                    position,
+                )
+            })
+            .collect();
+        if sender.send((seq, parsed)).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    #[test]
+    fn preserves_order() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 0..(BATCH_SIZE * 5 + 3) {
+            writeln!(input, "deposit,{},{tx},1.0", tx % 7).unwrap();
+        }
+        input.push_str("bogus,1,1,1.0\n");
+
+        let sequential: Vec<_> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<crate::bank::transaction::instruction::TransactionInstruction>()
+            .map(|record| record.ok().map(|ti| ti.tx))
+            .collect();
+
+        let mut pipelined = vec![];
+        let mut positions = vec![];
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let parser =
+            RecordParser::new(&mut reader, super::super::amounts::Notation::default()).unwrap();
+        read_records(&mut reader, &parser, 3, None, |record, position| {
+            pipelined.push(record.instruction.ok().map(|ti| ti.tx));
+            positions.push(position.line());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(pipelined, sequential);
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+    }
+}
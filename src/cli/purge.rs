@@ -0,0 +1,121 @@
+//! Purging a client from the files a run leaves behind, for right-to-erasure requests.
+//!
+//! The account is [purged](../../bank/purge/index.html) from a snapshot, and the client is redacted from an
+//! [audit log](../../bank/audit/index.html) and a [ledger journal](../../ledger/index.html).  Each file is rewritten
+//! next to itself and renamed over the original once it's complete, so an interrupted purge leaves the original
+//! untouched.  An audit log is verified before it's rewritten, since redacting it rebuilds the chain and would hide any
+//! earlier tampering.
+
+use super::replace_file;
+use crate::bank::account::AccountId;
+use crate::bank::audit;
+use crate::bank::Bank;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The files to purge a client from.
+#[derive(Debug, Default)]
+pub struct Files {
+    pub snapshot: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub journal: Option<PathBuf>,
+}
+
+/// Purge `client` from `files`, with `operator_reference` saying who authorized it, and write what was done to
+/// `output`.
+///
+/// # Errors
+///
+/// Will return `Err` if a file can't be read, parsed, or replaced, the client has no account in the snapshot, or the
+/// audit log has been altered.  Files already rewritten stay rewritten.
+pub fn purge<W: io::Write>(
+    client: AccountId,
+    operator_reference: &str,
+    files: &Files,
+    mut output: W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = &files.snapshot {
+        let mut bank = Bank::load_snapshot(io::BufReader::new(fs::File::open(path)?))?;
+        let account = bank.purge(&client, operator_reference)?;
+        replace_file(path, |writer| Ok(bank.save_snapshot(writer)?))?;
+        writeln!(
+            output,
+            "snapshot: purged client {client}, available {} kept in totals",
+            account.available
+        )?;
+    }
+    if let Some(path) = &files.audit_log {
+        if let Err(broken) = audit::verify(fs::File::open(path)?)? {
+            return Err(format!("audit log is broken, not redacting it: {broken}").into());
+        }
+        let mut redacted = 0;
+        replace_file(path, |writer| {
+            redacted = audit::redact(fs::File::open(path)?, writer, client, operator_reference)?;
+            Ok(())
+        })?;
+        let (_, last) =
+            audit::verify(fs::File::open(path)?)?.map_err(|broken| broken.to_string())?;
+        writeln!(
+            output,
+            "audit log: redacted {redacted} records, last hash now {last}"
+        )?;
+    }
+    if let Some(path) = &files.journal {
+        let mut redacted = 0;
+        replace_file(path, |writer| {
+            redacted = crate::ledger::redact_journal(fs::File::open(path)?, writer, client)?;
+            Ok(())
+        })?;
+        writeln!(output, "journal: redacted {redacted} rows")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::purge;
+
+    #[test]
+    fn purges_snapshot_and_audit_log() {
+        let dir = std::env::temp_dir().join(format!("transactomatic-purge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = Files {
+            snapshot: Some(dir.join("snapshot.json")),
+            audit_log: Some(dir.join("audit.jsonl")),
+            journal: None,
+        };
+        let mut bank = Bank::new();
+        let mut writer = audit::Writer::open(dir.join("audit.jsonl")).unwrap();
+        for line in ["deposit,1,1,5", "deposit,2,2,3", "withdrawal,1,3,1"] {
+            writer
+                .perform(&mut bank, line.parse().unwrap())
+                .unwrap()
+                .unwrap();
+        }
+        writer.flush().unwrap();
+        bank.save_snapshot(fs::File::create(dir.join("snapshot.json")).unwrap())
+            .unwrap();
+
+        let mut output = vec![];
+        purge(AccountId::Number(1), "DPO-7", &files, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("snapshot: purged client 1, available 4"));
+        assert!(output.contains("audit log: redacted 2 records"));
+
+        let restored =
+            Bank::load_snapshot(fs::File::open(dir.join("snapshot.json")).unwrap()).unwrap();
+        assert!(restored.account(&AccountId::Number(1)).is_none());
+        assert_eq!(restored.totals().total, bank.totals().total);
+        let log = fs::read_to_string(dir.join("audit.jsonl")).unwrap();
+        let tombstones = audit::records(log.as_bytes())
+            .filter(|record| record.as_ref().unwrap().client() == purge::tombstone())
+            .count();
+        assert_eq!(tombstones, 2);
+
+        // It's gone, so purging it again fails.
+        assert!(purge(AccountId::Number(1), "DPO-7", &files, io::sink()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
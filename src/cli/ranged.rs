@@ -0,0 +1,156 @@
+//! Seekable reads of remote objects with HTTP range requests, shared by the object storage inputs.
+//!
+//! A [`Reader`](struct.Reader.html) streams the object from one request for as long as it is read in order.  Seeking
+//! elsewhere starts a new request at the new offset, and so does a request that fails part way through, so a read of
+//! a multi-gigabyte object survives a dropped connection by picking up where it stopped.
+
+use std::io;
+use std::time::Duration;
+
+/// Number of times in a row a failed request is retried before the error is returned.
+const MAX_RETRIES: u32 = 3;
+
+/// How long to wait before the second retry in a row.  The first is immediate, and each after the second waits twice
+/// as long as the one before.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The body of a response.
+pub(crate) type Body = Box<dyn io::Read + Send + Sync>;
+
+/// An object that can be streamed from any offset.
+pub(crate) trait Source {
+    /// Start streaming the object from `offset` to its end.
+    fn read_from(&self, offset: u64) -> io::Result<Body>;
+}
+
+/// Reads an object of a known length from a [`Source`](trait.Source.html).
+pub(crate) struct Reader<S> {
+    source: S,
+    len: u64,
+    position: u64,
+    /// The body of the current request, which started at or before the position.
+    body: Option<Body>,
+}
+
+impl<S> Reader<S> {
+    pub(crate) fn new(source: S, len: u64) -> Self {
+        Self {
+            source,
+            len,
+            position: 0,
+            body: None,
+        }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn source(&self) -> &S {
+        &self.source
+    }
+}
+
+impl<S: Source> io::Read for Reader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let mut retries = 0;
+        loop {
+            let read = match &mut self.body {
+                Some(body) => body.read(buf),
+                None => self.source.read_from(self.position).and_then(|body| {
+                    let body = self.body.insert(body);
+                    body.read(buf)
+                }),
+            };
+            match read {
+                // A body that ends early was cut off, so it is resumed like one that fails.
+                Ok(0) => {}
+                Ok(n) => {
+                    self.position += n as u64;
+                    return Ok(n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if retries == MAX_RETRIES => return Err(err),
+                Err(err) => {
+                    tracing::warn!(%err, position = self.position, "object read failed; resuming");
+                }
+            }
+            if retries == MAX_RETRIES {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("object ended at byte {} of {}", self.position, self.len),
+                ));
+            }
+            self.body = None;
+            if retries > 0 {
+                std::thread::sleep(RETRY_DELAY * 2_u32.pow(retries - 1));
+            }
+            retries += 1;
+        }
+    }
+}
+
+impl<S> io::Seek for Reader<S> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        // Keep streaming the current request unless the position actually changes.
+        if position != self.position {
+            self.body = None;
+            self.position = position;
+        }
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::convert::TryFrom;
+    use std::io::{Read, Seek};
+
+    /// Serves a slice of an object, cutting every response off after `cut` bytes.
+    struct Flaky<'a> {
+        data: &'a [u8],
+        cut: usize,
+        requests: Cell<usize>,
+    }
+
+    impl Source for Flaky<'_> {
+        fn read_from(&self, offset: u64) -> io::Result<Body> {
+            self.requests.set(self.requests.get() + 1);
+            let start = usize::try_from(offset).unwrap();
+            let end = (start + self.cut).min(self.data.len());
+            Ok(Box::new(io::Cursor::new(self.data[start..end].to_vec())))
+        }
+    }
+
+    #[test]
+    fn resumes_and_seeks() {
+        let data: Vec<u8> = (0..=255).collect();
+        let flaky = Flaky {
+            data: &data,
+            cut: 100,
+            requests: Cell::new(0),
+        };
+        let mut reader = Reader::new(flaky, data.len() as u64);
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        assert_eq!(reader.source.requests.get(), 3);
+
+        reader.seek(io::SeekFrom::Start(250)).unwrap();
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[250..]);
+        assert_eq!(reader.source.requests.get(), 4);
+    }
+}
@@ -0,0 +1,190 @@
+//! Reconciliation of an account report against balances from another system.
+//!
+//! The expected balances are a CSV with a `client` column and any of `available`, `held`, `total`, and `locked`.
+//! Only the columns present are compared.  Amounts within the tolerance of each other are considered equal.
+
+use super::diff::read_accounts;
+use crate::bank::account::{AccountId, AccountSummary};
+use crate::bank::amount::Amount;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// A row of the expected balances file.
+#[derive(Debug, Deserialize)]
+struct Expected {
+    client: AccountId,
+    #[serde(default)]
+    available: Option<Amount>,
+    #[serde(default)]
+    held: Option<Amount>,
+    #[serde(default)]
+    total: Option<Amount>,
+    #[serde(default)]
+    locked: Option<bool>,
+}
+
+/// A field that didn't reconcile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    /// The account is only in one of the two files.
+    Account,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+/// One row of reconciliation output.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Mismatch {
+    pub client: AccountId,
+    pub field: Field,
+    pub expected: String,
+    pub actual: String,
+    /// `actual - expected` for amounts.
+    pub difference: Option<Amount>,
+}
+
+/// Compare `report` against `expected`, writing mismatches as CSV.  Returns `true` if everything reconciled.
+///
+/// # Errors
+///
+/// Will return `Err` if either input can't be read or the output can't be written.
+pub fn reconcile<R1: io::Read, R2: io::Read, W: io::Write>(
+    report: R1,
+    expected: R2,
+    tolerance: Amount,
+    output: W,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let report = read_accounts(report)?;
+    let expected = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(expected)
+        .deserialize()
+        .map(|row| row.map(|e: Expected| (e.client, e)))
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+    let mismatches = mismatches(&report, &expected, tolerance);
+    let mut writer = csv::Writer::from_writer(output);
+    for mismatch in &mismatches {
+        writer.serialize(mismatch)?;
+    }
+    writer.flush()?;
+    Ok(mismatches.is_empty())
+}
+
+fn mismatches(
+    report: &BTreeMap<AccountId, AccountSummary>,
+    expected: &BTreeMap<AccountId, Expected>,
+    tolerance: Amount,
+) -> Vec<Mismatch> {
+    let mut mismatches = vec![];
+    for (client, expected) in expected {
+        let Some(actual) = report.get(client) else {
+            mismatches.push(Mismatch {
+                client: *client,
+                field: Field::Account,
+                expected: "present".to_string(),
+                actual: "missing".to_string(),
+                difference: None,
+            });
+            continue;
+        };
+
+        for (field, want, got) in [
+            (Field::Available, expected.available, actual.available),
+            (Field::Held, expected.held, actual.held),
+            (Field::Total, expected.total, actual.total),
+        ] {
+            if let Some(mut want) = want {
+                let mut difference = got - want;
+                if difference.abs() > tolerance {
+                    // Rescaled to match the precision of the account report.
+                    let mut got = got;
+                    got.rescale(4);
+                    want.rescale(4);
+                    difference.rescale(4);
+                    mismatches.push(Mismatch {
+                        client: *client,
+                        field,
+                        expected: want.to_string(),
+                        actual: got.to_string(),
+                        difference: Some(difference),
+                    });
+                }
+            }
+        }
+        if let Some(want) = expected.locked {
+            if want != actual.locked {
+                mismatches.push(Mismatch {
+                    client: *client,
+                    field: Field::Locked,
+                    expected: want.to_string(),
+                    actual: actual.locked.to_string(),
+                    difference: None,
+                });
+            }
+        }
+    }
+
+    for client in report.keys().filter(|c| !expected.contains_key(c)) {
+        mismatches.push(Mismatch {
+            client: *client,
+            field: Field::Account,
+            expected: "missing".to_string(),
+            actual: "present".to_string(),
+            difference: None,
+        });
+    }
+    mismatches.sort_by_key(|m| m.client);
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = "client,available,held,total,locked
+1,1.5000,0.0000,1.5000,false
+2,2.0000,0.0000,2.0000,true
+3,3.0000,0.0000,3.0000,false
+";
+
+    #[test]
+    fn reconciles_within_tolerance() {
+        let expected = "client,total\n1,1.50005\n2,2\n3,3\n";
+        let mut output = vec![];
+        assert!(reconcile(
+            REPORT.as_bytes(),
+            expected.as_bytes(),
+            Amount::new(1, 4),
+            &mut output
+        )
+        .unwrap());
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let expected = "client,available,locked\n1,1.4,false\n2,2,false\n4,1,false\n";
+        let mut output = vec![];
+        assert!(!reconcile(
+            REPORT.as_bytes(),
+            expected.as_bytes(),
+            Amount::from(0),
+            &mut output
+        )
+        .unwrap());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,field,expected,actual,difference
+1,available,1.4000,1.5000,0.1000
+2,locked,false,true,
+3,account,missing,present,
+4,account,present,missing,
+"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! Rejects files: every record that couldn't be deserialized or applied, with where it starts in the input and why.
+//!
+//! A rejects file is CSV with `line` (1-based) and `byte` (0-based offset of the record's first byte) columns, the
+//! instruction's `client` and `tx` if it could be deserialized, and the `error`.
+
+use super::Location;
+use crate::bank::account::AccountId;
+use crate::bank::transaction::{Error, TransactionId};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A row of a rejects file.
+#[derive(Debug, Serialize)]
+struct Row {
+    line: u64,
+    byte: u64,
+    client: Option<AccountId>,
+    tx: Option<TransactionId>,
+    error: String,
+}
+
+/// Writes a rejects file.
+#[derive(Debug)]
+pub struct Writer {
+    writer: csv::Writer<fs::File>,
+}
+
+impl Writer {
+    /// Create a rejects file at `path`, replacing any existing one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be created.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: csv::Writer::from_writer(fs::File::create(path)?),
+        })
+    }
+
+    /// Record a record that couldn't be deserialized.
+    pub(super) fn unreadable(&mut self, location: Location, err: &csv::Error) -> csv::Result<()> {
+        self.writer.serialize(Row {
+            line: location.line,
+            byte: location.byte,
+            client: None,
+            tx: None,
+            error: err.to_string(),
+        })
+    }
+
+    /// Record an instruction the bank rejected.
+    pub(super) fn rejected(
+        &mut self,
+        location: Location,
+        client: AccountId,
+        tx: TransactionId,
+        err: Error,
+    ) -> csv::Result<()> {
+        self.writer.serialize(Row {
+            line: location.line,
+            byte: location.byte,
+            client: Some(client),
+            tx: Some(tx),
+            error: err.to_string(),
+        })
+    }
+
+    /// Flush the file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,252 @@
+//! Aggregates over a run, as an alternative to the account report.
+//!
+//! For every client: how much was deposited and withdrawn, how many disputes are still open, and how many chargebacks
+//! there were and for how much.  The same figures are also summed over the whole bank.  Only instructions that were
+//! applied are counted.
+
+use crate::bank::account::AccountId;
+use crate::bank::amount::Amount;
+use crate::bank::event::Event;
+use crate::bank::purge;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// How to write the aggregates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    /// A row per client in client id order, then a row without a client for the whole bank.
+    #[default]
+    Csv,
+    /// An object with a `clients` array and a `bank` object.
+    Json,
+}
+
+/// Figures for one client, or for the whole bank when `client` is `None`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Totals {
+    pub client: Option<AccountId>,
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    /// Disputes opened and not yet resolved or charged back.
+    pub open_disputes: u64,
+    pub chargebacks: u64,
+    pub charged_back: Amount,
+}
+
+/// Aggregates collected from a bank's events.
+#[derive(Debug, Default)]
+pub struct Aggregates {
+    clients: BTreeMap<AccountId, Totals>,
+}
+
+#[derive(Serialize)]
+struct Json {
+    clients: Vec<Totals>,
+    bank: Totals,
+}
+
+impl Totals {
+    /// A copy with amounts at the four decimal places used in the account report.
+    fn rescaled(&self) -> Self {
+        let mut totals = self.clone();
+        for amount in [
+            &mut totals.deposited,
+            &mut totals.withdrawn,
+            &mut totals.charged_back,
+        ] {
+            amount.rescale(4);
+        }
+        totals
+    }
+
+    fn add(&mut self, other: &Totals) {
+        self.deposited += other.deposited;
+        self.withdrawn += other.withdrawn;
+        self.open_disputes += other.open_disputes;
+        self.chargebacks += other.chargebacks;
+        self.charged_back += other.charged_back;
+    }
+}
+
+impl Aggregates {
+    /// Register an observer on `bank` that adds its events to `aggregates`.
+    ///
+    /// # Panics
+    ///
+    /// The observer panics if another thread panicked while holding `aggregates`' lock.
+    pub fn observe(aggregates: &Arc<Mutex<Self>>, bank: &mut Bank) {
+        let aggregates = Arc::clone(aggregates);
+        bank.register_observer(move |event: &Event| {
+            aggregates
+                .lock()
+                .expect("aggregates lock poisoned")
+                .record(event);
+        });
+    }
+
+    /// Add an event to the aggregates.  A purged client's figures are moved to the
+    /// [tombstone](../../bank/purge/constant.TOMBSTONE.html), so the bank's figures stay the same.
+    pub fn record(&mut self, event: &Event) {
+        if let Event::AccountPurged { client, .. } = event {
+            if let Some(purged) = self.clients.remove(client) {
+                let tombstone = purge::tombstone();
+                self.clients
+                    .entry(tombstone)
+                    .or_insert_with(|| Totals {
+                        client: Some(tombstone),
+                        ..Totals::default()
+                    })
+                    .add(&purged);
+            }
+            return;
+        }
+        let totals = self
+            .clients
+            .entry(event.client())
+            .or_insert_with(|| Totals {
+                client: Some(event.client()),
+                ..Totals::default()
+            });
+        match event {
+            Event::DepositApplied { amount, .. } => totals.deposited += *amount,
+            Event::WithdrawalApplied { amount, .. } => totals.withdrawn += *amount,
+            Event::DisputeOpened { .. } => totals.open_disputes += 1,
+            Event::DisputeResolved { .. } => {
+                totals.open_disputes = totals.open_disputes.saturating_sub(1);
+            }
+            Event::ChargebackApplied { amount, .. } => {
+                totals.open_disputes = totals.open_disputes.saturating_sub(1);
+                totals.chargebacks += 1;
+                totals.charged_back += *amount;
+            }
+            Event::AccountCreated { .. }
+            | Event::AccountReinstated { .. }
+            | Event::AccountUnlocked { .. }
+            | Event::AccountPurged { .. }
+            | Event::AccountAdministered { .. }
+            | Event::InstructionRolledBack { .. }
+            | Event::InstructionRejected { .. } => {}
+        }
+    }
+
+    /// Figures for each client, in client id order.
+    pub fn clients(&self) -> impl Iterator<Item = &Totals> {
+        self.clients.values()
+    }
+
+    /// Figures summed over every client.
+    #[must_use]
+    pub fn bank(&self) -> Totals {
+        let mut bank = Totals::default();
+        for totals in self.clients() {
+            bank.add(totals);
+        }
+        bank
+    }
+
+    /// Write the aggregates to `output`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the output can't be written.
+    pub fn write<W: io::Write>(
+        &self,
+        mut output: W,
+        format: Format,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match format {
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(output);
+                for totals in self.clients() {
+                    writer.serialize(totals.rescaled())?;
+                }
+                writer.serialize(self.bank().rescaled())?;
+                writer.flush()?;
+            }
+            Format::Json => {
+                serde_json::to_writer(
+                    &mut output,
+                    &Json {
+                        clients: self.clients().map(Totals::rescaled).collect(),
+                        bank: self.bank().rescaled(),
+                    },
+                )?;
+                writeln!(output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply every instruction in `input` and write the aggregates instead of the account report.
+///
+/// # Errors
+///
+/// Will return an `Err` if the input can't be read or the output can't be written.
+///
+/// # Panics
+///
+/// Panics if the bank's observer panicked.
+pub fn report<R: io::Read, W: io::Write>(
+    input: R,
+    output: W,
+    format: Format,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let aggregates = Arc::new(Mutex::new(Aggregates::default()));
+    let mut bank = Bank::new();
+    Aggregates::observe(&aggregates, &mut bank);
+    let mut reader = super::reader_builder().from_reader(input);
+    super::read_records(&mut reader, |record, _| {
+        super::handle_record(record, super::Sinks::default(), |ti, location, _| {
+            Ok(super::perform(
+                &mut bank,
+                ti,
+                location,
+                super::Sinks::default(),
+            )?)
+        })
+    })?;
+    let aggregates = aggregates.lock().expect("aggregates lock poisoned");
+    aggregates.write(output, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 10
+deposit, 1, 2, 5
+withdrawal, 1, 3, 2
+deposit, 2, 4, 7
+dispute, 1, 1,
+dispute, 1, 2,
+resolve, 1, 2,
+dispute, 2, 4,
+chargeback, 2, 4,
+withdrawal, 2, 5, 1
+";
+
+    #[test]
+    fn aggregates() {
+        let mut csv = vec![];
+        report(INPUT.as_bytes(), &mut csv, Format::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,deposited,withdrawn,open_disputes,chargebacks,charged_back\n\
+             1,15.0000,2.0000,1,0,0.0000\n\
+             2,7.0000,0.0000,0,1,7.0000\n\
+             ,22.0000,2.0000,1,1,7.0000\n"
+        );
+
+        let mut json = vec![];
+        report(INPUT.as_bytes(), &mut json, Format::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["clients"][1]["charged_back"], "7.0000");
+        assert_eq!(json["bank"]["open_disputes"], 1);
+        assert_eq!(json["bank"]["client"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,200 @@
+//! Reading inputs straight from S3, or a compatible store, given as `s3://bucket/key`.
+//!
+//! An [`Object`](struct.Object.html) streams the object's body into the CSV reader as it is read, so nothing is copied
+//! to local disk.  Seeking, for slices, checkpoints, and chunks, starts a new ranged `GET` at the new offset, and so
+//! does a request that fails part way through.
+//!
+//! Credentials come from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and `AWS_SESSION_TOKEN`, and requests are
+//! anonymous without them.  The region comes from `AWS_REGION`, defaulting to `us-east-1`, and `AWS_ENDPOINT_URL` points
+//! at another store such as `MinIO`, which is then addressed with path-style URLs.
+
+use super::ranged;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use std::io;
+use std::time::Duration;
+
+/// URL scheme of S3 inputs.
+pub const SCHEME: &str = "s3://";
+
+/// How long signed request URLs are valid for.  Each request is signed just before it is sent.
+const SIGNATURE_LIFETIME: Duration = Duration::from_mins(1);
+
+/// Errors related to opening an object.
+#[derive(Debug)]
+pub enum Error {
+    /// The input isn't an `s3://bucket/key` URL, or the endpoint isn't valid.
+    InvalidUrl(String),
+    /// The store couldn't be reached or refused the request.
+    Request(Box<ureq::Error>),
+}
+
+/// Where an object is and how to sign requests for it.
+struct Location {
+    bucket: Bucket,
+    credentials: Option<Credentials>,
+    key: String,
+}
+
+/// An object being read.
+pub struct Object {
+    reader: ranged::Reader<Location>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "invalid S3 URL: {url}"),
+            Error::Request(err) => write!(f, "S3 request failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUrl(_) => None,
+            Error::Request(err) => Some(&**err),
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(err: ureq::Error) -> Self {
+        Error::Request(Box::new(err))
+    }
+}
+
+impl std::fmt::Debug for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = self.reader.source();
+        f.debug_struct("Object")
+            .field("bucket", &location.bucket.name())
+            .field("key", &location.key)
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether `input` names an S3 object rather than a local file.
+#[must_use]
+pub fn is_s3(input: &str) -> bool {
+    input.starts_with(SCHEME)
+}
+
+/// Split an `s3://bucket/key` URL into the bucket and key.
+fn parse(url: &str) -> Option<(&str, &str)> {
+    let (bucket, key) = url.strip_prefix(SCHEME)?.split_once('/')?;
+    if bucket.is_empty() || key.is_empty() {
+        return None;
+    }
+    Some((bucket, key))
+}
+
+impl Object {
+    /// Open the object at `url`, configured from the environment, and look up its length.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the URL or endpoint is invalid or the object can't be found.
+    pub fn open(url: &str) -> Result<Self, Error> {
+        let (name, key) = parse(url).ok_or_else(|| Error::InvalidUrl(url.to_string()))?;
+        let region = std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let (endpoint, style) = match std::env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => (endpoint, UrlStyle::Path),
+            Err(_) => (
+                format!("https://s3.{region}.amazonaws.com"),
+                UrlStyle::VirtualHost,
+            ),
+        };
+        let invalid = || Error::InvalidUrl(endpoint.clone());
+        let bucket = Bucket::new(
+            endpoint.parse().map_err(|_| invalid())?,
+            style,
+            name.to_string(),
+            region,
+        )
+        .map_err(|_| invalid())?;
+        let location = Location {
+            bucket,
+            credentials: Credentials::from_env(),
+            key: key.to_string(),
+        };
+        let head = location
+            .bucket
+            .head_object(location.credentials.as_ref(), &location.key)
+            .sign(SIGNATURE_LIFETIME);
+        let response = ureq::request_url("HEAD", &head).call()?;
+        let len = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .unwrap_or_default();
+        Ok(Self {
+            reader: ranged::Reader::new(location, len),
+        })
+    }
+
+    /// Length of the object in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.reader.len()
+    }
+
+    /// Whether the object is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ranged::Source for Location {
+    fn read_from(&self, offset: u64) -> io::Result<ranged::Body> {
+        let get = self
+            .bucket
+            .get_object(self.credentials.as_ref(), &self.key)
+            .sign(SIGNATURE_LIFETIME);
+        let response = ureq::request_url("GET", &get)
+            .set("Range", &format!("bytes={offset}-"))
+            .call()
+            .map_err(io::Error::other)?;
+        Ok(response.into_reader())
+    }
+}
+
+impl io::Read for Object {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl io::Seek for Object {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(
+            parse("s3://exports/2024/05/01.csv"),
+            Some(("exports", "2024/05/01.csv"))
+        );
+        assert!(is_s3("s3://exports/01.csv"));
+        assert!(!is_s3("exports/01.csv"));
+        for invalid in [
+            "s3://exports",
+            "s3://exports/",
+            "s3:///01.csv",
+            "exports/01.csv",
+        ] {
+            assert_eq!(parse(invalid), None, "{invalid}");
+        }
+        assert!(matches!(
+            Object::open("s3://exports"),
+            Err(Error::InvalidUrl(_))
+        ));
+    }
+}
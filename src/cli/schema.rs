@@ -0,0 +1,184 @@
+//! Schema checks: a pass over an input that checks every record without applying any, so that a bad file is caught
+//! before a long run.
+//!
+//! The header row has to name the `type`, `client`, and `tx` columns, and every record has to deserialize into a
+//! well-formed instruction: a known type, valid ids and amounts, and an amount where the type needs one.  Columns
+//! that aren't instruction fields are reported but allowed, since they are ignored when processing.
+
+use super::{amounts, headers, RecordParser};
+use crate::bank::transaction::instruction::FIELDS;
+use std::collections::BTreeMap;
+use std::io;
+
+/// Columns every input needs.
+const REQUIRED: [&str; 3] = ["type", "client", "tx"];
+
+/// Number of errors kept as examples.
+const MAX_EXAMPLES: usize = 10;
+
+/// What a schema check found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    /// The header row, after mapping.
+    pub columns: Vec<String>,
+    /// Required columns the header row doesn't have.
+    pub missing: Vec<&'static str>,
+    /// Columns that aren't instruction fields.
+    pub unknown: Vec<String>,
+    /// Number of records, valid or not.
+    pub rows: u64,
+    /// Number of valid records of each type, by name.
+    pub kinds: BTreeMap<&'static str, u64>,
+    /// Number of invalid records.
+    pub errors: u64,
+    /// The line numbers and errors of the first few invalid records.
+    pub examples: Vec<(u64, String)>,
+}
+
+impl Schema {
+    /// Whether the input can be processed without any records being skipped.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.missing.is_empty() && self.errors == 0
+    }
+
+    fn record_error(&mut self, line: u64, error: String) {
+        self.errors += 1;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push((line, error));
+        }
+    }
+}
+
+/// Check every record of `input`, with its headers renamed by `mappings` and amounts written in `notation`.
+///
+/// # Errors
+///
+/// Will return `Err` if the input can't be read.
+pub fn check<R: io::Read>(
+    input: R,
+    mappings: &[headers::Mapping],
+    notation: amounts::Notation,
+) -> Result<Schema, csv::Error> {
+    let mut reader = super::reader_builder().from_reader(input);
+    headers::apply(&mut reader, mappings)?;
+    let parser = RecordParser::new(&mut reader, notation)?;
+    let columns: Vec<String> = parser.headers.iter().map(String::from).collect();
+    let mut schema = Schema {
+        missing: REQUIRED
+            .iter()
+            .copied()
+            .filter(|field| !columns.iter().any(|column| column == *field))
+            .collect(),
+        unknown: columns
+            .iter()
+            .filter(|column| !FIELDS.contains(&column.as_str()))
+            .cloned()
+            .collect(),
+        columns,
+        ..Schema::default()
+    };
+
+    let mut record = csv::StringRecord::new();
+    loop {
+        let line = reader.position().line();
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {
+                schema.rows += 1;
+                let line = record.position().map_or(line, csv::Position::line);
+                match parser.parse(&record).instruction {
+                    Ok(ti) => match ti.validate() {
+                        Ok(()) => *schema.kinds.entry(super::kind_name(ti.kind)).or_default() += 1,
+                        Err(err) => schema.record_error(line, err.to_string()),
+                    },
+                    Err(err) => schema.record_error(line, err.to_string()),
+                }
+            }
+            Err(err) if err.is_io_error() => return Err(err),
+            Err(err) => {
+                schema.rows += 1;
+                schema.record_error(line, err.to_string());
+            }
+        }
+    }
+    Ok(schema)
+}
+
+/// Write `schema` as a report for reading.
+///
+/// # Errors
+///
+/// Will return `Err` if the output can't be written.
+pub fn write<W: io::Write>(schema: &Schema, mut output: W) -> io::Result<()> {
+    let list = |items: &[&str]| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    let columns: Vec<_> = schema.columns.iter().map(String::as_str).collect();
+    let unknown: Vec<_> = schema.unknown.iter().map(String::as_str).collect();
+    writeln!(output, "columns: {}", list(&columns))?;
+    writeln!(output, "missing columns: {}", list(&schema.missing))?;
+    writeln!(output, "unknown columns: {}", list(&unknown))?;
+    writeln!(output, "rows: {}", schema.rows)?;
+    for (kind, count) in &schema.kinds {
+        writeln!(output, "  {kind}: {count}")?;
+    }
+    writeln!(output, "errors: {}", schema.errors)?;
+    for (line, error) in &schema.examples {
+        writeln!(output, "  line {line}: {error}")?;
+    }
+    if schema.errors > schema.examples.len() as u64 {
+        writeln!(
+            output,
+            "  and {} more",
+            schema.errors - schema.examples.len() as u64
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_kinds_and_errors() {
+        let input = "type, client, tx, amount, note
+deposit, 1, 1, 1.5, a
+deposit, 2, 2, 2, b
+withdrawal, 1, 3, 1, c
+refund, 1, 4, 1, d
+deposit, 1, 5,, e
+dispute, 1, 1,, f
+";
+        let schema = check(input.as_bytes(), &[], amounts::Notation::default()).unwrap();
+        assert!(!schema.is_valid());
+        assert!(schema.missing.is_empty());
+        assert_eq!(schema.unknown, ["note"]);
+        assert_eq!(schema.rows, 6);
+        assert_eq!(
+            schema.kinds.into_iter().collect::<Vec<_>>(),
+            [("deposit", 2), ("dispute", 1), ("withdrawal", 1)]
+        );
+        assert_eq!(schema.errors, 2);
+        assert_eq!(schema.examples[0].0, 5);
+        assert_eq!(schema.examples[1], (6, "amount is missing".to_string()));
+
+        let schema = check(
+            "kind,client\n".as_bytes(),
+            &[],
+            amounts::Notation::default(),
+        )
+        .unwrap();
+        assert_eq!(schema.missing, ["type", "tx"]);
+        let mut output = vec![];
+        write(&schema, &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("missing columns: type, tx\nunknown columns: kind\n"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Slices of an input, for processing part of a huge file without editing it, such as when bisecting which record
+//! corrupts balances.
+//!
+//! A slice can start at a byte offset, which has to be the start of a record, like the `byte` column of a rejects
+//! file.  It then skips a number of records and stops after a number of records.  Line numbers and byte offsets in
+//! logs and rejects files stay those of the whole file.
+
+use std::io::{self, Read, Seek};
+
+/// Which part of an input to process.  The default is the whole input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Slice {
+    /// Byte offset of the first record to read, after the header row.
+    pub start_offset: Option<u64>,
+    /// Number of records to skip, from the start offset if there is one.
+    pub skip_rows: u64,
+    /// Number of records to process after skipping.
+    pub limit: Option<u64>,
+}
+
+impl Slice {
+    /// Whether the slice is the whole input.
+    #[must_use]
+    pub fn is_whole(&self) -> bool {
+        *self == Slice::default()
+    }
+}
+
+/// Move `reader` to the first record of `slice`, reading the header row first if it hasn't been read.
+pub(super) fn start<R: Read + Seek>(
+    reader: &mut csv::Reader<R>,
+    slice: &Slice,
+) -> Result<(), Box<dyn std::error::Error>> {
+    reader.headers()?;
+    if let Some(offset) = slice.start_offset {
+        let mut position = reader.position().clone();
+        let Some(skipped) = offset.checked_sub(position.byte()) else {
+            return Err(format!("start offset {offset} is inside the header row").into());
+        };
+        // Count the lines being skipped, so that locations are still those of the whole file.
+        reader.seek_raw(io::SeekFrom::Start(position.byte()), position.clone())?;
+        let mut lines = 0;
+        let mut read = 0;
+        let mut buffer = [0; 8192];
+        let mut skipping = reader.get_mut().take(skipped);
+        loop {
+            let n = skipping.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            read += n as u64;
+            lines += buffer[..n].split(|b| *b == b'\n').count() as u64 - 1;
+        }
+        if read < skipped {
+            return Err(format!("start offset {offset} is past the end of the input").into());
+        }
+        let line = position.line() + lines;
+        position.set_byte(offset).set_line(line);
+        reader.seek_raw(io::SeekFrom::Start(offset), position)?;
+    }
+    let mut record = csv::ByteRecord::new();
+    for _ in 0..slice.skip_rows {
+        if !reader.read_byte_record(&mut record)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_at_offset_and_skips() {
+        let input =
+            "type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,1\ndeposit,1,3,1\ndeposit,1,4,1\n";
+        let offset = input.find("deposit,1,2").unwrap() as u64;
+        let mut reader = super::super::reader_builder().from_reader(io::Cursor::new(input));
+        let slice = Slice {
+            start_offset: Some(offset),
+            skip_rows: 1,
+            limit: None,
+        };
+        start(&mut reader, &slice).unwrap();
+        let mut record = csv::StringRecord::new();
+        assert!(reader.read_record(&mut record).unwrap());
+        assert_eq!(&record[2], "3");
+        assert_eq!(record.position().unwrap().line(), 4);
+
+        let mut reader = super::super::reader_builder().from_reader(io::Cursor::new(input));
+        let slice = Slice {
+            start_offset: Some(3),
+            ..Slice::default()
+        };
+        assert!(start(&mut reader, &slice).is_err());
+        assert!(!slice.is_whole());
+    }
+}
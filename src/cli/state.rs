@@ -0,0 +1,499 @@
+//! Portable archives of a bank's state, for moving it between machines and between the places state is kept.
+//!
+//! State is kept in three places: a [snapshot](../../bank/snapshot/index.html) file, such as the one `serve
+//! --snapshot` loads; a state directory of a long-running mode like `watch` or `redis`, which holds its latest
+//! snapshot; and a `--state-dir` [checkpoint](../checkpoint/index.html), which also holds how far through its input
+//! the run got.  An archive holds the state from any of them in one file that describes itself.
+//!
+//! The first line of an archive is a JSON [`Header`](struct.Header.html) and the rest is a snapshot of the bank:
+//!
+//! ```text
+//! {"format":"transactomatic-state","version":1,"exported_at":1760000000,"exported_by":"transactomatic 0.1.0",...}
+//! {"version":2,"accounts":[...],"transactions":[...],...}
+//! ```
+//!
+//! The header has the archive format version, where the state came from, and what's in it: the number of accounts and
+//! transactions, the bank's total funds, and the SHA-256 of the snapshot.  Importing an archive checks all of them
+//! before anything is written, so a truncated or altered archive is refused rather than half-imported.
+//!
+//! [`migrate`](fn.migrate.html) moves state from one place to another directly, without an archive, and reads it back
+//! afterwards to check that every account and transaction arrived.
+
+use super::checkpoint::{self, Checkpointer};
+use super::replace_file;
+use crate::bank::amount::Amount;
+use crate::bank::Bank;
+use crate::stream::Snapshotter;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What the first line of an archive says it is.
+pub const FORMAT: &str = "transactomatic-state";
+
+/// The archive format version written by this version of the crate.
+pub const VERSION: u32 = 1;
+
+/// Reasons an archive can't be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The file isn't a state archive.
+    NotAnArchive,
+    /// The archive was written in a format version this crate doesn't know about.
+    UnsupportedVersion(u32),
+    /// The archive's contents don't match its header; says what doesn't.
+    Corrupt(&'static str),
+    /// The state has no input position, which a checkpoint needs.
+    NoPosition,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotAnArchive => write!(f, "not a state archive"),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "state archive version {version} isn't supported, only up to {VERSION}"
+            ),
+            Error::Corrupt(what) => write!(f, "state archive is corrupt: {what} doesn't match"),
+            Error::NoPosition => write!(
+                f,
+                "state has no input position, so it can't be kept as a checkpoint"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Where state is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// A snapshot file.
+    Snapshot,
+    /// The state directory of a long-running mode, such as `watch` or `redis`.
+    StateDir,
+    /// A `--state-dir` checkpoint of a run over a file.
+    Checkpoint,
+}
+
+impl Kind {
+    /// The kind of state kept at `path`: a checkpoint or a state directory if it's a directory, otherwise a snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a directory can't be read.
+    pub fn of(path: &Path) -> io::Result<Self> {
+        if !path.is_dir() {
+            return Ok(Kind::Snapshot);
+        }
+        Ok(if Checkpointer::new(path, 0)?.exists() {
+            Kind::Checkpoint
+        } else {
+            Kind::StateDir
+        })
+    }
+}
+
+/// The first line of an archive.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Header {
+    /// Always [`FORMAT`](constant.FORMAT.html).
+    pub format: String,
+    pub version: u32,
+    /// Seconds since the Unix epoch when the archive was written.
+    pub exported_at: u64,
+    /// The program and version that wrote the archive.
+    pub exported_by: String,
+    /// Where the state was exported from.
+    pub source: Kind,
+    pub accounts: usize,
+    pub transactions: usize,
+    /// Total funds of every account.
+    pub total: Amount,
+    /// Input position of a checkpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) position: Option<checkpoint::Position>,
+    /// SHA-256 of the snapshot following the header, in lowercase hex.
+    pub sha256: String,
+}
+
+/// The part of a header that every version has.
+#[derive(Deserialize)]
+struct Version {
+    format: String,
+    version: u32,
+}
+
+/// Export the state kept at `source` as an archive written to `output`, returning its header.
+///
+/// # Errors
+///
+/// Will return `Err` if there's no state at `source`, it can't be read, or the archive can't be written.
+pub fn export<W: io::Write>(
+    source: &Path,
+    mut output: W,
+) -> Result<Header, Box<dyn std::error::Error>> {
+    let (kind, bank, position) = load(source)?;
+    let mut snapshot = vec![];
+    bank.save_snapshot(&mut snapshot)?;
+    let header = Header {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        exported_by: format!("transactomatic {}", env!("CARGO_PKG_VERSION")),
+        source: kind,
+        accounts: bank.totals().accounts,
+        transactions: bank.transactions().count(),
+        total: bank.totals().total,
+        position,
+        sha256: sha256(&snapshot),
+    };
+    serde_json::to_writer(&mut output, &header)?;
+    output.write_all(b"\n")?;
+    output.write_all(&snapshot)?;
+    output.flush()?;
+    Ok(header)
+}
+
+/// Read an archive from `input` and check that its contents match its header.
+///
+/// # Errors
+///
+/// Will return `Err` if the archive can't be read, isn't an archive, has an unsupported version, or doesn't match its
+/// header.
+pub fn read<R: io::Read>(input: R) -> Result<(Header, Bank), Box<dyn std::error::Error>> {
+    let mut input = io::BufReader::new(input);
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    // Checked before the rest of the header, which a later version might have changed.
+    let Ok(Version { format, version }) = serde_json::from_str(&line) else {
+        return Err(Error::NotAnArchive.into());
+    };
+    if format != FORMAT {
+        return Err(Error::NotAnArchive.into());
+    }
+    if version > VERSION {
+        return Err(Error::UnsupportedVersion(version).into());
+    }
+    let header: Header = serde_json::from_str(&line).map_err(|_| Error::NotAnArchive)?;
+    let mut snapshot = vec![];
+    input.read_to_end(&mut snapshot)?;
+    if sha256(&snapshot) != header.sha256 {
+        return Err(Error::Corrupt("the checksum").into());
+    }
+    let bank = Bank::load_snapshot(snapshot.as_slice())?;
+    if bank.totals().accounts != header.accounts
+        || bank.transactions().count() != header.transactions
+    {
+        return Err(Error::Corrupt("the number of accounts or transactions").into());
+    }
+    if bank.totals().total != header.total {
+        return Err(Error::Corrupt("the total funds").into());
+    }
+    Ok((header, bank))
+}
+
+/// Import the archive read from `input` into `destination` as state of `kind`, or of the kind it was exported from,
+/// returning its header.  Existing state at `destination` is only replaced if `force` is set.
+///
+/// # Errors
+///
+/// Will return `Err` if the archive can't be [read](fn.read.html), there's already state at `destination`, or the
+/// state can't be written.
+pub fn import<R: io::Read>(
+    input: R,
+    destination: &Path,
+    kind: Option<Kind>,
+    force: bool,
+) -> Result<Header, Box<dyn std::error::Error>> {
+    let (header, bank) = read(input)?;
+    let kind = kind.unwrap_or(header.source);
+    save(&bank, header.position.clone(), destination, kind, force)?;
+    Ok(header)
+}
+
+/// Move the state kept at `source` to `destination` as state of `kind`, or of the kind it is at `source`, and check
+/// that it arrived intact.  Existing state at `destination` is only replaced if `force` is set.  How far it has got
+/// is written to `output`, and every `every` accounts while checking.
+///
+/// # Errors
+///
+/// Will return `Err` if there's no state at `source`, it can't be read or written, there's already state at
+/// `destination`, or what was written doesn't match what was read.
+pub fn migrate<W: io::Write>(
+    source: &Path,
+    destination: &Path,
+    kind: Option<Kind>,
+    force: bool,
+    every: usize,
+    mut output: W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (from, bank, position) = load(source)?;
+    let transactions = bank.transactions().count();
+    writeln!(
+        output,
+        "read {} accounts and {transactions} transactions from {}",
+        bank.totals().accounts,
+        source.display()
+    )?;
+    let kind = kind.unwrap_or(from);
+    save(&bank, position, destination, kind, force)?;
+    writeln!(output, "wrote {}", destination.display())?;
+
+    let (_, migrated, _) = load(destination)?;
+    let accounts = bank.accounts().count();
+    for (done, account) in bank.accounts().enumerate() {
+        if migrated.account(&account.client) != Some(account)
+            || !bank
+                .history(account.client)
+                .eq(migrated.history(account.client))
+        {
+            return Err(format!("client {} doesn't match after migrating", account.client).into());
+        }
+        if every > 0 && (done + 1) % every == 0 {
+            writeln!(output, "checked {} of {accounts} accounts", done + 1)?;
+        }
+    }
+    if migrated.totals() != bank.totals() || migrated.transactions().count() != transactions {
+        return Err("totals don't match after migrating".into());
+    }
+    writeln!(
+        output,
+        "checked {accounts} accounts and {transactions} transactions"
+    )?;
+    Ok(())
+}
+
+/// The state kept at `source`: where it's kept, the bank, and the input position of a checkpoint.
+fn load(
+    source: &Path,
+) -> Result<(Kind, Bank, Option<checkpoint::Position>), Box<dyn std::error::Error>> {
+    let kind = Kind::of(source)?;
+    match kind {
+        Kind::Snapshot => {
+            let bank = Bank::load_snapshot(io::BufReader::new(fs::File::open(source)?))?;
+            Ok((kind, bank, None))
+        }
+        Kind::StateDir => {
+            let snapshotter = Snapshotter::new(source, 0)?;
+            if !snapshotter.exists() {
+                return Err(format!("no state in {}", source.display()).into());
+            }
+            Ok((kind, snapshotter.load()?, None))
+        }
+        Kind::Checkpoint => {
+            let checkpoint = Checkpointer::new(source, 0)?
+                .load()?
+                .ok_or_else(|| format!("no checkpoint in {}", source.display()))?;
+            let position = checkpoint::Position::from(&checkpoint.position);
+            Ok((kind, checkpoint.bank, Some(position)))
+        }
+    }
+}
+
+/// Keep `bank` at `destination` as state of `kind`, unless there's state there already and `force` isn't set.
+fn save(
+    bank: &Bank,
+    position: Option<checkpoint::Position>,
+    destination: &Path,
+    kind: Kind,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let exists = |exists: bool| -> Result<(), Box<dyn std::error::Error>> {
+        if exists && !force {
+            return Err(format!("there's already state in {}", destination.display()).into());
+        }
+        Ok(())
+    };
+    match kind {
+        Kind::Snapshot => {
+            exists(destination.exists())?;
+            replace_file(destination, |writer| Ok(bank.save_snapshot(writer)?))?;
+        }
+        Kind::StateDir => {
+            let mut snapshotter = Snapshotter::new(destination, 0)?;
+            exists(snapshotter.exists())?;
+            snapshotter.save(bank)?;
+        }
+        Kind::Checkpoint => {
+            let position = position.ok_or(Error::NoPosition)?;
+            let mut checkpointer = Checkpointer::new(destination, 0)?;
+            exists(checkpointer.exists())?;
+            checkpointer.save(bank, &position.into())?;
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 of `bytes`, in lowercase hex.
+fn sha256(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in Sha256::digest(bytes) {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+
+    #[test]
+    fn exports_and_imports() {
+        let dir = std::env::temp_dir().join(format!("transactomatic-state-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut bank = Bank::new();
+        for line in ["deposit,1,1,5", "deposit,2,2,3", "dispute,2,2"] {
+            bank.perform_transaction(line.parse().unwrap()).unwrap();
+        }
+        let mut position = csv::Position::new();
+        position.set_byte(42).set_line(4).set_record(3);
+        Checkpointer::new(dir.join("run"), 0)
+            .unwrap()
+            .save(&bank, &position)
+            .unwrap();
+
+        let mut archive = vec![];
+        let header = export(&dir.join("run"), &mut archive).unwrap();
+        assert_eq!(header.source, Kind::Checkpoint);
+        assert_eq!((header.accounts, header.transactions), (2, 2));
+
+        // Moved into a long-running mode's state directory, then back into a checkpoint.
+        import(
+            archive.as_slice(),
+            &dir.join("watch"),
+            Some(Kind::StateDir),
+            false,
+        )
+        .unwrap();
+        assert!(import(
+            archive.as_slice(),
+            &dir.join("watch"),
+            Some(Kind::StateDir),
+            false
+        )
+        .is_err());
+        import(archive.as_slice(), &dir.join("resumed"), None, false).unwrap();
+        let resumed = Checkpointer::new(dir.join("resumed"), 0)
+            .unwrap()
+            .load()
+            .unwrap()
+            .unwrap();
+        assert_eq!(resumed.position.byte(), 42);
+        assert_eq!(resumed.bank.totals(), bank.totals());
+
+        let mut again = vec![];
+        let header = export(&dir.join("watch"), &mut again).unwrap();
+        assert_eq!((header.source, header.position), (Kind::StateDir, None));
+        assert_eq!(
+            import(
+                again.as_slice(),
+                &dir.join("other"),
+                Some(Kind::Checkpoint),
+                false
+            )
+            .unwrap_err()
+            .to_string(),
+            Error::NoPosition.to_string()
+        );
+        let (_, restored) = read(again.as_slice()).unwrap();
+        assert_eq!(
+            restored.account(&AccountId::Number(2)).unwrap().held,
+            Amount::from(3)
+        );
+
+        let text = String::from_utf8(archive.clone()).unwrap();
+        let (first, body) = text.split_once('\n').unwrap();
+        let tampered = format!(
+            "{first}\n{}",
+            body.replacen("\"client\":1", "\"client\":3", 1)
+        );
+        assert_ne!(tampered, text);
+        assert_eq!(
+            read(tampered.as_bytes()).unwrap_err().to_string(),
+            Error::Corrupt("the checksum").to_string()
+        );
+        let newer = String::from_utf8(archive).unwrap().replacen(
+            "\"version\":1,",
+            "\"version\":2,\"layout\":[],",
+            1,
+        );
+        assert_eq!(
+            read(newer.as_bytes()).unwrap_err().to_string(),
+            Error::UnsupportedVersion(2).to_string()
+        );
+        assert!(read(&b"{\"version\":1}\n"[..]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn migrates_and_checks() {
+        let dir =
+            std::env::temp_dir().join(format!("transactomatic-migrate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut bank = Bank::new();
+        for line in ["deposit,1,1,5", "deposit,2,2,3", "withdrawal,1,3,1"] {
+            bank.perform_transaction(line.parse().unwrap()).unwrap();
+        }
+        bank.save_snapshot(fs::File::create(dir.join("snapshot.json")).unwrap())
+            .unwrap();
+
+        let mut output = vec![];
+        let (source, destination) = (dir.join("snapshot.json"), dir.join("redis"));
+        migrate(
+            &source,
+            &destination,
+            Some(Kind::StateDir),
+            false,
+            1,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap().lines().last(),
+            Some("checked 2 accounts and 3 transactions")
+        );
+        assert_eq!(Kind::of(&destination).unwrap(), Kind::StateDir);
+        assert_eq!(
+            Snapshotter::new(&destination, 0)
+                .unwrap()
+                .load()
+                .unwrap()
+                .totals(),
+            bank.totals()
+        );
+        assert!(migrate(
+            &source,
+            &destination,
+            Some(Kind::StateDir),
+            false,
+            0,
+            io::sink()
+        )
+        .is_err());
+        assert_eq!(
+            migrate(
+                &source,
+                &dir.join("run"),
+                Some(Kind::Checkpoint),
+                false,
+                0,
+                io::sink()
+            )
+            .unwrap_err()
+            .to_string(),
+            Error::NoPosition.to_string()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! Per-client statements over a date range, built from timestamped instructions.
+//!
+//! Every instruction is applied in input order, which should be chronological.  A statement opens with the client's
+//! balances at the start of the range, lists the instructions applied within it with the balances after each, and
+//! closes with the balances at the end of the range.  Disputes, resolutions, chargebacks, and reinstatements are
+//! listed with the amount of the transaction they refer to.  Instructions that couldn't be applied are left out.
+
+use super::date;
+use crate::bank::account::{Account, AccountId, AccountSummary};
+use crate::bank::amount::Amount;
+use crate::bank::transaction::instruction::TransactionInstructionKind;
+use crate::bank::transaction::TransactionId;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+
+/// How to write statements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    /// A row per line of every statement, with the client in the first column.
+    #[default]
+    Csv,
+    /// A table per statement, for reading.
+    Text,
+}
+
+/// The time statements cover, from `start` up to but not including `end`.  `None` leaves that side open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Period {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+/// An instruction applied within a statement's period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub timestamp: u64,
+    pub tx: TransactionId,
+    pub kind: TransactionInstructionKind,
+    /// The instruction's amount, or the referenced transaction's for disputes and the like.
+    pub amount: Option<Amount>,
+    /// The client's balances after the instruction.
+    pub balance: AccountSummary,
+}
+
+/// A client's statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub client: AccountId,
+    pub opening: AccountSummary,
+    pub lines: Vec<Line>,
+    pub closing: AccountSummary,
+}
+
+/// A row of the CSV format.
+#[derive(Debug, Serialize)]
+struct Row {
+    client: AccountId,
+    date: String,
+    entry: &'static str,
+    tx: Option<TransactionId>,
+    amount: Option<Amount>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+}
+
+impl Period {
+    /// The period from the start of `from` to the end of `to`, both `YYYY-MM-DD` dates parsed by
+    /// [`date::parse`](../date/fn.parse.html).
+    #[must_use]
+    pub fn from_dates(from: Option<u64>, to: Option<u64>) -> Self {
+        Self {
+            start: from,
+            end: to.map(|to| to + date::DAY),
+        }
+    }
+
+    fn contains_or_precedes(&self, timestamp: u64) -> bool {
+        self.end.is_none_or(|end| timestamp < end)
+    }
+
+    fn precedes(&self, timestamp: u64) -> bool {
+        self.start.is_some_and(|start| timestamp < start)
+    }
+
+    /// The first and last dates covered, or `None` for an open side.
+    fn dates(&self) -> (Option<String>, Option<String>) {
+        (
+            self.start.map(date::format_date),
+            self.end.map(|end| date::format_date(end - 1)),
+        )
+    }
+}
+
+impl Statement {
+    fn new(client: AccountId) -> Self {
+        let empty = AccountSummary::from(&Account::new(client));
+        Self {
+            client,
+            opening: empty,
+            lines: vec![],
+            closing: empty,
+        }
+    }
+}
+
+/// Apply every instruction in `input` and collect the statements of `client`, or of every client with an account by
+/// the end of `period`, in client id order.
+///
+/// # Errors
+///
+/// Will return `Err` if the input can't be read or an instruction has no timestamp.
+pub fn statements<R: io::Read>(
+    input: R,
+    client: Option<AccountId>,
+    period: Period,
+) -> Result<Vec<Statement>, Box<dyn std::error::Error>> {
+    let mut bank = Bank::new();
+    let mut statements = BTreeMap::new();
+    let mut reader = super::reader_builder().from_reader(input);
+    super::read_records(&mut reader, |record, _| {
+        super::handle_record(record, super::Sinks::default(), |ti, location, _| {
+            let Some(timestamp) = ti.timestamp else {
+                return Err(
+                    format!("instruction for transaction {} has no timestamp", ti.tx.0).into(),
+                );
+            };
+            let (account, tx, kind, amount) = (ti.client, ti.tx, ti.kind, ti.amount);
+            let balance = match bank.perform_transaction(ti) {
+                Ok(account) => AccountSummary::from(account),
+                Err(err) => {
+                    tracing::error!(
+                        ?err,
+                        line = location.line,
+                        byte = location.byte,
+                        "error applying transaction"
+                    );
+                    return Ok(());
+                }
+            };
+            if client.is_some_and(|client| client != account)
+                || !period.contains_or_precedes(timestamp)
+            {
+                return Ok(());
+            }
+
+            let statement = statements
+                .entry(account)
+                .or_insert_with(|| Statement::new(account));
+            if period.precedes(timestamp) {
+                statement.opening = balance;
+            } else {
+                let mut amount = amount.or_else(|| {
+                    bank.client_transaction(account, tx)
+                        .map(|transaction| transaction.amount)
+                });
+                if let Some(amount) = &mut amount {
+                    amount.rescale(4);
+                }
+                statement.lines.push(Line {
+                    timestamp,
+                    tx,
+                    kind,
+                    amount,
+                    balance,
+                });
+            }
+            statement.closing = balance;
+            Ok(())
+        })
+    })?;
+    Ok(statements.into_values().collect())
+}
+
+/// A statement's rows: opening balance, lines, and closing balance.
+fn rows(statement: &Statement, from: Option<&str>, to: Option<&str>) -> Vec<Row> {
+    let row = |date: String, entry, tx, amount, balance: &AccountSummary| Row {
+        client: statement.client,
+        date,
+        entry,
+        tx,
+        amount,
+        available: balance.available,
+        held: balance.held,
+        total: balance.total,
+    };
+    let mut rows = vec![row(
+        from.unwrap_or_default().to_string(),
+        "opening",
+        None,
+        None,
+        &statement.opening,
+    )];
+    rows.extend(statement.lines.iter().map(|line| {
+        row(
+            date::format_time(line.timestamp),
+            super::kind_name(line.kind),
+            Some(line.tx),
+            line.amount,
+            &line.balance,
+        )
+    }));
+    rows.push(row(
+        to.unwrap_or_default().to_string(),
+        "closing",
+        None,
+        None,
+        &statement.closing,
+    ));
+    rows
+}
+
+/// Write `statements` covering `period`.
+///
+/// # Errors
+///
+/// Will return `Err` if the output can't be written.
+pub fn write<W: io::Write>(
+    statements: &[Statement],
+    period: Period,
+    mut output: W,
+    format: Format,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (from, to) = period.dates();
+    let (from, to) = (from.as_deref(), to.as_deref());
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(output);
+            for statement in statements {
+                for row in rows(statement, from, to) {
+                    writer.serialize(row)?;
+                }
+            }
+            writer.flush()?;
+        }
+        Format::Text => {
+            for (i, statement) in statements.iter().enumerate() {
+                if i > 0 {
+                    writeln!(output)?;
+                }
+                writeln!(
+                    output,
+                    "Statement for client {} from {} to {}",
+                    statement.client,
+                    from.unwrap_or("the first instruction"),
+                    to.unwrap_or("the last instruction"),
+                )?;
+                writeln!(
+                    output,
+                    "{:<19}  {:<10}  {:>10}  {:>14}  {:>14}  {:>14}  {:>14}",
+                    "Date", "Entry", "Tx", "Amount", "Available", "Held", "Total"
+                )?;
+                for row in rows(statement, from, to) {
+                    writeln!(
+                        output,
+                        "{:<19}  {:<10}  {:>10}  {:>14}  {:>14}  {:>14}  {:>14}",
+                        row.date,
+                        row.entry,
+                        row.tx.map(|tx| tx.0.to_string()).unwrap_or_default(),
+                        row.amount
+                            .map(|amount| amount.to_string())
+                            .unwrap_or_default(),
+                        row.available.to_string(),
+                        row.held.to_string(),
+                        row.total.to_string(),
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply every instruction in `input` and write the statements of `client`, or of every client, over `period`.
+///
+/// # Errors
+///
+/// Will return `Err` if the input can't be read, an instruction has no timestamp, or the output can't be written.
+pub fn statement<R: io::Read, W: io::Write>(
+    input: R,
+    output: W,
+    client: Option<AccountId>,
+    period: Period,
+    format: Format,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write(&statements(input, client, period)?, period, output, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "type, client, tx, amount, correlation_id, operator_reference, timestamp
+deposit, 1, 1, 100,,, 1704067200
+deposit, 2, 2, 5,,, 1704153600
+withdrawal, 1, 3, 30,,, 1706745600
+dispute, 1, 1,,,, 1706832000
+withdrawal, 1, 4, 500,,, 1706918400
+resolve, 1, 1,,,, 1709251200
+";
+
+    #[test]
+    fn february() {
+        let period = Period::from_dates(
+            Some(date::parse("2024-02-01").unwrap()),
+            Some(date::parse("2024-02-29").unwrap()),
+        );
+        let mut output = vec![];
+        statement(
+            INPUT.as_bytes(),
+            &mut output,
+            Some(AccountId::Number(1)),
+            period,
+            Format::Csv,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,date,entry,tx,amount,available,held,total
+1,2024-02-01,opening,,,100.0000,0.0000,100.0000
+1,2024-02-01 00:00:00,withdrawal,3,30.0000,70.0000,0.0000,70.0000
+1,2024-02-02 00:00:00,dispute,1,100.0000,-30.0000,100.0000,70.0000
+1,2024-02-29,closing,,,-30.0000,100.0000,70.0000
+"
+        );
+
+        let all = statements(INPUT.as_bytes(), None, Period::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].lines.len(), 4, "the failed withdrawal is left out");
+        assert_eq!(all[0].closing.available.to_string(), "70.0000");
+
+        let missing = "type, client, tx, amount\ndeposit, 1, 1, 1\n";
+        assert!(statements(missing.as_bytes(), None, Period::default()).is_err());
+    }
+}
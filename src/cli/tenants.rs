@@ -0,0 +1,227 @@
+//! Multi-tenant processing, for one consolidated feed covering several business units.
+//!
+//! An input with a `tenant` column is processed as one independent [Bank](../../bank/struct.Bank.html) per tenant.
+//! Clients and transaction ids of different tenants never meet, so tenant `a`'s client 1 and tenant `b`'s client 1
+//! are different accounts, and both can have a transaction 1.
+//!
+//! The account report gets a leading `tenant` column, with each tenant's accounts together and the tenants in name
+//! order:
+//!
+//! ```text
+//! tenant,client,available,held,total,locked
+//! retail,1,1.5000,0.0000,1.5000,false
+//! wholesale,1,200.0000,0.0000,200.0000,false
+//! ```
+//!
+//! With [`Options::tenant_dir`](../struct.Options.html#structfield.tenant_dir) each tenant's report is written to
+//! `TENANT.csv` in that directory instead, in the usual format, and nothing is written to the output.  Tenants are
+//! then used as file names, so they can only contain letters, digits, `-`, `_`, and `.`, and can't start with `.`.
+//!
+//! Records without a tenant, or with one that can't be used, are logged and skipped.  Options that keep state across
+//! the whole run, such as checkpoints and the write-ahead log, can't be combined with a tenant column.
+
+use super::{
+    configure_bank, create_report_file, finish_report_file, handle_record, interrupted, perform,
+    read_records_on, write_report, ErrorLimit, Interrupted, Options, Reading, Sinks,
+};
+use crate::bank::account::{AccountId, AccountSummary};
+use crate::bank::amount::Amount;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// The column that names a record's tenant.
+pub const TENANT_COLUMN: &str = "tenant";
+
+/// A row of the combined account report.
+#[derive(Serialize)]
+struct TenantAccount<'a> {
+    tenant: &'a str,
+    client: AccountId,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+/// Whether `reader`'s input has a tenant column.
+///
+/// # Errors
+///
+/// Will return `Err` if the header row can't be read.
+pub(super) fn has_column<R: io::Read>(reader: &mut csv::Reader<R>) -> csv::Result<bool> {
+    Ok(reader
+        .headers()?
+        .iter()
+        .any(|header| header == TENANT_COLUMN))
+}
+
+/// Process `reader` with a bank for each tenant and write their reports.
+pub(super) fn run<R: io::Read + io::Seek + Send, W: io::Write>(
+    reader: &mut csv::Reader<R>,
+    output: W,
+    options: &mut Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(option) = unsupported(options) {
+        return Err(format!("{option} can't be combined with a tenant column").into());
+    }
+    let mut banks: BTreeMap<String, Bank> = BTreeMap::new();
+    let reading = Reading::from(&*options);
+    let mut limit = ErrorLimit::new(options.max_errors);
+    // Taken out of the options while reading, since new banks are configured from them.
+    let mut rejects = options.rejects.take();
+    let mut diagnostics = options.diagnostics.take();
+    let read = {
+        let file_names = options.tenant_dir.is_some();
+        let options = &*options;
+        read_records_on(reader, &mut [], reading, |mut record, _| {
+            if options.shutdown.requested() {
+                return Err(Interrupted::at(record.location).into());
+            }
+            limit.count(&record)?;
+            let tenant = record.tenant.take().unwrap_or_default();
+            if let Err(reason) = check(&tenant, file_names) {
+                tracing::error!(
+                    tenant,
+                    line = record.location.line,
+                    byte = record.location.byte,
+                    "skipping record: {reason}"
+                );
+                return Ok(());
+            }
+            let sinks = Sinks {
+                rejects: rejects.as_mut(),
+                diagnostics: diagnostics.as_mut(),
+            };
+            handle_record(record, sinks, |ti, location, sinks| {
+                let bank = match banks.entry(tenant) {
+                    std::collections::btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                    std::collections::btree_map::Entry::Vacant(entry) => {
+                        tracing::info!(tenant = entry.key(), "new tenant");
+                        let mut bank = Bank::new();
+                        configure_bank(&mut bank, options, 0, 1)?;
+                        entry.insert(bank)
+                    }
+                };
+                Ok(perform(bank, ti, location, sinks)?)
+            })
+        })
+    };
+    options.diagnostics = diagnostics;
+    if let Some(rejects) = &mut rejects {
+        rejects.flush()?;
+    }
+    options.rejects = rejects;
+    let interrupted = interrupted(read)?;
+    if let Some(interrupted) = interrupted {
+        return Err(interrupted.into());
+    }
+    if let Some(metrics) = &options.metrics {
+        metrics.observe_banks(banks.values());
+    }
+    match options.tenant_dir.clone() {
+        Some(dir) => {
+            fs::create_dir_all(&dir)?;
+            for (tenant, bank) in &banks {
+                let path = dir.join(format!("{tenant}.csv"));
+                let mut file = create_report_file(&path, options)?;
+                write_report(bank, &mut file, options.report)?;
+                finish_report_file(file, &path, options)?;
+            }
+            Ok(())
+        }
+        None => write_combined(&banks, output, options),
+    }
+}
+
+/// Write every tenant's accounts to one report with a tenant column.
+fn write_combined<W: io::Write>(
+    banks: &BTreeMap<String, Bank>,
+    output: W,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(output);
+    let mut count = 0_usize;
+    for (tenant, bank) in banks {
+        let mut accounts: Vec<AccountSummary> = bank.accounts().map(AccountSummary::from).collect();
+        if options.report.sorted {
+            accounts.sort_unstable_by_key(|account| account.client);
+        }
+        for account in accounts {
+            writer.serialize(TenantAccount {
+                tenant,
+                client: account.client,
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                locked: account.locked,
+            })?;
+            count += 1;
+            if options
+                .report
+                .flush_every
+                .is_some_and(|n| count.is_multiple_of(n))
+            {
+                writer.flush()?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Why `tenant` can't be used, if it can't.  Tenants used as `file_names` are held to stricter rules.
+fn check(tenant: &str, file_names: bool) -> Result<(), &'static str> {
+    if tenant.is_empty() {
+        return Err("no tenant");
+    }
+    let plain = !tenant.starts_with('.')
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if file_names && !plain {
+        return Err("tenant can't be used as a file name");
+    }
+    Ok(())
+}
+
+/// The first option set in `options` that keeps state or writes files across tenants.
+fn unsupported(options: &Options) -> Option<&'static str> {
+    [
+        (options.checkpointer.is_some(), "checkpoints"),
+        (options.wal.is_some(), "a write-ahead log"),
+        (options.audit.is_some(), "an audit log"),
+        (options.applied.is_some(), "applied events"),
+        (options.dedup.is_some(), "a dedup index"),
+        (!options.merge.is_empty(), "merged inputs"),
+        (options.threads > 1, "multiple threads"),
+        (options.ledger.is_some(), "a ledger"),
+        (options.fraud.is_some(), "a fraud report"),
+        (!options.accounts.is_empty(), "an accounts file"),
+        (options.report.metadata, "account metadata"),
+        (
+            options.negative_report.is_some() || options.locked_report.is_some(),
+            "follow-up reports",
+        ),
+        (options.balance_history.is_some(), "balance history"),
+    ]
+    .iter()
+    .find_map(|&(set, option)| set.then_some(option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_tenants() {
+        assert_eq!(check("retail", true), Ok(()));
+        assert_eq!(check("eu.retail-2", true), Ok(()));
+        assert!(check("", false).is_err());
+        assert_eq!(check("../retail", false), Ok(()));
+        assert!(check("../retail", true).is_err());
+        assert!(check(".hidden", true).is_err());
+    }
+}
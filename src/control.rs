@@ -0,0 +1,212 @@
+//! This module contains a control interface for long-running modes, served on a Unix domain socket.
+//!
+//! Operators can inspect and correct a live [Bank](../bank/struct.Bank.html) without opening a network port.  Each line
+//! sent to the socket is one command, and each command gets a reply:
+//!
+//! | Command | Reply |
+//! |---|---|
+//! | `report` | The account report as CSV, in client id order. |
+//! | `snapshot {path}` | Writes a [snapshot](../bank/snapshot/index.html) of the bank to `path` and replies `ok`. |
+//! | `unlock {client}` | Unlocks the client's account and replies `ok`. |
+//! | `purge {client} {reference}` | [Purges](../bank/purge/index.html) the client's account, recording `reference` as who authorized it, and replies `ok`. |
+//!
+//! Failed commands get a single `error: ...` line.  Paths are on the server's file system, not the caller's.
+//!
+//! ```text
+//! $ echo report | socat - UNIX-CONNECT:/run/transactomatic.sock
+//! client,available,held,total,locked
+//! 1,1.5000,0.0000,1.5000,false
+//! ```
+//!
+//! Anyone who can connect can unlock and purge accounts, so the socket is created readable and writable only by its owner.
+
+use crate::bank::account::{AccountId, AccountSummary};
+use crate::bank::Bank;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A control socket for a bank.
+#[derive(Debug)]
+pub struct Control {
+    listener: UnixListener,
+    bank: Arc<Mutex<Bank>>,
+}
+
+impl Control {
+    /// Listen on the socket at `path`.  A stale socket left behind by a previous process is replaced, but one that is
+    /// still being served isn't.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the socket can't be created.
+    pub fn bind<P: AsRef<Path>>(path: P, bank: Arc<Mutex<Bank>>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let listener = match UnixListener::bind(path) {
+            Err(err)
+                if err.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() =>
+            {
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            result => result?,
+        };
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        tracing::info!(?path, "control socket listening");
+        Ok(Self { listener, bank })
+    }
+
+    /// Serve connections on a background thread until the listener fails.
+    pub fn spawn(self) {
+        thread::spawn(move || self.run());
+    }
+
+    /// Serve connections, each on its own thread, until the listener fails.
+    pub fn run(&self) {
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let bank = Arc::clone(&self.bank);
+                    thread::spawn(move || {
+                        if let Err(err) = serve(&stream, &bank) {
+                            tracing::debug!(?err, "control connection failed");
+                        }
+                    });
+                }
+                Err(err) => {
+                    tracing::error!(?err, "error accepting control connection");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Answer commands from one connection until it closes.
+fn serve(stream: &UnixStream, bank: &Mutex<Bank>) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(stream);
+    for line in io::BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        tracing::debug!(command = %line, "control command");
+        match execute(bank, &line) {
+            Ok(reply) => writer.write_all(reply.as_bytes())?,
+            Err(err) => writeln!(writer, "error: {err}")?,
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Run one command, returning its reply.
+fn execute(bank: &Mutex<Bank>, command: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut words = command.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("report"), None, _) => {
+            let bank = bank.lock().expect("bank lock poisoned");
+            let mut accounts: Vec<AccountSummary> =
+                bank.accounts().map(AccountSummary::from).collect();
+            accounts.sort_unstable_by_key(|account| account.client);
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for account in accounts {
+                writer.serialize(account)?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        (Some("snapshot"), Some(path), None) => {
+            let path = PathBuf::from(path);
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            let mut writer = io::BufWriter::new(fs::File::create(&tmp)?);
+            bank.lock()
+                .expect("bank lock poisoned")
+                .save_snapshot(&mut writer)?;
+            writer
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?
+                .sync_all()?;
+            fs::rename(tmp, &path)?;
+            Ok("ok\n".to_string())
+        }
+        (Some("unlock"), Some(client), None) => {
+            let client: AccountId = client.parse()?;
+            match bank.lock().expect("bank lock poisoned").unlock(&client) {
+                Some(_) => Ok("ok\n".to_string()),
+                None => Err(format!("no account for client {client}").into()),
+            }
+        }
+        (Some("purge"), Some(client), Some(reference)) if words.next().is_none() => {
+            let client: AccountId = client.parse()?;
+            bank.lock()
+                .expect("bank lock poisoned")
+                .purge(&client, reference)?;
+            Ok("ok\n".to_string())
+        }
+        _ => Err(format!("unknown command {command:?}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind,
+    };
+    use crate::bank::transaction::TransactionId;
+    use std::io::Read;
+
+    #[test]
+    fn commands() {
+        let dir =
+            std::env::temp_dir().join(format!("transactomatic-control-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut bank = Bank::new();
+        bank.perform_transaction(TransactionInstruction {
+            kind: TransactionInstructionKind::Deposit,
+            client: AccountId::Number(2),
+            tx: TransactionId(1),
+            amount: Some(Amount::from(3)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        })
+        .unwrap();
+        bank.account_mut(&AccountId::Number(2)).unwrap().locked = true;
+        let bank = Arc::new(Mutex::new(bank));
+
+        let socket = dir.join("control.sock");
+        Control::bind(&socket, Arc::clone(&bank)).unwrap().spawn();
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        let snapshot = dir.join("snapshot");
+        write!(
+            stream,
+            "unlock 2\nunlock 3\nsnapshot {}\nreport\nfrobnicate\npurge 3 DPO-1\npurge 2 DPO-1\n",
+            snapshot.display()
+        )
+        .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+
+        assert_eq!(
+            reply,
+            "ok\nerror: no account for client 3\nok\n\
+             client,available,held,total,locked\n2,3.0000,0.0000,3.0000,false\n\
+             error: unknown command \"frobnicate\"\n\
+             error: account not found\nok\n"
+        );
+        let restored = Bank::load_snapshot(fs::File::open(&snapshot).unwrap()).unwrap();
+        assert!(!restored.account(&AccountId::Number(2)).unwrap().locked);
+
+        // The socket is still in use, so binding it again fails rather than stealing it.
+        assert!(Control::bind(&socket, bank).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,474 @@
+//! This module contains a terminal dashboard for watching a [Bank](../bank/struct.Bank.html) as it works.
+//!
+//! [`Dashboard::install`](struct.Dashboard.html#method.install) registers a hook on a bank that keeps what the
+//! dashboard shows: counts of applied and rejected instructions, every account's balances, the most recent rejections,
+//! and the most recent instructions of each client.  Like [metrics](../metrics/index.html), one dashboard can be
+//! installed on several banks, such as the shards of a multi-threaded run.
+//! [`Dashboard::spawn`](struct.Dashboard.html#method.spawn) draws it on a background thread, a few times a second, until
+//! the operator closes it:
+//!
+//! | Key | |
+//! |---|---|
+//! | `↑` / `↓` | Select an account. |
+//! | `Enter` | Show the selected client's recent instructions. |
+//! | `Esc` | Back to the accounts. |
+//! | `s` | Sort the accounts by total or by held funds. |
+//! | `q` / `Ctrl-C` | Close the dashboard.  Processing carries on. |
+//!
+//! The dashboard is drawn on standard error, so the account report on standard output can still be redirected.
+
+use crate::bank::account::{Account, AccountId, AccountSummary};
+use crate::bank::amount::Amount;
+use crate::bank::event::Event;
+use crate::bank::hook::Hook;
+use crate::bank::transaction::instruction::{TransactionInstruction, TransactionInstructionKind};
+use crate::bank::transaction::{Error, TransactionId};
+use crate::bank::Bank;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::crossterm::event::{self as input, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::{execute, terminal};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of rejections kept for the rejections pane.
+const RECENT_REJECTIONS: usize = 100;
+/// Number of instructions kept for each client's history.
+const CLIENT_HISTORY: usize = 100;
+/// Number of accounts listed.
+const TOP_ACCOUNTS: usize = 100;
+/// How long to wait for a key before redrawing.
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// What the dashboard shows, shared between the banks it's installed on and the thread drawing it.
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    state: Mutex<State>,
+    finished: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    applied: u64,
+    rejected: u64,
+    accounts: HashMap<AccountId, AccountSummary>,
+    rejections: VecDeque<Entry>,
+    history: HashMap<AccountId, VecDeque<Entry>>,
+}
+
+/// An instruction as the dashboard remembers it.
+#[derive(Debug, Clone)]
+struct Entry {
+    kind: TransactionInstructionKind,
+    client: AccountId,
+    tx: TransactionId,
+    amount: Option<Amount>,
+    /// The account after the instruction was applied, or why it was rejected.
+    outcome: Result<AccountSummary, Error>,
+}
+
+/// Records instructions for a `Dashboard`.
+struct DashboardHook(Arc<Dashboard>);
+
+/// What the accounts are sorted by, largest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Total,
+    Held,
+}
+
+/// The drawing thread's own state: what's selected and how fast instructions are going.
+#[derive(Debug)]
+struct View {
+    sort: SortBy,
+    accounts: TableState,
+    /// The client whose history is shown, if one is.
+    detail: Option<AccountId>,
+    /// The client of the selected account when the accounts were last drawn.
+    selected_client: Option<AccountId>,
+    /// Instructions processed and when, at the last throughput sample.
+    sampled: (Instant, u64),
+    throughput: f64,
+}
+
+impl Dashboard {
+    #[must_use]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Show the accounts `bank` already has, and record every instruction it processes from now on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the drawing thread panicked while holding the dashboard's lock.
+    pub fn install(self: &Arc<Self>, bank: &mut Bank) {
+        {
+            let mut state = self.state.lock().expect("dashboard lock poisoned");
+            for account in bank.accounts() {
+                state
+                    .accounts
+                    .insert(account.client, AccountSummary::from(account));
+            }
+        }
+        bank.register_hook(DashboardHook(Arc::clone(self)));
+        // Instructions rejected by another hook never reach this one's `after_apply`.
+        let dashboard = Arc::clone(self);
+        bank.register_observer(move |event: &Event| {
+            if let Event::InstructionRejected {
+                client,
+                tx,
+                kind,
+                error: Error::RejectedByHook,
+                ..
+            } = event
+            {
+                dashboard.record(Entry {
+                    kind: *kind,
+                    client: *client,
+                    tx: *tx,
+                    amount: None,
+                    outcome: Err(Error::RejectedByHook),
+                });
+            }
+        });
+    }
+
+    /// Note that processing has finished.  The dashboard stays open, showing the final state, until it's closed.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    /// Draw the dashboard on the terminal on a background thread until the operator closes it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the thread can't be started.
+    #[must_use]
+    pub fn spawn(self: &Arc<Self>) -> thread::JoinHandle<io::Result<()>> {
+        let dashboard = Arc::clone(self);
+        thread::Builder::new()
+            .name("dashboard".to_string())
+            .spawn(move || dashboard.run())
+            .expect("could not start dashboard thread")
+    }
+
+    fn record(&self, entry: Entry) {
+        let mut state = self.state.lock().expect("dashboard lock poisoned");
+        if let Ok(account) = entry.outcome {
+            state.applied += 1;
+            state.accounts.insert(entry.client, account);
+        } else {
+            state.rejected += 1;
+            if state.rejections.len() == RECENT_REJECTIONS {
+                state.rejections.pop_back();
+            }
+            state.rejections.push_front(entry.clone());
+        }
+        let history = state.history.entry(entry.client).or_default();
+        if history.len() == CLIENT_HISTORY {
+            history.pop_back();
+        }
+        history.push_front(entry);
+    }
+
+    /// Take over the terminal and draw until closed, putting the terminal back however that happens.
+    fn run(&self) -> io::Result<()> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stderr(), terminal::EnterAlternateScreen)?;
+        let result = Terminal::new(CrosstermBackend::new(io::stderr()))
+            .and_then(|mut terminal| self.run_on(&mut terminal));
+        let restored = execute!(io::stderr(), terminal::LeaveAlternateScreen)
+            .and_then(|()| terminal::disable_raw_mode());
+        result.and(restored)
+    }
+
+    fn run_on<B: Backend>(&self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        let mut view = View::new();
+        loop {
+            terminal.draw(|frame| self.draw(frame, &mut view))?;
+            if !input::poll(REFRESH)? {
+                continue;
+            }
+            if let input::Event::Key(key) = input::read()? {
+                let interrupt =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (interrupt || !view.handle(key.code)) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame<'_>, view: &mut View) {
+        let state = self.state.lock().expect("dashboard lock poisoned");
+        view.sample(state.applied + state.rejected);
+
+        let [header, body] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(frame.area());
+        let status = if self.finished.load(Ordering::Relaxed) {
+            "finished"
+        } else {
+            "running"
+        };
+        let summary = format!(
+            "{status}  applied {}  rejected {}  {:.0}/s  accounts {}",
+            state.applied,
+            state.rejected,
+            view.throughput,
+            state.accounts.len()
+        );
+        frame.render_widget(
+            Paragraph::new(Line::from(summary)).block(Block::bordered().title("transactomatic")),
+            header,
+        );
+
+        if let Some(client) = view.detail {
+            let entries = state.history.get(&client).into_iter().flatten();
+            frame.render_widget(
+                entry_table(entries, format!("client {client} (Esc to go back)")),
+                body,
+            );
+            return;
+        }
+
+        let [accounts, rejections] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(body);
+        let top = top_accounts(&state.accounts, view.sort, TOP_ACCOUNTS);
+        if let Some(selected) = view.accounts.selected() {
+            view.accounts
+                .select(Some(selected.min(top.len().saturating_sub(1))));
+        }
+        let title = match view.sort {
+            SortBy::Total => "accounts by total (s: by held, Enter: history)",
+            SortBy::Held => "accounts by held (s: by total, Enter: history)",
+        };
+        let rows = top.iter().map(|account| {
+            Row::new([
+                account.client.to_string(),
+                account.available.to_string(),
+                account.held.to_string(),
+                account.total.to_string(),
+                if account.locked { "locked" } else { "" }.to_string(),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(22),
+                Constraint::Min(10),
+                Constraint::Min(10),
+                Constraint::Min(10),
+                Constraint::Length(6),
+            ],
+        )
+        .header(
+            Row::new(["client", "available", "held", "total", ""])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(title));
+        frame.render_stateful_widget(table, accounts, &mut view.accounts);
+        // Remember which client is selected, for Enter.
+        view.selected_client = view
+            .accounts
+            .selected()
+            .and_then(|i| top.get(i))
+            .map(|account| account.client);
+
+        frame.render_widget(
+            entry_table(state.rejections.iter(), "recent rejections".to_string()),
+            rejections,
+        );
+    }
+}
+
+impl Hook for DashboardHook {
+    fn after_apply(
+        &mut self,
+        instruction: &TransactionInstruction,
+        result: Result<&Account, Error>,
+    ) {
+        self.0.record(Entry {
+            kind: instruction.kind,
+            client: instruction.client,
+            tx: instruction.tx,
+            amount: instruction.amount,
+            outcome: result.map(AccountSummary::from),
+        });
+    }
+}
+
+impl View {
+    fn new() -> Self {
+        Self {
+            sort: SortBy::Total,
+            accounts: TableState::default().with_selected(0),
+            detail: None,
+            selected_client: None,
+            sampled: (Instant::now(), 0),
+            throughput: 0.0,
+        }
+    }
+
+    /// Update the throughput if a second has passed since the last sample.
+    #[allow(clippy::cast_precision_loss)] // Counts of instructions are nowhere near 2^52.
+    fn sample(&mut self, processed: u64) {
+        let (at, before) = self.sampled;
+        let elapsed = at.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.throughput = processed.saturating_sub(before) as f64 / elapsed.as_secs_f64();
+            self.sampled = (Instant::now(), processed);
+        }
+    }
+
+    /// Act on a key.  Returns `false` if the dashboard should close.
+    fn handle(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Char('q') => return false,
+            KeyCode::Down => self.accounts.select_next(),
+            KeyCode::Up => self.accounts.select_previous(),
+            KeyCode::Char('s') => {
+                self.sort = match self.sort {
+                    SortBy::Total => SortBy::Held,
+                    SortBy::Held => SortBy::Total,
+                };
+            }
+            KeyCode::Enter => self.detail = self.detail.or(self.selected_client),
+            KeyCode::Esc => self.detail = None,
+            _ => {}
+        }
+        true
+    }
+}
+
+/// The `n` accounts with the most funds by `sort`, largest first.
+fn top_accounts(
+    accounts: &HashMap<AccountId, AccountSummary>,
+    sort: SortBy,
+    n: usize,
+) -> Vec<AccountSummary> {
+    let key = |account: &AccountSummary| match sort {
+        SortBy::Total => (std::cmp::Reverse(account.total), account.client),
+        SortBy::Held => (std::cmp::Reverse(account.held), account.client),
+    };
+    let mut top: Vec<AccountSummary> = accounts.values().copied().collect();
+    if top.len() > n {
+        top.select_nth_unstable_by_key(n, key);
+        top.truncate(n);
+    }
+    top.sort_unstable_by_key(key);
+    top
+}
+
+/// A table of instructions, most recent first.
+fn entry_table<'a, I: Iterator<Item = &'a Entry>>(entries: I, title: String) -> Table<'a> {
+    let rows = entries.map(|entry| {
+        let outcome = match entry.outcome {
+            Ok(account) => format!(
+                "available {}, held {}{}",
+                account.available,
+                account.held,
+                if account.locked { ", locked" } else { "" }
+            ),
+            Err(err) => err.to_string(),
+        };
+        Row::new([
+            entry.client.to_string(),
+            entry.tx.0.to_string(),
+            crate::cli::kind_name(entry.kind).to_string(),
+            entry
+                .amount
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+            outcome,
+        ])
+    });
+    Table::new(
+        rows,
+        [
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Min(20),
+        ],
+    )
+    .header(
+        Row::new(["client", "tx", "type", "amount", "outcome"])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .block(Block::bordered().title(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        client: u16,
+        tx: u64,
+        amount: i64,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn records_and_draws() {
+        let dashboard = Dashboard::new();
+        let mut bank = Bank::new();
+        dashboard.install(&mut bank);
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, 1, 5));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 2, 2, 9));
+        let _ =
+            bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 1, 3, 7));
+
+        {
+            let state = dashboard.state.lock().unwrap();
+            assert_eq!((state.applied, state.rejected), (2, 1));
+            let top = top_accounts(&state.accounts, SortBy::Total, 1);
+            assert_eq!(top.len(), 1);
+            assert_eq!(top[0].client, AccountId::Number(2));
+            assert_eq!(state.rejections[0].outcome, Err(Error::InsufficientFunds));
+            assert_eq!(state.history[&AccountId::Number(1)].len(), 2);
+        }
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 20)).unwrap();
+        let mut view = View::new();
+        terminal
+            .draw(|frame| dashboard.draw(frame, &mut view))
+            .unwrap();
+        let screen = format!("{:?}", terminal.backend().buffer());
+        assert!(screen.contains("applied 2  rejected 1"), "{}", screen);
+        assert!(screen.contains("insufficient funds"), "{}", screen);
+
+        // The first account is selected; Enter shows its history.
+        assert!(view.handle(KeyCode::Enter));
+        assert_eq!(view.detail, Some(AccountId::Number(2)));
+        terminal
+            .draw(|frame| dashboard.draw(frame, &mut view))
+            .unwrap();
+        let screen = format!("{:?}", terminal.backend().buffer());
+        assert!(screen.contains("client 2 (Esc to go back)"), "{}", screen);
+        assert!(!view.handle(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,321 @@
+//! This module contains heuristics that flag suspicious activity on a [Bank](../bank/struct.Bank.html).
+//!
+//! [`Screener::install`](struct.Screener.html#method.install) registers an observer, so flagging never blocks or
+//! changes an instruction; flagged clients are only reported.  The rules are:
+//!
+//! | Rule | Flags |
+//! |---|---|
+//! | `rapid_withdrawal` | A withdrawal of at least the amount of one of the client's deposits, within `rapid_window` of the client's deposits and withdrawals after it. |
+//! | `chargebacks` | A client's `chargeback_limit`th chargeback. |
+//! | `structuring` | A client's `structuring_count`th deposit within `structuring_margin` below `structuring_threshold`. |
+//!
+//! Each rule is only applied to applied instructions, and only looks at one client at a time, so one `Screener` can
+//! be installed on every shard of a multi-threaded run.  Flags are also raised as [alerts](../alert/index.html) to the
+//! screener's sinks as they happen.
+
+use crate::alert::{self, Alert, Sinks};
+use crate::bank::account::AccountId;
+use crate::bank::amount::Amount;
+use crate::bank::event::Event;
+use crate::bank::purge;
+use crate::bank::transaction::TransactionId;
+use crate::bank::Bank;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Thresholds for the rules.  Deserializes from JSON with amounts as strings; missing fields keep their defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    /// How many of a client's deposits and withdrawals a deposit stays recent for.
+    pub rapid_window: u64,
+    /// Number of chargebacks a client can have before being flagged.
+    pub chargeback_limit: u32,
+    /// Deposits at or over this amount are assumed to be reported, so deposits just under it are suspicious.
+    pub structuring_threshold: Amount,
+    /// How far under the threshold a deposit counts as just under it.
+    pub structuring_margin: Amount,
+    /// Number of deposits just under the threshold a client can make before being flagged.
+    pub structuring_count: u32,
+}
+
+/// Which rule raised a flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    RapidWithdrawal,
+    Chargebacks,
+    Structuring,
+}
+
+/// A client flagged by a rule, with the transaction that tripped it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Flag {
+    pub client: AccountId,
+    pub rule: Rule,
+    pub tx: TransactionId,
+    /// A human-readable explanation.
+    pub detail: String,
+}
+
+/// Applies [`Rules`](struct.Rules.html) to the events of one or more banks and collects the flags raised.
+#[derive(Debug)]
+pub struct Screener {
+    rules: Rules,
+    sinks: Sinks,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    clients: HashMap<AccountId, Client>,
+    flags: Vec<Flag>,
+}
+
+/// What the rules remember about a client.
+#[derive(Debug, Default)]
+struct Client {
+    /// Number of deposits and withdrawals applied.
+    transactions: u64,
+    /// Deposits still within the rapid window, with the value of `transactions` when they were made.
+    recent_deposits: VecDeque<(u64, TransactionId, Amount)>,
+    chargebacks: u32,
+    near_threshold: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            rapid_window: 3,
+            chargeback_limit: 2,
+            structuring_threshold: Amount::from(10_000),
+            structuring_margin: Amount::from(1_000),
+            structuring_count: 3,
+        }
+    }
+}
+
+impl Screener {
+    #[must_use]
+    pub fn new(rules: Rules) -> Arc<Self> {
+        Self::with_sinks(rules, Sinks::default())
+    }
+
+    /// A screener that also sends each flag to `sinks` as an alert.
+    #[must_use]
+    pub fn with_sinks(rules: Rules, sinks: Sinks) -> Arc<Self> {
+        Arc::new(Self {
+            rules,
+            sinks,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Screen every instruction `bank` applies from now on.
+    pub fn install(self: &Arc<Self>, bank: &mut Bank) {
+        let screener = Arc::clone(self);
+        bank.register_observer(move |event: &Event| screener.screen(event));
+    }
+
+    /// The flags raised so far, by client and then in the order they were raised.
+    ///
+    /// # Panics
+    ///
+    /// Panics if screening panicked on another thread.
+    #[must_use]
+    pub fn flags(&self) -> Vec<Flag> {
+        let mut flags = self
+            .state
+            .lock()
+            .expect("screener lock poisoned")
+            .flags
+            .clone();
+        flags.sort_by_key(|flag| flag.client);
+        flags
+    }
+
+    /// Write the flags as CSV.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the report can't be written.
+    pub fn write_report<W: io::Write>(&self, output: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(output);
+        for flag in self.flags() {
+            writer.serialize(flag)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn screen(&self, event: &Event) {
+        let rules = &self.rules;
+        let mut state = self.state.lock().expect("screener lock poisoned");
+        let State { clients, flags } = &mut *state;
+        let client = event.client();
+        let mut flag = |rule, tx, detail: String| {
+            tracing::warn!(?client, ?rule, ?tx, %detail, "client flagged");
+            self.sinks.alert(&Alert {
+                source: alert::Source::Fraud,
+                client,
+                tx,
+                rule: Some(rule),
+                detail: detail.clone(),
+            });
+            flags.push(Flag {
+                client,
+                rule,
+                tx,
+                detail,
+            });
+        };
+
+        match *event {
+            Event::DepositApplied { tx, amount, .. } => {
+                let history = clients.entry(client).or_default();
+                history.transactions += 1;
+                history.expire(rules.rapid_window);
+                history
+                    .recent_deposits
+                    .push_back((history.transactions, tx, amount));
+                if amount < rules.structuring_threshold
+                    && amount >= rules.structuring_threshold - rules.structuring_margin
+                {
+                    history.near_threshold += 1;
+                    if history.near_threshold == rules.structuring_count {
+                        flag(
+                            Rule::Structuring,
+                            tx,
+                            format!(
+                                "{} deposits just under {}",
+                                history.near_threshold,
+                                alert::detail_amount(rules.structuring_threshold)
+                            ),
+                        );
+                    }
+                }
+            }
+            Event::WithdrawalApplied { tx, amount, .. } => {
+                let history = clients.entry(client).or_default();
+                history.transactions += 1;
+                history.expire(rules.rapid_window);
+                if let Some(i) = history
+                    .recent_deposits
+                    .iter()
+                    .position(|(_, _, deposited)| amount >= *deposited)
+                {
+                    let (_, deposit, deposited) = history.recent_deposits.remove(i).unwrap();
+                    flag(
+                        Rule::RapidWithdrawal,
+                        tx,
+                        format!(
+                            "withdrew {} soon after depositing {} in tx {}",
+                            alert::detail_amount(amount),
+                            alert::detail_amount(deposited),
+                            deposit.0
+                        ),
+                    );
+                }
+            }
+            Event::ChargebackApplied { tx, .. } => {
+                let history = clients.entry(client).or_default();
+                history.chargebacks += 1;
+                if history.chargebacks == rules.chargeback_limit {
+                    flag(
+                        Rule::Chargebacks,
+                        tx,
+                        format!("{} chargebacks", history.chargebacks),
+                    );
+                }
+            }
+            Event::AccountPurged { .. } => {
+                clients.remove(&client);
+                let tombstone = purge::tombstone();
+                for flag in flags.iter_mut().filter(|flag| flag.client == client) {
+                    flag.client = tombstone;
+                    flag.tx = purge::TOMBSTONE_TX;
+                    flag.detail = String::new();
+                }
+            }
+            Event::AccountCreated { .. }
+            | Event::DisputeOpened { .. }
+            | Event::DisputeResolved { .. }
+            | Event::AccountReinstated { .. }
+            | Event::AccountUnlocked { .. }
+            | Event::AccountAdministered { .. }
+            | Event::InstructionRolledBack { .. }
+            | Event::InstructionRejected { .. } => {}
+        }
+    }
+}
+
+impl Client {
+    /// Forget deposits that are no longer recent.
+    fn expire(&mut self, window: u64) {
+        while let Some((at, _, _)) = self.recent_deposits.front() {
+            if self.transactions - at <= window {
+                break;
+            }
+            self.recent_deposits.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_suspicious_clients() {
+        let screener = Screener::new(Rules::default());
+        let mut bank = Bank::new();
+        screener.install(&mut bank);
+        for line in [
+            // Client 1 takes a deposit straight back out.
+            "deposit,1,1,100",
+            "withdrawal,1,2,50",
+            "withdrawal,1,3,10",
+            "deposit,1,4,20",
+            "withdrawal,1,5,20",
+            // Client 2 stays under the threshold.
+            "deposit,2,7,9500",
+            "deposit,2,8,9999.99",
+            "deposit,2,9,10000",
+            "deposit,2,10,9000",
+            // Client 3 has two chargebacks, the second of which is flagged.
+            "deposit,3,11,1",
+            "deposit,3,12,1",
+            "dispute,3,11",
+            "chargeback,3,11",
+        ] {
+            let _ = bank.perform_transaction(line.parse().unwrap());
+        }
+        // Client 3's account is locked now, so a third chargeback can't happen; count it directly.
+        screener.screen(&Event::ChargebackApplied {
+            client: AccountId::Number(3),
+            tx: TransactionId(12),
+            amount: Amount::from(1),
+        });
+
+        let flags: Vec<_> = screener
+            .flags()
+            .into_iter()
+            .map(|f| (f.client.number().unwrap(), f.rule, f.tx.0))
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                (1, Rule::RapidWithdrawal, 5),
+                (2, Rule::Structuring, 10),
+                (3, Rule::Chargebacks, 12),
+            ]
+        );
+
+        let mut report = vec![];
+        screener.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("client,rule,tx,detail\n1,rapid_withdrawal,5,"));
+    }
+}
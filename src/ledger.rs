@@ -0,0 +1,636 @@
+//! This module contains a double-entry view of a [Bank](../bank/struct.Bank.html).
+//!
+//! [`Ledger::install`](struct.Ledger.html#method.install) registers an observer that turns every event into a journal
+//! entry whose debits equal its credits, against four internal ledger accounts:
+//!
+//! | Event | Debit | Credit |
+//! |---|---|---|
+//! | Deposit | `cash` | `customer_liability` |
+//! | Withdrawal | `customer_liability` | `cash` |
+//! | Dispute | `customer_liability` | `disputes_held` |
+//! | Resolve | `disputes_held` | `customer_liability` |
+//! | Chargeback | `disputes_held` | `cash` |
+//! | [Adjustment](../bank/admin/index.html) up | `cash` | `customer_liability` |
+//! | Adjustment down | `customer_liability` | `cash` |
+//!
+//! A chargeback that leaves a client owing money, because the disputed funds were already withdrawn, also writes the
+//! shortfall off to `chargeback_losses` in the same entry.  Reinstating or unlocking the account puts it back on the
+//! client, and a rollback posts the reverse of the entry it undoes.
+//!
+//! Purging an account redacts its entries: they're kept, so the journal's numbering and trial balance don't change,
+//! but they stand for the [tombstone](../bank/purge/fn.tombstone.html) client with no transaction, and the first one
+//! carries the total debits and credits of all of them to each ledger account while the rest are emptied.
+//! [`redact_journal`](fn.redact_journal.html) does the same to a journal that has already been written.
+//!
+//! The [trial balance](struct.TrialBalance.html) totals the debits and credits of each ledger account, and checks that
+//! they're equal overall.  Each ledger only sees one client at a time, so one `Ledger` can be installed on every shard
+//! of a multi-threaded run.
+
+use crate::bank::account::AccountId;
+use crate::bank::admin::Action;
+use crate::bank::amount::Amount;
+use crate::bank::event::Event;
+use crate::bank::purge;
+use crate::bank::transaction::TransactionId;
+use crate::bank::Bank;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// An internal ledger account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    /// Money held by the bank.
+    Cash,
+    /// What the bank owes clients, less disputed funds.
+    CustomerLiability,
+    /// Disputed funds, held until the dispute is resolved or charged back.
+    DisputesHeld,
+    /// What clients owed after chargebacks, written off.
+    ChargebackLosses,
+}
+
+impl LedgerAccount {
+    /// Every ledger account, in trial balance order.
+    pub const ALL: [LedgerAccount; 4] = [
+        LedgerAccount::Cash,
+        LedgerAccount::CustomerLiability,
+        LedgerAccount::DisputesHeld,
+        LedgerAccount::ChargebackLosses,
+    ];
+}
+
+/// Why an entry was posted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Memo {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// A written-off shortfall was put back on the client's account when it was reinstated or unlocked.
+    WriteOffReversed,
+    /// The reverse of an entry for an instruction that was rolled back.
+    Rollback,
+    /// Redacted when the client's account was purged.
+    Purged,
+    /// An operator adjusted the client's balance.
+    Adjustment,
+}
+
+/// A journal entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Position in the journal, starting at 1.
+    pub number: u64,
+    pub client: AccountId,
+    /// The transaction the entry is for, if there is one.
+    pub tx: Option<TransactionId>,
+    pub memo: Memo,
+    pub lines: Vec<Line>,
+}
+
+/// A debit or credit of one ledger account.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    pub account: LedgerAccount,
+    pub debit: Amount,
+    pub credit: Amount,
+}
+
+impl Line {
+    fn debit(account: LedgerAccount, amount: Amount) -> Self {
+        Self {
+            account,
+            debit: amount,
+            credit: Amount::default(),
+        }
+    }
+
+    fn credit(account: LedgerAccount, amount: Amount) -> Self {
+        Self {
+            account,
+            debit: Amount::default(),
+            credit: amount,
+        }
+    }
+}
+
+impl Entry {
+    /// Whether the entry's debits equal its credits.
+    #[must_use]
+    pub fn is_balanced(&self) -> bool {
+        let (debits, credits) = totals(&self.lines);
+        debits == credits
+    }
+}
+
+/// Total debits and total credits of `lines`.
+fn totals<'a>(lines: impl IntoIterator<Item = &'a Line>) -> (Amount, Amount) {
+    let (mut debits, mut credits) = (Amount::default(), Amount::default());
+    for line in lines {
+        debits += line.debit;
+        credits += line.credit;
+    }
+    (debits, credits)
+}
+
+/// A row of the journal export: one line of an entry.
+#[derive(Deserialize, Serialize)]
+struct JournalRow {
+    entry: u64,
+    client: AccountId,
+    tx: Option<TransactionId>,
+    memo: Memo,
+    account: LedgerAccount,
+    debit: Option<Amount>,
+    credit: Option<Amount>,
+}
+
+/// `amount` for the journal export, or `None` if it's zero.
+fn nonzero(mut amount: Amount) -> Option<Amount> {
+    amount.rescale(4);
+    (amount != Amount::default()).then_some(amount)
+}
+
+/// Copy a journal written by [`Ledger::write_journal`](struct.Ledger.html#method.write_journal) from `input` to
+/// `output`, redacting the rows of `client` the way the ledger does when the client's account is purged.  Returns the
+/// number of rows redacted.
+///
+/// # Errors
+///
+/// Will return `Err` if the journal can't be read or the output can't be written.
+pub fn redact_journal<R: io::Read, W: io::Write>(
+    input: R,
+    output: W,
+    client: AccountId,
+) -> Result<usize, csv::Error> {
+    let rows = csv::Reader::from_reader(input)
+        .deserialize()
+        .collect::<Result<Vec<JournalRow>, _>>()?;
+    let mut lines = BTreeMap::new();
+    let mut redacted = 0;
+    for row in rows.iter().filter(|row| row.client == client) {
+        let sums = lines
+            .entry(row.account)
+            .or_insert((Amount::default(), Amount::default()));
+        sums.0 += row.debit.unwrap_or_default();
+        sums.1 += row.credit.unwrap_or_default();
+        redacted += 1;
+    }
+    let mut writer = csv::Writer::from_writer(output);
+    for mut row in rows {
+        if row.client != client {
+            row.debit = row.debit.and_then(nonzero);
+            row.credit = row.credit.and_then(nonzero);
+            writer.serialize(row)?;
+            continue;
+        }
+        // The totals take the place of the client's first row, and its other rows are dropped.
+        for (account, (debit, credit)) in std::mem::take(&mut lines) {
+            writer.serialize(JournalRow {
+                entry: row.entry,
+                client: purge::tombstone(),
+                tx: None,
+                memo: Memo::Purged,
+                account,
+                debit: nonzero(debit),
+                credit: nonzero(credit),
+            })?;
+        }
+    }
+    writer.flush()?;
+    Ok(redacted)
+}
+
+/// The total debits and credits of each ledger account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialBalance {
+    pub accounts: Vec<Balance>,
+}
+
+/// A ledger account's row of the trial balance.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Balance {
+    pub account: LedgerAccount,
+    pub debit: Amount,
+    pub credit: Amount,
+}
+
+impl TrialBalance {
+    /// Total debits and total credits.
+    #[must_use]
+    pub fn totals(&self) -> (Amount, Amount) {
+        let (mut debits, mut credits) = (Amount::default(), Amount::default());
+        for balance in &self.accounts {
+            debits += balance.debit;
+            credits += balance.credit;
+        }
+        (debits, credits)
+    }
+
+    /// Whether total debits equal total credits.
+    #[must_use]
+    pub fn is_balanced(&self) -> bool {
+        let (debits, credits) = self.totals();
+        debits == credits
+    }
+
+    /// Write the trial balance as CSV, with a last `total` row.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the output can't be written.
+    pub fn write<W: io::Write>(&self, output: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(output);
+        for balance in &self.accounts {
+            let mut balance = *balance;
+            balance.debit.rescale(4);
+            balance.credit.rescale(4);
+            writer.serialize(balance)?;
+        }
+        let (mut debits, mut credits) = self.totals();
+        debits.rescale(4);
+        credits.rescale(4);
+        writer.serialize(("total", debits, credits))?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Keeps a double-entry journal of the events of the banks it's installed on.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: Vec<Entry>,
+    /// Numbers of entries that have been reversed by a rollback.
+    reversed: HashSet<u64>,
+    clients: HashMap<AccountId, Client>,
+}
+
+/// A client's share of the ledger accounts, credit balances positive.
+#[derive(Debug, Default)]
+struct Client {
+    liability: Amount,
+    held: Amount,
+    written_off: Amount,
+}
+
+impl Ledger {
+    #[must_use]
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Post an entry for every event of `bank` from now on.
+    pub fn install(self: &Arc<Self>, bank: &mut Bank) {
+        let ledger = Arc::clone(self);
+        bank.register_observer(move |event: &Event| ledger.post(event));
+    }
+
+    /// The journal so far, in the order entries were posted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if posting panicked on another thread.
+    #[must_use]
+    pub fn entries(&self) -> Vec<Entry> {
+        self.state
+            .lock()
+            .expect("ledger lock poisoned")
+            .entries
+            .clone()
+    }
+
+    /// The trial balance of the journal so far.
+    ///
+    /// # Panics
+    ///
+    /// Panics if posting panicked on another thread.
+    #[must_use]
+    pub fn trial_balance(&self) -> TrialBalance {
+        let state = self.state.lock().expect("ledger lock poisoned");
+        let accounts = LedgerAccount::ALL
+            .iter()
+            .map(|&account| {
+                let lines = state
+                    .entries
+                    .iter()
+                    .flat_map(|entry| &entry.lines)
+                    .filter(|line| line.account == account);
+                let (debit, credit) = totals(lines);
+                Balance {
+                    account,
+                    debit,
+                    credit,
+                }
+            })
+            .collect();
+        TrialBalance { accounts }
+    }
+
+    /// Write the journal as CSV, a row per line of each entry.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the output can't be written.
+    pub fn write_journal<W: io::Write>(&self, output: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(output);
+        for entry in self.entries() {
+            for line in &entry.lines {
+                writer.serialize(JournalRow {
+                    entry: entry.number,
+                    client: entry.client,
+                    tx: entry.tx,
+                    memo: entry.memo,
+                    account: line.account,
+                    debit: nonzero(line.debit),
+                    credit: nonzero(line.credit),
+                })?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn post(&self, event: &Event) {
+        use LedgerAccount::{Cash, ChargebackLosses, CustomerLiability, DisputesHeld};
+
+        let mut state = self.state.lock().expect("ledger lock poisoned");
+        let client = event.client();
+        let (tx, memo, mut lines) = match *event {
+            Event::DepositApplied { tx, amount, .. } => (
+                Some(tx),
+                Memo::Deposit,
+                vec![
+                    Line::debit(Cash, amount),
+                    Line::credit(CustomerLiability, amount),
+                ],
+            ),
+            Event::WithdrawalApplied { tx, amount, .. } => (
+                Some(tx),
+                Memo::Withdrawal,
+                vec![
+                    Line::debit(CustomerLiability, amount),
+                    Line::credit(Cash, amount),
+                ],
+            ),
+            Event::DisputeOpened { tx, amount, .. } => (
+                Some(tx),
+                Memo::Dispute,
+                vec![
+                    Line::debit(CustomerLiability, amount),
+                    Line::credit(DisputesHeld, amount),
+                ],
+            ),
+            Event::DisputeResolved { tx, amount, .. } => (
+                Some(tx),
+                Memo::Resolve,
+                vec![
+                    Line::debit(DisputesHeld, amount),
+                    Line::credit(CustomerLiability, amount),
+                ],
+            ),
+            Event::ChargebackApplied { tx, amount, .. } => {
+                let mut lines = vec![
+                    Line::debit(DisputesHeld, amount),
+                    Line::credit(Cash, amount),
+                ];
+                let balances = state.clients.entry(client).or_default();
+                let owed = -(balances.liability + balances.held - amount);
+                if owed > Amount::default() {
+                    lines.push(Line::debit(ChargebackLosses, owed));
+                    lines.push(Line::credit(CustomerLiability, owed));
+                }
+                (Some(tx), Memo::Chargeback, lines)
+            }
+            Event::AccountReinstated { .. } | Event::AccountUnlocked { .. } => {
+                let tx = match *event {
+                    Event::AccountReinstated { tx, .. } => Some(tx),
+                    _ => None,
+                };
+                let written_off = state.clients.entry(client).or_default().written_off;
+                if written_off <= Amount::default() {
+                    return;
+                }
+                (
+                    tx,
+                    Memo::WriteOffReversed,
+                    vec![
+                        Line::debit(CustomerLiability, written_off),
+                        Line::credit(ChargebackLosses, written_off),
+                    ],
+                )
+            }
+            Event::InstructionRolledBack { tx, .. } => {
+                let Some(lines) = state.reversal(client, tx) else {
+                    return;
+                };
+                (Some(tx), Memo::Rollback, lines)
+            }
+            Event::AccountAdministered {
+                action: Action::Adjust { amount },
+                ..
+            } => {
+                let lines = if amount < Amount::default() {
+                    vec![
+                        Line::debit(CustomerLiability, -amount),
+                        Line::credit(Cash, -amount),
+                    ]
+                } else {
+                    vec![
+                        Line::debit(Cash, amount),
+                        Line::credit(CustomerLiability, amount),
+                    ]
+                };
+                (None, Memo::Adjustment, lines)
+            }
+            Event::AccountPurged { .. } => {
+                state.purge(client);
+                return;
+            }
+            Event::AccountCreated { .. }
+            | Event::AccountAdministered { .. }
+            | Event::InstructionRejected { .. } => return,
+        };
+        lines.retain(|line| line.debit != Amount::default() || line.credit != Amount::default());
+        state.push(client, tx, memo, lines);
+    }
+}
+
+impl State {
+    /// The reverse of the latest entry for `tx` of `client` that hasn't been reversed yet, if there is one.
+    fn reversal(&mut self, client: AccountId, tx: TransactionId) -> Option<Vec<Line>> {
+        let undone = self.entries.iter().rev().find(|entry| {
+            entry.client == client
+                && entry.tx == Some(tx)
+                && entry.memo != Memo::Rollback
+                && !self.reversed.contains(&entry.number)
+        })?;
+        self.reversed.insert(undone.number);
+        let lines = undone
+            .lines
+            .iter()
+            .map(|line| Line {
+                account: line.account,
+                debit: line.credit,
+                credit: line.debit,
+            })
+            .collect();
+        Some(lines)
+    }
+
+    /// Redact the entries of `client`, whose account has been purged, and forget its balances.
+    fn purge(&mut self, client: AccountId) {
+        let Some(first) = self.entries.iter().position(|entry| entry.client == client) else {
+            return;
+        };
+        let mut lines = BTreeMap::new();
+        for entry in self.entries[first..]
+            .iter_mut()
+            .filter(|entry| entry.client == client)
+        {
+            for line in entry.lines.drain(..) {
+                let sums = lines
+                    .entry(line.account)
+                    .or_insert_with(|| Line::debit(line.account, Amount::default()));
+                sums.debit += line.debit;
+                sums.credit += line.credit;
+            }
+            entry.client = purge::tombstone();
+            entry.tx = None;
+            entry.memo = Memo::Purged;
+        }
+        self.entries[first].lines = lines.into_values().collect();
+        self.clients.remove(&client);
+    }
+
+    /// Add an entry to the journal and to the client's balances.
+    fn push(&mut self, client: AccountId, tx: Option<TransactionId>, memo: Memo, lines: Vec<Line>) {
+        use LedgerAccount::{Cash, ChargebackLosses, CustomerLiability, DisputesHeld};
+
+        let balances = self.clients.entry(client).or_default();
+        for line in &lines {
+            let change = line.credit - line.debit;
+            match line.account {
+                CustomerLiability => balances.liability += change,
+                DisputesHeld => balances.held += change,
+                ChargebackLosses => balances.written_off -= change,
+                Cash => {}
+            }
+        }
+        let entry = Entry {
+            number: self.entries.len() as u64 + 1,
+            client,
+            tx,
+            memo,
+            lines,
+        };
+        debug_assert!(entry.is_balanced(), "{:?}", entry);
+        tracing::trace!(?entry, "posted journal entry");
+        self.entries.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posts_balanced_entries() {
+        let ledger = Ledger::new();
+        let mut bank = Bank::new();
+        ledger.install(&mut bank);
+        for line in [
+            "deposit,1,1,100",
+            "withdrawal,1,2,30",
+            "dispute,1,1",
+            "resolve,1,1",
+            // Client 2 withdraws a deposit and then charges it back, owing 40.
+            "deposit,2,3,50",
+            "withdrawal,2,4,40",
+            "dispute,2,3",
+            "chargeback,2,3",
+        ] {
+            bank.perform_transaction(line.parse().unwrap()).unwrap();
+        }
+        let entries = ledger.entries();
+        assert_eq!(entries.len(), 8);
+        assert!(entries.iter().all(Entry::is_balanced));
+        assert_eq!(
+            entries[7].lines,
+            [
+                Line::debit(LedgerAccount::DisputesHeld, Amount::from(50)),
+                Line::credit(LedgerAccount::Cash, Amount::from(50)),
+                Line::debit(LedgerAccount::ChargebackLosses, Amount::from(40)),
+                Line::credit(LedgerAccount::CustomerLiability, Amount::from(40)),
+            ]
+        );
+
+        let trial_balance = ledger.trial_balance();
+        assert!(trial_balance.is_balanced());
+        let balance = |account| {
+            let b = trial_balance.accounts[account as usize];
+            b.debit - b.credit
+        };
+        assert_eq!(balance(LedgerAccount::Cash), Amount::from(30));
+        assert_eq!(balance(LedgerAccount::CustomerLiability), Amount::from(-70));
+        assert_eq!(balance(LedgerAccount::DisputesHeld), Amount::default());
+        assert_eq!(balance(LedgerAccount::ChargebackLosses), Amount::from(40));
+
+        bank.perform_transaction("reinstate,2,3,,,ops-1".parse().unwrap())
+            .unwrap();
+        let entries = ledger.entries();
+        assert_eq!(entries[8].memo, Memo::WriteOffReversed);
+        assert!(ledger.trial_balance().is_balanced());
+
+        let mut journal = vec![];
+        ledger.write_journal(&mut journal).unwrap();
+        let journal = String::from_utf8(journal).unwrap();
+        assert!(journal.starts_with(
+            "entry,client,tx,memo,account,debit,credit\n1,1,1,deposit,cash,100.0000,\n1,1,1,deposit,customer_liability,,100.0000\n"
+        ));
+    }
+
+    #[test]
+    fn redacts_purged_clients() {
+        let ledger = Ledger::new();
+        let mut bank = Bank::new();
+        ledger.install(&mut bank);
+        for line in ["deposit,1,1,100", "deposit,2,2,5", "withdrawal,1,3,30"] {
+            bank.perform_transaction(line.parse().unwrap()).unwrap();
+        }
+        let mut written = vec![];
+        ledger.write_journal(&mut written).unwrap();
+        let before = ledger.trial_balance();
+
+        bank.purge(&AccountId::Number(1), "DPO-1").unwrap();
+        assert_eq!(ledger.trial_balance(), before);
+        let entries = ledger.entries();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(Entry::is_balanced));
+        assert_eq!(entries[0].client, purge::tombstone());
+        assert_eq!((entries[0].tx, entries[0].memo), (None, Memo::Purged));
+        assert!(entries[2].lines.is_empty());
+        assert_eq!(entries[1].client, AccountId::Number(2));
+
+        let mut journal = vec![];
+        ledger.write_journal(&mut journal).unwrap();
+        let mut redacted = vec![];
+        assert_eq!(
+            redact_journal(written.as_slice(), &mut redacted, AccountId::Number(1)).unwrap(),
+            4
+        );
+        assert_eq!(
+            String::from_utf8(redacted).unwrap(),
+            String::from_utf8(journal).unwrap()
+        );
+    }
+}
@@ -1,4 +1,22 @@
 #![warn(clippy::all, rust_2018_idioms, clippy::pedantic)]
 
-pub mod bank;
+pub mod alert;
+pub mod anomaly;
+pub use transactomatic_core::bank;
 pub mod cli;
+#[cfg(unix)]
+pub mod control;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod fraud;
+pub mod ledger;
+pub mod metrics;
+pub mod policy;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod shutdown;
+pub mod stream;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "webhooks")]
+pub mod webhook;
@@ -1,48 +1,954 @@
 #![warn(clippy::all, rust_2018_idioms, clippy::pedantic)]
 
+use std::fs::File;
 use std::io;
+use std::path::Path;
+use std::time::Duration;
 
+use clap::Parser;
 use tracing::subscriber::set_global_default;
 use tracing_log::LogTracer;
 use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, EnvFilter, Registry};
-use transactomatic::cli;
+use transactomatic::alert::{self, Sinks};
+use transactomatic::anomaly::Detector;
+use transactomatic::bank::{audit, retention::RetentionPolicy, wal};
+use transactomatic::cli::{self, checkpoint::Checkpointer};
+#[cfg(feature = "tui")]
+use transactomatic::dashboard::Dashboard;
+use transactomatic::fraud::{Rules, Screener};
+use transactomatic::ledger::Ledger;
+use transactomatic::metrics::Metrics;
+use transactomatic::shutdown::Shutdown;
+use transactomatic::stream;
+#[cfg(feature = "webhooks")]
+use transactomatic::webhook;
+#[cfg(feature = "server")]
+use transactomatic::{
+    bank::Bank,
+    server::{
+        auth::{self, Tokens},
+        lanes::Lanes,
+        limit::Limits,
+        tenants::Tenants,
+        Server,
+    },
+};
 
 const EXIT_INVALID_USAGE: i32 = 1;
 const EXIT_ERROR_OPENING_FILE: i32 = 2;
 const EXIT_ERROR_PROCESSING: i32 = 3;
+const EXIT_OUT_OF_BALANCE: i32 = 4;
+const EXIT_AUDIT_LOG_BROKEN: i32 = 5;
+const EXIT_SCHEMA_INVALID: i32 = 6;
+const EXIT_STATE_DIVERGED: i32 = 7;
+const EXIT_INTERRUPTED: i32 = 8;
 
 fn main() {
-    init_logging();
+    let args = cli::Args::try_parse().unwrap_or_else(|err| {
+        let _ = err.print();
+        std::process::exit(if err.use_stderr() {
+            EXIT_INVALID_USAGE
+        } else {
+            0
+        });
+    });
+    init_logging(!dashboard_requested(&args), args.log_level());
+
+    if let Some(command) = args.command {
+        run_command(command);
+        return;
+    }
 
-    let mut args = std::env::args();
+    let input = args.input.as_ref().expect("input is required");
+    let (reader, len) = open_input(input);
+    let reader = match &args.input_manifest {
+        Some(manifest) => verify_input(reader, input, manifest, args.on_mismatch),
+        None => reader,
+    };
+    let expected_records = args
+        .expected_records
+        .or_else(|| len.map(cli::estimate_records));
 
-    let input_file = args.nth(1).unwrap_or_else(|| {
-        eprintln!("Input file must be provided");
-        std::process::exit(EXIT_INVALID_USAGE);
+    let mut options = options(&args, expected_records);
+    options.diagnostics = diagnostics(&args, input);
+    options.shutdown = shutdown();
+    let mut output = cli::output::Writer::new(
+        std::io::stdout().lock(),
+        args.output_compress,
+        &args.encrypt_to,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("error setting up output: {e}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    });
+    #[cfg(feature = "tui")]
+    let dashboard = options.dashboard.clone().map(start_dashboard);
+    let result = cli::run_with_options(reader, &mut output, &mut options);
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        close_dashboard(dashboard);
+    }
+    #[cfg(feature = "webhooks")]
+    if let Some(notifier) = &options.webhooks {
+        notifier.finish();
+    }
+    if let Err(err) = result {
+        if let Some(interrupted) = err.downcast_ref::<cli::Interrupted>() {
+            interrupted_exit(&args, interrupted);
+        }
+        eprintln!("error processing transaction instructions: {err:?}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+    let digest = output.finish().map(|(_, digest)| digest);
+    let digest = digest.unwrap_or_else(|e| {
+        eprintln!("error writing account report: {e}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
     });
+    if let (Some(path), Some(manifest)) = (&args.manifest, &mut options.manifest) {
+        manifest.add(Path::new(cli::manifest::STDOUT), digest);
+        if let Err(err) = manifest.write(path) {
+            eprintln!("error writing manifest: {err}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }
+    }
+    if let (Some(path), Some(metrics)) = (&args.metrics, &options.metrics) {
+        if let Err(err) = std::fs::write(path, metrics.render()) {
+            eprintln!("error writing metrics: {err}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }
+    }
+    if let (Some(path), Some(screener)) = (&args.fraud_report, &options.fraud) {
+        let written = File::create(path)
+            .map_err(csv::Error::from)
+            .and_then(|file| screener.write_report(file));
+        if let Err(err) = written {
+            eprintln!("error writing fraud report: {err}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }
+    }
+    if let (Some(path), Some(detector)) = (&args.anomaly_report, &options.anomalies) {
+        write_anomalies(path, detector);
+    }
+    if let (Some(path), Some(ledger)) = (&args.ledger, &options.ledger) {
+        write_ledger(path, args.trial_balance.as_deref(), ledger);
+    }
+}
 
-    let reader = std::fs::OpenOptions::new()
-        .read(true)
-        .write(false)
-        .open(input_file)
-        .unwrap_or_else(|e| {
-            eprintln!("error opening input file: {}", e);
+/// Stop cleanly on `SIGINT` and `SIGTERM`.
+fn shutdown() -> Shutdown {
+    let shutdown = Shutdown::new();
+    if let Err(err) = shutdown.on_signals() {
+        eprintln!("error installing signal handlers: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+    shutdown
+}
+
+/// Say where an interrupted run stopped and how to pick it up again, and exit.
+fn interrupted_exit(args: &cli::Args, interrupted: &cli::Interrupted) -> ! {
+    if args.state_dir.is_some() {
+        eprintln!("{interrupted}; run again with the same --state-dir to resume");
+    } else if !args.merge.is_empty() {
+        eprintln!("{interrupted}");
+    } else {
+        eprintln!(
+            "{interrupted}; --start-offset {} processes the rest of the input",
+            interrupted.byte
+        );
+    }
+    std::process::exit(EXIT_INTERRUPTED);
+}
+
+/// A printer for diagnostics about `input` if `args` ask for them, quoting lines if the input is a local file.
+fn diagnostics(args: &cli::Args, input: &Path) -> Option<cli::diagnostics::Printer> {
+    use std::io::IsTerminal;
+
+    let stderr = std::io::stderr();
+    let print = match args.diagnostics {
+        cli::diagnostics::When::Always => true,
+        cli::diagnostics::When::Never => false,
+        cli::diagnostics::When::Auto => stderr.is_terminal() && !dashboard_requested(args),
+    };
+    if !print {
+        return None;
+    }
+    let color = !args.no_color && std::env::var_os("NO_COLOR").is_none() && stderr.is_terminal();
+    let printer = cli::diagnostics::Printer::new(stderr, color);
+    Some(match File::open(input) {
+        Ok(file) => printer.with_source(file, input.display().to_string()),
+        Err(_) => printer,
+    })
+}
+
+/// The options for processing the input, opening the files they need.
+fn options(args: &cli::Args, expected_records: Option<usize>) -> cli::Options {
+    let mut sinks = Sinks::default();
+    if args.alert_stderr {
+        sinks.add(std::sync::Arc::new(alert::Stderr));
+    }
+    #[cfg(feature = "webhooks")]
+    let notifier = args.webhooks.as_deref().map(webhooks);
+    #[cfg(feature = "webhooks")]
+    if let Some(notifier) = &notifier {
+        sinks.add(notifier.clone());
+    }
+    let mut options = cli::Options {
+        threads: args.threads,
+        queue_capacity: args.queue_capacity,
+        headers: args.map_header.clone(),
+        amounts: cli::amounts::Notation {
+            format: args.amounts,
+            locale: args.locale,
+        },
+        max_errors: args.max_errors,
+        slice: cli::slice::Slice {
+            start_offset: args.start_offset,
+            skip_rows: args.skip_rows,
+            limit: args.limit,
+        },
+        parse_threads: args.parse_threads,
+        chunk_size: args.chunk_size,
+        merge: args.merge.clone(),
+        spill_after: args.spill_after,
+        retention: RetentionPolicy {
+            drop_charged_back: args.drop_charged_back,
+            dispute_window: args.dispute_window,
+        },
+        id_scope: args.tx_id_scope,
+        report: cli::ReportOptions {
+            sorted: args.sorted,
+            flush_every: args.flush_every,
+            metadata: args.with_metadata,
+        },
+        expected_records,
+        minimum_balance: args.minimum_balance,
+        check_invariants: args.check_invariants,
+        tenant_dir: args.tenant_dir.clone(),
+        negative_report: args.negative_report.clone(),
+        locked_report: args.locked_report.clone(),
+        balance_period: args.balance_history.as_ref().map(|_| args.balance_period),
+        balance_history: args.balance_history.clone(),
+        compression: args.output_compress,
+        recipients: args.encrypt_to.clone(),
+        manifest: args
+            .manifest
+            .as_ref()
+            .map(|_| cli::manifest::Manifest::default()),
+        metrics: args.metrics.as_ref().map(|_| Metrics::new()),
+        fraud: args
+            .fraud_report
+            .as_ref()
+            .map(|_| Screener::with_sinks(fraud_rules(args.fraud_rules.as_deref()), sinks.clone())),
+        anomalies: args
+            .anomaly_report
+            .as_ref()
+            .map(|_| Detector::with_sinks(args.anomaly_sigmas, sinks.clone())),
+        ledger: args.ledger.as_ref().map(|_| Ledger::new()),
+        #[cfg(feature = "tui")]
+        dashboard: args.dashboard.then(Dashboard::new),
+        #[cfg(feature = "webhooks")]
+        webhooks: notifier,
+        ..cli::Options::default()
+    };
+    if let Some(path) = &args.accounts {
+        options.accounts = cli::accounts::read(open_file(path)).unwrap_or_else(|e| {
+            eprintln!("error reading accounts file: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+    }
+    open_logs(args, &mut options);
+    options
+}
+
+/// Open the checkpoint directory, logs, and record files asked for in `args`.
+fn open_logs(args: &cli::Args, options: &mut cli::Options) {
+    if let Some(state_dir) = &args.state_dir {
+        let checkpointer =
+            Checkpointer::new(state_dir, args.checkpoint_interval).unwrap_or_else(|e| {
+                eprintln!("error creating state directory: {e}");
+                std::process::exit(EXIT_ERROR_OPENING_FILE);
+            });
+        options.checkpointer = Some(checkpointer);
+    }
+    if let Some(wal) = &args.wal {
+        let wal = wal::Writer::open(wal).unwrap_or_else(|e| {
+            eprintln!("error opening write-ahead log: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+        options.wal = Some(wal.with_retry(args.retry.policy()));
+    }
+    if let Some(audit_log) = &args.audit_log {
+        let audit = audit::Writer::open(audit_log).unwrap_or_else(|e| {
+            eprintln!("error opening audit log: {e}");
             std::process::exit(EXIT_ERROR_OPENING_FILE);
         });
+        options.audit = Some(audit.with_retry(args.retry.policy()));
+    }
+    if let Some(rejects) = &args.rejects {
+        let rejects = cli::rejects::Writer::create(rejects).unwrap_or_else(|e| {
+            eprintln!("error creating rejects file: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+        options.rejects = Some(rejects);
+    }
+    if let Some(applied) = &args.applied_events {
+        let applied = cli::applied::Writer::create(applied).unwrap_or_else(|e| {
+            eprintln!("error creating applied events file: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+        options.applied = Some(applied);
+    }
+    if let Some(index) = &args.dedup_index {
+        let index = cli::dedup::Index::open(index).unwrap_or_else(|e| {
+            eprintln!("error opening dedup index: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+        options.dedup = Some(index);
+    }
+}
+
+fn schema(command: cli::SchemaCommand) {
+    let cli::SchemaCommand::Check {
+        input,
+        map_header,
+        amounts,
+        locale,
+    } = command;
+    let notation = cli::amounts::Notation {
+        format: amounts,
+        locale,
+    };
+    let schema = cli::schema::check(open_file(&input), &map_header, notation).unwrap_or_else(|e| {
+        eprintln!("error reading input: {e}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    });
+    if let Err(err) = cli::schema::write(&schema, std::io::stdout()) {
+        eprintln!("error writing schema report: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+    if !schema.is_valid() {
+        std::process::exit(EXIT_SCHEMA_INVALID);
+    }
+}
+
+fn write_anomalies(path: &Path, detector: &Detector) {
+    let written = File::create(path)
+        .map_err(csv::Error::from)
+        .and_then(|file| detector.write_report(file));
+    if let Err(err) = written {
+        eprintln!("error writing anomaly report: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+}
+
+/// Write the ledger's journal and trial balance, exiting if the ledger is out of balance.
+fn write_ledger(journal: &Path, trial_balance_path: Option<&Path>, ledger: &Ledger) {
+    let written = File::create(journal)
+        .map_err(csv::Error::from)
+        .and_then(|file| ledger.write_journal(file));
+    if let Err(err) = written {
+        eprintln!("error writing ledger journal: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+    let trial_balance = ledger.trial_balance();
+    if let Some(path) = trial_balance_path {
+        let written = File::create(path)
+            .map_err(csv::Error::from)
+            .and_then(|file| trial_balance.write(file));
+        if let Err(err) = written {
+            eprintln!("error writing trial balance: {err}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }
+    }
+    if !trial_balance.is_balanced() {
+        let (debits, credits) = trial_balance.totals();
+        eprintln!("ledger is out of balance: debits {debits}, credits {credits}");
+        std::process::exit(EXIT_OUT_OF_BALANCE);
+    }
+}
+
+/// Start notifying the webhooks in the file at `path`.
+#[cfg(feature = "webhooks")]
+fn webhooks(path: &Path) -> std::sync::Arc<webhook::Notifier> {
+    let hooks = webhook::read(path).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(EXIT_INVALID_USAGE);
+    });
+    webhook::Notifier::start(hooks)
+}
+
+fn fraud_rules(path: Option<&Path>) -> Rules {
+    let Some(path) = path else {
+        return Rules::default();
+    };
+    serde_json::from_reader(io::BufReader::new(open_file(path))).unwrap_or_else(|e| {
+        eprintln!("error reading fraud rules: {e}");
+        std::process::exit(EXIT_INVALID_USAGE);
+    })
+}
+
+fn replay(log: &Path, until: wal::Until, anomaly_report: Option<&Path>, anomaly_sigmas: f64) {
+    let detector = anomaly_report.map(|_| Detector::new(anomaly_sigmas));
+    if let Err(err) = cli::replay(open_file(log), std::io::stdout(), until, detector.as_ref()) {
+        eprintln!("error replaying log: {err:?}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+    if let (Some(path), Some(detector)) = (anomaly_report, &detector) {
+        write_anomalies(path, detector);
+    }
+}
+
+/// Check that the write-ahead log at `log` reproduces the snapshot at `snapshot`, exiting if it doesn't.
+fn verify(log: &Path, snapshot: &Path) {
+    match cli::verify(open_file(log), open_file(snapshot), std::io::stdout()) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(EXIT_STATE_DIVERGED),
+        Err(err) => {
+            eprintln!("error verifying log: {err:?}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }
+    }
+}
 
-    if let Err(err) = cli::run(reader, std::io::stdout()) {
-        eprintln!("error processing transaction instructions: {:?}", err);
+fn run_command(command: cli::Command) {
+    match command {
+        cli::Command::Replay {
+            log,
+            until_seq,
+            until_time,
+            anomaly_report,
+            anomaly_sigmas,
+        } => {
+            let until = match (until_seq, until_time) {
+                (Some(seq), _) => wal::Until::Seq(seq),
+                (None, Some(timestamp)) => wal::Until::Timestamp(timestamp),
+                (None, None) => wal::Until::End,
+            };
+            replay(&log, until, anomaly_report.as_deref(), anomaly_sigmas);
+        }
+        cli::Command::Verify { log, snapshot } => verify(&log, &snapshot),
+        cli::Command::Report { input, format } => {
+            if let Err(err) = cli::report::report(open_file(&input), std::io::stdout(), format) {
+                eprintln!("error processing transaction instructions: {err:?}");
+                std::process::exit(EXIT_ERROR_PROCESSING);
+            }
+        }
+        cli::Command::Statement {
+            input,
+            client,
+            from,
+            to,
+            format,
+        } => {
+            if let Err(err) = cli::statement::statement(
+                open_file(&input),
+                std::io::stdout(),
+                client,
+                cli::statement::Period::from_dates(from, to),
+                format,
+            ) {
+                eprintln!("error writing statements: {err:?}");
+                std::process::exit(EXIT_ERROR_PROCESSING);
+            }
+        }
+        cli::Command::Schema(command) => schema(command),
+        cli::Command::VerifyAudit { log } => {
+            match cli::verify_audit(open_file(&log), std::io::stdout()) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(EXIT_AUDIT_LOG_BROKEN),
+                Err(err) => {
+                    eprintln!("error verifying audit log: {err:?}");
+                    std::process::exit(EXIT_ERROR_PROCESSING);
+                }
+            }
+        }
+        cli::Command::Purge(args) => purge(args),
+        cli::Command::ExportState { source, archive } => export_state(&source, &archive),
+        cli::Command::ImportState(args) => import_state(&args),
+        cli::Command::MigrateStore(args) => migrate_store(&args),
+        cli::Command::Diff { before, after } => {
+            if let Err(err) =
+                cli::diff::diff(open_file(&before), open_file(&after), std::io::stdout())
+            {
+                eprintln!("error comparing reports: {err:?}");
+                std::process::exit(EXIT_ERROR_PROCESSING);
+            }
+        }
+        cli::Command::Reconcile {
+            report,
+            expected,
+            tolerance,
+        } => match cli::reconcile::reconcile(
+            open_file(&report),
+            open_file(&expected),
+            tolerance,
+            std::io::stdout(),
+        ) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(EXIT_OUT_OF_BALANCE),
+            Err(err) => {
+                eprintln!("error reconciling report: {err:?}");
+                std::process::exit(EXIT_ERROR_PROCESSING);
+            }
+        },
+        #[cfg(feature = "server")]
+        cli::Command::Serve(args) => serve(&args),
+        #[cfg(feature = "kafka")]
+        cli::Command::Kafka(args) => consume_kafka(args),
+        #[cfg(feature = "redis")]
+        cli::Command::Redis(args) => consume_redis(args),
+        #[cfg(feature = "amqp")]
+        cli::Command::Amqp(args) => consume_amqp(args),
+        cli::Command::Watch(args) => watch(args),
+    }
+}
+
+fn purge(args: cli::PurgeArgs) {
+    let files = cli::purge::Files {
+        snapshot: args.snapshot,
+        audit_log: args.audit_log,
+        journal: args.journal,
+    };
+    if let Err(err) = cli::purge::purge(args.client, &args.reference, &files, std::io::stdout()) {
+        eprintln!("error purging client {}: {err}", args.client);
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+}
+
+fn export_state(source: &Path, archive: &Path) {
+    let exported = File::create(archive)
+        .map_err(Into::into)
+        .and_then(|file| cli::state::export(source, io::BufWriter::new(file)));
+    match exported {
+        Ok(header) => println!(
+            "exported {} accounts and {} transactions from {}",
+            header.accounts,
+            header.transactions,
+            source.display()
+        ),
+        Err(err) => {
+            eprintln!("error exporting state: {err}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }
+    }
+}
+
+fn import_state(args: &cli::ImportStateArgs) {
+    match cli::state::import(
+        open_file(&args.archive),
+        &args.destination,
+        args.kind,
+        args.force,
+    ) {
+        Ok(header) => println!(
+            "imported {} accounts and {} transactions exported by {}",
+            header.accounts, header.transactions, header.exported_by
+        ),
+        Err(err) => {
+            eprintln!("error importing state: {err}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }
+    }
+}
+
+fn migrate_store(args: &cli::MigrateStoreArgs) {
+    if let Err(err) = cli::state::migrate(
+        &args.source,
+        &args.destination,
+        args.kind,
+        args.force,
+        args.progress_every,
+        io::stderr(),
+    ) {
+        eprintln!("error migrating state: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+}
+
+fn watch(args: cli::WatchArgs) {
+    let options = stream::watch::Options {
+        dir: args.dir,
+        state_dir: args.state_dir,
+        poll_interval: Duration::from_secs(args.poll_interval),
+        control_socket: args.control_socket,
+        policy: args.policy,
+        #[cfg(feature = "tui")]
+        dashboard: args.dashboard.then(Dashboard::new),
+        shutdown: shutdown(),
+    };
+    #[cfg(feature = "tui")]
+    let dashboard = options.dashboard.clone().map(start_dashboard);
+    let result = stream::watch::run(&options);
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        close_dashboard(dashboard);
+    }
+    if let Err(err) = result {
+        eprintln!("error watching directory: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+}
+
+/// Whether a dashboard is going to be drawn on the terminal.
+fn dashboard_requested(args: &cli::Args) -> bool {
+    #[cfg(feature = "tui")]
+    return args.dashboard
+        || matches!(&args.command, Some(cli::Command::Watch(watch)) if watch.dashboard);
+    #[cfg(not(feature = "tui"))]
+    {
+        let _ = args;
+        false
+    }
+}
+
+/// Start drawing `dashboard`, which needs standard error to be a terminal.
+#[cfg(feature = "tui")]
+fn start_dashboard(
+    dashboard: std::sync::Arc<Dashboard>,
+) -> (
+    std::sync::Arc<Dashboard>,
+    std::thread::JoinHandle<io::Result<()>>,
+) {
+    use std::io::IsTerminal;
+
+    if !io::stderr().is_terminal() {
+        eprintln!("the dashboard needs standard error to be a terminal");
+        std::process::exit(EXIT_INVALID_USAGE);
+    }
+    let handle = dashboard.spawn();
+    (dashboard, handle)
+}
+
+/// Mark the dashboard's processing finished and wait for the operator to close it.
+#[cfg(feature = "tui")]
+fn close_dashboard(
+    (dashboard, handle): (
+        std::sync::Arc<Dashboard>,
+        std::thread::JoinHandle<io::Result<()>>,
+    ),
+) {
+    dashboard.finish();
+    match handle.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => eprintln!("error drawing dashboard: {err}"),
+        Err(_) => eprintln!("dashboard stopped unexpectedly"),
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &cli::ServeArgs) {
+    let bank = match &args.snapshot {
+        Some(path) => Bank::load_snapshot(open_file(path)).unwrap_or_else(|e| {
+            eprintln!("error loading snapshot: {e}");
+            std::process::exit(EXIT_ERROR_PROCESSING);
+        }),
+        None => Bank::new(),
+    };
+    let server = bind(args, bank);
+    let server = server
+        .with_limits(Limits {
+            per_connection: args.connection_rate,
+            per_client: args.client_rate,
+        })
+        .with_shutdown(shutdown());
+    let server = match &args.policy {
+        Some(path) => server.with_policy(policy(path)),
+        None => server,
+    };
+    let server = match &args.tenant_tokens {
+        Some(path) => server.with_tenants(Tenants::read(path).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(EXIT_INVALID_USAGE);
+        })),
+        None => server,
+    };
+    let tokens = match &args.api_tokens {
+        Some(path) => Tokens::read(path).map(Some),
+        None => Tokens::from_env(),
+    };
+    let server = match tokens {
+        Ok(Some(_)) if args.tenant_tokens.is_some() => {
+            eprintln!("{} can't be combined with --tenant-tokens", auth::ENV);
+            std::process::exit(EXIT_INVALID_USAGE);
+        }
+        Ok(Some(tokens)) => server.with_tokens(tokens),
+        Ok(None) => server,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(EXIT_INVALID_USAGE);
+        }
+    };
+    let server = match &args.audit_log {
+        Some(path) => server.with_audit(
+            audit::Writer::open(path)
+                .unwrap_or_else(|e| {
+                    eprintln!("error opening audit log: {e}");
+                    std::process::exit(EXIT_ERROR_OPENING_FILE);
+                })
+                .with_retry(args.retry.policy()),
+        ),
+        None => server,
+    };
+    let server = match &args.priority_lanes {
+        Some(path) => server.with_lanes(Lanes::read(path).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(EXIT_INVALID_USAGE);
+        })),
+        None => server,
+    };
+    if let Some(path) = &args.control_socket {
+        control(path, server.bank());
+    }
+    #[cfg(feature = "webhooks")]
+    let notifier = args.webhooks.as_deref().map(|path| {
+        let notifier = webhooks(path);
+        notifier.install(&mut server.bank().lock().expect("bank lock poisoned"));
+        notifier
+    });
+    tracing::info!(addr = ?server.local_addr(), "serving");
+    server.run(args.threads);
+    #[cfg(feature = "webhooks")]
+    if let Some(notifier) = notifier {
+        notifier.finish();
+    }
+}
+
+/// A server listening where `args` say, with TLS if they give a certificate.
+#[cfg(feature = "server")]
+fn bind(args: &cli::ServeArgs, bank: Bank) -> Server {
+    let addr = &args.addr;
+    #[cfg(feature = "tls")]
+    let server = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let config =
+                transactomatic::server::tls::config(cert, key, args.tls_client_ca.as_deref())
+                    .unwrap_or_else(|e| {
+                        eprintln!("{e}");
+                        std::process::exit(EXIT_INVALID_USAGE);
+                    });
+            Server::bind_tls(addr, bank, config)
+        }
+        _ => Server::bind(addr, bank),
+    };
+    #[cfg(not(feature = "tls"))]
+    let server = Server::bind(addr, bank);
+    server.unwrap_or_else(|e| {
+        eprintln!("error listening on {addr}: {e}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    })
+}
+
+/// A reloader for the policy file at `path`, reloading on `SIGHUP` too.
+#[cfg(feature = "server")]
+fn policy(path: &Path) -> transactomatic::policy::Reloader {
+    let reloader = transactomatic::policy::Reloader::new(path).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(EXIT_INVALID_USAGE);
+    });
+    if let Err(err) = reloader.on_hangup() {
+        eprintln!("error installing signal handlers: {err}");
         std::process::exit(EXIT_ERROR_PROCESSING);
     }
+    reloader
 }
 
-/// Initialize logging just like `env_logger`, but default to level OFF to avoid polluting output.
-fn init_logging() {
+#[cfg(feature = "server")]
+fn control(path: &Path, bank: std::sync::Arc<std::sync::Mutex<Bank>>) {
+    #[cfg(unix)]
+    match transactomatic::control::Control::bind(path, bank) {
+        Ok(control) => control.spawn(),
+        Err(err) => {
+            eprintln!("error creating control socket {}: {err}", path.display());
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = bank;
+        eprintln!(
+            "can't serve control socket {}: Unix sockets aren't supported",
+            path.display()
+        );
+        std::process::exit(EXIT_INVALID_USAGE);
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn consume_kafka(args: cli::KafkaArgs) {
+    let format = match args.avro_schema {
+        Some(path) => {
+            let schema = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| apache_avro::Schema::parse_str(&s).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| {
+                    eprintln!("error loading Avro schema: {e}");
+                    std::process::exit(EXIT_ERROR_OPENING_FILE);
+                });
+            stream::Format::Avro(schema)
+        }
+        None => stream::Format::Json,
+    };
+    let options = stream::kafka::Options {
+        brokers: args.brokers,
+        topic: args.topic,
+        group: args.group,
+        format,
+        state_dir: args.state_dir,
+        snapshot_interval: args.snapshot_interval,
+        retry: args.retry.policy(),
+        publish: stream::kafka::Topics {
+            events: args.events_topic,
+            accounts: args.accounts_topic,
+            rejections: args.rejections_topic,
+            dead_letters: args.dead_letter_topic,
+        },
+        control_socket: args.control_socket,
+        policy: args.policy,
+        dead_letters: args.dead_letter_file,
+        shutdown: shutdown(),
+    };
+    if let Err(err) = stream::kafka::run(&options) {
+        eprintln!("error consuming from Kafka: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+}
+
+#[cfg(feature = "redis")]
+fn consume_redis(args: cli::RedisArgs) {
+    let options = stream::redis::Options {
+        url: args.url,
+        stream: args.stream,
+        group: args.group,
+        consumer: args.consumer,
+        format: stream::Format::Json,
+        state_dir: args.state_dir,
+        snapshot_interval: args.snapshot_interval,
+        retry: args.retry.policy(),
+        batch_size: args.batch_size,
+        control_socket: args.control_socket,
+        policy: args.policy,
+        dead_letters: args.dead_letter_file,
+        shutdown: shutdown(),
+    };
+    if let Err(err) = stream::redis::run(&options) {
+        eprintln!("error consuming from Redis: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+}
+
+#[cfg(feature = "amqp")]
+fn consume_amqp(args: cli::AmqpArgs) {
+    let options = stream::amqp::Options {
+        url: args.url,
+        queue: args.queue,
+        dead_letter_exchange: args.dead_letter_exchange,
+        format: stream::Format::Json,
+        state_dir: args.state_dir,
+        snapshot_interval: args.snapshot_interval,
+        retry: args.retry.policy(),
+        control_socket: args.control_socket,
+        policy: args.policy,
+        dead_letters: args.dead_letter_file,
+        shutdown: shutdown(),
+    };
+    if let Err(err) = stream::amqp::run(&options) {
+        eprintln!("error consuming from AMQP: {err}");
+        std::process::exit(EXIT_ERROR_PROCESSING);
+    }
+}
+
+/// An input to process, from a local file or object storage.
+trait Input: io::Read + io::Seek + Send {}
+
+impl<T: io::Read + io::Seek + Send> Input for T {}
+
+/// Open the input at `path`, returning its length in bytes if known.
+fn open_input(path: &Path) -> (Box<dyn Input>, Option<u64>) {
+    #[cfg(feature = "s3")]
+    if let Some(url) = path.to_str().filter(|path| cli::s3::is_s3(path)) {
+        let object = cli::s3::Object::open(url).unwrap_or_else(|e| {
+            eprintln!("error opening input object: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+        let len = object.len();
+        return (Box::new(object), Some(len));
+    }
+    #[cfg(feature = "gcs")]
+    if let Some(url) = path.to_str().filter(|path| cli::gcs::is_gcs(path)) {
+        let object = cli::gcs::Object::open(url).unwrap_or_else(|e| {
+            eprintln!("error opening input object: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+        let len = object.len();
+        return (Box::new(object), Some(len));
+    }
+    #[cfg(feature = "azure")]
+    if let Some(url) = path.to_str().filter(|path| cli::azure::is_azure(path)) {
+        let blob = cli::azure::Blob::open(url).unwrap_or_else(|e| {
+            eprintln!("error opening input blob: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        });
+        let len = blob.len();
+        return (Box::new(blob), Some(len));
+    }
+    let file = open_file(path);
+    let len = file.metadata().ok().map(|metadata| metadata.len());
+    (Box::new(file), len)
+}
+
+/// Check `reader`, opened from `input`, against its entry in the manifest at `manifest` as it's read.
+fn verify_input(
+    reader: Box<dyn Input>,
+    input: &Path,
+    manifest: &Path,
+    on_mismatch: cli::manifest::OnMismatch,
+) -> Box<dyn Input> {
+    let manifest = cli::manifest::Manifest::read(open_file(manifest)).unwrap_or_else(|e| {
+        eprintln!("error reading input manifest: {e}");
+        std::process::exit(EXIT_ERROR_OPENING_FILE);
+    });
+    match (manifest.find(input), on_mismatch) {
+        (Some(entry), _) => Box::new(cli::manifest::Verifier::new(
+            reader,
+            entry.clone(),
+            on_mismatch,
+        )),
+        (None, cli::manifest::OnMismatch::Fail) => {
+            eprintln!("input isn't in the input manifest");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        }
+        (None, cli::manifest::OnMismatch::Warn) => {
+            tracing::warn!(
+                ?input,
+                "input isn't in the input manifest; not verifying it"
+            );
+            reader
+        }
+    }
+}
+
+fn open_file(path: &Path) -> File {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(false)
+        .open(path)
+        .unwrap_or_else(|e| {
+            eprintln!("error opening input file: {e}");
+            std::process::exit(EXIT_ERROR_OPENING_FILE);
+        })
+}
+
+/// Initialize logging to standard error, filtered by `RUST_LOG` if it's set and at `level` otherwise.  Nothing is
+/// logged if `enabled` is false, such as when log lines would be drawn over the dashboard.
+fn init_logging(enabled: bool, level: &str) {
+    if !enabled {
+        return;
+    }
     LogTracer::init().expect("could not capture logs");
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
     let layer = tracing_subscriber::fmt::layer()
         .with_span_events(FmtSpan::FULL)
         .with_writer(io::stderr);
     let subscriber = Registry::default().with(env_filter).with(layer);
-    set_global_default(subscriber).expect("error creating tracing subscriber")
+    set_global_default(subscriber).expect("error creating tracing subscriber");
 }
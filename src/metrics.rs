@@ -0,0 +1,416 @@
+//! This module contains metrics about a [Bank](../bank/struct.Bank.html) in the Prometheus text format.
+//!
+//! [`Metrics::install`](struct.Metrics.html#method.install) registers a hook and an observer on a bank that count
+//! every instruction and time how long it takes to apply.  One `Metrics` can be installed on several banks, such as the
+//! shards of a multi-threaded run, to add them all up.  The account gauges are taken from a bank when
+//! [`observe_accounts`](struct.Metrics.html#method.observe_accounts) is called.
+//!
+//! | Metric | Type | |
+//! |---|---|---|
+//! | `transactomatic_instructions_total{kind, outcome}` | counter | Instructions by kind, and `applied` or the reason they were rejected. |
+//! | `transactomatic_apply_duration_seconds` | histogram | Time taken to apply each instruction, including hooks. |
+//! | `transactomatic_accounts` | gauge | Number of accounts. |
+//! | `transactomatic_locked_accounts` | gauge | Number of locked accounts. |
+//! | `transactomatic_queue_depth` | gauge | Instructions queued for the shards of a multi-threaded run. |
+//! | `transactomatic_queue_depth_peak` | gauge | The largest queue depth so far. |
+//! | `transactomatic_queue_capacity` | gauge | Instructions each shard can queue. |
+//! | `transactomatic_queue_stall_seconds_total` | counter | Time spent waiting for room in a full shard queue. |
+//!
+//! Instructions rejected by another hook are counted, but not timed.  The queue metrics are only there once
+//! [`observe_queue`](struct.Metrics.html#method.observe_queue) has been called.
+
+use crate::bank::account::Account;
+use crate::bank::event::Event;
+use crate::bank::hook::{Decision, Hook};
+use crate::bank::shard::QueueStats;
+use crate::bank::transaction::instruction::{TransactionInstruction, TransactionInstructionKind};
+use crate::bank::transaction::Error;
+use crate::bank::Bank;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Content type of the rendered metrics, for HTTP responses.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+const KINDS: [&str; 6] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "reinstate",
+];
+
+const OUTCOMES: [&str; 16] = [
+    "applied",
+    "insufficient_funds",
+    "account_frozen",
+    "negative_amount",
+    "duplicate_transaction",
+    "transaction_not_found",
+    "client_mismatch",
+    "not_disputed",
+    "rejected_by_hook",
+    "transaction_retired",
+    "missing_amount",
+    "withdrawal_not_allowed",
+    "below_minimum_balance",
+    "missing_operator_reference",
+    "not_charged_back",
+    "amount_overflow",
+];
+
+/// Upper bounds of the latency histogram's buckets, in seconds.  Applying an instruction normally takes microseconds;
+/// the larger buckets catch spilled transactions being read back from disk and slow hooks.
+const BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.1,
+];
+
+/// Counters and gauges for one or more banks.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    instructions: [[AtomicU64; OUTCOMES.len()]; KINDS.len()],
+    /// Observations at or below each bucket's bound, not yet accumulated.
+    buckets: [AtomicU64; BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_nanos: AtomicU64,
+    accounts: AtomicU64,
+    locked_accounts: AtomicU64,
+    queue: Mutex<Option<Arc<QueueStats>>>,
+}
+
+/// Times and counts instructions for a `Metrics`.
+struct MetricsHook {
+    metrics: Arc<Metrics>,
+    started: Option<Instant>,
+}
+
+impl Metrics {
+    #[must_use]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Count and time every instruction `bank` processes from now on.
+    pub fn install(self: &Arc<Self>, bank: &mut Bank) {
+        bank.register_hook(MetricsHook {
+            metrics: Arc::clone(self),
+            started: None,
+        });
+        // Instructions rejected by another hook never reach this one's `after_apply`.
+        let metrics = Arc::clone(self);
+        bank.register_observer(move |event: &Event| {
+            if let Event::InstructionRejected {
+                kind,
+                error: Error::RejectedByHook,
+                ..
+            } = event
+            {
+                metrics.count(*kind, Err(Error::RejectedByHook));
+            }
+        });
+    }
+
+    /// Set the account gauges from `bank`.
+    pub fn observe_accounts(&self, bank: &Bank) {
+        self.observe_banks([bank]);
+    }
+
+    /// Set the account gauges from all of `banks` together, for when accounts are split between banks.
+    pub fn observe_banks<'a, I: IntoIterator<Item = &'a Bank>>(&self, banks: I) {
+        let (accounts, locked_accounts) =
+            banks.into_iter().fold((0, 0), |(accounts, locked), bank| {
+                let totals = bank.totals();
+                (accounts + totals.accounts, locked + totals.locked_accounts)
+            });
+        let count = |n: usize| u64::try_from(n).unwrap_or(u64::MAX);
+        self.accounts.store(count(accounts), Ordering::Relaxed);
+        self.locked_accounts
+            .store(count(locked_accounts), Ordering::Relaxed);
+    }
+
+    /// Report the queue metrics from `stats`, as they change.
+    ///
+    /// # Panics
+    ///
+    /// Panics if rendering the metrics panicked on another thread.
+    pub fn observe_queue(&self, stats: Arc<QueueStats>) {
+        *self.queue.lock().expect("metrics lock poisoned") = Some(stats);
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        let _ = self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "# HELP transactomatic_instructions_total Instructions processed, by kind and outcome."
+        )?;
+        writeln!(out, "# TYPE transactomatic_instructions_total counter")?;
+        for (kind, counts) in KINDS.iter().zip(&self.instructions) {
+            for (outcome, count) in OUTCOMES.iter().zip(counts) {
+                let count = count.load(Ordering::Relaxed);
+                if count > 0 {
+                    writeln!(
+                        out,
+                        "transactomatic_instructions_total{{kind=\"{kind}\",outcome=\"{outcome}\"}} {count}"
+                    )?;
+                }
+            }
+        }
+
+        writeln!(
+            out,
+            "# HELP transactomatic_apply_duration_seconds Time taken to apply an instruction."
+        )?;
+        writeln!(
+            out,
+            "# TYPE transactomatic_apply_duration_seconds histogram"
+        )?;
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                "transactomatic_apply_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}"
+            )?;
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "transactomatic_apply_duration_seconds_bucket{{le=\"+Inf\"}} {count}"
+        )?;
+        let sum = Duration::from_nanos(self.latency_nanos.load(Ordering::Relaxed)).as_secs_f64();
+        writeln!(out, "transactomatic_apply_duration_seconds_sum {sum}")?;
+        writeln!(out, "transactomatic_apply_duration_seconds_count {count}")?;
+
+        writeln!(out, "# HELP transactomatic_accounts Number of accounts.")?;
+        writeln!(out, "# TYPE transactomatic_accounts gauge")?;
+        writeln!(
+            out,
+            "transactomatic_accounts {}",
+            self.accounts.load(Ordering::Relaxed)
+        )?;
+        writeln!(
+            out,
+            "# HELP transactomatic_locked_accounts Number of locked accounts."
+        )?;
+        writeln!(out, "# TYPE transactomatic_locked_accounts gauge")?;
+        writeln!(
+            out,
+            "transactomatic_locked_accounts {}",
+            self.locked_accounts.load(Ordering::Relaxed)
+        )?;
+
+        match &*self.queue.lock().expect("metrics lock poisoned") {
+            Some(stats) => write_queue(out, stats),
+            None => Ok(()),
+        }
+    }
+
+    fn count(&self, kind: TransactionInstructionKind, result: Result<(), Error>) {
+        self.instructions[kind_index(kind)][outcome_index(result)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn time(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_nanos.fetch_add(
+            u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl Hook for MetricsHook {
+    fn before_apply(&mut self, _instruction: &TransactionInstruction) -> Decision {
+        self.started = Some(Instant::now());
+        Decision::Allow
+    }
+
+    fn after_apply(
+        &mut self,
+        instruction: &TransactionInstruction,
+        result: Result<&Account, Error>,
+    ) {
+        if let Some(started) = self.started.take() {
+            self.metrics.time(started.elapsed());
+        }
+        self.metrics.count(instruction.kind, result.map(|_| ()));
+    }
+}
+
+fn write_queue(out: &mut String, stats: &QueueStats) -> std::fmt::Result {
+    let gauges = [
+        (
+            "queue_depth",
+            "Instructions queued for the shards and not yet applied.",
+            stats.depth(),
+        ),
+        (
+            "queue_depth_peak",
+            "The largest number of instructions queued for the shards.",
+            stats.peak(),
+        ),
+        (
+            "queue_capacity",
+            "Instructions each shard can queue.",
+            stats.capacity(),
+        ),
+    ];
+    for (name, help, value) in gauges {
+        writeln!(out, "# HELP transactomatic_{name} {help}")?;
+        writeln!(out, "# TYPE transactomatic_{name} gauge")?;
+        writeln!(out, "transactomatic_{name} {value}")?;
+    }
+    writeln!(
+        out,
+        "# HELP transactomatic_queue_stall_seconds_total Time spent waiting for room in a full shard queue."
+    )?;
+    writeln!(
+        out,
+        "# TYPE transactomatic_queue_stall_seconds_total counter"
+    )?;
+    writeln!(
+        out,
+        "transactomatic_queue_stall_seconds_total {}",
+        stats.stalled().as_secs_f64()
+    )
+}
+
+fn kind_index(kind: TransactionInstructionKind) -> usize {
+    match kind {
+        TransactionInstructionKind::Deposit => 0,
+        TransactionInstructionKind::Withdrawal => 1,
+        TransactionInstructionKind::Dispute => 2,
+        TransactionInstructionKind::Resolve => 3,
+        TransactionInstructionKind::Chargeback => 4,
+        TransactionInstructionKind::Reinstate => 5,
+    }
+}
+
+fn outcome_index(result: Result<(), Error>) -> usize {
+    match result {
+        Ok(()) => 0,
+        Err(Error::InsufficientFunds) => 1,
+        Err(Error::AccountFrozen) => 2,
+        Err(Error::NegativeAmount) => 3,
+        Err(Error::DuplicateTransaction) => 4,
+        Err(Error::TransactionNotFound) => 5,
+        Err(Error::ClientMismatch) => 6,
+        Err(Error::NotDisputed) => 7,
+        Err(Error::RejectedByHook) => 8,
+        Err(Error::TransactionRetired) => 9,
+        Err(Error::MissingAmount) => 10,
+        Err(Error::WithdrawalNotAllowed) => 11,
+        Err(Error::BelowMinimumBalance) => 12,
+        Err(Error::MissingOperatorReference) => 13,
+        Err(Error::NotChargedBack) => 14,
+        Err(Error::AmountOverflow) => 15,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::TransactionId;
+
+    fn instruction(kind: TransactionInstructionKind, tx: u64) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(1),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(1)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    struct RejectWithdrawals;
+
+    impl Hook for RejectWithdrawals {
+        fn before_apply(&mut self, instruction: &TransactionInstruction) -> Decision {
+            if instruction.kind == TransactionInstructionKind::Withdrawal {
+                Decision::Reject
+            } else {
+                Decision::Allow
+            }
+        }
+    }
+
+    #[test]
+    fn counts_and_renders() {
+        let metrics = Metrics::new();
+        let mut bank = Bank::new();
+        metrics.install(&mut bank);
+        bank.register_hook(RejectWithdrawals);
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 2));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Dispute, 1));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Chargeback, 1));
+        metrics.observe_accounts(&bank);
+
+        let text = metrics.render();
+        for line in [
+            r#"transactomatic_instructions_total{kind="deposit",outcome="applied"} 1"#,
+            r#"transactomatic_instructions_total{kind="deposit",outcome="duplicate_transaction"} 1"#,
+            r#"transactomatic_instructions_total{kind="withdrawal",outcome="rejected_by_hook"} 1"#,
+            r#"transactomatic_instructions_total{kind="chargeback",outcome="applied"} 1"#,
+            r#"transactomatic_apply_duration_seconds_bucket{le="+Inf"} 4"#,
+            "transactomatic_apply_duration_seconds_count 4",
+            "transactomatic_accounts 1",
+            "transactomatic_locked_accounts 1",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{} missing from\n{}",
+                line,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn queue() {
+        let metrics = Metrics::new();
+        assert!(!metrics.render().contains("queue"));
+
+        let sharded = crate::bank::shard::ShardedBank::with_banks_and_capacity([Bank::new()], 8);
+        metrics.observe_queue(sharded.stats());
+        sharded
+            .submit(instruction(TransactionInstructionKind::Deposit, 1))
+            .unwrap();
+        sharded.finish().unwrap();
+
+        let text = metrics.render();
+        for line in [
+            "transactomatic_queue_depth 0",
+            "transactomatic_queue_depth_peak 1",
+            "transactomatic_queue_capacity 8",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{} missing from\n{}",
+                line,
+                text
+            );
+        }
+        assert!(text.contains("# TYPE transactomatic_queue_stall_seconds_total counter\n"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Policy: the bank settings a long-running mode can change without restarting.
+//!
+//! A policy file is JSON with any of these fields; missing fields keep their defaults, which apply no policy:
+//!
+//! ```json
+//! {"minimum_balance": "10", "dispute_window": 1000, "drop_charged_back": true, "check_invariants": true, "archive_after_days": 90}
+//! ```
+//!
+//! | Field | Effect |
+//! |---|---|
+//! | `minimum_balance` | The [minimum balance](../bank/struct.Bank.html#method.set_minimum_balance) of accounts without their own. |
+//! | `dispute_window` | How many recent deposits and withdrawals can be disputed. See the [retention policy](../bank/retention/index.html). |
+//! | `drop_charged_back` | Whether charged back transactions are retired. |
+//! | `check_invariants` | Whether every instruction is [checked](../bank/invariant/index.html) for broken invariants, stopping the process if one breaks. |
+//! | `archive_after_days` | How many days an empty account can go without an instruction before it's [archived](../bank/archive/index.html) to a file in the temporary directory. |
+//!
+//! A [`Reloader`](struct.Reloader.html) reads the file again when it changes, or on `SIGHUP`, and the new policy
+//! applies to the instructions after that.  Accounts and transactions are kept, since none of these settings are part
+//! of the bank's state.  A file that can't be read or parsed is logged and the previous policy stays in place.
+
+use crate::bank::amount::Amount;
+use crate::bank::archive::ArchivePolicy;
+use crate::bank::retention::RetentionPolicy;
+use crate::bank::Bank;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Seconds in a day.
+const DAY: u64 = 86_400;
+
+/// Least time between checks of the file's modification time.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bank settings that can be changed at any time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub minimum_balance: Option<Amount>,
+    pub dispute_window: Option<usize>,
+    pub drop_charged_back: bool,
+    pub check_invariants: bool,
+    pub archive_after_days: Option<u64>,
+}
+
+/// Errors reading a policy file.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading policy: {err}"),
+            Error::Json(err) => write!(f, "invalid policy: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl Policy {
+    /// Read a policy file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't a valid policy.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Apply the policy to `bank` from its next instruction.
+    pub fn apply(&self, bank: &mut Bank) {
+        bank.set_minimum_balance(self.minimum_balance);
+        bank.set_retention_policy(RetentionPolicy {
+            drop_charged_back: self.drop_charged_back,
+            dispute_window: self.dispute_window,
+        });
+        bank.set_invariant_checks(self.check_invariants);
+        bank.set_archive_policy(self.archive_after_days.map(|days| ArchivePolicy {
+            dormant_for: Duration::from_secs(days.saturating_mul(DAY)),
+            dir: std::env::temp_dir(),
+        }));
+    }
+}
+
+/// Reads a policy file again when it changes or when the process gets `SIGHUP`.
+#[derive(Debug)]
+pub struct Reloader {
+    path: PathBuf,
+    policy: Policy,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    hangup: Arc<AtomicBool>,
+}
+
+impl Reloader {
+    /// Read the policy file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't a valid policy.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let modified = modified(&path);
+        let policy = Policy::read(&path)?;
+        Ok(Self {
+            path,
+            policy,
+            modified,
+            checked: Instant::now(),
+            hangup: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// The policy as last read.
+    #[must_use]
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Reload on `SIGHUP` as well as when the file changes.  `SIGHUP` no longer ends the process.
+    ///
+    /// # Errors
+    ///
+    /// Will return an `Err` if the signal handler can't be installed.
+    #[cfg(unix)]
+    pub fn on_hangup(&self) -> io::Result<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&self.hangup))?;
+        Ok(())
+    }
+
+    /// Signals aren't handled on this platform.
+    ///
+    /// # Errors
+    ///
+    /// Never returns an `Err`.
+    #[cfg(not(unix))]
+    pub fn on_hangup(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Read the file again if `SIGHUP` was received or the file has changed, returning the new policy if it differs
+    /// from the last one.
+    pub fn poll(&mut self) -> Option<Policy> {
+        let hangup = self.hangup.swap(false, Ordering::Relaxed);
+        if !hangup && self.checked.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        self.checked = Instant::now();
+        let modified = modified(&self.path);
+        if !hangup && modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match Policy::read(&self.path) {
+            Ok(policy) if policy == self.policy => None,
+            Ok(policy) => {
+                tracing::info!(path = ?self.path, ?policy, "reloaded policy");
+                self.policy = policy.clone();
+                Some(policy)
+            }
+            Err(err) => {
+                tracing::error!(path = ?self.path, %err, "keeping the previous policy");
+                None
+            }
+        }
+    }
+}
+
+/// When the file at `path` was last modified, if that can be found out.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloads_on_change() {
+        let path =
+            std::env::temp_dir().join(format!("transactomatic-policy-{}.json", std::process::id()));
+        fs::write(&path, r#"{"minimum_balance": "10"}"#).unwrap();
+        let mut reloader = Reloader::new(&path).unwrap();
+        assert_eq!(reloader.policy().minimum_balance, Some(Amount::from(10)));
+        assert_eq!(reloader.poll(), None);
+
+        fs::write(&path, r#"{"dispute_window": 5}"#).unwrap();
+        reloader.hangup.store(true, Ordering::Relaxed);
+        let policy = reloader.poll().unwrap();
+        assert_eq!(policy.minimum_balance, None);
+        assert_eq!(policy.dispute_window, Some(5));
+
+        // A broken file keeps the policy in place.
+        fs::write(&path, "{").unwrap();
+        reloader.hangup.store(true, Ordering::Relaxed);
+        assert_eq!(reloader.poll(), None);
+        assert_eq!(reloader.policy().dispute_window, Some(5));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Administrative requests, for operators correcting accounts without stopping the server.
+//!
+//! | Request | Response |
+//! |---|---|
+//! | `POST /admin/accounts/{client}` | Take the [action](../../bank/admin/index.html) in the body on the client's account, and return the account. |
+//!
+//! The body is the action as JSON: `{"action": "lock"}`, `{"action": "unlock"}`, `{"action": "close"}`,
+//! `{"action": "adjust", "amount": "-1.5"}`, or `{"action": "force_resolve", "tx": 7}`.  An action that can't be taken
+//! gets a `422` response with the reason, and one on a client without an account gets `404`.
+//!
+//! Administrative requests need a [token](../auth/index.html) with the `admin` permission, and the token's name is
+//! recorded as the operator who took the action.  A server without tokens refuses them with `403`, since it has no
+//! operator to record.  With an [audit log](../../bank/audit/index.html), every action taken is recorded in it.
+
+use super::Reply;
+use crate::bank::account::AccountSummary;
+use crate::bank::admin::{self, Action};
+use crate::bank::audit;
+use crate::bank::Bank;
+use std::sync::Mutex;
+use tiny_http::Method;
+
+/// Whether a request for `path` is administrative.
+pub(crate) fn is_admin(path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or_default();
+    path == "/admin" || path.starts_with("/admin/")
+}
+
+/// Route an administrative request from `operator` to the bank, recording actions in `audit` if it's given.
+pub(crate) fn respond(
+    bank: &Mutex<Bank>,
+    audit: Option<&Mutex<audit::Writer>>,
+    operator: Option<&str>,
+    method: &Method,
+    url: &str,
+    body: &str,
+) -> Reply {
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let ["admin", "accounts", client] = segments.as_slice() else {
+        return Reply::not_found();
+    };
+    if method != &Method::Post {
+        return Reply::error(405, &"method not allowed");
+    }
+    let Some(operator) = operator else {
+        return Reply::error(403, &"administrative requests need an admin token");
+    };
+    let Ok(client) = client.parse() else {
+        return Reply::not_found();
+    };
+    let action: Action = match serde_json::from_str(body) {
+        Ok(action) => action,
+        Err(err) => return Reply::error(400, &err),
+    };
+
+    let mut bank = bank.lock().expect("bank lock poisoned");
+    let result = match audit {
+        None => bank.administer(&client, action, operator),
+        Some(audit) => {
+            let mut audit = audit.lock().expect("audit lock poisoned");
+            let recorded = audit
+                .administer(&mut bank, client, action, operator)
+                .and_then(|result| audit.flush().map(|()| result));
+            match recorded {
+                Ok(result) => result,
+                Err(err) => {
+                    tracing::error!(%err, ?client, ?action, "action taken but not recorded");
+                    return Reply::error(500, &err);
+                }
+            }
+        }
+    };
+    match result {
+        Ok(account) => Reply::json(200, &AccountSummary::from(account)),
+        Err(admin::Error::NotFound) => Reply::not_found(),
+        Err(err) => Reply::error(422, &err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use std::fs;
+
+    #[test]
+    fn administers_and_records() {
+        let path =
+            std::env::temp_dir().join(format!("transactomatic-admin-audit-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let audit = Mutex::new(audit::Writer::open(&path).unwrap());
+        let bank = Mutex::new(Bank::new());
+        super::super::respond(
+            &bank,
+            Some(&audit),
+            1,
+            &Method::Post,
+            "/transactions",
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "5"}"#,
+        );
+        let post = |url: &str, operator: Option<&str>, body: &str| {
+            respond(&bank, Some(&audit), operator, &Method::Post, url, body)
+        };
+
+        let reply = post("/admin/accounts/1", Some("ops"), r#"{"action": "lock"}"#);
+        assert_eq!(reply.status, 200);
+        assert!(reply.body.ends_with(r#""locked":true}"#), "{}", reply.body);
+        let reply = post(
+            "/admin/accounts/1",
+            Some("ops"),
+            r#"{"action": "adjust", "amount": "-6"}"#,
+        );
+        assert_eq!(reply.status, 422);
+        assert_eq!(
+            post("/admin/accounts/2", Some("ops"), r#"{"action": "lock"}"#).status,
+            404
+        );
+        assert_eq!(
+            post("/admin/accounts/1", None, r#"{"action": "lock"}"#).status,
+            403
+        );
+        assert_eq!(
+            post("/admin/accounts/1", Some("ops"), r#"{"action": "melt"}"#).status,
+            400
+        );
+        assert_eq!(
+            respond(
+                &bank,
+                None,
+                Some("ops"),
+                &Method::Get,
+                "/admin/accounts/1",
+                ""
+            )
+            .status,
+            405
+        );
+
+        let log = fs::read_to_string(&path).unwrap();
+        let records: Vec<audit::Record> =
+            audit::records(log.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].client(), AccountId::Number(1));
+        assert_eq!(records[1].admin.as_ref().unwrap().operator_reference, "ops");
+        assert!(audit::verify(log.as_bytes()).unwrap().is_ok());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Token authentication, for a server reachable from beyond the machine it runs on.
+//!
+//! With tokens, every request has to carry one as `Authorization: Bearer TOKEN`, and the token has to allow what the
+//! request does:
+//!
+//! | Permission | Allows |
+//! |---|---|
+//! | `submit` | Submitting instructions with `POST /transactions`. |
+//! | `read` | Reading accounts and transactions, and following `GET /events`. |
+//! | `admin` | Everything, including [administrative requests](../admin/index.html). |
+//!
+//! A request without a known token gets `401 Unauthorized`, and one whose token doesn't allow it gets
+//! `403 Forbidden`; neither reaches the bank.  `GET /metrics`, `GET /versions`, and `GET /capabilities` need no token.
+//!
+//! Tokens are read from a JSON file, or from the `TRANSACTOMATIC_API_TOKENS` environment variable in the same form,
+//! naming each token so it can be logged without logging the token itself:
+//!
+//! ```json
+//! {"ingest": {"token": "4f0c8a6e1d", "permissions": ["submit"]}, "ops": {"token": "9b27d3c5aa", "permissions": ["admin"]}}
+//! ```
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tiny_http::{Method, Request};
+
+/// The environment variable tokens are read from when there's no token file.
+pub const ENV: &str = "TRANSACTOMATIC_API_TOKENS";
+
+/// Errors reading tokens.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A named token is empty.
+    EmptyToken(String),
+    /// A named token has no permissions.
+    NoPermissions(String),
+    /// The same token is given two names, named here.
+    SharedToken(String, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading API tokens: {err}"),
+            Error::Json(err) => write!(f, "invalid API tokens: {err}"),
+            Error::EmptyToken(name) => write!(f, "API token {name} is empty"),
+            Error::NoPermissions(name) => write!(f, "API token {name} has no permissions"),
+            Error::SharedToken(a, b) => write!(f, "API tokens {a} and {b} are the same"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::EmptyToken(_) | Error::NoPermissions(_) | Error::SharedToken(..) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+/// What a token allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Submit,
+    Read,
+    Admin,
+}
+
+impl Permission {
+    /// The permission a request made with `method` for `path` needs.
+    pub(crate) fn needed(method: &Method, path: &str) -> Self {
+        if super::admin::is_admin(path) {
+            return Permission::Admin;
+        }
+        match method {
+            Method::Get | Method::Head => Permission::Read,
+            _ => Permission::Submit,
+        }
+    }
+}
+
+/// A named token and what it allows, as read from the token file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Grant {
+    pub token: String,
+    pub permissions: Vec<Permission>,
+}
+
+/// Why a request was turned away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Denied {
+    /// The request has no token, or one that isn't known.
+    Unauthenticated,
+    /// The named token doesn't allow the request.
+    Forbidden(String),
+}
+
+/// Known tokens and what they allow.
+#[derive(Debug)]
+pub struct Tokens {
+    /// Each token's name and permissions.
+    tokens: HashMap<String, (String, Vec<Permission>)>,
+}
+
+impl Tokens {
+    /// Tokens granted in `grants`, keyed by name.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a token is empty, has no permissions, or is given two names.
+    pub fn new(grants: HashMap<String, Grant>) -> Result<Self, Error> {
+        let mut tokens = HashMap::new();
+        for (name, grant) in grants {
+            if grant.token.is_empty() {
+                return Err(Error::EmptyToken(name));
+            }
+            if grant.permissions.is_empty() {
+                return Err(Error::NoPermissions(name));
+            }
+            if let Some((other, _)) = tokens.insert(grant.token, (name.clone(), grant.permissions))
+            {
+                return Err(Error::SharedToken(other, name));
+            }
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Read tokens from the JSON file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't valid.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        Self::new(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Read tokens from the [`ENV`](constant.ENV.html) environment variable, if it's set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the variable isn't valid.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        match std::env::var(ENV) {
+            Ok(json) => Self::new(serde_json::from_str(&json)?).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Check that `request` carries a known token allowing `needed`, returning the token's name.
+    pub(crate) fn authorize(&self, request: &Request, needed: Permission) -> Result<&str, Denied> {
+        let (name, permissions) = super::bearer(request)
+            .and_then(|token| self.tokens.get(token))
+            .ok_or(Denied::Unauthenticated)?;
+        if permissions.contains(&needed) || permissions.contains(&Permission::Admin) {
+            Ok(name)
+        } else {
+            Err(Denied::Forbidden(name.clone()))
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Streaming of bank events over WebSocket.
+//!
+//! `GET /events` upgrades to a WebSocket that receives a text message for every [event](../../bank/event/enum.Event.html)
+//! from the bank, optionally only those for one client with `GET /events?client=1`.  Each message is the event as JSON
+//! plus the client's account as it is when the message is sent:
+//!
+//! ```json
+//! {"event":"DepositApplied","client":1,"tx":1,"amount":"2.5","account":{"client":1,"available":"2.5000",...}}
+//! ```
+//!
+//! Events are queued for each subscriber so that slow ones don't hold up the bank.  A subscriber whose queue is full
+//! misses events until it catches up.
+
+use crate::bank::account::{AccountId, AccountSummary};
+use crate::bank::event::Event;
+use crate::bank::Bank;
+use serde::Serialize;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Request, Response};
+use tungstenite::protocol::{Role, WebSocket};
+
+/// Number of events that can be waiting for each subscriber.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The WebSocket connections events are sent to.
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers(Mutex<Vec<Subscriber>>);
+
+#[derive(Debug)]
+struct Subscriber {
+    client: Option<AccountId>,
+    sender: mpsc::SyncSender<Event>,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    account: Option<AccountSummary>,
+}
+
+impl Subscribers {
+    /// Receive events for `client`, or for everyone.
+    pub(crate) fn subscribe(&self, client: Option<AccountId>) -> mpsc::Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        self.0
+            .lock()
+            .expect("subscribers lock poisoned")
+            .push(Subscriber { client, sender });
+        receiver
+    }
+
+    /// Queue `event` for everyone subscribed to it, dropping subscribers that have gone away.
+    pub(crate) fn publish(&self, event: &Event) {
+        let mut subscribers = self.0.lock().expect("subscribers lock poisoned");
+        subscribers.retain(|subscriber| {
+            if subscriber
+                .client
+                .is_some_and(|client| client != event.client())
+            {
+                return true;
+            }
+            match subscriber.sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(mpsc::TrySendError::Full(_)) => {
+                    tracing::warn!(?event, "subscriber is behind, dropping event");
+                    true
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+/// Upgrade `request` to a WebSocket and stream events to it on a new thread.
+pub(crate) fn accept(request: Request, bank: Arc<Mutex<Bank>>, subscribers: &Subscribers) {
+    let client = match client_filter(request.url()) {
+        Ok(client) => client,
+        Err(reply) => {
+            let _ = request.respond(Response::from_string(reply).with_status_code(400));
+            return;
+        }
+    };
+    let Some(key) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| tungstenite::handshake::derive_accept_key(header.value.as_bytes()))
+    else {
+        let _ = request
+            .respond(Response::from_string("expected a WebSocket upgrade").with_status_code(400));
+        return;
+    };
+
+    // Subscribe before the handshake completes so the client doesn't miss anything sent right after it.
+    let receiver = subscribers.subscribe(client);
+    let accept = Header::from_bytes(&b"Sec-WebSocket-Accept"[..], key.as_bytes())
+        .expect("accept key is valid");
+    let stream = request.upgrade("websocket", Response::empty(101).with_header(accept));
+    tracing::debug!(?client, "event subscriber connected");
+
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        for event in receiver {
+            let account = bank
+                .lock()
+                .expect("bank lock poisoned")
+                .account(&event.client())
+                .map(AccountSummary::from);
+            let message = match serde_json::to_string(&Message {
+                event: &event,
+                account,
+            }) {
+                Ok(message) => message,
+                Err(err) => {
+                    tracing::error!(?err, "error serializing event");
+                    continue;
+                }
+            };
+            if let Err(err) = socket.send(tungstenite::Message::Text(message)) {
+                tracing::debug!(?err, "event subscriber disconnected");
+                return;
+            }
+        }
+    });
+}
+
+/// The client in a `client=` query parameter.
+fn client_filter(url: &str) -> Result<Option<AccountId>, String> {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    for pair in query.split('&') {
+        if let Some(client) = pair.strip_prefix("client=") {
+            return client
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid client {client:?}"));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Server;
+    use super::*;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind,
+    };
+    use crate::bank::transaction::TransactionId;
+    use std::net::TcpStream;
+
+    fn deposit(client: u16, tx: u64) -> TransactionInstruction {
+        TransactionInstruction {
+            kind: TransactionInstructionKind::Deposit,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(2)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn streams_filtered_events() {
+        let server = Arc::new(Server::bind("127.0.0.1:0", Bank::new()).unwrap());
+        let addr = server.local_addr().unwrap();
+        {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run(1));
+        }
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let (mut socket, _) =
+            tungstenite::client(format!("ws://{addr}/events?client=2"), stream).unwrap();
+
+        {
+            let bank = server.bank();
+            let mut bank = bank.lock().unwrap();
+            bank.perform_transaction(deposit(1, 1)).unwrap();
+            bank.perform_transaction(deposit(2, 2)).unwrap();
+        }
+
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert_eq!(
+            message,
+            r#"{"event":"AccountCreated","client":2,"account":{"client":2,"available":"2.0000","held":"0.0000","total":"2.0000","locked":false}}"#
+        );
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert!(message.starts_with(r#"{"event":"DepositApplied","client":2,"tx":2"#));
+    }
+
+    #[test]
+    fn filter() {
+        assert_eq!(client_filter("/events"), Ok(None));
+        assert_eq!(
+            client_filter("/events?x=1&client=3"),
+            Ok(Some(AccountId::Number(3)))
+        );
+        assert_eq!(
+            client_filter("/events?client=CUST-3"),
+            Ok(Some("CUST-3".parse().unwrap()))
+        );
+        assert!(client_filter("/events?client=").is_err());
+    }
+}
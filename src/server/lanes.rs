@@ -0,0 +1,343 @@
+//! Priority lanes, so that urgent requests don't wait behind a bulk load.
+//!
+//! Without lanes, requests waiting for the bank get it in whatever order its lock hands it out.  With lanes, requests
+//! that change the bank queue for it instead, and the queue lets the request in the earliest lane go first, then the
+//! one that has waited longest.  A request that is already applying finishes first; lanes only decide who is next.
+//!
+//! Lanes are read from a JSON file, earliest first, each listing the sources of requests that go in it:
+//!
+//! ```json
+//! [["chargeback", "admin"], ["dispute", "resolve", "token:ops"]]
+//! ```
+//!
+//! | Source | Requests |
+//! |---|---|
+//! | `deposit`, `withdrawal`, `dispute`, `resolve`, `chargeback`, `reinstate` | `POST /transactions` with an instruction of that type. |
+//! | `admin` | [Administrative requests](../admin/index.html). |
+//! | `token:NAME` | Requests made with the [token](../auth/index.html) named `NAME`. |
+//!
+//! A request goes in the earliest lane any of its sources is in, and one in no lane goes after all of them.  Reads
+//! don't queue, since they don't wait on each other's changes.
+//!
+//! Requests for the same client are never reordered: a client's requests are applied in the order they arrived, and
+//! a client's request waiting in an earlier lane takes the client's requests that arrived before it along with it.  So
+//! a chargeback can overtake other clients' deposits, but not the deposit it charges back.  Requests for different
+//! clients aren't reordered beyond what's already waiting at the same time either, so a producer that waits for each
+//! response still has its instructions applied in order.
+
+use crate::bank::account::AccountId;
+use crate::bank::transaction::instruction::TransactionInstructionKind;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+/// Sources of instructions, by instruction type.
+const TYPES: [&str; 6] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "reinstate",
+];
+
+/// Errors reading lanes.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A lane names a source that isn't an instruction type, `admin`, or a token.
+    UnknownSource(String),
+    /// A source is in more than one lane.
+    RepeatedSource(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading priority lanes: {err}"),
+            Error::Json(err) => write!(f, "invalid priority lanes: {err}"),
+            Error::UnknownSource(source) => write!(f, "unknown priority lane source {source:?}"),
+            Error::RepeatedSource(source) => {
+                write!(
+                    f,
+                    "priority lane source {source:?} is in more than one lane"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::UnknownSource(_) | Error::RepeatedSource(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+/// The sources in each lane, earliest first, as read from the lanes file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Config(pub Vec<Vec<String>>);
+
+/// Where a request queues: its lane, and the client it's for, if it's for one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Place {
+    pub(crate) lane: usize,
+    pub(crate) client: Option<AccountId>,
+}
+
+/// Requests waiting for the bank, and whether one is applying.
+#[derive(Debug, Default)]
+struct Queue {
+    /// The place of each waiting request, and when it arrived.
+    waiting: Vec<(Place, u64)>,
+    arrived: u64,
+    busy: bool,
+}
+
+impl Queue {
+    /// When the request to go next arrived.  That's the one in the earliest lane, then the one that arrived first, but
+    /// only the first of each client's requests can go, in the earliest lane of any of them.
+    fn next(&self) -> Option<u64> {
+        let same_client =
+            |place: &Place, other: &Place| place.client.is_some() && place.client == other.client;
+        self.waiting
+            .iter()
+            .filter(|(place, arrived)| {
+                !self
+                    .waiting
+                    .iter()
+                    .any(|(other, earlier)| same_client(place, other) && earlier < arrived)
+            })
+            .map(|(place, arrived)| {
+                let lane = self
+                    .waiting
+                    .iter()
+                    .filter(|(other, _)| same_client(place, other))
+                    .map(|(other, _)| other.lane)
+                    .fold(place.lane, usize::min);
+                (lane, *arrived)
+            })
+            .min()
+            .map(|(_, arrived)| arrived)
+    }
+}
+
+/// The queue in front of the bank.
+#[derive(Debug)]
+pub struct Lanes {
+    sources: HashMap<String, usize>,
+    queue: Mutex<Queue>,
+    turn: Condvar,
+}
+
+/// A request's turn with the bank, which passes to the next request when dropped.
+#[derive(Debug)]
+pub(crate) struct Turn<'a> {
+    lanes: &'a Lanes,
+}
+
+impl Lanes {
+    /// Lanes with the sources in `config`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a source isn't known or is in more than one lane.
+    pub fn new(config: Config) -> Result<Self, Error> {
+        let mut sources = HashMap::new();
+        for (lane, lane_sources) in config.0.into_iter().enumerate() {
+            for source in lane_sources {
+                let known = TYPES.contains(&source.as_str())
+                    || source == "admin"
+                    || source
+                        .strip_prefix("token:")
+                        .is_some_and(|name| !name.is_empty());
+                if !known {
+                    return Err(Error::UnknownSource(source));
+                }
+                if sources.contains_key(&source) {
+                    return Err(Error::RepeatedSource(source));
+                }
+                sources.insert(source, lane);
+            }
+        }
+        Ok(Self {
+            sources,
+            queue: Mutex::new(Queue::default()),
+            turn: Condvar::new(),
+        })
+    }
+
+    /// Read lanes from the JSON file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't valid.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        Self::new(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// The lane of a request from `sources`: the earliest any of them is in.
+    pub(crate) fn lane<'s>(&self, sources: impl IntoIterator<Item = &'s str>) -> usize {
+        sources
+            .into_iter()
+            .filter_map(|source| self.sources.get(source).copied())
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// The place of a request for `path` with `body`, made with the token named `token` if it had one.
+    pub(crate) fn place_of(&self, token: Option<&str>, path: &str, body: &str) -> Place {
+        #[derive(Deserialize)]
+        struct Instruction {
+            #[serde(rename = "type")]
+            kind: TransactionInstructionKind,
+            client: AccountId,
+        }
+
+        let mut sources = vec![];
+        let mut client = None;
+        if super::admin::is_admin(path) {
+            sources.push("admin".to_string());
+            client = path
+                .split('?')
+                .next()
+                .and_then(|path| path.strip_prefix("/admin/accounts/"))
+                .and_then(|client| client.parse().ok());
+        } else if let Ok(instruction) = serde_json::from_str::<Instruction>(body) {
+            sources.push(crate::cli::kind_name(instruction.kind).to_string());
+            client = Some(instruction.client);
+        }
+        if let Some(token) = token {
+            sources.push(format!("token:{token}"));
+        }
+        Place {
+            lane: self.lane(sources.iter().map(String::as_str)),
+            client,
+        }
+    }
+
+    /// Wait for a turn with the bank at `place`.
+    pub(crate) fn enter(&self, place: Place) -> Turn<'_> {
+        let mut queue = self.queue.lock().expect("lane lock poisoned");
+        let arrived = queue.arrived;
+        queue.arrived += 1;
+        queue.waiting.push((place, arrived));
+        while queue.busy || queue.next() != Some(arrived) {
+            queue = self.turn.wait(queue).expect("lane lock poisoned");
+        }
+        queue.waiting.retain(|(_, other)| *other != arrived);
+        queue.busy = true;
+        Turn { lanes: self }
+    }
+
+    /// Number of requests waiting for a turn.
+    #[cfg(test)]
+    fn waiting(&self) -> usize {
+        self.queue.lock().expect("lane lock poisoned").waiting.len()
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.lanes.queue.lock().expect("lane lock poisoned").busy = false;
+        self.lanes.turn.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn earliest_lane_goes_first() {
+        let lanes = Lanes::new(
+            serde_json::from_str(r#"[["chargeback", "admin"], ["token:ops"]]"#).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lanes.lane(["deposit", "token:ops"]), 1);
+        assert_eq!(lanes.lane(["chargeback", "token:ops"]), 0);
+        assert_eq!(lanes.lane(["deposit", "token:ingest"]), usize::MAX);
+        assert!(matches!(
+            Lanes::new(Config(vec![vec!["refund".to_string()]])),
+            Err(Error::UnknownSource(_))
+        ));
+        assert!(matches!(
+            Lanes::new(Config(vec![
+                vec!["admin".to_string()],
+                vec!["admin".to_string()]
+            ])),
+            Err(Error::RepeatedSource(_))
+        ));
+
+        let place = lanes.place_of(
+            Some("ops"),
+            "/transactions",
+            r#"{"type": "deposit", "client": 7, "tx": 1, "amount": "1"}"#,
+        );
+        assert_eq!(place.lane, 1);
+        assert_eq!(place.client, Some(AccountId::Number(7)));
+        let place = lanes.place_of(None, "/admin/accounts/7", r#"{"action": "lock"}"#);
+        assert_eq!(place.lane, 0);
+        assert_eq!(place.client, Some(AccountId::Number(7)));
+
+        // The chargeback overtakes other clients' requests, taking its client's deposit with it.
+        let order = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            let turn = lanes.enter(Place {
+                lane: usize::MAX,
+                client: None,
+            });
+            let requests = [
+                ("deposit", 1),
+                ("token:ops", 2),
+                ("deposit", 3),
+                ("chargeback", 3),
+            ];
+            for (waiting, (source, client)) in requests.iter().copied().enumerate() {
+                let (lanes, order) = (&lanes, &order);
+                scope.spawn(move || {
+                    let _turn = lanes.enter(Place {
+                        lane: lanes.lane([source]),
+                        client: Some(AccountId::Number(client)),
+                    });
+                    order.lock().unwrap().push((source, client));
+                });
+                while lanes.waiting() <= waiting {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            drop(turn);
+        });
+        assert_eq!(
+            order.into_inner().unwrap(),
+            [
+                ("deposit", 3),
+                ("chargeback", 3),
+                ("token:ops", 2),
+                ("deposit", 1)
+            ]
+        );
+    }
+}
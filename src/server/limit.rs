@@ -0,0 +1,148 @@
+//! Request rate limits, so that one producer can't keep the bank busy for everyone else.
+//!
+//! Limits are token buckets: each connection or client can make a second's worth of requests in a burst, and then as
+//! many per second as its rate.  A request over the limit is answered with `429 Too Many Requests` and a
+//! `Retry-After` header, without touching the bank.  Connections are told apart by their remote address and port, and
+//! clients by the `client` of the instruction they submit, so only `POST /transactions` counts towards a client's
+//! limit.  With [tenants](../tenants/index.html), each tenant's clients have their own limits.
+
+use crate::bank::account::AccountId;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before full ones are dropped, since a full bucket is the same as a new one.
+const PRUNE_AFTER: usize = 10_000;
+
+/// Requests per second allowed for each connection and each client.  `None` means no limit.  Rates have to be positive
+/// and finite; [`Server::with_limits`](../struct.Server.html#method.with_limits) panics otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub per_connection: Option<f64>,
+    pub per_client: Option<f64>,
+}
+
+/// A token bucket.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets for each key, all with the same rate.
+#[derive(Debug)]
+struct Buckets<K> {
+    rate: f64,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Eq + Hash> Buckets<K> {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn burst(&self) -> f64 {
+        self.rate.ceil().max(1.0)
+    }
+
+    /// Take a token from `key`'s bucket at `now`, or say how long until there is one.
+    fn take(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let burst = self.burst();
+        let mut buckets = self.buckets.lock().expect("rate limit lock poisoned");
+        if buckets.len() >= PRUNE_AFTER {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate < burst
+            });
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// Applies [`Limits`](struct.Limits.html) to requests.
+#[derive(Debug, Default)]
+pub(crate) struct Limiter {
+    connections: Option<Buckets<SocketAddr>>,
+    clients: Option<Buckets<(Option<String>, AccountId)>>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            connections: limits.per_connection.map(Buckets::new),
+            clients: limits.per_client.map(Buckets::new),
+        }
+    }
+
+    /// Whether instructions are limited per client, so their clients need to be read before they're applied.
+    pub(crate) fn limits_clients(&self) -> bool {
+        self.clients.is_some()
+    }
+
+    /// Count a request on the connection from `addr`, or say how long to wait before retrying if it's over the limit.
+    pub(crate) fn connection(&self, addr: SocketAddr) -> Result<(), Duration> {
+        match &self.connections {
+            Some(buckets) => buckets.take(addr, Instant::now()),
+            None => Ok(()),
+        }
+    }
+
+    /// Count an instruction for `tenant`'s `client`, or say how long to wait before retrying if it's over the limit.
+    pub(crate) fn client(&self, tenant: Option<&str>, client: AccountId) -> Result<(), Duration> {
+        match &self.clients {
+            Some(buckets) => buckets.take((tenant.map(str::to_string), client), Instant::now()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refills_at_the_rate() {
+        let buckets = Buckets::new(2.0);
+        let start = Instant::now();
+        assert_eq!(buckets.take(1, start), Ok(()));
+        assert_eq!(buckets.take(1, start), Ok(()));
+        assert_eq!(buckets.take(1, start), Err(Duration::from_millis(500)));
+        assert_eq!(buckets.take(2, start), Ok(()));
+
+        let later = start + Duration::from_millis(250);
+        assert_eq!(buckets.take(1, later), Err(Duration::from_millis(250)));
+        let later = start + Duration::from_millis(500);
+        assert_eq!(buckets.take(1, later), Ok(()));
+
+        // A long pause only refills up to the burst.
+        let later = start + Duration::from_secs(30);
+        assert_eq!(buckets.take(1, later), Ok(()));
+        assert_eq!(buckets.take(1, later), Ok(()));
+        assert!(buckets.take(1, later).is_err());
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let limiter = Limiter::default();
+        for _ in 0..100 {
+            assert_eq!(limiter.client(None, AccountId::Number(1)), Ok(()));
+        }
+        assert!(!limiter.limits_clients());
+    }
+}
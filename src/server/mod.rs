@@ -0,0 +1,1102 @@
+//! This module contains an HTTP API in front of a live [Bank](../bank/struct.Bank.html).
+//!
+//! Instead of producing batch files, other services can submit instructions one at a time and read balances back:
+//!
+//! | Request | Response |
+//! |---|---|
+//! | `POST /transactions` | Apply the instruction in the body and return the client's account. |
+//! | `GET /accounts` | Every account, in client id order. |
+//! | `GET /accounts/{client}` | One account, even if it has been [archived](../bank/archive/index.html). |
+//! | `GET /transactions/{tx}` | One transaction, including its amendment history. |
+//! | `GET /events` | A WebSocket streaming [events](events/index.html) as they happen. |
+//! | `GET /metrics` | [Metrics](../metrics/index.html) in the Prometheus text format. |
+//! | `POST /admin/accounts/{client}` | An [administrative action](admin/index.html) on the client's account. |
+//! | `GET /versions` | The API [versions](version/index.html) the server speaks. |
+//! | `GET /capabilities` | What a version accepts, and the optional features turned on. |
+//!
+//! Every path can also be given with the API version it's written against, as in `POST /v1/transactions`; paths
+//! without one are version 1.
+//!
+//! Instructions use the same fields as the CSV input, as JSON: `{"type": "deposit", "client": 1, "tx": 1, "amount":
+//! "1.5"}`, with an optional `correlation_id`.  Accounts are returned in the same form as the account report.  A
+//! rejected instruction gets a `422` response with the reason as `{"error": "..."}`, and its correlation id if it had
+//! one.
+//!
+//! Requests are handled on a small pool of threads sharing the bank behind a mutex, so instructions are applied one at
+//! a time in the order they arrive.  Requests can be [rate limited](limit/index.html) per connection and per client.
+//! A [policy](../policy/index.html) file is reloaded before a request when it has changed.  Requests that change the
+//! bank can instead queue for it in [priority lanes](lanes/index.html), so that chargebacks and administrative
+//! requests don't wait behind a bulk load of deposits.
+//!
+//! A server can also keep a separate bank for each of several [tenants](tenants/index.html), chosen by the token a
+//! request carries, or serve only requests whose [token](auth/index.html) allows them.  With the `tls` feature it can
+//! serve [HTTPS](tls/index.html), optionally requiring client certificates.
+
+use crate::bank::account::{AccountId, AccountSummary};
+use crate::bank::audit;
+use crate::bank::event::Event;
+use crate::bank::transaction::{instruction::TransactionInstruction, TransactionId};
+use crate::bank::Bank;
+use crate::metrics::{self, Metrics};
+use crate::policy::Reloader;
+use crate::shutdown::Shutdown;
+use auth::{Denied, Permission, Tokens};
+use events::Subscribers;
+use lanes::Lanes;
+use limit::{Limiter, Limits};
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tenants::Tenants;
+use tiny_http::{Header, Method, Request, Response};
+
+pub mod admin;
+pub mod auth;
+pub mod events;
+pub mod lanes;
+pub mod limit;
+pub mod tenants;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod version;
+
+/// Longest a shutdown goes unnoticed while waiting for a request.
+const SHUTDOWN_CHECK: Duration = Duration::from_millis(100);
+
+/// An HTTP server applying instructions to a bank.
+pub struct Server {
+    http: tiny_http::Server,
+    scope: Scope,
+    tenants: Option<Tenants>,
+    tokens: Option<Tokens>,
+    audit: Option<Mutex<audit::Writer>>,
+    #[cfg(feature = "tls")]
+    tls: Option<tls::Terminator>,
+    metrics: Arc<Metrics>,
+    limiter: Limiter,
+    lanes: Option<Lanes>,
+    policy: Option<Mutex<Reloader>>,
+    shutdown: Shutdown,
+}
+
+/// A bank requests are served from, and the WebSocket connections following its events.
+#[derive(Debug, Clone)]
+pub(crate) struct Scope {
+    bank: Arc<Mutex<Bank>>,
+    subscribers: Arc<Subscribers>,
+}
+
+impl Scope {
+    /// Serve `bank`, publishing its events and counting its instructions in `metrics`.
+    fn new(mut bank: Bank, metrics: &Arc<Metrics>) -> Self {
+        let subscribers = Arc::new(Subscribers::default());
+        let publisher = Arc::clone(&subscribers);
+        bank.register_observer(move |event: &Event| publisher.publish(event));
+        metrics.install(&mut bank);
+        Self {
+            bank: Arc::new(Mutex::new(bank)),
+            subscribers,
+        }
+    }
+}
+
+/// A response before it's turned into HTTP.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Reply {
+    pub(crate) status: u16,
+    pub(crate) body: String,
+}
+
+impl Reply {
+    fn json<T: Serialize + ?Sized>(status: u16, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self { status, body },
+            Err(err) => Self::error(500, &err),
+        }
+    }
+
+    fn error(status: u16, err: &dyn std::fmt::Display) -> Self {
+        Self {
+            status,
+            body: serde_json::json!({ "error": err.to_string() }).to_string(),
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::error(404, &"not found")
+    }
+
+    fn unauthorized() -> Self {
+        Self::error(401, &"missing or unknown token")
+    }
+
+    fn too_many_requests() -> Self {
+        Self::error(429, &"rate limit exceeded")
+    }
+}
+
+impl Server {
+    /// Listen on `addr`, serving `bank`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address can't be bound.
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        bank: Bank,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let metrics = Metrics::new();
+        Ok(Self {
+            http: tiny_http::Server::http(addr)?,
+            scope: Scope::new(bank, &metrics),
+            tenants: None,
+            tokens: None,
+            audit: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            metrics,
+            limiter: Limiter::default(),
+            lanes: None,
+            policy: None,
+            shutdown: Shutdown::default(),
+        })
+    }
+
+    /// Listen on `addr` for TLS connections with `config`, serving `bank`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address can't be bound.
+    #[cfg(feature = "tls")]
+    pub fn bind_tls<A: ToSocketAddrs>(
+        addr: A,
+        bank: Bank,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut server = Self::bind("127.0.0.1:0", bank)?;
+        let backend = server
+            .local_addr()
+            .ok_or("not listening on an IP address")?;
+        server.tls = Some(tls::Terminator::bind(addr, config, backend)?);
+        Ok(server)
+    }
+
+    /// Limit the rate of requests to `limits`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a rate isn't a positive, finite number of requests per second.
+    #[must_use]
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        for rate in [limits.per_connection, limits.per_client].iter().flatten() {
+            assert!(
+                *rate > 0.0 && rate.is_finite(),
+                "invalid rate {}, expected a positive number",
+                rate
+            );
+        }
+        self.limiter = Limiter::new(limits);
+        self
+    }
+
+    /// Queue requests that change the bank in `lanes`.
+    #[must_use]
+    pub fn with_lanes(mut self, lanes: Lanes) -> Self {
+        self.lanes = Some(lanes);
+        self
+    }
+
+    /// Apply `reloader`'s policy to the bank, and apply it again whenever it changes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bank's lock is poisoned.
+    #[must_use]
+    pub fn with_policy(mut self, reloader: Reloader) -> Self {
+        reloader
+            .policy()
+            .apply(&mut self.scope.bank.lock().expect("bank lock poisoned"));
+        self.policy = Some(Mutex::new(reloader));
+        self
+    }
+
+    /// Serve each of `tenants` from its own bank, to requests with one of its tokens, instead of serving the bank
+    /// given to [`bind`](#method.bind).
+    #[must_use]
+    pub fn with_tenants(mut self, tenants: Tenants) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    /// Only serve requests carrying one of `tokens` that [allows](auth/index.html) them.
+    #[must_use]
+    pub fn with_tokens(mut self, tokens: Tokens) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Record every change to the bank in the [audit log](../bank/audit/index.html) `writer` appends to, including
+    /// [administrative actions](admin/index.html).
+    #[must_use]
+    pub fn with_audit(mut self, writer: audit::Writer) -> Self {
+        self.audit = Some(Mutex::new(writer));
+        self
+    }
+
+    /// Stop taking requests once `shutdown` is requested.
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// The address the server is listening on.
+    #[must_use]
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return tls.local_addr();
+        }
+        self.http.server_addr().to_ip()
+    }
+
+    /// The bank being served.  Changes made through it are visible to clients immediately.  With
+    /// [tenants](#method.with_tenants) it's the bank given to [`bind`](#method.bind), which isn't served.
+    #[must_use]
+    pub fn bank(&self) -> Arc<Mutex<Bank>> {
+        Arc::clone(&self.scope.bank)
+    }
+
+    /// Handle requests on `threads` threads until the listener fails or a shutdown is requested.  Requests already
+    /// being handled are finished before this returns.
+    pub fn run(&self, threads: usize) {
+        thread::scope(|scope| {
+            #[cfg(feature = "tls")]
+            if let Some(tls) = &self.tls {
+                scope.spawn(move || tls.run(&self.shutdown, SHUTDOWN_CHECK));
+            }
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| loop {
+                    if self.shutdown.requested() {
+                        return;
+                    }
+                    match self.http.recv_timeout(SHUTDOWN_CHECK) {
+                        Ok(Some(request)) => {
+                            self.reload();
+                            self.handle(request);
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            tracing::error!(?err, "error receiving request");
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        if self.shutdown.requested() {
+            tracing::info!("stopped after shutdown request");
+        }
+    }
+
+    /// Apply the policy to the banks again if it has changed.
+    fn reload(&self) {
+        let Some(reloader) = &self.policy else {
+            return;
+        };
+        let policy = reloader.lock().expect("policy lock poisoned").poll();
+        if let Some(policy) = policy {
+            for scope in self.scopes() {
+                policy.apply(&mut scope.bank.lock().expect("bank lock poisoned"));
+            }
+        }
+    }
+
+    /// Every bank: the one given to [`bind`](#method.bind), and those of tenants.
+    fn scopes(&self) -> Vec<Scope> {
+        let mut scopes = vec![self.scope.clone()];
+        if let Some(tenants) = &self.tenants {
+            scopes.extend(tenants.scopes());
+        }
+        scopes
+    }
+
+    /// A new tenant's bank, with the current policy.
+    fn new_scope(&self) -> Scope {
+        let mut bank = Bank::new();
+        if let Some(reloader) = &self.policy {
+            reloader
+                .lock()
+                .expect("policy lock poisoned")
+                .policy()
+                .apply(&mut bank);
+        }
+        Scope::new(bank, &self.metrics)
+    }
+
+    /// Check `request` for `path` carries a token allowing it, if the server has tokens, returning the token's name.
+    fn authorize(&self, request: &Request, path: &str) -> Result<Option<String>, Denied> {
+        let Some(tokens) = &self.tokens else {
+            return Ok(None);
+        };
+        let name = tokens.authorize(request, Permission::needed(request.method(), path))?;
+        Ok(Some(name.to_string()))
+    }
+
+    fn handle(&self, mut request: Request) {
+        if let Err((reply, header)) = self.admit_connection(&request) {
+            return send(request, &reply, "application/json", header);
+        }
+        let requested = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv(version::HEADER))
+            .map(|header| header.value.to_string());
+        let (version, path) = match version::negotiate(request.url(), requested.as_deref()) {
+            Ok((version, path)) => (version, path.to_string()),
+            Err(reply) => return send(request, &reply, "application/json", None),
+        };
+        if request.method() == &Method::Get {
+            if let Some(reply) = self.negotiation(version, &path) {
+                return send(
+                    request,
+                    &reply,
+                    "application/json",
+                    Some(version::header(version)),
+                );
+            }
+        }
+        if request.method() == &Method::Get && path == "/metrics" {
+            let scopes = self.scopes();
+            let banks: Vec<_> = scopes
+                .iter()
+                .map(|scope| scope.bank.lock().expect("bank lock poisoned"))
+                .collect();
+            self.metrics.observe_banks(banks.iter().map(|bank| &**bank));
+            drop(banks);
+            let reply = Reply {
+                status: 200,
+                body: self.metrics.render(),
+            };
+            return send(
+                request,
+                &reply,
+                metrics::CONTENT_TYPE,
+                Some(version::header(version)),
+            );
+        }
+
+        let operator = match self.authorize(&request, &path) {
+            Ok(operator) => operator,
+            Err(denied) => return deny(request, &denied, version),
+        };
+        let (tenant, scope) = match &self.tenants {
+            None => (None, self.scope.clone()),
+            Some(tenants) => {
+                let Some(tenant) = tenants.tenant(&request) else {
+                    return deny(request, &Denied::Unauthenticated, version);
+                };
+                (Some(tenant), tenants.scope(tenant, || self.new_scope()))
+            }
+        };
+        if request.method() == &Method::Get && path.split('?').next() == Some("/events") {
+            events::accept(request, scope.bank, &scope.subscribers);
+            return;
+        }
+
+        let mut body = String::new();
+        if let Err(err) = request.as_reader().read_to_string(&mut body) {
+            return send(
+                request,
+                &Reply::error(400, &err),
+                "application/json",
+                Some(version::header(version)),
+            );
+        }
+        if let Err(wait) = self.admit_client(tenant, request.method(), &path, &body) {
+            return send(
+                request,
+                &Reply::too_many_requests(),
+                "application/json",
+                [retry_after(wait), version::header(version)],
+            );
+        }
+        let reply = self.dispatch(
+            &scope.bank,
+            operator.as_deref(),
+            version,
+            request.method(),
+            &path,
+            &body,
+        );
+        send(
+            request,
+            &reply,
+            "application/json",
+            Some(version::header(version)),
+        );
+    }
+
+    /// Route a request made with the token named `operator` to `bank`, queueing it in its [lane](lanes/index.html)
+    /// first if it changes the bank.
+    fn dispatch(
+        &self,
+        bank: &Mutex<Bank>,
+        operator: Option<&str>,
+        version: u32,
+        method: &Method,
+        path: &str,
+        body: &str,
+    ) -> Reply {
+        let _turn = match &self.lanes {
+            Some(lanes) if method != &Method::Get && method != &Method::Head => {
+                Some(lanes.enter(lanes.place_of(operator, path, body)))
+            }
+            _ => None,
+        };
+        let audit = self.audit.as_ref();
+        if admin::is_admin(path) {
+            admin::respond(bank, audit, operator, method, path, body)
+        } else {
+            respond(bank, audit, version, method, path, body)
+        }
+    }
+
+    /// The reply to a request negotiating the API [version](version/index.html), if `path` is one.
+    fn negotiation(&self, version: u32, path: &str) -> Option<Reply> {
+        match path.split('?').next() {
+            Some("/versions") => Some(Reply::json(
+                200,
+                &version::Versions {
+                    versions: version::VERSIONS,
+                    default: version::DEFAULT,
+                },
+            )),
+            Some("/capabilities") => {
+                let mut features = vec!["events", "metrics"];
+                if self.tenants.is_some() {
+                    features.push("tenants");
+                }
+                if self.tokens.is_some() {
+                    features.push("tokens");
+                }
+                if self.audit.is_some() {
+                    features.push("audit_log");
+                }
+                if self.limiter.limits_clients() {
+                    features.push("client_rate_limits");
+                }
+                if self.lanes.is_some() {
+                    features.push("priority_lanes");
+                }
+                if self.policy.is_some() {
+                    features.push("policy");
+                }
+                Some(Reply::json(
+                    200,
+                    &version::Capabilities::of(version, features),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Refuse `request` if its connection didn't come through TLS when the server has it, or is over its rate limit.
+    fn admit_connection(&self, request: &Request) -> Result<(), (Reply, Option<Header>)> {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() && self.remote_addr(request).is_none() {
+            tracing::warn!(addr = ?request.remote_addr(), "refused a connection that didn't come through TLS");
+            return Err((Reply::error(403, &"connections have to use TLS"), None));
+        }
+        if let Some(addr) = self.remote_addr(request) {
+            if let Err(wait) = self.limiter.connection(addr) {
+                tracing::debug!(%addr, "connection over its rate limit");
+                return Err((Reply::too_many_requests(), Some(retry_after(wait))));
+            }
+        }
+        Ok(())
+    }
+
+    /// The address `request` came from, which with TLS is the client's rather than the relay's.  With TLS, `None`
+    /// means the request didn't come through a relay at all.
+    #[cfg_attr(not(feature = "tls"), allow(clippy::unused_self))]
+    fn remote_addr(&self, request: &Request) -> Option<SocketAddr> {
+        let addr = *request.remote_addr()?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return tls.peer(addr);
+        }
+        Some(addr)
+    }
+
+    /// Count the instruction in `body` towards its client's rate limit, if clients are limited and the request is an
+    /// instruction.  Bodies that aren't instructions are left for [`respond`](fn.respond.html) to reject.  Clients of
+    /// different tenants are limited separately.
+    fn admit_client(
+        &self,
+        tenant: Option<&str>,
+        method: &Method,
+        url: &str,
+        body: &str,
+    ) -> Result<(), Duration> {
+        #[derive(Deserialize)]
+        struct Instruction {
+            client: AccountId,
+        }
+
+        if !self.limiter.limits_clients()
+            || method != &Method::Post
+            || url.split('?').next() != Some("/transactions")
+        {
+            return Ok(());
+        }
+        match serde_json::from_str::<Instruction>(body) {
+            Ok(instruction) => self.limiter.client(tenant, instruction.client).inspect_err(
+                |_| tracing::debug!(client = %instruction.client, "client over its rate limit"),
+            ),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// The token `request` carries as `Authorization: Bearer TOKEN`, if it has one.
+fn bearer(request: &Request) -> Option<&str> {
+    let value = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))?
+        .value
+        .as_str();
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Turn `request` away for `denied`, in API `version`.
+fn deny(request: Request, denied: &Denied, version: u32) {
+    match denied {
+        Denied::Unauthenticated => {
+            tracing::debug!(url = request.url(), "request without a known token");
+            send(
+                request,
+                &Reply::unauthorized(),
+                "application/json",
+                [challenge(), version::header(version)],
+            );
+        }
+        Denied::Forbidden(token) => {
+            tracing::debug!(
+                url = request.url(),
+                token,
+                "request not allowed by its token"
+            );
+            send(
+                request,
+                &Reply::error(403, &"token doesn't allow this request"),
+                "application/json",
+                Some(version::header(version)),
+            );
+        }
+    }
+}
+
+/// A `WWW-Authenticate` header asking for a bearer token.
+fn challenge() -> Header {
+    Header::from_bytes(&b"WWW-Authenticate"[..], &b"Bearer"[..])
+        .expect("challenge is a valid header")
+}
+
+/// A `Retry-After` header telling the client to wait `wait` before retrying.
+fn retry_after(wait: Duration) -> Header {
+    // Retry-After is in whole seconds, so round up rather than invite an early retry.
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    Header::from_bytes(&b"Retry-After"[..], seconds.max(1).to_string())
+        .expect("retry after is a valid header")
+}
+
+/// Send `reply` as the response to `request`, with `headers`.
+fn send(
+    request: Request,
+    reply: &Reply,
+    content_type: &str,
+    headers: impl IntoIterator<Item = Header>,
+) {
+    tracing::debug!(method = %request.method(), url = request.url(), status = reply.status, "handled request");
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+        .expect("content type is a valid header");
+    let mut response = Response::from_string(reply.body.as_str())
+        .with_status_code(reply.status)
+        .with_header(content_type);
+    for header in headers {
+        response.add_header(header);
+    }
+    if let Err(err) = request.respond(response) {
+        tracing::warn!(?err, "error sending response");
+    }
+}
+
+/// Route a request in API `version` to the bank, recording changes in `audit` if it's given.
+pub(crate) fn respond(
+    bank: &Mutex<Bank>,
+    audit: Option<&Mutex<audit::Writer>>,
+    version: u32,
+    method: &Method,
+    url: &str,
+    body: &str,
+) -> Reply {
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Post, ["transactions"]) => {
+            let ti: TransactionInstruction = match serde_json::from_str(body) {
+                Ok(ti) => ti,
+                Err(err) => return Reply::error(400, &err),
+            };
+            if !version::accepts(version, ti.kind) {
+                return Reply::error(
+                    422,
+                    &format!(
+                        "instruction type {} isn't in API version {version}",
+                        crate::cli::kind_name(ti.kind)
+                    ),
+                );
+            }
+            let correlation_id = ti.correlation_id.clone();
+            let mut bank = bank.lock().expect("bank lock poisoned");
+            let result = match audit {
+                None => bank.perform_transaction(ti),
+                Some(audit) => {
+                    let mut audit = audit.lock().expect("audit lock poisoned");
+                    let recorded = audit
+                        .perform(&mut bank, ti)
+                        .and_then(|result| audit.flush().map(|()| result));
+                    match recorded {
+                        Ok(result) => result,
+                        Err(err) => {
+                            tracing::error!(%err, "instruction applied but not recorded");
+                            return Reply::error(500, &err);
+                        }
+                    }
+                }
+            };
+            match result {
+                Ok(account) => Reply::json(200, &AccountSummary::from(account)),
+                Err(err) => match correlation_id {
+                    Some(correlation_id) => Reply::json(
+                        422,
+                        &serde_json::json!({ "error": err.to_string(), "correlation_id": correlation_id }),
+                    ),
+                    None => Reply::error(422, &err),
+                },
+            }
+        }
+        (Method::Get, ["accounts"]) => {
+            let bank = bank.lock().expect("bank lock poisoned");
+            let mut accounts: Vec<AccountSummary> =
+                bank.accounts().map(AccountSummary::from).collect();
+            accounts.sort_unstable_by_key(|account| account.client);
+            Reply::json(200, &accounts)
+        }
+        (Method::Get, ["accounts", client]) => {
+            let Ok(client) = client.parse() else {
+                return Reply::not_found();
+            };
+            let bank = bank.lock().expect("bank lock poisoned");
+            match bank.account(&client) {
+                Some(account) => Reply::json(200, &AccountSummary::from(account)),
+                None => match bank.archived_account(&client) {
+                    Some(account) => Reply::json(200, &AccountSummary::from(&account)),
+                    None => Reply::not_found(),
+                },
+            }
+        }
+        (Method::Get, ["transactions", tx]) => {
+            let Ok(tx) = tx.parse() else {
+                return Reply::not_found();
+            };
+            let bank = bank.lock().expect("bank lock poisoned");
+            match bank.transaction(&TransactionId(tx)) {
+                Some(txn) => Reply::json(200, &*txn),
+                None => Reply::not_found(),
+            }
+        }
+        (_, ["transactions" | "accounts"] | ["accounts" | "transactions", _]) => {
+            Reply::error(405, &"method not allowed")
+        }
+        _ => Reply::not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn post(bank: &Mutex<Bank>, body: &str) -> Reply {
+        respond(bank, None, 1, &Method::Post, "/transactions", body)
+    }
+
+    fn get(bank: &Mutex<Bank>, url: &str) -> Reply {
+        respond(bank, None, 1, &Method::Get, url, "")
+    }
+
+    #[test]
+    fn routes() {
+        let bank = Mutex::new(Bank::new());
+
+        let reply = post(
+            &bank,
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#,
+        );
+        assert_eq!(reply.status, 200);
+        assert_eq!(
+            reply.body,
+            r#"{"client":1,"available":"2.5000","held":"0.0000","total":"2.5000","locked":false}"#
+        );
+
+        let reply = post(
+            &bank,
+            r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "5"}"#,
+        );
+        assert_eq!(reply.status, 422);
+        assert_eq!(reply.body, r#"{"error":"insufficient funds"}"#);
+        let reply = post(
+            &bank,
+            r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "5", "correlation_id": "req-7"}"#,
+        );
+        assert_eq!(
+            reply.body,
+            r#"{"correlation_id":"req-7","error":"insufficient funds"}"#
+        );
+        assert_eq!(post(&bank, "{").status, 400);
+
+        post(
+            &bank,
+            r#"{"type": "deposit", "client": 2, "tx": 3, "amount": "1"}"#,
+        );
+        let reply = get(&bank, "/accounts?pretty");
+        assert_eq!(reply.status, 200);
+        let accounts: Vec<AccountSummary> = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(
+            accounts.iter().map(|a| a.client).collect::<Vec<_>>(),
+            [AccountId::Number(1), AccountId::Number(2)]
+        );
+
+        assert_eq!(get(&bank, "/accounts/2").status, 200);
+        assert_eq!(get(&bank, "/accounts/3").status, 404);
+        assert_eq!(get(&bank, "/accounts/x").status, 404);
+        assert_eq!(get(&bank, "/transactions/3").status, 200);
+        assert_eq!(get(&bank, "/transactions/2").status, 404);
+        assert_eq!(get(&bank, "/nothing").status, 404);
+        assert_eq!(
+            respond(&bank, None, 1, &Method::Delete, "/accounts/1", "").status,
+            405
+        );
+    }
+
+    #[test]
+    fn rate_limited() {
+        let limits = Limits {
+            per_connection: None,
+            per_client: Some(1.0),
+        };
+        let server = Arc::new(
+            Server::bind("127.0.0.1:0", Bank::new())
+                .unwrap()
+                .with_limits(limits),
+        );
+        let addr = server.local_addr().unwrap();
+        {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run(1));
+        }
+        let post = |client: u16, tx: u64| {
+            let body =
+                format!(r#"{{"type": "deposit", "client": {client}, "tx": {tx}, "amount": "1"}}"#);
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST /transactions HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        assert!(post(1, 1).starts_with("HTTP/1.1 200"));
+        let response = post(1, 2);
+        assert!(response.starts_with("HTTP/1.1 429"), "{}", response);
+        assert!(response.contains("Retry-After: 1\r\n"), "{}", response);
+        assert!(response.ends_with(r#"{"error":"rate limit exceeded"}"#));
+        assert!(post(2, 3).starts_with("HTTP/1.1 200"));
+        assert!(server
+            .bank()
+            .lock()
+            .unwrap()
+            .transaction(&TransactionId(2))
+            .is_none());
+    }
+
+    #[test]
+    fn scoped_to_tenants() {
+        let tokens = [
+            ("a".to_string(), vec!["token-a".to_string()]),
+            ("b".to_string(), vec!["token-b".to_string()]),
+        ];
+        let tenants = Tenants::new(HashMap::from(tokens)).unwrap();
+        let server = Arc::new(
+            Server::bind("127.0.0.1:0", Bank::new())
+                .unwrap()
+                .with_tenants(tenants),
+        );
+        let addr = server.local_addr().unwrap();
+        {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run(1));
+        }
+        let request = |method: &str, url: &str, token: Option<&str>, body: &str| {
+            let authorization = token
+                .map(|token| format!("Authorization: Bearer {token}\r\n"))
+                .unwrap_or_default();
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "{method} {url} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{authorization}Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1"}"#;
+
+        let response = request("POST", "/transactions", None, deposit);
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+        assert!(
+            response.contains("WWW-Authenticate: Bearer\r\n"),
+            "{}",
+            response
+        );
+        let response = request("POST", "/transactions", Some("token-c"), deposit);
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+
+        let response = request("POST", "/transactions", Some("token-a"), deposit);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request("GET", "/accounts/1", Some("token-b"), "");
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        let response = request("GET", "/transactions/1", Some("token-b"), "");
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        // The same transaction id is new to tenant b.
+        let response = request("POST", "/transactions", Some("token-b"), deposit);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request("GET", "/accounts", Some("token-a"), "");
+        assert!(response.ends_with(r#"[{"client":1,"available":"1.0000","held":"0.0000","total":"1.0000","locked":false}]"#), "{}", response);
+
+        let response = request("GET", "/metrics", None, "");
+        assert!(
+            response.contains("transactomatic_accounts 2\n"),
+            "{}",
+            response
+        );
+        assert!(server.bank().lock().unwrap().accounts().next().is_none());
+    }
+
+    #[test]
+    fn authorized_by_token() {
+        let grant = |token: &str, permissions: &[Permission]| auth::Grant {
+            token: token.to_string(),
+            permissions: permissions.to_vec(),
+        };
+        let grants = [
+            (
+                "ingest".to_string(),
+                grant("t-ingest", &[Permission::Submit]),
+            ),
+            ("reader".to_string(), grant("t-reader", &[Permission::Read])),
+            ("ops".to_string(), grant("t-ops", &[Permission::Admin])),
+        ];
+        let tokens = Tokens::new(HashMap::from(grants)).unwrap();
+        let server = Arc::new(
+            Server::bind("127.0.0.1:0", Bank::new())
+                .unwrap()
+                .with_tokens(tokens),
+        );
+        let addr = server.local_addr().unwrap();
+        {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run(1));
+        }
+        let request = |method: &str, url: &str, token: Option<&str>, body: &str| {
+            let authorization = token
+                .map(|token| format!("Authorization: Bearer {token}\r\n"))
+                .unwrap_or_default();
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "{method} {url} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{authorization}Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let deposit =
+            |tx: u64| format!(r#"{{"type": "deposit", "client": 1, "tx": {tx}, "amount": "1"}}"#);
+
+        let response = request("POST", "/transactions", None, &deposit(1));
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+        assert!(response.contains("WWW-Authenticate: Bearer\r\n"));
+        let response = request("POST", "/transactions", Some("t-reader"), &deposit(1));
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        let response = request("POST", "/transactions", Some("t-ingest"), &deposit(1));
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let response = request("GET", "/accounts/1", Some("t-ingest"), "");
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        let response = request("GET", "/accounts/1", Some("t-reader"), "");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request("POST", "/transactions", Some("t-ops"), &deposit(2));
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request("GET", "/transactions/2", Some("t-ops"), "");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let response = request("GET", "/metrics", None, "");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request("GET", "/capabilities", None, "");
+        assert!(response.contains(r#""features":["events","metrics","tokens"]"#));
+    }
+
+    #[test]
+    fn rejects_shared_tokens() {
+        let tokens = [
+            ("a".to_string(), vec!["token".to_string()]),
+            ("b".to_string(), vec!["token".to_string()]),
+        ];
+        assert!(matches!(
+            Tenants::new(HashMap::from(tokens)),
+            Err(tenants::Error::SharedToken(..))
+        ));
+    }
+
+    #[test]
+    fn stops_on_shutdown() {
+        let shutdown = Shutdown::new();
+        let server = Server::bind("127.0.0.1:0", Bank::new())
+            .unwrap()
+            .with_shutdown(shutdown.clone());
+        let running = thread::spawn(move || server.run(2));
+        shutdown.request();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn versioned() {
+        let server = Arc::new(Server::bind("127.0.0.1:0", Bank::new()).unwrap());
+        let addr = server.local_addr().unwrap();
+        {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run(1));
+        }
+        let request = |method: &str, url: &str, headers: &str, body: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "{method} {url} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1"}"#;
+
+        let response = request("GET", "/versions", "", "");
+        assert!(
+            response.ends_with(r#"{"versions":[1],"default":1}"#),
+            "{}",
+            response
+        );
+        let response = request("GET", "/v1/capabilities", "", "");
+        assert!(response.contains(r#""version":1,"instruction_types":["deposit","#));
+        assert!(response.ends_with(r#""features":["events","metrics"]}"#));
+
+        let response = request("POST", "/v1/transactions", "", deposit);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(
+            response.contains("Transactomatic-Api-Version: 1\r\n"),
+            "{}",
+            response
+        );
+        let response = request(
+            "GET",
+            "/accounts/1",
+            "Transactomatic-Api-Version: 1\r\n",
+            "",
+        );
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = request("GET", "/v2/accounts/1", "", "");
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        assert!(response.ends_with(r#""versions":[1]}"#), "{}", response);
+        let response = request("GET", "/accounts", "Transactomatic-Api-Version: 2\r\n", "");
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+    }
+
+    #[test]
+    fn over_http() {
+        let server = Arc::new(Server::bind("127.0.0.1:0", Bank::new()).unwrap());
+        let addr = server.local_addr().unwrap();
+        {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run(1));
+        }
+
+        let body = r#"{"type": "deposit", "client": 7, "tx": 1, "amount": "1"}"#;
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /transactions HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("application/json"));
+        assert!(server
+            .bank()
+            .lock()
+            .unwrap()
+            .account(&AccountId::Number(7))
+            .is_some());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(
+            response.contains("text/plain; version=0.0.4"),
+            "{}",
+            response
+        );
+        assert!(response.contains(
+            "transactomatic_instructions_total{kind=\"deposit\",outcome=\"applied\"} 1\n"
+        ));
+        assert!(response.contains("transactomatic_accounts 1\n"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Tenant-scoped access, for one server shared by several business units.
+//!
+//! With tenants, the server keeps a separate bank for each one, and every request has to carry one of a tenant's
+//! tokens as `Authorization: Bearer TOKEN`.  The token decides the bank a request is served from, so the routes are the
+//! same as without tenants and there is no way to name another tenant's accounts: tenant `a`'s `GET /accounts/1` is
+//! its own client 1, and `GET /events` streams only its own events.  A request without a known token gets
+//! `401 Unauthorized` and never reaches a bank.  `GET /metrics` is for operators and needs no token; it only has
+//! counts, across all tenants.
+//!
+//! Tokens are read from a JSON file mapping each tenant to its tokens:
+//!
+//! ```json
+//! {"retail": ["4f0c8a6e1d"], "wholesale": ["9b27d3c5aa", "e81f0b6c42"]}
+//! ```
+//!
+//! A tenant's bank starts empty and is created on its first request.
+
+use super::Scope;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use tiny_http::Request;
+
+/// Errors reading a tenant token file.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A tenant has an empty token.
+    EmptyToken(String),
+    /// The same token is given to two tenants, named here.
+    SharedToken(String, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading tenant tokens: {err}"),
+            Error::Json(err) => write!(f, "invalid tenant tokens: {err}"),
+            Error::EmptyToken(tenant) => write!(f, "tenant {tenant} has an empty token"),
+            Error::SharedToken(a, b) => write!(f, "tenants {a} and {b} share a token"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::EmptyToken(_) | Error::SharedToken(..) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+/// Tenants, their tokens, and the banks of those that have made requests.
+#[derive(Debug)]
+pub struct Tenants {
+    /// The tenant each token belongs to.
+    tokens: HashMap<String, String>,
+    scopes: Mutex<HashMap<String, Scope>>,
+}
+
+impl Tenants {
+    /// Tenants with the tokens in `tokens`, keyed by tenant.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a token is empty or belongs to more than one tenant.
+    pub fn new(tokens: HashMap<String, Vec<String>>) -> Result<Self, Error> {
+        let mut tenants = HashMap::new();
+        for (tenant, tokens) in tokens {
+            for token in tokens {
+                if token.is_empty() {
+                    return Err(Error::EmptyToken(tenant));
+                }
+                if let Some(other) = tenants.insert(token, tenant.clone()) {
+                    return Err(Error::SharedToken(other, tenant));
+                }
+            }
+        }
+        Ok(Self {
+            tokens: tenants,
+            scopes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Read tenants and their tokens from the JSON file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't valid.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        Self::new(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// The tenant whose token `request` carries, if it has a known one.
+    pub(crate) fn tenant(&self, request: &Request) -> Option<&str> {
+        self.tokens.get(super::bearer(request)?).map(String::as_str)
+    }
+
+    /// `tenant`'s bank, made with `new` if it's the tenant's first request.
+    pub(crate) fn scope(&self, tenant: &str, new: impl FnOnce() -> Scope) -> Scope {
+        let mut scopes = self.scopes.lock().expect("tenants lock poisoned");
+        if let Some(scope) = scopes.get(tenant) {
+            return scope.clone();
+        }
+        tracing::info!(tenant, "new tenant");
+        let scope = new();
+        scopes.insert(tenant.to_string(), scope.clone());
+        scope
+    }
+
+    /// The banks of every tenant that has made a request.
+    pub(crate) fn scopes(&self) -> Vec<Scope> {
+        self.scopes
+            .lock()
+            .expect("tenants lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
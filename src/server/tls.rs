@@ -0,0 +1,366 @@
+//! TLS for the HTTP server, so instructions aren't sent in cleartext.
+//!
+//! The server is given a certificate chain and its private key as PEM files, and optionally the PEM certificates of
+//! the authorities client certificates must be signed by.  With those, a client has to present a certificate signed by
+//! one of them, and a connection without one is refused during the handshake, before any request is read.
+//!
+//! TLS is terminated in front of the HTTP server: each connection is decrypted on its own thread and relayed over
+//! loopback to the server, which only listens there.  The server still sees each request as coming from the client's
+//! address, so [rate limits](../limit/index.html) per connection work as they do without TLS.  Requests on loopback
+//! connections that aren't relayed are refused with `403`, so a local process can't get around TLS or the client
+//! certificate check by connecting to the server directly.
+
+use crate::shutdown::Shutdown;
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{VerifierBuilderError, WebPkiClientVerifier};
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{self, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Errors setting up TLS.
+#[derive(Debug)]
+pub enum Error {
+    /// A PEM file, named here, can't be read.
+    Pem(PathBuf, pem::Error),
+    /// A PEM file, named here, has no certificates.
+    NoCertificate(PathBuf),
+    /// The certificates of client authorities can't be used.
+    ClientCa(VerifierBuilderError),
+    /// The certificate and key can't be used together.
+    Tls(rustls::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Pem(path, err) => write!(f, "error reading {}: {err}", path.display()),
+            Error::NoCertificate(path) => write!(f, "no certificates in {}", path.display()),
+            Error::ClientCa(err) => write!(f, "invalid client certificate authorities: {err}"),
+            Error::Tls(err) => write!(f, "invalid certificate or key: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Pem(_, err) => Some(err),
+            Error::ClientCa(err) => Some(err),
+            Error::Tls(err) => Some(err),
+            Error::NoCertificate(_) => None,
+        }
+    }
+}
+
+impl From<VerifierBuilderError> for Error {
+    fn from(err: VerifierBuilderError) -> Self {
+        Error::ClientCa(err)
+    }
+}
+
+impl From<rustls::Error> for Error {
+    fn from(err: rustls::Error) -> Self {
+        Error::Tls(err)
+    }
+}
+
+/// A TLS configuration serving the certificate chain in `cert` with the private key in `key`, requiring client
+/// certificates signed by one of the authorities in `client_ca` if it's given.
+///
+/// # Errors
+///
+/// Will return `Err` if a file can't be read or has no certificates, or the certificates or key can't be used.
+pub fn config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, Error> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let chain = certificates(cert)?;
+    let key =
+        PrivateKeyDer::from_pem_file(key).map_err(|err| Error::Pem(key.to_path_buf(), err))?;
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        None => builder.with_no_client_auth(),
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(path)? {
+                roots.add(cert)?;
+            }
+            builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?,
+            )
+        }
+    };
+    Ok(Arc::new(builder.with_single_cert(chain, key)?))
+}
+
+/// The certificates in the PEM file at `path`.
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|err| Error::Pem(path.to_path_buf(), err))?;
+    if certs.is_empty() {
+        return Err(Error::NoCertificate(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+/// Accepts TLS connections and relays them to the HTTP server.
+#[derive(Debug)]
+pub(crate) struct Terminator {
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    /// Where the HTTP server listens.
+    backend: SocketAddr,
+    /// The client each relayed connection is for, by the relay's own address.
+    peers: Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>,
+}
+
+impl Terminator {
+    /// Listen on `addr` with `config`, relaying to `backend`.
+    pub(crate) fn bind<A: ToSocketAddrs>(
+        addr: A,
+        config: Arc<ServerConfig>,
+        backend: SocketAddr,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            config,
+            backend,
+            peers: Arc::default(),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// The client a request the HTTP server got from `relay` is for, while its connection is open.
+    pub(crate) fn peer(&self, relay: SocketAddr) -> Option<SocketAddr> {
+        self.peers
+            .lock()
+            .expect("peers lock poisoned")
+            .get(&relay)
+            .copied()
+    }
+
+    /// Accept connections until `shutdown` is requested, checking every `check`.
+    pub(crate) fn run(&self, shutdown: &Shutdown, check: std::time::Duration) {
+        while !shutdown.requested() {
+            match self.listener.accept() {
+                Ok((client, addr)) => {
+                    let config = Arc::clone(&self.config);
+                    let peers = Arc::clone(&self.peers);
+                    let backend = self.backend;
+                    thread::spawn(move || {
+                        if let Err(err) = relay(config, client, addr, backend, &peers) {
+                            tracing::debug!(%addr, %err, "TLS connection failed");
+                        }
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(check),
+                Err(err) => tracing::warn!(%err, "error accepting TLS connection"),
+            }
+        }
+    }
+}
+
+/// A TLS connection and the socket it's written to.
+struct Session {
+    conn: ServerConnection,
+    client: TcpStream,
+}
+
+impl Session {
+    /// Send everything the connection has to send.
+    fn flush(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.client)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decrypt what `client`, at `addr`, sends and relay it to `backend`, and encrypt and send back what `backend` replies,
+/// until either closes.
+fn relay(
+    config: Arc<ServerConfig>,
+    client: TcpStream,
+    addr: SocketAddr,
+    backend: SocketAddr,
+    peers: &Mutex<HashMap<SocketAddr, SocketAddr>>,
+) -> io::Result<()> {
+    client.set_nonblocking(false)?;
+    let upstream = TcpStream::connect(backend)?;
+    let relay_addr = upstream.local_addr()?;
+    peers
+        .lock()
+        .expect("peers lock poisoned")
+        .insert(relay_addr, addr);
+
+    let session = Arc::new(Mutex::new(Session {
+        conn: ServerConnection::new(config).map_err(io::Error::other)?,
+        client: client.try_clone()?,
+    }));
+    let responses = {
+        let session = Arc::clone(&session);
+        let mut upstream = upstream.try_clone()?;
+        thread::spawn(move || -> io::Result<()> {
+            let mut buf = [0; 16 * 1024];
+            loop {
+                let n = upstream.read(&mut buf)?;
+                let mut session = session.lock().expect("session lock poisoned");
+                if n == 0 {
+                    session.conn.send_close_notify();
+                    session.flush()?;
+                    return session.client.shutdown(net::Shutdown::Both);
+                }
+                session.conn.writer().write_all(&buf[..n])?;
+                session.flush()?;
+            }
+        })
+    };
+    let result = requests(&session, client, &upstream);
+    let _ = upstream.shutdown(net::Shutdown::Write);
+    let sent = responses
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("relay thread panicked")));
+    peers
+        .lock()
+        .expect("peers lock poisoned")
+        .remove(&relay_addr);
+    result.and(sent)
+}
+
+/// Decrypt what `client` sends and write it to `upstream`, until the client closes.
+fn requests(
+    session: &Mutex<Session>,
+    mut client: TcpStream,
+    mut upstream: &TcpStream,
+) -> io::Result<()> {
+    let mut buf = [0; 16 * 1024];
+    loop {
+        // Reads nothing once the client closes, or the backend does and the socket is shut down.
+        let n = client.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut session = session.lock().expect("session lock poisoned");
+        let mut data = &buf[..n];
+        while !data.is_empty() {
+            session.conn.read_tls(&mut data)?;
+            let state = match session.conn.process_new_packets() {
+                Ok(state) => state,
+                Err(err) => {
+                    // Tell the client why, then give up on it.
+                    let _ = session.flush();
+                    return Err(io::Error::other(err));
+                }
+            };
+            let mut plaintext = vec![0; state.plaintext_bytes_to_read()];
+            session.conn.reader().read_exact(&mut plaintext)?;
+            upstream.write_all(&plaintext)?;
+            if state.peer_has_closed() {
+                return Ok(());
+            }
+        }
+        session.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::Bank;
+    use crate::server::Server;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, StreamOwned};
+    use std::convert::TryFrom;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/tls")
+            .join(name)
+    }
+
+    /// Get `/accounts` over TLS from `addr`, with a client certificate if `authenticate`.
+    fn get(addr: SocketAddr, authenticate: bool) -> io::Result<String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(certificates(&fixture("ca.pem")).unwrap());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = if authenticate {
+            let key = PrivateKeyDer::from_pem_file(fixture("client.key")).unwrap();
+            builder
+                .with_client_auth_cert(certificates(&fixture("client.pem")).unwrap(), key)
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        let name = ServerName::try_from("localhost").unwrap();
+        let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+        let mut stream = StreamOwned::new(conn, TcpStream::connect(addr)?);
+        write!(
+            stream,
+            "GET /accounts HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn serves_over_tls() {
+        let config = config(
+            &fixture("server.pem"),
+            &fixture("server.key"),
+            Some(&fixture("ca.pem")),
+        )
+        .unwrap();
+        let shutdown = Shutdown::new();
+        let server = Arc::new(
+            Server::bind_tls("127.0.0.1:0", Bank::new(), config)
+                .unwrap()
+                .with_shutdown(shutdown.clone()),
+        );
+        let addr = server.local_addr().unwrap();
+        let running = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run(1))
+        };
+
+        let response = get(addr, true).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("[]"), "{}", response);
+        assert!(get(addr, false).is_err());
+
+        let mut direct = TcpStream::connect(server.tls.as_ref().unwrap().backend).unwrap();
+        write!(
+            direct,
+            "GET /accounts HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        direct.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+
+        shutdown.request();
+        running.join().unwrap();
+        assert!(matches!(
+            super::config(&fixture("ca.pem"), &fixture("missing.key"), None),
+            Err(Error::Pem(..))
+        ));
+    }
+}
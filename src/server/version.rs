@@ -0,0 +1,164 @@
+//! Protocol versions of the HTTP API, so the instruction schema can change without breaking deployed clients.
+//!
+//! A request names the version it speaks in its path, as in `POST /v1/transactions`, or in a
+//! `Transactomatic-Api-Version` header.  Requests that do neither are version 1, which is the API as it was before it
+//! had versions, so clients written then carry on working.  Every response says which version it's in with the same
+//! header, and a request for a version the server doesn't speak gets `400 Bad Request` listing the ones it does.
+//!
+//! Clients negotiate with two requests that need no token:
+//!
+//! | Request | Response |
+//! |---|---|
+//! | `GET /versions` | The versions the server speaks, and the one requests get if they don't say. |
+//! | `GET /v1/capabilities` | What version 1 accepts: instruction types and fields, and the optional features turned on. |
+//!
+//! A later version can add instruction types or fields.  Instructions sent in an earlier version are still read the
+//! way that version defines them, and a type the version doesn't have is rejected with `422` rather than guessed at.
+
+use super::Reply;
+use crate::bank::transaction::instruction::TransactionInstructionKind;
+use crate::cli::kind_name;
+use serde::Serialize;
+use tiny_http::Header;
+
+/// The versions this server speaks, oldest first.
+pub const VERSIONS: &[u32] = &[1];
+
+/// The version of requests that don't say.
+pub const DEFAULT: u32 = 1;
+
+/// The header naming a request's or response's version.
+pub const HEADER: &str = "Transactomatic-Api-Version";
+
+/// Instruction types of version 1.
+const V1_KINDS: &[&str] = &[
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "reinstate",
+];
+
+/// Instruction fields of version 1.
+const V1_FIELDS: &[&str] = &[
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "correlation_id",
+    "operator_reference",
+    "timestamp",
+];
+
+/// The response to `GET /versions`.
+#[derive(Debug, Serialize)]
+pub struct Versions {
+    pub versions: &'static [u32],
+    pub default: u32,
+}
+
+/// What a version accepts, the response to `GET /v{version}/capabilities`.
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub version: u32,
+    pub instruction_types: &'static [&'static str],
+    pub instruction_fields: &'static [&'static str],
+    /// Optional features turned on for this server, such as `tenants`.
+    pub features: Vec<&'static str>,
+}
+
+impl Capabilities {
+    /// What `version`, which the server speaks, accepts, with `features` turned on.
+    pub(crate) fn of(version: u32, features: Vec<&'static str>) -> Self {
+        debug_assert!(
+            VERSIONS.contains(&version),
+            "unsupported API version {}",
+            version
+        );
+        Self {
+            version,
+            instruction_types: V1_KINDS,
+            instruction_fields: V1_FIELDS,
+            features,
+        }
+    }
+}
+
+/// The version a request speaks, from the path of its `url` or its `requested` header, and its path without the
+/// version.
+pub(crate) fn negotiate<'a>(
+    url: &'a str,
+    requested: Option<&str>,
+) -> Result<(u32, &'a str), Reply> {
+    let (in_path, path) = match url
+        .strip_prefix("/v")
+        .and_then(|rest| rest.split_once('/').or(Some((rest, ""))))
+        .filter(|(number, _)| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+    {
+        Some((number, _)) => (Some(number), &url[2 + number.len()..]),
+        None => (None, url),
+    };
+    let version = match (in_path, requested.map(str::trim)) {
+        (Some(a), Some(b)) if a != b => {
+            return Err(Reply::error(
+                400,
+                &format!("API version {a} in the path doesn't match {HEADER} {b}"),
+            ))
+        }
+        (Some(version), _) | (None, Some(version)) => version,
+        (None, None) => return Ok((DEFAULT, path)),
+    };
+    match version.parse() {
+        Ok(version) if VERSIONS.contains(&version) => Ok((version, path)),
+        _ => Err(Reply::json(
+            400,
+            &serde_json::json!({
+                "error": format!("unsupported API version {version}"),
+                "versions": VERSIONS,
+            }),
+        )),
+    }
+}
+
+/// Whether instructions of `kind` can be sent in `version`.
+pub(crate) fn accepts(version: u32, kind: TransactionInstructionKind) -> bool {
+    debug_assert!(
+        VERSIONS.contains(&version),
+        "unsupported API version {}",
+        version
+    );
+    V1_KINDS.contains(&kind_name(kind))
+}
+
+/// The header saying a response is in `version`.
+pub(crate) fn header(version: u32) -> Header {
+    Header::from_bytes(HEADER.as_bytes(), version.to_string()).expect("version is a valid header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates() {
+        assert_eq!(negotiate("/accounts", None), Ok((1, "/accounts")));
+        assert_eq!(negotiate("/v1/accounts?x", None), Ok((1, "/accounts?x")));
+        assert_eq!(negotiate("/v1", None), Ok((1, "")));
+        assert_eq!(negotiate("/accounts", Some("1")), Ok((1, "/accounts")));
+        assert_eq!(negotiate("/v1/accounts", Some(" 1")), Ok((1, "/accounts")));
+        assert_eq!(negotiate("/values", None), Ok((1, "/values")));
+
+        let unsupported = negotiate("/v2/accounts", None).unwrap_err();
+        assert_eq!(unsupported.status, 400);
+        assert_eq!(
+            unsupported.body,
+            r#"{"error":"unsupported API version 2","versions":[1]}"#
+        );
+        assert_eq!(negotiate("/accounts", Some("one")).unwrap_err().status, 400);
+        assert_eq!(
+            negotiate("/v1/accounts", Some("2")).unwrap_err().status,
+            400
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Stopping long runs cleanly when asked to.
+//!
+//! A [`Shutdown`](struct.Shutdown.html) is a flag that processing checks between instructions, or between batches for
+//! the message broker consumers, so that it stops at a point where everything it has read has been applied and
+//! written out.  On Unix the flag is set by `SIGINT` or `SIGTERM`; a second signal ends the process straight away, for
+//! when stopping cleanly takes too long.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Whether a shutdown has been requested.  Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request a shutdown on `SIGINT` or `SIGTERM`, and exit on a second one.
+    ///
+    /// # Errors
+    ///
+    /// Will return an `Err` if the signal handlers can't be installed.
+    #[cfg(unix)]
+    pub fn on_signals(&self) -> io::Result<()> {
+        use signal_hook::{
+            consts::{SIGINT, SIGTERM},
+            flag,
+        };
+        for signal in [SIGINT, SIGTERM] {
+            // Registered first so that it sees the flag before this signal sets it.
+            flag::register_conditional_shutdown(signal, 1, Arc::clone(&self.requested))?;
+            flag::register(signal, Arc::clone(&self.requested))?;
+        }
+        Ok(())
+    }
+
+    /// Signals aren't handled on this platform.
+    ///
+    /// # Errors
+    ///
+    /// Never returns an `Err`.
+    #[cfg(not(unix))]
+    pub fn on_signals(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Request a shutdown.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a shutdown has been requested.
+    #[must_use]
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+}
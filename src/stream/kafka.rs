@@ -0,0 +1,305 @@
+//! Consuming instructions from a Kafka topic.
+//!
+//! The runner joins a consumer group and applies messages from each partition in offset order.  Instructions for one
+//! client must be applied in order, so producers should key messages by client to keep each client on one partition.
+//! Offsets are committed to Kafka right after each [snapshot](../struct.Snapshotter.html), so the committed offsets
+//! always match the saved state.
+//!
+//! The runner can also publish what happens in the bank to other [topics](struct.Topics.html), making it a complete
+//! stream processor.  Published messages are JSON keyed by client id, and are sent after each batch of input is
+//! applied but before it is committed.  A restarted runner reapplies everything since the last snapshot, so
+//! downstream consumers can see the same message more than once.
+//!
+//! Messages that can't be decoded or applied can be sent as [dead letters](../struct.DeadLetter.html) to a topic,
+//! keyed by their source, or to a [file](../struct.DeadLetterFile.html), or both.  Their source is the topic,
+//! partition, and offset they were read from, as `instructions/0/17`.
+
+use super::{apply, policy, reload, start, DeadLetter, DeadLetterFile, Error, Format, Snapshotter};
+use crate::bank::account::{AccountId, AccountSummary};
+use crate::bank::event::Event;
+use crate::bank::retry::Retry;
+use crate::bank::Bank;
+use crate::shutdown::Shutdown;
+use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use kafka::producer::{Producer, Record, RequiredAcks};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Where to consume from and how.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Bootstrap brokers, as `host:port`.
+    pub brokers: Vec<String>,
+    pub topic: String,
+    /// Consumer group to join.  Offsets are committed for this group.
+    pub group: String,
+    pub format: Format,
+    /// Directory to keep snapshots in.
+    pub state_dir: PathBuf,
+    /// Number of messages between snapshots.
+    pub snapshot_interval: u64,
+    /// Retry writing snapshots after transient storage errors.
+    pub retry: Retry,
+    /// Where to publish what happens in the bank.
+    pub publish: Topics,
+    /// Serve the [control interface](../../control/index.html) on this Unix socket.
+    pub control_socket: Option<PathBuf>,
+    /// Apply the [policy](../../policy/index.html) in this file, reloading it when it changes or on `SIGHUP`.
+    pub policy: Option<PathBuf>,
+    /// Append messages that can't be decoded or applied to this file.
+    pub dead_letters: Option<PathBuf>,
+    /// Stop consuming once this is requested.
+    pub shutdown: Shutdown,
+}
+
+/// Topics to publish to.  Nothing is published to topics that aren't set.
+#[derive(Debug, Clone, Default)]
+pub struct Topics {
+    /// Every [event](../../bank/event/enum.Event.html) except rejections: applied transactions, disputes, and so on.
+    pub events: Option<String>,
+    /// Each changed account as it is at the end of a batch, in the same form as the account report.
+    pub accounts: Option<String>,
+    /// Rejected instructions, with the reason.
+    pub rejections: Option<String>,
+    /// Messages that can't be decoded or applied, as [dead letters](../struct.DeadLetter.html).
+    pub dead_letters: Option<String>,
+}
+
+/// Publishes the bank's events to Kafka.
+struct Publisher {
+    producer: Producer,
+    topics: Topics,
+    /// Events since the last publish, collected by an observer on the bank.
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl From<kafka::Error> for Error {
+    fn from(err: kafka::Error) -> Self {
+        Error::Kafka(err)
+    }
+}
+
+/// Consume and apply messages until an error occurs or a shutdown is requested.
+///
+/// # Errors
+///
+/// Will return `Err` if the brokers can't be reached, offsets can't be committed, or a snapshot can't be written.
+/// Messages that can't be decoded or applied are skipped, after being sent to the dead-letter topic and file if there
+/// are any.
+///
+/// # Panics
+///
+/// Panics if the control interface panicked while holding the bank's lock.
+pub fn run(options: &Options) -> Result<(), Error> {
+    let mut snapshotter =
+        Snapshotter::new(&options.state_dir, options.snapshot_interval)?.with_retry(options.retry);
+    let shared = start(&snapshotter, options.control_socket.as_deref())?;
+    let mut reloader = policy(
+        options.policy.as_deref(),
+        &mut shared.lock().expect("bank lock poisoned"),
+    )?;
+    let mut publisher = Publisher::new(
+        &options.brokers,
+        &options.publish,
+        &mut shared.lock().expect("bank lock poisoned"),
+    )?;
+    let mut dead_letters = options
+        .dead_letters
+        .as_deref()
+        .map(DeadLetterFile::open)
+        .transpose()?;
+    let mut consumer = Consumer::from_hosts(options.brokers.clone())
+        .with_topic(options.topic.clone())
+        .with_group(options.group.clone())
+        .with_fallback_offset(FetchOffset::Earliest)
+        .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+        .create()?;
+    tracing::info!(topic = %options.topic, group = %options.group, "consuming");
+
+    // The offset of the last message applied from each partition.
+    let mut last = BTreeMap::new();
+    while !options.shutdown.requested() {
+        let message_sets = consumer.poll()?;
+        let mut bank = shared.lock().expect("bank lock poisoned");
+        reload(reloader.as_mut(), &mut bank);
+        let mut count = 0;
+        let mut letters = vec![];
+        for set in message_sets.iter() {
+            for message in set.messages() {
+                tracing::trace!(
+                    partition = set.partition(),
+                    offset = message.offset,
+                    "applying message"
+                );
+                if let Err(failure) = apply(&mut bank, &options.format, message.value) {
+                    let source = format!("{}/{}/{}", set.topic(), set.partition(), message.offset);
+                    letters.push(DeadLetter::new(source, message.value, &failure));
+                }
+            }
+            if let Some(message) = set.messages().last() {
+                last.insert(set.partition(), message.offset);
+            }
+            count += set.messages().len() as u64;
+            consumer.consume_messageset(set)?;
+        }
+        if let Some(dead_letters) = &mut dead_letters {
+            dead_letters.write(&letters)?;
+        }
+        if let Some(publisher) = &mut publisher {
+            publisher.publish(&bank, &letters)?;
+        }
+        if snapshotter.applied(&bank, count)? {
+            consumer.commit_consumed()?;
+        }
+    }
+    snapshotter.save(&shared.lock().expect("bank lock poisoned"))?;
+    consumer.commit_consumed()?;
+    tracing::info!(offsets = ?last, "stopped after shutdown request");
+    Ok(())
+}
+
+impl Publisher {
+    /// Start collecting `bank`'s events, if there are any topics to publish them to.
+    fn new(brokers: &[String], topics: &Topics, bank: &mut Bank) -> Result<Option<Self>, Error> {
+        if topics.events.is_none()
+            && topics.accounts.is_none()
+            && topics.rejections.is_none()
+            && topics.dead_letters.is_none()
+        {
+            return Ok(None);
+        }
+        let producer = Producer::from_hosts(brokers.to_vec())
+            .with_required_acks(RequiredAcks::All)
+            .create()?;
+        let events = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&events);
+        bank.register_observer(move |event: &Event| {
+            sink.lock()
+                .expect("event buffer lock poisoned")
+                .push(event.clone());
+        });
+        Ok(Some(Self {
+            producer,
+            topics: topics.clone(),
+            events,
+        }))
+    }
+
+    /// Send everything collected since the last call, and `letters`.
+    fn publish(&mut self, bank: &Bank, letters: &[DeadLetter]) -> Result<(), Error> {
+        let events = std::mem::take(&mut *self.events.lock().expect("event buffer lock poisoned"));
+        let mut messages = messages(&self.topics, &events, bank)?;
+        if let Some(topic) = &self.topics.dead_letters {
+            for letter in letters {
+                let value = serde_json::to_string(letter).map_err(Error::Json)?;
+                messages.push((topic.as_str(), letter.source.clone(), value));
+            }
+        }
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<_> = messages
+            .iter()
+            .map(|(topic, key, value)| Record::from_key_value(topic, key.as_str(), value.as_str()))
+            .collect();
+        for confirm in self.producer.send_all(&records)? {
+            for partition in confirm.partition_confirms {
+                partition.offset.map_err(kafka::Error::Kafka)?;
+            }
+        }
+        tracing::debug!(messages = records.len(), "published");
+        Ok(())
+    }
+}
+
+/// The topic, key, and value of each message to publish for `events`.
+fn messages<'a>(
+    topics: &'a Topics,
+    events: &[Event],
+    bank: &Bank,
+) -> Result<Vec<(&'a str, String, String)>, Error> {
+    let mut messages = vec![];
+    let mut changed: Vec<AccountId> = vec![];
+    for event in events {
+        let client = event.client();
+        let topic = if let Event::InstructionRejected { .. } = event {
+            &topics.rejections
+        } else {
+            if !changed.contains(&client) {
+                changed.push(client);
+            }
+            &topics.events
+        };
+        if let Some(topic) = topic {
+            let value = serde_json::to_string(event).map_err(Error::Json)?;
+            messages.push((topic.as_str(), client.to_string(), value));
+        }
+    }
+    if let Some(topic) = &topics.accounts {
+        for client in changed {
+            if let Some(account) = bank.account(&client) {
+                let value =
+                    serde_json::to_string(&AccountSummary::from(account)).map_err(Error::Json)?;
+                messages.push((topic.as_str(), client.to_string(), value));
+            }
+        }
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind,
+    };
+    use crate::bank::transaction::TransactionId;
+
+    fn instruction(
+        kind: TransactionInstructionKind,
+        client: u16,
+        tx: u64,
+    ) -> TransactionInstruction {
+        TransactionInstruction {
+            kind,
+            client: AccountId::Number(client),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(1)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn messages_by_topic() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&events);
+        let mut bank = Bank::new();
+        bank.register_observer(move |event: &Event| sink.lock().unwrap().push(event.clone()));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, 1));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Deposit, 1, 2));
+        let _ = bank.perform_transaction(instruction(TransactionInstructionKind::Withdrawal, 2, 3));
+
+        let topics = Topics {
+            events: None,
+            accounts: Some("accounts".to_string()),
+            rejections: Some("rejections".to_string()),
+            dead_letters: None,
+        };
+        let messages = messages(&topics, &events.lock().unwrap(), &bank).unwrap();
+        let summary: Vec<_> = messages
+            .iter()
+            .map(|(topic, key, _)| (*topic, key.as_str()))
+            .collect();
+        // Client 2's account is created even though the withdrawal is rejected.
+        assert_eq!(
+            summary,
+            [("rejections", "2"), ("accounts", "1"), ("accounts", "2")]
+        );
+        assert!(messages[0].2.contains(r#""error":"InsufficientFunds""#));
+        assert!(messages[1].2.contains(r#""total":"2.0000""#));
+    }
+}
@@ -0,0 +1,562 @@
+//! This module contains runners that apply instructions from message brokers, or from files dropped into a
+//! [directory](watch/index.html), instead of a single input file.
+//!
+//! A runner is long-lived: it keeps one [Bank](../bank/struct.Bank.html) in memory and applies instructions as they
+//! arrive.  State is kept durable with periodic [snapshots](struct.Snapshotter.html) in a state directory, and a
+//! runner only tells the broker a message is done with once the snapshot covering it has been written.  After a crash
+//! the runner starts from the last snapshot and the broker redelivers everything after it.
+//!
+//! A runner stops cleanly when its [shutdown](../shutdown/index.html) is requested: it finishes the batch it is
+//! applying, writes a snapshot, tells the broker everything applied is done with, and returns.
+//!
+//! A runner can apply a [policy](../policy/index.html) from a file, which is reloaded between batches when the file
+//! changes or the process gets `SIGHUP`.
+//!
+//! A runner can also serve the [control interface](../control/index.html) on a Unix socket.  The bank is then shared
+//! with it behind a mutex, which the runner holds while it applies each batch.
+//!
+//! Messages carry one instruction each, with the same fields as the CSV input, in one of the supported
+//! [formats](enum.Format.html).
+//!
+//! Messages that can't be decoded, and instructions the bank rejects, fail the same way however often they're
+//! redelivered, so they're logged and skipped rather than stopping the stream.  A broker runner can also send each one
+//! to a dead-letter sink as a [`DeadLetter`](struct.DeadLetter.html), with the reason it failed, so it can be looked
+//! at and fixed up later: a [file](struct.DeadLetterFile.html) of JSON lines, or a Kafka
+//! [topic](kafka/struct.Topics.html).  Dead letters are written before the messages they came from are acknowledged.
+
+use crate::bank::retry::{Retry, Transient};
+use crate::bank::transaction::{self, instruction::TransactionInstruction};
+use crate::bank::{snapshot, Bank};
+use crate::policy::{self, Reloader};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "amqp")]
+pub mod amqp;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod watch;
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+/// Errors related to running a stream.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Snapshot(snapshot::Error),
+    /// A message couldn't be decoded as JSON.
+    Json(serde_json::Error),
+    /// A message couldn't be decoded as Avro.
+    #[cfg(feature = "avro")]
+    Avro(Box<apache_avro::Error>),
+    /// A message's fields don't make up an instruction.
+    Fields(csv::Error),
+    /// The policy file couldn't be read when starting.
+    Policy(policy::Error),
+    #[cfg(feature = "kafka")]
+    Kafka(::kafka::Error),
+    #[cfg(feature = "redis")]
+    Redis(::redis::RedisError),
+    #[cfg(feature = "amqp")]
+    Amqp(Box<amiquip::Error>),
+}
+
+/// How instructions are encoded in messages.
+#[derive(Debug, Clone)]
+pub enum Format {
+    /// A JSON object: `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+    Json,
+    /// A single Avro datum written with this schema: a record with `type`, `client`, `tx`, and `amount` fields.
+    /// Payloads in the Confluent wire format have their five-byte header skipped; the schema id in it isn't checked.
+    #[cfg(feature = "avro")]
+    Avro(apache_avro::Schema),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::Snapshot(err) => write!(f, "{err}"),
+            Error::Json(err) => write!(f, "invalid JSON instruction: {err}"),
+            #[cfg(feature = "avro")]
+            Error::Avro(err) => write!(f, "invalid Avro instruction: {err}"),
+            Error::Fields(err) => write!(f, "invalid instruction: {err}"),
+            Error::Policy(err) => write!(f, "{err}"),
+            #[cfg(feature = "kafka")]
+            Error::Kafka(err) => write!(f, "Kafka error: {err}"),
+            #[cfg(feature = "redis")]
+            Error::Redis(err) => write!(f, "Redis error: {err}"),
+            #[cfg(feature = "amqp")]
+            Error::Amqp(err) => write!(f, "AMQP error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Snapshot(err) => Some(err),
+            Error::Json(err) => Some(err),
+            #[cfg(feature = "avro")]
+            Error::Avro(err) => Some(&**err),
+            Error::Fields(err) => Some(err),
+            Error::Policy(err) => Some(err),
+            #[cfg(feature = "kafka")]
+            Error::Kafka(err) => Some(err),
+            #[cfg(feature = "redis")]
+            Error::Redis(err) => Some(err),
+            #[cfg(feature = "amqp")]
+            Error::Amqp(err) => Some(&**err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Only storage errors are transient.  The runners reconnect to brokers themselves, or stop.
+impl Transient for Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            Error::Io(err) => err.is_transient(),
+            Error::Snapshot(err) => err.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+impl From<snapshot::Error> for Error {
+    fn from(err: snapshot::Error) -> Self {
+        Error::Snapshot(err)
+    }
+}
+
+impl From<policy::Error> for Error {
+    fn from(err: policy::Error) -> Self {
+        Error::Policy(err)
+    }
+}
+
+/// Why a message wasn't applied.
+#[derive(Debug)]
+pub enum Failure {
+    /// The message isn't an instruction.
+    Undecodable(Error),
+    /// The bank rejected the instruction.
+    Rejected(transaction::Error),
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Undecodable(err) => write!(f, "{err}"),
+            Failure::Rejected(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Failure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Failure::Undecodable(err) => Some(err),
+            Failure::Rejected(err) => Some(err),
+        }
+    }
+}
+
+/// A message that failed, as it's sent to a dead-letter sink.
+///
+/// Serializes as e.g. `{"source":"instructions/0/17","failure":"rejected","reason":"insufficient funds","payload":"..."}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeadLetter {
+    /// Where the message was read from, such as a topic, partition, and offset, or a stream and entry id.
+    pub source: String,
+    /// `undecodable` or `rejected`.
+    pub failure: &'static str,
+    /// Why the message failed.
+    pub reason: String,
+    /// The message as it was read, as text.  Bytes that aren't UTF-8 are replaced.
+    pub payload: String,
+}
+
+impl DeadLetter {
+    /// The dead letter for the message from `source` with `payload`, which failed with `failure`.
+    #[must_use]
+    pub fn new(source: String, payload: &[u8], failure: &Failure) -> Self {
+        Self {
+            source,
+            failure: match failure {
+                Failure::Undecodable(_) => "undecodable",
+                Failure::Rejected(_) => "rejected",
+            },
+            reason: failure.to_string(),
+            payload: String::from_utf8_lossy(payload).into_owned(),
+        }
+    }
+}
+
+/// Appends dead letters to a file, one JSON object per line.
+#[derive(Debug)]
+pub struct DeadLetterFile {
+    writer: io::BufWriter<fs::File>,
+}
+
+impl DeadLetterFile {
+    /// Append to the file at `path`, creating it if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be opened.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            writer: io::BufWriter::new(file),
+        })
+    }
+
+    /// Append `letters` and flush them to the file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be written.
+    pub fn write(&mut self, letters: &[DeadLetter]) -> Result<(), Error> {
+        if letters.is_empty() {
+            return Ok(());
+        }
+        for letter in letters {
+            serde_json::to_writer(&mut self.writer, letter).map_err(Error::Json)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Format {
+    /// Decode one message.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the message isn't an instruction in this format.
+    pub fn decode(&self, payload: &[u8]) -> Result<TransactionInstruction, Error> {
+        match self {
+            Format::Json => serde_json::from_slice(payload).map_err(Error::Json),
+            #[cfg(feature = "avro")]
+            Format::Avro(schema) => {
+                let mut payload = match payload {
+                    [0, _, _, _, _, rest @ ..] => rest,
+                    _ => payload,
+                };
+                let value = apache_avro::from_avro_datum(schema, &mut payload, None)
+                    .map_err(|err| Error::Avro(Box::new(err)))?;
+                apache_avro::from_value(&value).map_err(|err| Error::Avro(Box::new(err)))
+            }
+        }
+    }
+}
+
+/// Decode and apply one message.
+///
+/// # Errors
+///
+/// Will return `Err` if the message can't be decoded or the bank rejects it.  It has been logged and skipped.
+pub fn apply(bank: &mut Bank, format: &Format, payload: &[u8]) -> Result<(), Failure> {
+    apply_decoded(bank, format.decode(payload))
+}
+
+/// Apply a decoded message.  Like input files, messages that can't be decoded or applied are logged and skipped rather
+/// than stopping the stream.
+///
+/// # Errors
+///
+/// Will return `Err` if the message wasn't decoded or the bank rejects it.  It has been logged and skipped.
+pub fn apply_decoded(
+    bank: &mut Bank,
+    decoded: Result<TransactionInstruction, Error>,
+) -> Result<(), Failure> {
+    match decoded {
+        Ok(ti) => {
+            let correlation_id = ti.correlation_id.clone();
+            if let Err(err) = bank.perform_transaction(ti) {
+                tracing::error!(
+                    ?err,
+                    correlation_id = correlation_id.as_deref(),
+                    "error applying transaction"
+                );
+                return Err(Failure::Rejected(err));
+            }
+            Ok(())
+        }
+        Err(err) => {
+            tracing::warn!(%err, "skipping undecodable message");
+            Err(Failure::Undecodable(err))
+        }
+    }
+}
+
+/// Apply the policy in the file at `path` to `bank`, if there is one, and return a reloader for it that also reloads
+/// on `SIGHUP`.
+fn policy(path: Option<&std::path::Path>, bank: &mut Bank) -> Result<Option<Reloader>, Error> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let reloader = Reloader::new(path)?;
+    reloader.on_hangup()?;
+    reloader.policy().apply(bank);
+    Ok(Some(reloader))
+}
+
+/// Apply the policy to `bank` again if it has changed.
+fn reload(reloader: Option<&mut Reloader>, bank: &mut Bank) {
+    if let Some(policy) = reloader.and_then(Reloader::poll) {
+        policy.apply(bank);
+    }
+}
+
+/// Load the bank a runner starts from and, if `control` is set, serve the control interface for it on that socket.
+fn start(
+    snapshotter: &Snapshotter,
+    control: Option<&std::path::Path>,
+) -> Result<std::sync::Arc<std::sync::Mutex<Bank>>, Error> {
+    use std::sync::{Arc, Mutex};
+
+    let bank = Arc::new(Mutex::new(snapshotter.load()?));
+    if let Some(path) = control {
+        #[cfg(unix)]
+        crate::control::Control::bind(path, Arc::clone(&bank))?.spawn();
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("can't serve control socket {path:?}: Unix sockets aren't supported"),
+        )
+        .into());
+    }
+    Ok(bank)
+}
+
+/// Takes periodic snapshots of a runner's bank in a state directory.
+#[derive(Debug)]
+pub struct Snapshotter {
+    dir: PathBuf,
+    interval: u64,
+    since_last: u64,
+    retry: Retry,
+}
+
+impl Snapshotter {
+    /// Keep snapshots in `dir`, taking one every `interval` messages.  An interval of 0 snapshots after every batch.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `dir` doesn't exist and can't be created.
+    pub fn new<P: Into<PathBuf>>(dir: P, interval: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            interval,
+            since_last: 0,
+            retry: Retry::default(),
+        })
+    }
+
+    /// Retry writing snapshots after transient errors.  Snapshots are written whole, so a retry starts again.
+    #[must_use]
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Whether a snapshot has been taken.
+    #[must_use]
+    pub fn exists(&self) -> bool {
+        self.dir.join(SNAPSHOT_FILE).exists()
+    }
+
+    /// Load the most recent snapshot, or an empty bank if there isn't one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a snapshot exists but can't be read.
+    pub fn load(&self) -> Result<Bank, Error> {
+        match fs::File::open(self.dir.join(SNAPSHOT_FILE)) {
+            Ok(file) => Ok(Bank::load_snapshot(io::BufReader::new(file))?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Bank::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Note that a batch of `count` messages has been applied, taking a snapshot if the interval has been reached.
+    /// Returns whether a snapshot was taken, in which case everything applied so far is durable.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a snapshot is due and can't be written.
+    pub fn applied(&mut self, bank: &Bank, count: u64) -> Result<bool, Error> {
+        self.since_last += count;
+        if self.since_last == 0 || self.since_last < self.interval {
+            return Ok(false);
+        }
+        self.save(bank)?;
+        Ok(true)
+    }
+
+    /// Take a snapshot now.  It is written to a temporary file and renamed over the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot can't be written.
+    pub fn save(&mut self, bank: &Bank) -> Result<(), Error> {
+        self.retry.run(|| self.write(bank))?;
+        self.since_last = 0;
+        Ok(())
+    }
+
+    fn write(&self, bank: &Bank) -> Result<(), Error> {
+        tracing::debug!(dir = ?self.dir, "writing snapshot");
+        let tmp = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut writer = io::BufWriter::new(fs::File::create(&tmp)?);
+        bank.save_snapshot(&mut writer)?;
+        writer.flush()?;
+        writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        fs::rename(tmp, self.dir.join(SNAPSHOT_FILE))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::{instruction::TransactionInstructionKind, TransactionId};
+
+    #[test]
+    fn decode_json() {
+        let ti = Format::Json
+            .decode(br#"{"type": "deposit", "client": 1, "tx": 2, "amount": "1.5"}"#)
+            .unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Deposit);
+        assert_eq!(ti.client, AccountId::Number(1));
+        assert_eq!(ti.tx, TransactionId(2));
+        assert_eq!(ti.amount, Some(Amount::new(15, 1)));
+        assert!(Format::Json.decode(b"deposit,1,2,1.5").is_err());
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn decode_avro() {
+        use apache_avro::types::{Record, Value};
+
+        let schema = apache_avro::Schema::parse_str(
+            r#"{"type": "record", "name": "Instruction", "fields": [
+                {"name": "type", "type": "string"},
+                {"name": "client", "type": "int"},
+                {"name": "tx", "type": "long"},
+                {"name": "amount", "type": ["null", "string"]}
+            ]}"#,
+        )
+        .unwrap();
+        let mut record = Record::new(&schema).unwrap();
+        record.put("type", "dispute");
+        record.put("client", 3);
+        record.put("tx", 4_i64);
+        record.put("amount", Value::Union(0, Box::new(Value::Null)));
+        let datum = apache_avro::to_avro_datum(&schema, record).unwrap();
+
+        let format = Format::Avro(schema);
+        let ti = format.decode(&datum).unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Dispute);
+        assert_eq!(ti.client, AccountId::Number(3));
+        assert_eq!(ti.tx, TransactionId(4));
+        assert_eq!(ti.amount, None);
+
+        // Confluent wire format: magic byte and schema id.
+        let framed: Vec<u8> = [0, 0, 0, 0, 1].iter().chain(&datum).copied().collect();
+        assert_eq!(format.decode(&framed).unwrap(), ti);
+    }
+
+    #[test]
+    fn snapshots() {
+        let dir =
+            std::env::temp_dir().join(format!("transactomatic-stream-{}", std::process::id()));
+        let mut snapshotter = Snapshotter::new(&dir, 3).unwrap();
+        let mut bank = snapshotter.load().unwrap();
+        assert_eq!(bank.accounts().count(), 0);
+
+        apply(
+            &mut bank,
+            &Format::Json,
+            br#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2"}"#,
+        )
+        .unwrap();
+        assert!(apply(&mut bank, &Format::Json, b"not json").is_err());
+        assert!(!snapshotter.applied(&bank, 2).unwrap());
+        assert!(snapshotter
+            .load()
+            .unwrap()
+            .account(&AccountId::Number(1))
+            .is_none());
+        assert!(snapshotter.applied(&bank, 1).unwrap());
+        assert_eq!(
+            snapshotter.load().unwrap().account(&AccountId::Number(1)),
+            bank.account(&AccountId::Number(1))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dead_letters() {
+        let path = std::env::temp_dir().join(format!(
+            "transactomatic-dead-letters-{}.jsonl",
+            std::process::id()
+        ));
+        let mut bank = Bank::new();
+        let mut letters = vec![];
+        for (offset, payload) in [
+            &br#"{"type": "withdrawal", "client": 1, "tx": 1, "amount": "2"}"#[..],
+            b"not json",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let failure = apply(&mut bank, &Format::Json, payload).unwrap_err();
+            letters.push(DeadLetter::new(
+                format!("instructions/0/{offset}"),
+                payload,
+                &failure,
+            ));
+        }
+        let mut file = DeadLetterFile::open(&path).unwrap();
+        file.write(&letters[..1]).unwrap();
+        file.write(&letters[1..]).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"source":"instructions/0/0","failure":"rejected","reason":"insufficient funds","payload":"{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 1, \"amount\": \"2\"}"}"#
+        );
+        assert!(lines[1]
+            .starts_with(r#"{"source":"instructions/0/1","failure":"undecodable","reason":"#));
+        assert_eq!(lines.len(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+}
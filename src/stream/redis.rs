@@ -0,0 +1,261 @@
+//! Consuming instructions from a Redis Stream.
+//!
+//! The runner reads the stream with `XREADGROUP` as one consumer of a consumer group, creating the group if it doesn't
+//! exist.  Each runner keeps its own bank, so a group should only have one consumer unless clients are split between
+//! streams some other way.
+//!
+//! Entries are acknowledged with `XACK` once a [snapshot](../struct.Snapshotter.html) covering them has been written.
+//! On startup the runner first rereads its own pending entries, which were applied after the last snapshot but not
+//! acknowledged, and then moves on to new ones.
+//!
+//! An entry is either a `payload` field holding an encoded instruction in the runner's [format](../enum.Format.html),
+//! or `type`, `client`, `tx`, and `amount` fields parsed like the columns of the CSV input:
+//!
+//! ```text
+//! XADD instructions * type deposit client 1 tx 1 amount 1.5
+//! ```
+//!
+//! Entries that can't be decoded or applied can be written to a [dead-letter file](../struct.DeadLetterFile.html),
+//! with the stream and entry id as their source.  Entries without a `payload` are written as a JSON object of their
+//! fields.
+
+use super::{
+    apply_decoded, policy, reload, start, DeadLetter, DeadLetterFile, Error, Format, Snapshotter,
+};
+use crate::bank::retry::Retry;
+use crate::bank::transaction::instruction::TransactionInstruction;
+use crate::shutdown::Shutdown;
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use redis::Commands;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// How long a read waits for new entries, in milliseconds.  Also how long a shutdown can go unnoticed.
+const BLOCK_MILLIS: usize = 5000;
+
+/// Fields read from entries without a `payload`, in the order of the CSV columns.
+const FIELDS: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "correlation_id",
+    "operator_reference",
+    "timestamp",
+];
+
+/// Where to consume from and how.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Connection URL, like `redis://127.0.0.1/`.
+    pub url: String,
+    pub stream: String,
+    /// Consumer group to read as.  Created at the start of the stream if it doesn't exist.
+    pub group: String,
+    /// This runner's name within the group.
+    pub consumer: String,
+    /// Format of `payload` fields.
+    pub format: Format,
+    /// Directory to keep snapshots in.
+    pub state_dir: PathBuf,
+    /// Number of entries between snapshots.
+    pub snapshot_interval: u64,
+    /// Retry writing snapshots after transient storage errors.
+    pub retry: Retry,
+    /// Largest number of entries read at once.
+    pub batch_size: usize,
+    /// Serve the [control interface](../../control/index.html) on this Unix socket.
+    pub control_socket: Option<PathBuf>,
+    /// Apply the [policy](../../policy/index.html) in this file, reloading it when it changes or on `SIGHUP`.
+    pub policy: Option<PathBuf>,
+    /// Append entries that can't be decoded or applied to this file.
+    pub dead_letters: Option<PathBuf>,
+    /// Stop consuming once this is requested.
+    pub shutdown: Shutdown,
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(err: redis::RedisError) -> Self {
+        Error::Redis(err)
+    }
+}
+
+/// Consume and apply entries until an error occurs or a shutdown is requested.
+///
+/// # Errors
+///
+/// Will return `Err` if the server can't be reached, entries can't be acknowledged, or a snapshot can't be written.
+/// Entries that can't be decoded or applied are skipped, after being written to the dead-letter file if there is one.
+///
+/// # Panics
+///
+/// Panics if the control interface panicked while holding the bank's lock.
+pub fn run(options: &Options) -> Result<(), Error> {
+    let mut snapshotter =
+        Snapshotter::new(&options.state_dir, options.snapshot_interval)?.with_retry(options.retry);
+    let shared = start(&snapshotter, options.control_socket.as_deref())?;
+    let mut reloader = policy(
+        options.policy.as_deref(),
+        &mut shared.lock().expect("bank lock poisoned"),
+    )?;
+    let mut dead_letters = options
+        .dead_letters
+        .as_deref()
+        .map(DeadLetterFile::open)
+        .transpose()?;
+    let mut connection = redis::Client::open(options.url.as_str())?.get_connection()?;
+    let created: Result<(), _> =
+        connection.xgroup_create_mkstream(&options.stream, &options.group, "0");
+    match created {
+        Err(err) if err.code() != Some("BUSYGROUP") => return Err(err.into()),
+        _ => {}
+    }
+    tracing::info!(stream = %options.stream, group = %options.group, "consuming");
+
+    // Ids of entries applied but not yet acknowledged.
+    let mut unacked: Vec<String> = vec![];
+    // Where to continue reading this consumer's pending entries from, until they run out.
+    let mut pending_from = Some("0".to_string());
+    // The id of the last entry applied.
+    let mut last: Option<String> = None;
+    while !options.shutdown.requested() {
+        let read_options = StreamReadOptions::default()
+            .group(&options.group, &options.consumer)
+            .count(options.batch_size.max(1))
+            .block(BLOCK_MILLIS);
+        let from = pending_from.as_deref().unwrap_or(">");
+        let reply: Option<StreamReadReply> =
+            connection.xread_options(&[&options.stream], &[from], &read_options)?;
+        let entries: Vec<StreamId> = reply
+            .into_iter()
+            .flat_map(|reply| reply.keys)
+            .flat_map(|key| key.ids)
+            .collect();
+
+        let mut bank = shared.lock().expect("bank lock poisoned");
+        reload(reloader.as_mut(), &mut bank);
+        if pending_from.is_some() {
+            pending_from = entries.last().map(|entry| entry.id.clone());
+            if pending_from.is_none() {
+                tracing::debug!("caught up with pending entries");
+            }
+        }
+        let mut letters = vec![];
+        for entry in &entries {
+            tracing::trace!(id = %entry.id, "applying entry");
+            if let Err(failure) = apply_decoded(&mut bank, decode(&options.format, entry)) {
+                let source = format!("{}/{}", options.stream, entry.id);
+                letters.push(DeadLetter::new(source, &payload(entry), &failure));
+            }
+        }
+        if let Some(dead_letters) = &mut dead_letters {
+            dead_letters.write(&letters)?;
+        }
+        unacked.extend(entries.iter().map(|entry| entry.id.clone()));
+        if let Some(entry) = entries.last() {
+            last = Some(entry.id.clone());
+        }
+
+        if snapshotter.applied(&bank, entries.len() as u64)? && !unacked.is_empty() {
+            let _: usize = connection.xack(&options.stream, &options.group, &unacked)?;
+            unacked.clear();
+        }
+    }
+    snapshotter.save(&shared.lock().expect("bank lock poisoned"))?;
+    if !unacked.is_empty() {
+        let _: usize = connection.xack(&options.stream, &options.group, &unacked)?;
+    }
+    tracing::info!(?last, "stopped after shutdown request");
+    Ok(())
+}
+
+/// An entry's `payload` field, or its fields as a JSON object if it has none.
+fn payload(entry: &StreamId) -> Vec<u8> {
+    if let Some(payload) = entry.get::<Vec<u8>>("payload") {
+        return payload;
+    }
+    let fields: BTreeMap<&str, String> = entry
+        .map
+        .keys()
+        .filter_map(|field| Some((field.as_str(), entry.get::<String>(field)?)))
+        .collect();
+    serde_json::to_vec(&fields).unwrap_or_default()
+}
+
+/// Decode the instruction in an entry.
+fn decode(format: &Format, entry: &StreamId) -> Result<TransactionInstruction, Error> {
+    if let Some(payload) = entry.get::<Vec<u8>>("payload") {
+        return format.decode(&payload);
+    }
+    let headers = csv::StringRecord::from(FIELDS.to_vec());
+    let record: csv::StringRecord = FIELDS
+        .iter()
+        .map(|field| entry.get::<String>(field).unwrap_or_default())
+        .map(|value| value.trim().to_string())
+        .collect();
+    record.deserialize(Some(&headers)).map_err(Error::Fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::amount::Amount;
+    use crate::bank::transaction::instruction::TransactionInstructionKind;
+    use redis::Value;
+
+    fn entry(fields: &[(&str, &str)]) -> StreamId {
+        StreamId {
+            id: "1-0".to_string(),
+            map: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::BulkString(v.as_bytes().to_vec())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn decode_entries() {
+        let ti = decode(
+            &Format::Json,
+            &entry(&[
+                ("type", "deposit"),
+                ("client", "1"),
+                ("tx", "2"),
+                ("amount", " 1.5"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Deposit);
+        assert_eq!(ti.client, AccountId::Number(1));
+        assert_eq!(ti.amount, Some(Amount::new(15, 1)));
+
+        let ti = decode(
+            &Format::Json,
+            &entry(&[
+                ("type", "dispute"),
+                ("client", "1"),
+                ("tx", "2"),
+                ("correlation_id", "req-1"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Dispute);
+        assert_eq!(ti.amount, None);
+        assert_eq!(ti.correlation_id.as_deref(), Some("req-1"));
+
+        let ti = decode(
+            &Format::Json,
+            &entry(&[("payload", r#"{"type": "resolve", "client": 3, "tx": 4}"#)]),
+        )
+        .unwrap();
+        assert_eq!(ti.kind, TransactionInstructionKind::Resolve);
+        assert_eq!(ti.client, AccountId::Number(3));
+
+        assert!(decode(&Format::Json, &entry(&[("type", "deposit")])).is_err());
+        assert_eq!(
+            payload(&entry(&[("type", "deposit"), ("client", "1")])),
+            br#"{"client":"1","type":"deposit"}"#
+        );
+    }
+}
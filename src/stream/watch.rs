@@ -0,0 +1,217 @@
+//! Ingesting CSV files dropped into a directory.
+//!
+//! The runner polls a drop directory and processes each new file in name order, keeping one bank across all of them.
+//! A file is applied whole or not at all: every record is read before any is applied, and a file with a record that
+//! can't be read is moved to `failed/` without touching the bank.  Instructions the bank rejects are logged and
+//! skipped as with any input.  Once a file is applied a [snapshot](../struct.Snapshotter.html) is written and the file
+//! is moved to `done/`.  A crash between the two applies the file again on restart.
+//!
+//! A shutdown is noticed between files, and while waiting for new ones.  Files not yet applied are left in the
+//! directory for the next run.
+//!
+//! Producers should write files elsewhere, or under a name starting with `.`, and rename them into the directory once
+//! they are complete, so that a file is never picked up half written.
+
+use super::{apply_decoded, policy, reload, start, Error, Snapshotter};
+use crate::bank::Bank;
+use crate::shutdown::Shutdown;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Subdirectory of the drop directory that applied files are moved to.
+pub const DONE_DIR: &str = "done";
+/// Subdirectory of the drop directory that unreadable files are moved to.
+pub const FAILED_DIR: &str = "failed";
+
+/// What to watch and how.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Directory that files are dropped into.
+    pub dir: PathBuf,
+    pub state_dir: PathBuf,
+    /// How long to wait before looking for new files when there are none.
+    pub poll_interval: Duration,
+    pub control_socket: Option<PathBuf>,
+    /// Apply the [policy](../../policy/index.html) in this file, reloading it when it changes or on `SIGHUP`.
+    pub policy: Option<PathBuf>,
+    /// Record instructions for a terminal dashboard.
+    #[cfg(feature = "tui")]
+    pub dashboard: Option<std::sync::Arc<crate::dashboard::Dashboard>>,
+    /// Stop watching once this is requested.
+    pub shutdown: Shutdown,
+}
+
+/// Longest a shutdown goes unnoticed while waiting for new files.
+const SHUTDOWN_CHECK: Duration = Duration::from_millis(100);
+
+/// Process files as they appear until an error occurs or a shutdown is requested.
+///
+/// # Errors
+///
+/// Will return `Err` if the directory can't be read, a file can't be moved, or a snapshot can't be written.  Files
+/// that can't be read are moved to `failed/` instead.
+///
+/// # Panics
+///
+/// Panics if the control interface panicked while holding the bank's lock.
+pub fn run(options: &Options) -> Result<(), Error> {
+    let mut snapshotter = Snapshotter::new(&options.state_dir, 0)?;
+    let shared = start(&snapshotter, options.control_socket.as_deref())?;
+    let mut reloader = policy(
+        options.policy.as_deref(),
+        &mut shared.lock().expect("bank lock poisoned"),
+    )?;
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = &options.dashboard {
+        dashboard.install(&mut shared.lock().expect("bank lock poisoned"));
+    }
+    tracing::info!(dir = ?options.dir, "watching");
+    while !options.shutdown.requested() {
+        let processed = {
+            let mut bank = shared.lock().expect("bank lock poisoned");
+            reload(reloader.as_mut(), &mut bank);
+            poll(&mut bank, &mut snapshotter, &options.dir, &options.shutdown)?
+        };
+        if processed == 0 {
+            wait(options.poll_interval, &options.shutdown);
+        }
+    }
+    tracing::info!(dir = ?options.dir, "stopped after shutdown request");
+    Ok(())
+}
+
+/// Sleep for `interval`, or until `shutdown` is requested.
+fn wait(interval: Duration, shutdown: &Shutdown) {
+    let until = Instant::now() + interval;
+    while !shutdown.requested() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(SHUTDOWN_CHECK));
+    }
+}
+
+/// Process the files waiting in `dir` in name order, stopping early if `shutdown` is requested, and return how many
+/// were processed.
+///
+/// # Errors
+///
+/// Will return `Err` if the directory can't be read, a file can't be moved, or a snapshot can't be written.
+pub fn poll(
+    bank: &mut Bank,
+    snapshotter: &mut Snapshotter,
+    dir: &Path,
+    shutdown: &Shutdown,
+) -> Result<usize, Error> {
+    fs::create_dir_all(dir.join(DONE_DIR))?;
+    fs::create_dir_all(dir.join(FAILED_DIR))?;
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    let mut processed = 0;
+    for path in &files {
+        if shutdown.requested() {
+            break;
+        }
+        ingest(bank, snapshotter, dir, path)?;
+        processed += 1;
+    }
+    Ok(processed)
+}
+
+/// Apply the file at `path` and move it out of `dir`.
+fn ingest(
+    bank: &mut Bank,
+    snapshotter: &mut Snapshotter,
+    dir: &Path,
+    path: &Path,
+) -> Result<(), Error> {
+    let instructions: Result<Vec<_>, csv::Error> = fs::File::open(path)
+        .map_err(csv::Error::from)
+        .and_then(|file| crate::cli::instructions(io::BufReader::new(file)).collect());
+    let moved_to = match instructions {
+        Ok(instructions) => {
+            tracing::info!(file = ?path, records = instructions.len(), "applying file");
+            for ti in instructions {
+                // Rejections are logged like any input's.
+                let _ = apply_decoded(bank, Ok(ti));
+            }
+            snapshotter.save(bank)?;
+            DONE_DIR
+        }
+        Err(err) => {
+            tracing::error!(file = ?path, %err, "moving unreadable file aside");
+            FAILED_DIR
+        }
+    };
+    let name = path.file_name().expect("directory entries have names");
+    fs::rename(path, dir.join(moved_to).join(name))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+
+    #[test]
+    fn moves_files_after_applying_them() {
+        let root =
+            std::env::temp_dir().join(format!("transactomatic-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("drop");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("1.csv"),
+            "type,client,tx,amount\ndeposit,1,1,2.5\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("2.csv"),
+            "type,client,tx,amount\ndeposit,1,2,1\nbogus,1,3,1\n",
+        )
+        .unwrap();
+        fs::write(dir.join(".3.csv"), "type,client,tx,amount\ndeposit,1,4,1\n").unwrap();
+
+        let mut snapshotter = Snapshotter::new(root.join("state"), 0).unwrap();
+        let mut bank = snapshotter.load().unwrap();
+        let shutdown = Shutdown::new();
+        assert_eq!(
+            poll(&mut bank, &mut snapshotter, &dir, &shutdown).unwrap(),
+            2
+        );
+        assert_eq!(
+            poll(&mut bank, &mut snapshotter, &dir, &shutdown).unwrap(),
+            0
+        );
+
+        let client = AccountId::Number(1);
+        assert_eq!(bank.account(&client).unwrap().total().to_string(), "2.5000");
+        assert!(dir.join(DONE_DIR).join("1.csv").exists());
+        assert!(dir.join(FAILED_DIR).join("2.csv").exists());
+        assert!(dir.join(".3.csv").exists());
+        let restored = snapshotter.load().unwrap();
+        assert_eq!(
+            restored.account(&client).unwrap().total().to_string(),
+            "2.5000"
+        );
+
+        fs::write(dir.join("4.csv"), "type,client,tx,amount\ndeposit,1,5,1\n").unwrap();
+        shutdown.request();
+        assert_eq!(
+            poll(&mut bank, &mut snapshotter, &dir, &shutdown).unwrap(),
+            0
+        );
+        assert!(dir.join("4.csv").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
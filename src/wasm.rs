@@ -0,0 +1,115 @@
+//! This module contains a JavaScript API for the [Bank](../bank/struct.Bank.html), for running the same engine in a
+//! browser or Node.
+//!
+//! Build the library for `wasm32-unknown-unknown` without the command line application and generate bindings with
+//! `wasm-bindgen` or `wasm-pack`:
+//!
+//! ```text
+//! cargo build --lib --release --target wasm32-unknown-unknown --no-default-features --features wasm
+//! ```
+//!
+//! Nothing on this path touches the file system or installs a logger.  From JavaScript:
+//!
+//! ```js
+//! const bank = new Bank();
+//! bank.apply({ type: "deposit", client: 1, tx: 1, amount: "2.5" });
+//! bank.applyCsv("withdrawal, 1, 2, 1.0");
+//! bank.account(1); // { client: 1, available: "1.5000", held: "0.0000", total: "1.5000", locked: false }
+//! ```
+//!
+//! Accounts are plain objects in the same form as the account report, with amounts as strings so they keep their
+//! precision.  Rejected instructions throw an `Error` with the reason.
+
+use crate::bank::account::{AccountId, AccountSummary};
+use crate::bank::transaction::instruction::TransactionInstruction;
+use crate::bank::Bank;
+use wasm_bindgen::prelude::*;
+
+/// A bank, exported to JavaScript as `Bank`.
+#[wasm_bindgen(js_name = Bank)]
+#[derive(Debug, Default)]
+pub struct WasmBank {
+    bank: Bank,
+}
+
+#[wasm_bindgen(js_class = Bank)]
+impl WasmBank {
+    #[wasm_bindgen(constructor)]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply an instruction object with the same fields as the CSV input, returning the client's account.
+    ///
+    /// # Errors
+    ///
+    /// Throws if the object isn't an instruction or the bank rejects it.
+    pub fn apply(&mut self, instruction: JsValue) -> Result<JsValue, JsError> {
+        let ti: TransactionInstruction = serde_wasm_bindgen::from_value(instruction)?;
+        self.perform(ti)
+    }
+
+    /// Apply one line of CSV input without a header, like `deposit,1,1,2.5`, returning the client's account.
+    ///
+    /// # Errors
+    ///
+    /// Throws if the line isn't an instruction or the bank rejects it.
+    #[wasm_bindgen(js_name = applyCsv)]
+    pub fn apply_csv(&mut self, line: &str) -> Result<JsValue, JsError> {
+        let ti: TransactionInstruction = line.parse()?;
+        self.perform(ti)
+    }
+
+    /// A client's account, or `undefined` if there isn't one.
+    ///
+    /// # Errors
+    ///
+    /// Throws if `client` isn't a number or a string, or the account can't be converted to a JavaScript object.
+    pub fn account(&self, client: JsValue) -> Result<JsValue, JsError> {
+        let client: AccountId = serde_wasm_bindgen::from_value(client)?;
+        match self.bank.account(&client) {
+            Some(account) => to_js(&AccountSummary::from(account)),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    /// Every account, in client id order.
+    ///
+    /// # Errors
+    ///
+    /// Throws if the accounts can't be converted to JavaScript objects.
+    pub fn accounts(&self) -> Result<JsValue, JsError> {
+        to_js(&sorted_accounts(&self.bank))
+    }
+
+    fn perform(&mut self, ti: TransactionInstruction) -> Result<JsValue, JsError> {
+        let account = self.bank.perform_transaction(ti)?;
+        to_js(&AccountSummary::from(account))
+    }
+}
+
+fn to_js<T: serde::Serialize + ?Sized>(value: &T) -> Result<JsValue, JsError> {
+    Ok(serde_wasm_bindgen::to_value(value)?)
+}
+
+fn sorted_accounts(bank: &Bank) -> Vec<AccountSummary> {
+    let mut accounts: Vec<AccountSummary> = bank.accounts().map(AccountSummary::from).collect();
+    accounts.sort_unstable_by_key(|account| account.client);
+    accounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_in_order() {
+        let mut bank = Bank::new();
+        for line in ["deposit,3,2,1", "deposit,1,3,1"] {
+            bank.perform_transaction(line.parse().unwrap()).unwrap();
+        }
+        let clients: Vec<_> = sorted_accounts(&bank).iter().map(|a| a.client).collect();
+        assert_eq!(clients, [AccountId::Number(1), AccountId::Number(3)]);
+    }
+}
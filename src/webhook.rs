@@ -0,0 +1,430 @@
+//! This module contains webhooks, which notify other services of significant events on a
+//! [Bank](../bank/struct.Bank.html) as they happen, so they don't have to poll reports.
+//!
+//! Each hook is a URL and the triggers it's notified of:
+//!
+//! | Trigger | Fires on |
+//! |---|---|
+//! | `chargeback` | A chargeback. |
+//! | `account_locked` | An account being locked, by a chargeback or by an operator locking or closing it. |
+//! | `large_transaction` | A deposit or withdrawal of at least the hook's `large_transaction` amount. |
+//! | `alert` | An [alert](../alert/index.html) from the fraud or anomaly screens, once the notifier is one of their sinks. |
+//!
+//! Hooks are read from a JSON array, with amounts as strings:
+//!
+//! ```json
+//! [{"url": "https://risk.example.com/hooks", "secret": "6b1d9e", "triggers": ["chargeback", "large_transaction"], "large_transaction": "10000"}]
+//! ```
+//!
+//! A notification is `POST`ed as JSON with the triggers it fired and the [event](../bank/event/enum.Event.html), e.g.
+//! `{"triggers":["chargeback","account_locked"],"event":{"event":"ChargebackApplied","client":1,"tx":7,"amount":"5.0000"}}`.
+//! Amounts are written to the four decimal places of the account report.  Alerts are sent the same way, with an
+//! `alert` instead of an `event`.  A notification is signed with the hook's secret: the `Transactomatic-Signature`
+//! header is `sha256=` and the hex HMAC-SHA256 of the body, which the receiver can check to know the notification came
+//! from us.
+//!
+//! Notifications are sent from a thread per hook, so a slow or unreachable hook never holds up the bank or the other
+//! hooks.  A failed notification is retried `retries` times, waiting `backoff_ms` milliseconds before the first retry
+//! and twice as long before each one after it, unless the hook answered with a `4xx` status other than `429`, which
+//! retrying won't change.  Notifications that still fail are logged and dropped.
+
+use crate::alert::{Alert, AlertSink};
+use crate::bank::admin::Action;
+use crate::bank::amount::Amount;
+use crate::bank::event::Event;
+use crate::bank::Bank;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The header carrying a notification's signature.
+pub const SIGNATURE_HEADER: &str = "Transactomatic-Signature";
+
+/// Longest a hook is given to answer a notification.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors reading hooks.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A hook, named by its URL, has no triggers.
+    NoTriggers(String),
+    /// A hook, named by its URL, has an empty secret.
+    EmptySecret(String),
+    /// A hook, named by its URL, has the `large_transaction` trigger without an amount.
+    NoThreshold(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "error reading webhooks: {err}"),
+            Error::Json(err) => write!(f, "invalid webhooks: {err}"),
+            Error::NoTriggers(url) => write!(f, "webhook {url} has no triggers"),
+            Error::EmptySecret(url) => write!(f, "webhook {url} has an empty secret"),
+            Error::NoThreshold(url) => write!(
+                f,
+                "webhook {url} is triggered by large transactions but has no large_transaction amount"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::NoTriggers(_) | Error::EmptySecret(_) | Error::NoThreshold(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+/// What a hook is notified of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Chargeback,
+    AccountLocked,
+    LargeTransaction,
+    Alert,
+}
+
+/// A URL notified of events, as read from the hooks file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    pub url: String,
+    /// Key the notifications are signed with.
+    pub secret: String,
+    pub triggers: Vec<Trigger>,
+    /// Deposits and withdrawals of at least this amount fire `large_transaction`.
+    #[serde(default)]
+    pub large_transaction: Option<Amount>,
+    /// How many times a failed notification is retried.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Milliseconds to wait before the first retry.  Each retry after it waits twice as long as the one before.
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+fn default_retries() -> u32 {
+    5
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+impl Hook {
+    /// The triggers `event` fires for this hook, in the order the hook lists them.
+    fn fired(&self, event: &Event) -> Vec<Trigger> {
+        self.triggers
+            .iter()
+            .copied()
+            .filter(|trigger| match (trigger, event) {
+                (Trigger::Chargeback | Trigger::AccountLocked, Event::ChargebackApplied { .. })
+                | (
+                    Trigger::AccountLocked,
+                    Event::AccountAdministered {
+                        action: Action::Lock | Action::Close,
+                        ..
+                    },
+                ) => true,
+                (
+                    Trigger::LargeTransaction,
+                    Event::DepositApplied { amount, .. } | Event::WithdrawalApplied { amount, .. },
+                ) => self
+                    .large_transaction
+                    .is_some_and(|threshold| *amount >= threshold),
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// The `Transactomatic-Signature` header of a notification with `body`.
+    fn signature(&self, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(body.as_bytes());
+        let mut signature = String::from("sha256=");
+        for byte in mac.finalize().into_bytes() {
+            let _ = write!(signature, "{byte:02x}");
+        }
+        signature
+    }
+
+    /// Send the notification with `body`, retrying it as the hook allows.
+    fn deliver(&self, agent: &ureq::Agent, body: &str) {
+        let signature = self.signature(body);
+        let mut delay = Duration::from_millis(self.backoff_ms);
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            let sent = agent
+                .post(&self.url)
+                .set("Content-Type", "application/json")
+                .set(SIGNATURE_HEADER, &signature)
+                .send_string(body);
+            match sent {
+                Ok(_) => return,
+                Err(ureq::Error::Status(status, _)) if status < 500 && status != 429 => {
+                    tracing::warn!(url = %self.url, status, "webhook refused notification");
+                    return;
+                }
+                Err(err) => {
+                    tracing::debug!(url = %self.url, attempt, %err, "webhook notification failed");
+                }
+            }
+        }
+        tracing::warn!(url = %self.url, retries = self.retries, "webhook notification dropped after retrying");
+    }
+}
+
+/// A notification as it's sent.
+#[derive(Serialize)]
+struct Notification<'a> {
+    triggers: Vec<Trigger>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'a Event>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alert: Option<&'a Alert>,
+}
+
+/// Read hooks from the JSON file at `path`.
+///
+/// # Errors
+///
+/// Will return `Err` if the file can't be read or isn't valid.
+pub fn read(path: &Path) -> Result<Vec<Hook>, Error> {
+    let hooks: Vec<Hook> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    for hook in &hooks {
+        if hook.triggers.is_empty() {
+            return Err(Error::NoTriggers(hook.url.clone()));
+        }
+        if hook.secret.is_empty() {
+            return Err(Error::EmptySecret(hook.url.clone()));
+        }
+        if hook.triggers.contains(&Trigger::LargeTransaction) && hook.large_transaction.is_none() {
+            return Err(Error::NoThreshold(hook.url.clone()));
+        }
+    }
+    Ok(hooks)
+}
+
+/// Notifies hooks of the events of one or more banks.
+#[derive(Debug)]
+pub struct Notifier {
+    hooks: Vec<Hook>,
+    /// Each hook's queue of notification bodies, until the notifier is finished.
+    queues: Mutex<Vec<mpsc::Sender<String>>>,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl Notifier {
+    /// Start a thread sending each of `hooks` its notifications.
+    #[must_use]
+    pub fn start(hooks: Vec<Hook>) -> Arc<Self> {
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let mut queues = Vec::with_capacity(hooks.len());
+        let mut workers = Vec::with_capacity(hooks.len());
+        for hook in &hooks {
+            let (queue, notifications) = mpsc::channel::<String>();
+            let hook = hook.clone();
+            let agent = agent.clone();
+            queues.push(queue);
+            workers.push(thread::spawn(move || {
+                for body in notifications {
+                    hook.deliver(&agent, &body);
+                }
+            }));
+        }
+        Arc::new(Self {
+            hooks,
+            queues: Mutex::new(queues),
+            workers: Mutex::new(workers),
+        })
+    }
+
+    /// Notify the hooks of every event `bank` has from now on.
+    pub fn install(self: &Arc<Self>, bank: &mut Bank) {
+        let notifier = Arc::clone(self);
+        bank.register_observer(move |event: &Event| notifier.notify(event));
+    }
+
+    fn notify(&self, event: &Event) {
+        self.send(|hook| hook.fired(event), Some(&event.rescaled()), None);
+    }
+
+    /// Queue a notification of `event` or `alert` for each hook `fired` gives triggers for.
+    fn send(
+        &self,
+        fired: impl Fn(&Hook) -> Vec<Trigger>,
+        event: Option<&Event>,
+        alert: Option<&Alert>,
+    ) {
+        let queues = self.queues.lock().expect("webhook queues lock poisoned");
+        for (hook, queue) in self.hooks.iter().zip(queues.iter()) {
+            let triggers = fired(hook);
+            if triggers.is_empty() {
+                continue;
+            }
+            let notification = Notification {
+                triggers,
+                event,
+                alert,
+            };
+            match serde_json::to_string(&notification) {
+                Ok(body) => {
+                    let _ = queue.send(body);
+                }
+                Err(err) => tracing::error!(%err, "can't serialize webhook notification"),
+            }
+        }
+    }
+
+    /// Stop taking events, and wait for the notifications already taken to be sent or dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if notifying panicked on another thread.
+    pub fn finish(&self) {
+        self.queues
+            .lock()
+            .expect("webhook queues lock poisoned")
+            .clear();
+        let workers =
+            std::mem::take(&mut *self.workers.lock().expect("webhook workers lock poisoned"));
+        for worker in workers {
+            worker.join().expect("webhook thread panicked");
+        }
+    }
+}
+
+impl AlertSink for Notifier {
+    fn alert(&self, alert: &Alert) {
+        let fired = |hook: &Hook| {
+            hook.triggers
+                .iter()
+                .copied()
+                .filter(|trigger| *trigger == Trigger::Alert)
+                .collect()
+        };
+        self.send(fired, None, Some(alert));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::account::AccountId;
+    use crate::bank::transaction::instruction::{
+        TransactionInstruction, TransactionInstructionKind,
+    };
+    use crate::bank::transaction::TransactionId;
+    use std::io::{BufRead, BufReader, Read, Write as _};
+    use std::net::TcpListener;
+
+    fn deposit(tx: u64, amount: i64) -> TransactionInstruction {
+        TransactionInstruction {
+            kind: TransactionInstructionKind::Deposit,
+            client: AccountId::Number(1),
+            tx: TransactionId(tx),
+            amount: Some(Amount::from(amount)),
+            correlation_id: None,
+            operator_reference: None,
+            timestamp: None,
+        }
+    }
+
+    /// Answer `statuses.len()` requests on `listener` with `statuses` in turn, returning each request's signature and
+    /// body.
+    fn answer(listener: &TcpListener, statuses: &[u16]) -> Vec<(String, String)> {
+        statuses
+            .iter()
+            .map(|status| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let (mut signature, mut length) = (String::new(), 0);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(": ").unwrap_or((line, ""));
+                    if name.eq_ignore_ascii_case(SIGNATURE_HEADER) {
+                        signature = value.to_string();
+                    } else if name.eq_ignore_ascii_case("content-length") {
+                        length = value.parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+                (signature, String::from_utf8(body).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn notifies_and_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hook = Hook {
+            url: format!("http://{}/hook", listener.local_addr().unwrap()),
+            secret: "key".to_string(),
+            triggers: vec![Trigger::LargeTransaction, Trigger::AccountLocked],
+            large_transaction: Some(Amount::from(100)),
+            retries: 2,
+            backoff_ms: 1,
+        };
+        let notifier = Notifier::start(vec![hook.clone()]);
+        let mut bank = Bank::new();
+        notifier.install(&mut bank);
+        bank.perform_transaction(deposit(1, 99)).unwrap();
+        bank.perform_transaction(deposit(2, 100)).unwrap();
+
+        let requests = answer(&listener, &[503, 200]);
+        notifier.finish();
+        let body = r#"{"triggers":["large_transaction"],"event":{"event":"DepositApplied","client":1,"tx":2,"amount":"100.0000"}}"#;
+        assert_eq!(requests[0], requests[1]);
+        assert_eq!(requests[0].1, body);
+        assert_eq!(requests[0].0, hook.signature(body));
+        assert!(hook.signature(body).starts_with("sha256="));
+
+        let locked = hook.fired(&Event::AccountAdministered {
+            client: AccountId::Number(1),
+            action: Action::Close,
+            operator_reference: "ops".to_string(),
+        });
+        assert_eq!(locked, [Trigger::AccountLocked]);
+    }
+}
@@ -1,4 +1,7 @@
+use std::sync::Arc;
+use transactomatic::bank::transaction::IdScope;
 use transactomatic::cli;
+use transactomatic::ledger::Ledger;
 
 macro_rules! integration_test {
     ($($name:ident: $in_file:expr),*) => {
@@ -48,5 +51,773 @@ integration_test![
     simple_chargeback: "simple_chargeback",
     simple_dispute: "simple_dispute",
     simple_whitespace: "simple_whitespace",
-    withdraw_neg: "withdraw_neg"
+    withdraw_neg: "withdraw_neg",
+    client_codes: "client_codes"
 ];
+
+/// Sort output lines so that reports can be compared regardless of row order.
+fn sorted_lines(s: &str) -> Vec<&str> {
+    let mut lines = s.trim().split('\n').collect::<Vec<&str>>();
+    lines.sort_unstable();
+    lines
+}
+
+/// A fresh, empty directory under the system temp dir.
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("transactomatic-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn resume_from_checkpoint() {
+    let input = include_str!("complex_in1.csv");
+    let want = include_str!("complex_out1.csv");
+    let state_dir = temp_dir("resume_from_checkpoint");
+
+    // Simulate a crash part way through by running over a prefix of the input.
+    let prefix_len = input.match_indices('\n').nth(4).unwrap().0 + 1;
+    let mut options = cli::Options {
+        checkpointer: Some(cli::checkpoint::Checkpointer::new(&state_dir, 1).unwrap()),
+        ..cli::Options::default()
+    };
+    cli::run_with_options(
+        std::io::Cursor::new(&input[..prefix_len]),
+        std::io::sink(),
+        &mut options,
+    )
+    .unwrap();
+
+    let mut options = cli::Options {
+        checkpointer: Some(cli::checkpoint::Checkpointer::new(&state_dir, 1).unwrap()),
+        ..cli::Options::default()
+    };
+    let mut writer = vec![];
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+    let got = String::from_utf8(writer).unwrap();
+    assert_eq!(sorted_lines(want), sorted_lines(&got));
+
+    // Running again after completion resumes at the end and reports the same state.
+    let mut writer = vec![];
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+    let got = String::from_utf8(writer).unwrap();
+    assert_eq!(sorted_lines(want), sorted_lines(&got));
+
+    std::fs::remove_dir_all(state_dir).unwrap();
+}
+
+#[test]
+fn interrupted() {
+    let input = include_str!("complex_in1.csv");
+    let want = include_str!("complex_out1.csv");
+    let state_dir = temp_dir("interrupted");
+
+    let shutdown = transactomatic::shutdown::Shutdown::new();
+    shutdown.request();
+    let mut options = cli::Options {
+        checkpointer: Some(cli::checkpoint::Checkpointer::new(&state_dir, 100).unwrap()),
+        shutdown,
+        ..cli::Options::default()
+    };
+    let mut writer = vec![];
+    let err =
+        cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap_err();
+    let header_len = input.find('\n').unwrap() as u64 + 1;
+    assert_eq!(
+        err.downcast_ref::<cli::Interrupted>(),
+        Some(&cli::Interrupted {
+            line: 2,
+            byte: header_len
+        })
+    );
+    assert!(writer.is_empty());
+
+    // The checkpoint taken on the way out resumes where the run stopped.
+    let mut options = cli::Options {
+        checkpointer: Some(cli::checkpoint::Checkpointer::new(&state_dir, 100).unwrap()),
+        ..cli::Options::default()
+    };
+    let mut writer = vec![];
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+    let got = String::from_utf8(writer).unwrap();
+    assert_eq!(sorted_lines(want), sorted_lines(&got));
+
+    std::fs::remove_dir_all(state_dir).unwrap();
+}
+
+#[test]
+fn rejects_file() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,10\n\
+        withdrawal,1,2,50\n\
+        refund,1,3,1\n\
+        deposit,1,4,\n\
+        dispute,1,1,\n";
+    let path = temp_dir("rejects_file").with_extension("csv");
+    for parse_threads in [0, 2] {
+        let mut options = cli::Options {
+            parse_threads,
+            rejects: Some(cli::rejects::Writer::create(&path).unwrap()),
+            ..cli::Options::default()
+        };
+        cli::run_with_options(std::io::Cursor::new(input), std::io::sink(), &mut options).unwrap();
+        drop(options);
+
+        let rejects = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<Vec<&str>> = rejects
+            .lines()
+            .map(|line| line.splitn(5, ',').collect())
+            .collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], ["line", "byte", "client", "tx", "error"]);
+        assert_eq!(rows[1], ["3", "37", "1", "2", "insufficient funds"]);
+        assert_eq!(rows[2][..4], ["4", "55", "", ""]);
+        assert_eq!(rows[3], ["5", "68", "1", "4", "amount is missing"]);
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn applied_events() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,10\n\
+        withdrawal,1,2,50\n\
+        dispute,1,1,\n";
+    let path = temp_dir("applied_events").with_extension("jsonl");
+    let mut options = cli::Options {
+        applied: Some(cli::applied::Writer::create(&path).unwrap()),
+        ..cli::Options::default()
+    };
+    cli::run_with_options(std::io::Cursor::new(input), std::io::sink(), &mut options).unwrap();
+    drop(options);
+
+    let events = std::fs::read_to_string(&path).unwrap();
+    let events: Vec<&str> = events.lines().collect();
+    assert_eq!(
+        events,
+        [
+            r#"{"seq":1,"event":"DepositApplied","client":1,"tx":1,"amount":"10.0000","before":null,"after":{"client":1,"available":"10.0000","held":"0.0000","total":"10.0000","locked":false}}"#,
+            r#"{"seq":2,"event":"DisputeOpened","client":1,"tx":1,"amount":"10.0000","before":{"client":1,"available":"10.0000","held":"0.0000","total":"10.0000","locked":false},"after":{"client":1,"available":"0.0000","held":"10.0000","total":"10.0000","locked":false}}"#,
+        ]
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn ledger() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,10\n\
+        withdrawal,1,2,4\n\
+        deposit,2,3,5\n\
+        dispute,2,3,\n\
+        chargeback,2,3,\n";
+    for threads in [1, 2] {
+        let ledger = Ledger::new();
+        let mut options = cli::Options {
+            threads,
+            ledger: Some(Arc::clone(&ledger)),
+            ..cli::Options::default()
+        };
+        cli::run_with_options(std::io::Cursor::new(input), std::io::sink(), &mut options).unwrap();
+
+        assert_eq!(ledger.entries().len(), 5);
+        let trial_balance = ledger.trial_balance();
+        assert!(trial_balance.is_balanced());
+        let mut written = vec![];
+        trial_balance.write(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "account,debit,credit\n\
+             cash,15.0000,9.0000\n\
+             customer_liability,9.0000,15.0000\n\
+             disputes_held,5.0000,5.0000\n\
+             chargeback_losses,0.0000,0.0000\n\
+             total,29.0000,29.0000\n"
+        );
+    }
+}
+
+#[test]
+fn max_errors() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,10\n\
+        deposit,1,2,x\n\
+        withdrawal,1,3,50\n\
+        refund,1,4,1\n";
+    for (threads, max_errors, ok) in [
+        (1, None, true),
+        (1, Some(1), false),
+        (2, Some(1), false),
+        (1, Some(2), true),
+    ] {
+        let mut options = cli::Options {
+            threads,
+            max_errors,
+            ..cli::Options::default()
+        };
+        let result =
+            cli::run_with_options(std::io::Cursor::new(input), std::io::sink(), &mut options);
+        match result {
+            Ok(()) => assert!(ok),
+            Err(err) => {
+                assert!(!ok);
+                assert_eq!(
+                    err.to_string(),
+                    "2 of the first 4 records couldn't be deserialized, more than the maximum of 1; the first was on \
+                     line 3, byte 37"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn slices() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1\n\
+        deposit,1,2,2\n\
+        deposit,1,3,4\n\
+        deposit,1,4,8\n";
+    let offset = input.find("deposit,1,2").unwrap() as u64;
+    for threads in [1, 2] {
+        for parse_threads in [0, 2] {
+            let mut options = cli::Options {
+                threads,
+                parse_threads,
+                slice: cli::slice::Slice {
+                    start_offset: Some(offset),
+                    skip_rows: 1,
+                    limit: Some(1),
+                },
+                ..cli::Options::default()
+            };
+            let mut output = vec![];
+            cli::run_with_options(std::io::Cursor::new(input), &mut output, &mut options).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client,available,held,total,locked\n1,4.0000,0.0000,4.0000,false\n"
+            );
+        }
+    }
+}
+
+#[test]
+fn chunked() {
+    let input = include_str!("complex_in1.csv");
+    let want = include_str!("complex_out1.csv");
+    for threads in [1, 3] {
+        for chunk_size in [1, 64, 1 << 20] {
+            let mut options = cli::Options {
+                threads,
+                parse_threads: 2,
+                chunk_size: Some(chunk_size),
+                ..cli::Options::default()
+            };
+            let mut output = vec![];
+            cli::run_with_options(std::io::Cursor::new(input), &mut output, &mut options).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), want);
+        }
+    }
+}
+
+#[test]
+fn merge_by_timestamp() {
+    let input = "type,client,tx,amount,timestamp\n\
+        deposit,1,1,10,10\n\
+        withdrawal,1,2,10,30\n";
+    let disputes = temp_dir("merge_by_timestamp").with_extension("csv");
+    std::fs::write(
+        &disputes,
+        "type,client,tx,amount,timestamp\ndispute,1,1,,20\n",
+    )
+    .unwrap();
+    for threads in [1, 2] {
+        let mut options = cli::Options {
+            threads,
+            merge: vec![disputes.clone()],
+            ..cli::Options::default()
+        };
+        let mut output = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut output, &mut options).unwrap();
+        // The dispute comes before the withdrawal, which fails because the deposit is held.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,0.0000,10.0000,10.0000,false\n"
+        );
+    }
+    std::fs::remove_file(disputes).unwrap();
+}
+
+#[test]
+fn dedup_index() {
+    let monday = "type,client,tx,amount,correlation_id\n\
+        deposit,1,1,10,\n\
+        dispute,1,1,,\n\
+        resolve,1,1,,\n\
+        withdrawal,1,2,4,req-1\n";
+    let tuesday = "type,client,tx,amount,correlation_id\n\
+        deposit,1,3,1,\n\
+        dispute,1,1,,\n\
+        withdrawal,1,4,4,req-1\n";
+    let path = temp_dir("dedup_index").with_extension("jsonl");
+    let _ = std::fs::remove_file(&path);
+
+    let mut want = vec![
+        "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n",
+        // Redelivered: the deposit and withdrawal are skipped, leaving a dispute and resolve of nothing.
+        "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n",
+        // The new deposit applies, and the withdrawal with a seen correlation id is skipped.
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n",
+    ]
+    .into_iter();
+    for (threads, input) in [(1, monday), (1, monday), (2, tuesday)] {
+        let mut options = cli::Options {
+            threads,
+            dedup: Some(cli::dedup::Index::open(&path).unwrap()),
+            ..cli::Options::default()
+        };
+        let mut output = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut output, &mut options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), want.next().unwrap());
+    }
+    assert_eq!(cli::dedup::Index::open(&path).unwrap().len(), 3);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn replay_wal() {
+    let input = include_str!("complex_in1.csv");
+    let want = include_str!("complex_out1.csv");
+    let log = temp_dir("replay_wal").with_extension("jsonl");
+    let _ = std::fs::remove_file(&log);
+
+    let mut options = cli::Options {
+        wal: Some(transactomatic::bank::wal::Writer::open(&log).unwrap()),
+        ..cli::Options::default()
+    };
+    cli::run_with_options(std::io::Cursor::new(input), std::io::sink(), &mut options).unwrap();
+    drop(options);
+
+    let mut writer = vec![];
+    cli::replay(
+        std::fs::File::open(&log).unwrap(),
+        &mut writer,
+        transactomatic::bank::wal::Until::End,
+        None,
+    )
+    .unwrap();
+    let got = String::from_utf8(writer).unwrap();
+    assert_eq!(sorted_lines(want), sorted_lines(&got));
+
+    std::fs::remove_file(log).unwrap();
+}
+
+#[test]
+fn verify_audit_log() {
+    let input = include_str!("complex_in1.csv");
+    let log = temp_dir("verify_audit_log").with_extension("jsonl");
+    let _ = std::fs::remove_file(&log);
+
+    let mut options = cli::Options {
+        audit: Some(transactomatic::bank::audit::Writer::open(&log).unwrap()),
+        ..cli::Options::default()
+    };
+    cli::run_with_options(std::io::Cursor::new(input), std::io::sink(), &mut options).unwrap();
+    drop(options);
+
+    let mut writer = vec![];
+    let intact = cli::verify_audit(std::fs::File::open(&log).unwrap(), &mut writer).unwrap();
+    let got = String::from_utf8(writer).unwrap();
+    assert!(intact, "{}", got);
+    assert!(got.starts_with("ok: 6 records"), "{}", got);
+
+    let mut lines: Vec<String> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    lines.swap(1, 2);
+    let mut writer = vec![];
+    assert!(!cli::verify_audit(lines.join("\n").as_bytes(), &mut writer).unwrap());
+
+    std::fs::remove_file(log).unwrap();
+}
+
+#[test]
+fn multiple_threads() {
+    for (input, want) in [
+        (
+            include_str!("simple_in1.csv"),
+            include_str!("simple_out1.csv"),
+        ),
+        (
+            include_str!("complex_in1.csv"),
+            include_str!("complex_out1.csv"),
+        ),
+    ] {
+        let mut options = cli::Options {
+            threads: 4,
+            ..cli::Options::default()
+        };
+        let mut writer = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+        let got = String::from_utf8(writer).unwrap();
+        assert_eq!(sorted_lines(want), sorted_lines(&got));
+    }
+}
+
+#[test]
+fn parse_threads() {
+    let input = include_str!("complex_in1.csv");
+    let want = include_str!("complex_out1.csv");
+    for threads in [1, 4] {
+        let mut options = cli::Options {
+            threads,
+            parse_threads: 2,
+            ..cli::Options::default()
+        };
+        let mut writer = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+        let got = String::from_utf8(writer).unwrap();
+        assert_eq!(sorted_lines(want), sorted_lines(&got));
+    }
+}
+
+#[test]
+fn spill_transactions() {
+    let input = include_str!("complex_in1.csv");
+    let want = include_str!("complex_out1.csv");
+    for threads in [1, 2] {
+        let mut options = cli::Options {
+            threads,
+            spill_after: Some(2),
+            ..cli::Options::default()
+        };
+        let mut writer = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+        let got = String::from_utf8(writer).unwrap();
+        assert_eq!(sorted_lines(want), sorted_lines(&got));
+    }
+}
+
+#[test]
+fn per_client_tx_ids() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,5.0\n\
+        deposit,2,1,7.0\n\
+        deposit,2,2,1.0\n\
+        dispute,2,1,\n\
+        deposit,2,1,9.0\n";
+    for (threads, spill_after) in [(1, None), (2, Some(1))] {
+        let mut options = cli::Options {
+            threads,
+            spill_after,
+            id_scope: IdScope::PerClient,
+            ..cli::Options::default()
+        };
+        let mut writer = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+        let got = String::from_utf8(writer).unwrap();
+        assert_eq!(
+            sorted_lines(&got),
+            [
+                "1,5.0000,0.0000,5.0000,false",
+                "2,1.0000,7.0000,8.0000,false",
+                "client,available,held,total,locked"
+            ]
+        );
+    }
+}
+
+#[test]
+fn lenient_amounts() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,\"$1,234.50\"\n\
+        withdrawal,1,2,$234.50\n\
+        deposit,1,3,(1.00)\n";
+    for (format, parse_threads, want) in [
+        (cli::amounts::Format::Strict, 0, None),
+        (
+            cli::amounts::Format::Lenient,
+            0,
+            Some("1,1000.0000,0.0000,1000.0000,false"),
+        ),
+        (
+            cli::amounts::Format::Lenient,
+            2,
+            Some("1,1000.0000,0.0000,1000.0000,false"),
+        ),
+    ] {
+        let mut options = cli::Options {
+            amounts: cli::amounts::Notation {
+                format,
+                ..cli::amounts::Notation::default()
+            },
+            parse_threads,
+            ..cli::Options::default()
+        };
+        let mut writer = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+        let got = String::from_utf8(writer).unwrap();
+        assert_eq!(got.lines().nth(1), want);
+    }
+}
+
+#[test]
+fn decimal_commas() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,\"1,50\"\n\
+        deposit,1,2,\"1.000,25\"\n";
+    let mut options = cli::Options {
+        amounts: cli::amounts::Notation {
+            format: cli::amounts::Format::Lenient,
+            locale: cli::amounts::Locale::Auto,
+        },
+        ..cli::Options::default()
+    };
+    let mut writer = vec![];
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+    let got = String::from_utf8(writer).unwrap();
+    assert_eq!(
+        got.lines().nth(1),
+        Some("1,1001.7500,0.0000,1001.7500,false")
+    );
+}
+
+/// Counts flushes so that streaming output can be checked.
+#[derive(Default)]
+struct FlushCounter {
+    data: Vec<u8>,
+    flushes: usize,
+}
+
+impl std::io::Write for FlushCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn sorted_streaming_report() {
+    let input = "type,client,tx,amount\n\
+        deposit,3,1,1.0\n\
+        deposit,1,2,1.0\n\
+        deposit,2,3,1.0\n";
+    let mut options = cli::Options {
+        report: cli::ReportOptions {
+            sorted: true,
+            flush_every: Some(1),
+            ..cli::ReportOptions::default()
+        },
+        ..cli::Options::default()
+    };
+    let mut writer = FlushCounter::default();
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+
+    assert_eq!(
+        String::from_utf8(writer.data).unwrap(),
+        "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,1.0000,0.0000,1.0000,false
+3,1.0000,0.0000,1.0000,false
+"
+    );
+    // At least once per account, on top of flushing at the end.
+    assert!(writer.flushes > 3);
+}
+
+#[test]
+fn expected_records() {
+    let input = include_str!("complex_in1.csv");
+    let want = include_str!("complex_out1.csv");
+    let mut options = cli::Options {
+        expected_records: Some(cli::estimate_records(input.len() as u64)),
+        ..cli::Options::default()
+    };
+    let mut writer = vec![];
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+    let got = String::from_utf8(writer).unwrap();
+    assert_eq!(sorted_lines(want), sorted_lines(&got));
+}
+
+#[test]
+fn account_metadata() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        deposit,2,2,2.0\n";
+    let accounts = "client,name,reference,tags\n\
+        1,Ada Lovelace,CUST-1,vip;early\n\
+        3,Charles Babbage,,\n";
+    for threads in [1, 2] {
+        let mut options = cli::Options {
+            threads,
+            accounts: cli::accounts::read(accounts.as_bytes()).unwrap(),
+            report: cli::ReportOptions {
+                sorted: true,
+                metadata: true,
+                ..cli::ReportOptions::default()
+            },
+            ..cli::Options::default()
+        };
+        let mut writer = vec![];
+        cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "client,available,held,total,locked,name,reference,tags
+1,1.0000,0.0000,1.0000,false,Ada Lovelace,CUST-1,vip;early
+2,2.0000,0.0000,2.0000,false,,,
+3,0.0000,0.0000,0.0000,false,Charles Babbage,,
+"
+        );
+    }
+}
+
+#[test]
+fn compressed_output() {
+    use std::io::Read;
+
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        withdrawal,1,2,1.0\n\
+        dispute,1,1,\n";
+    let dir = temp_dir("compressed_output");
+    std::fs::create_dir_all(&dir).unwrap();
+    let negative = dir.join("negative.csv.gz");
+    let mut options = cli::Options {
+        negative_report: Some(negative.clone()),
+        compression: Some(cli::compress::Compression::Gzip),
+        ..cli::Options::default()
+    };
+    let mut output = cli::compress::Writer::new(vec![], options.compression).unwrap();
+    cli::run_with_options(std::io::Cursor::new(input), &mut output, &mut options).unwrap();
+    let output = output.finish().unwrap();
+
+    let mut report = String::new();
+    flate2::read::GzDecoder::new(&*output)
+        .read_to_string(&mut report)
+        .unwrap();
+    assert_eq!(
+        report,
+        "client,available,held,total,locked\n1,-1.0000,1.0000,0.0000,false\n"
+    );
+    let mut negative_report = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(&negative).unwrap())
+        .read_to_string(&mut negative_report)
+        .unwrap();
+    assert!(negative_report.starts_with("client,"));
+    assert!(negative_report.contains("\n1,-1.0000,"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn output_manifest() {
+    use sha2::Digest;
+
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        withdrawal,1,2,1.0\n\
+        dispute,1,1,\n\
+        deposit,2,3,1.0\n";
+    let dir = temp_dir("output_manifest");
+    std::fs::create_dir_all(&dir).unwrap();
+    let negative = dir.join("negative.csv");
+    let mut options = cli::Options {
+        negative_report: Some(negative.clone()),
+        manifest: Some(cli::manifest::Manifest::default()),
+        ..cli::Options::default()
+    };
+    let mut output = cli::output::Writer::new(vec![], None, &[]).unwrap();
+    cli::run_with_options(std::io::Cursor::new(input), &mut output, &mut options).unwrap();
+    let (report, digest) = output.finish().unwrap();
+    assert_eq!(digest.rows, 2);
+    assert_eq!(
+        digest.sha256,
+        format!("{:x}", sha2::Sha256::digest(&report))
+    );
+
+    let manifest = options.manifest.unwrap();
+    assert_eq!(manifest.files.len(), 1);
+    let entry = &manifest.files[0];
+    assert_eq!(entry.file, negative.to_string_lossy());
+    assert_eq!(entry.digest.rows, 1);
+    let written = std::fs::read(&negative).unwrap();
+    assert_eq!(
+        entry.digest.sha256,
+        format!("{:x}", sha2::Sha256::digest(&written))
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn input_manifest() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        deposit,1,2,1.0\n";
+    let entry = |rows| cli::manifest::Entry {
+        file: "input.csv".to_string(),
+        digest: cli::manifest::Digest {
+            rows,
+            sha256: "0".repeat(64),
+        },
+    };
+    let on_mismatch = cli::manifest::OnMismatch::Fail;
+    // Two rows too many is caught before the whole input is read, and the checksum at the end.
+    for rows in [0, 2] {
+        let reader =
+            cli::manifest::Verifier::new(std::io::Cursor::new(input), entry(rows), on_mismatch);
+        let mut output = vec![];
+        let err =
+            cli::run_with_options(reader, &mut output, &mut cli::Options::default()).unwrap_err();
+        assert!(
+            err.to_string().contains("doesn't match its manifest"),
+            "{}",
+            err
+        );
+        assert!(output.is_empty());
+    }
+}
+
+#[test]
+fn tenants() {
+    let input = "type,client,tx,amount,tenant\n\
+                 deposit,1,1,1.5,retail\n\
+                 deposit,1,1,200,wholesale\n\
+                 withdrawal,1,2,1,retail\n\
+                 deposit,2,3,1,\n";
+    let mut options = cli::Options {
+        report: cli::ReportOptions {
+            sorted: true,
+            ..cli::ReportOptions::default()
+        },
+        ..cli::Options::default()
+    };
+    let mut writer = vec![];
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+    assert_eq!(
+        String::from_utf8(writer).unwrap(),
+        "tenant,client,available,held,total,locked\n\
+         retail,1,0.5000,0.0000,0.5000,false\n\
+         wholesale,1,200.0000,0.0000,200.0000,false\n"
+    );
+
+    let dir = temp_dir("tenants");
+    let mut options = cli::Options {
+        tenant_dir: Some(dir.clone()),
+        ..cli::Options::default()
+    };
+    let mut writer = vec![];
+    cli::run_with_options(std::io::Cursor::new(input), &mut writer, &mut options).unwrap();
+    assert!(writer.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("wholesale.csv")).unwrap(),
+        "client,available,held,total,locked\n1,200.0000,0.0000,200.0000,false\n"
+    );
+    assert!(dir.join("retail.csv").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}